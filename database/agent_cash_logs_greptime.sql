@@ -11,6 +11,7 @@ CREATE TABLE agent_cash_logs (
 
     -- 字段（数值型数据，用于聚合分析）
     cash DOUBLE NOT NULL,            -- 主体现金
+    real_cash DOUBLE NOT NULL,       -- 按价格指数折算的实际现金
     total_trades BIGINT NOT NULL,     -- 累计交易数
 
     -- 指定时间索引
@@ -35,6 +36,7 @@ CREATE TABLE agent_cash_logs (
 --     1001,          -- agent_id
 --     'Consumer_1001', -- agent_name
 --     5000.0,        -- cash
+--     4800.0,        -- real_cash
 --     15             -- total_trades
 -- );
 
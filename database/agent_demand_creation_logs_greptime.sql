@@ -0,0 +1,13 @@
+-- Agent需求产生日志表
+CREATE TABLE IF NOT EXISTS agent_demand_creation_logs (
+   `timestamp` timestamp NOT NULL,
+    round BIGINT NOT NULL,
+    task_id STRING NOT NULL INVERTED index,
+    agent_id BIGINT NOT NULL INVERTED index,
+    agent_name STRING NOT NULL,
+    product_id BIGINT NOT NULL INVERTED index,
+    agent_pref_original_elastic DOUBLE NULL,
+   TIME INDEX (`timestamp`),
+    -- 设置标签（提高查询性能）
+    PRIMARY KEY (task_id, agent_id, round)
+);
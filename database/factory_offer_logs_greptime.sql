@@ -0,0 +1,28 @@
+-- GreptimeDB建表语句 for factory_offer_logs
+CREATE TABLE factory_offer_logs (
+    -- 时间索引字段
+    `timestamp` TIMESTAMP NOT NULL,
+
+    -- 标签字段（用于分组、过滤的高频查询字段）
+    round BIGINT,                    -- 模拟轮次
+    task_id STRING INVERTED INDEX,                  -- 任务ID
+    factory_id BIGINT INVERTED INDEX,               -- 工厂ID
+    factory_name STRING,             -- 工厂名称
+    product_id BIGINT INVERTED INDEX,               -- 产品ID
+
+    -- 字段（数值型数据，用于聚合分析）
+    supply_range_lower DOUBLE NOT NULL, -- 轮末报价区间下限
+    supply_range_upper DOUBLE NOT NULL, -- 轮末报价区间上限
+    offer_price DOUBLE NOT NULL,     -- 轮末按剩余库存计算的实际报价
+    stock INT NOT NULL,              -- 轮末剩余库存
+
+    -- 指定时间索引
+    TIME INDEX (`timestamp`),
+
+    -- 设置标签（提高查询性能）
+    PRIMARY KEY (task_id, factory_id, round)
+);
+
+-- 说明：
+-- 1. 每个工厂每轮结束时记录一行
+-- 2. offer_price与报价区间一起可以分析稀缺度和促销对报价的影响
@@ -0,0 +1,9 @@
+-- GreptimeDB迁移语句：为已存在的agent_cash_logs和trade_logs补充新增的列
+-- 新建的表已包含这些列（见agent_cash_logs_greptime.sql和trade_logs_greptime.sql），无需执行
+-- 迁移前写入的行没有这些值，新列为NULL
+
+-- 按价格指数折算的实际现金
+ALTER TABLE agent_cash_logs ADD COLUMN real_cash DOUBLE;
+
+-- 扣除生产者税后工厂实际入账的价格
+ALTER TABLE trade_logs ADD COLUMN net_price DOUBLE;
@@ -0,0 +1,29 @@
+-- GreptimeDB建表语句 for negotiation_attempt_logs
+CREATE TABLE negotiation_attempt_logs (
+    -- 时间索引字段
+    `timestamp` TIMESTAMP NOT NULL,
+
+    -- 标签字段（用于分组、过滤的高频查询字段）
+    round BIGINT,                    -- 模拟轮次
+    task_id STRING INVERTED INDEX,                  -- 任务ID
+    agent_id BIGINT INVERTED INDEX,                 -- 主体ID
+    factory_id BIGINT INVERTED INDEX,               -- 工厂ID
+    product_id BIGINT INVERTED INDEX,               -- 产品ID
+    interval_relation STRING,        -- 区间关系（Overlapping/AgentBelowFactory/AgentAboveFactory）
+
+    -- 字段（数值型数据，用于聚合分析）
+    attempt INT NOT NULL,            -- 该主体本轮对该商品的第几次尝试
+    offer_price DOUBLE NOT NULL,     -- 工厂当时的报价
+    supply_range_lower DOUBLE NOT NULL, -- 工厂报价区间下限
+    supply_range_upper DOUBLE NOT NULL, -- 工厂报价区间上限
+
+    -- 指定时间索引
+    TIME INDEX (`timestamp`),
+
+    -- 设置标签（提高查询性能）
+    PRIMARY KEY (task_id, agent_id, product_id, factory_id, round, attempt)
+);
+
+-- 说明：
+-- 1. 只在开启verbose_negotiation_log时写入，每次实际比较过报价区间的撮合尝试一行
+-- 2. 用于排查交易失败原因，例如主体区间始终低于工厂区间
@@ -0,0 +1,19 @@
+-- GreptimeDB建表语句 for simulation_runs
+CREATE TABLE simulation_runs (
+    -- 时间索引字段
+    `timestamp` TIMESTAMP NOT NULL,
+
+    -- 标签字段
+    task_id STRING INVERTED INDEX,                  -- 任务ID
+
+    -- 字段
+    final_round BIGINT NOT NULL,     -- 结束时的轮次
+    reason STRING NOT NULL,          -- 结束原因（MaxRounds/AllAgentsBroke/Stalled/Converged/NoTrades/Interrupted）
+    total_trades BIGINT NOT NULL,    -- 累计成交数
+
+    -- 指定时间索引
+    TIME INDEX (`timestamp`),
+
+    -- 每次模拟一行
+    PRIMARY KEY (task_id)
+);
@@ -19,6 +19,7 @@ CREATE TABLE trade_logs (
     -- 字段（数值型数据，用于聚合分析）
     agent_cash DOUBLE NOT NULL,      -- 主体现金
     price DOUBLE,                    -- 成交价格（可选）
+    net_price DOUBLE,                -- 扣除生产者税后工厂实际入账的价格（可选）
     factory_supply_range_lower DOUBLE NOT NULL, -- 工厂供应范围下限
     factory_supply_range_upper DOUBLE NOT NULL, -- 工厂供应范围上限
     factory_stock INT NOT NULL,      -- 工厂库存
@@ -59,6 +60,7 @@ CREATE TABLE trade_logs (
 --     'Success',     -- trade_result
 --     'Overlapping', -- interval_relation
 --     150.5,         -- price
+--     135.45,        -- net_price
 --     100.0,         -- factory_supply_range_lower
 --     200.0,         -- factory_supply_range_upper
 --     9,             -- factory_stock
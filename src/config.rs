@@ -0,0 +1,1192 @@
+use toml::Value;
+
+/// 市场运行参数，对应config.toml中的[market]段
+#[derive(Clone, Debug)]
+pub struct MarketConfig {
+    /// 最多运行的轮数，可用命令行参数--rounds覆盖
+    pub max_round: u64,
+    /// 商品至少需要多少家在营工厂才允许交易，低于该数量时本轮跳过该商品
+    pub min_viable_factories: usize,
+    /// 连续多少轮所有商品的最低报价都高于所有有需求agent的区间时判定为僵局，0表示不检测
+    pub stall_rounds: u32,
+    /// 判定为僵局后的处理方式
+    pub stall_action: StallAction,
+    /// 连续多少轮所有商品的成交价波动都不超过convergence_tolerance时判定为收敛并结束模拟，0表示不检测
+    pub convergence_rounds: u32,
+    /// 收敛判定的价格波动上限：每个商品最近成交价的标准差
+    pub convergence_tolerance: f64,
+    /// 全局随机种子，设置后每个商品的工厂按 种子+商品ID+seed_offset 生成，可复现
+    pub seed: Option<u64>,
+    /// 保留最近多少轮的区间变化记录，用于调试，0表示不记录
+    pub diff_capacity: usize,
+    /// 是否处理Ctrl-C：开启后收到中断信号时完成当前轮再结束模拟，而不是直接退出进程
+    pub handle_interrupt: bool,
+    /// 逐个agent/工厂日志的抽样比例(0~1)，按ID固定抽样，1表示全部记录
+    pub log_sample_ratio: f64,
+    /// 日志攒够多少行后在一个事务中批量写入，每轮结束时写入剩余部分，0表示逐行写入
+    pub log_batch_size: usize,
+    /// 后台写入日志的队列容量，模拟线程只负责入队，0表示在模拟线程中同步写入
+    pub log_queue_capacity: usize,
+    /// 日志队列满时的处理方式
+    pub log_overflow: LogOverflow,
+    /// 每轮每个商品的成交方式
+    pub clearing: ClearingMode,
+    /// 逐个撮合时，同一商品报价相同的工厂谁先接待agent
+    pub tie_break: TieBreak,
+    /// 每轮agent参与撮合的先后顺序
+    pub process_order: ProcessOrder,
+    /// 每个商品每轮最多成交的agent数，达到后其余agent推迟到下一轮，None表示不限
+    pub max_buyers_per_product_per_round: Option<usize>,
+    /// 口碑传播：成交后把兴趣传给按ID排成环的最近几个agent，0表示不传播
+    pub word_of_mouth_neighbors: usize,
+    /// 每次口碑传播给邻居增加的需求概率
+    pub word_of_mouth_boost: f64,
+    /// 预先安排的供给冲击，到达指定轮次时自动生效
+    pub shock_schedule: Vec<Shock>,
+    /// 商品没有任何在营工厂时，是否停止agent对它产生需求并清除已有需求；
+    /// false时只在metrics中标记
+    pub suppress_unsupplied_demand: bool,
+    /// 是否每轮打印需求到成交的转化漏斗
+    pub conversion_report: bool,
+    /// 是否记录每个agent每次与工厂撮合的尝试(工厂报价与区间关系)，数据量很大，默认关闭
+    pub verbose_negotiation_log: bool,
+    /// 价格指数的基期轮次：该轮结束时各商品最近成交均价作为一篮子商品的基期价格，
+    /// 之后首次成交的商品以首次成交时的均价为基期价格
+    pub price_index_base_round: u64,
+    /// 每轮结束时把Prometheus文本格式的指标写入该文件，供textfile收集器抓取；
+    /// 需要开启metrics特性，None表示不写
+    pub metrics_path: Option<String>,
+    /// 消费者参数
+    pub agent: AgentConfig,
+    /// 工厂参数
+    pub factory: FactoryConfig,
+}
+
+impl Default for MarketConfig {
+    fn default() -> Self {
+        MarketConfig {
+            max_round: 8000,
+            min_viable_factories: 1,
+            stall_rounds: 0,
+            stall_action: StallAction::Shift(0.1),
+            convergence_rounds: 0,
+            convergence_tolerance: 0.5,
+            seed: None,
+            diff_capacity: 0,
+            handle_interrupt: false,
+            log_sample_ratio: 1.0,
+            log_batch_size: 0,
+            log_queue_capacity: 0,
+            log_overflow: LogOverflow::Block,
+            clearing: ClearingMode::Sequential,
+            tie_break: TieBreak::LowestId,
+            process_order: ProcessOrder::Shuffled,
+            max_buyers_per_product_per_round: None,
+            word_of_mouth_neighbors: 0,
+            word_of_mouth_boost: 0.2,
+            shock_schedule: Vec::new(),
+            suppress_unsupplied_demand: false,
+            conversion_report: false,
+            verbose_negotiation_log: false,
+            price_index_base_round: 1,
+            metrics_path: None,
+            agent: AgentConfig::default(),
+            factory: FactoryConfig::default(),
+        }
+    }
+}
+
+impl MarketConfig {
+    /// 从解析后的toml读取[market]段，缺省的字段使用默认值
+    pub fn from_toml(value: &Value) -> Self {
+        let mut config = MarketConfig {
+            agent: AgentConfig::from_toml(value),
+            factory: FactoryConfig::from_toml(value),
+            ..Default::default()
+        };
+        let Some(market) = value.get("market") else {
+            return config;
+        };
+
+        if let Some(v) = market
+            .get("min_viable_factories")
+            .and_then(Value::as_integer)
+        {
+            config.min_viable_factories = v.max(0) as usize;
+        }
+        if let Some(v) = market.get("max_round").and_then(Value::as_integer) {
+            config.max_round = v.max(1) as u64;
+        }
+        if let Some(v) = market.get("stall_rounds").and_then(Value::as_integer) {
+            config.stall_rounds = v.max(0) as u32;
+        }
+        let shift_ratio = market
+            .get("stall_shift_ratio")
+            .and_then(Value::as_float)
+            .unwrap_or(0.1);
+        config.stall_action = match market.get("stall_action").and_then(Value::as_str) {
+            Some("terminate") => StallAction::Terminate,
+            _ => StallAction::Shift(shift_ratio.clamp(0.0, 1.0)),
+        };
+        if let Some(v) = market.get("convergence_rounds").and_then(Value::as_integer) {
+            config.convergence_rounds = v.max(0) as u32;
+        }
+        if let Some(v) = market
+            .get("convergence_tolerance")
+            .and_then(Value::as_float)
+        {
+            config.convergence_tolerance = v.max(0.0);
+        }
+        if let Some(v) = market.get("seed").and_then(Value::as_integer) {
+            config.seed = Some(v as u64);
+        }
+        if let Some(v) = market.get("handle_interrupt").and_then(Value::as_bool) {
+            config.handle_interrupt = v;
+        }
+        if let Some(v) = market.get("log_sample_ratio").and_then(Value::as_float) {
+            config.log_sample_ratio = v.clamp(0.0, 1.0);
+        }
+        if let Some(v) = market.get("log_batch_size").and_then(Value::as_integer) {
+            config.log_batch_size = v.max(0) as usize;
+        }
+        if let Some(v) = market.get("log_queue_capacity").and_then(Value::as_integer) {
+            config.log_queue_capacity = v.max(0) as usize;
+        }
+        if market.get("log_overflow").and_then(Value::as_str) == Some("drop") {
+            config.log_overflow = LogOverflow::Drop;
+        }
+        if let Some("auction") = market.get("clearing").and_then(Value::as_str) {
+            config.clearing = ClearingMode::Auction;
+        }
+        match market.get("tie_break").and_then(Value::as_str) {
+            Some("highest_stock") => config.tie_break = TieBreak::HighestStock,
+            Some("round_robin") => config.tie_break = TieBreak::RoundRobin,
+            _ => {}
+        }
+        match market.get("process_order").and_then(Value::as_str) {
+            Some("by_id") => config.process_order = ProcessOrder::ById,
+            Some("by_cash") => config.process_order = ProcessOrder::ByCash,
+            _ => {}
+        }
+        if let Some(v) = market
+            .get("max_buyers_per_product_per_round")
+            .and_then(Value::as_integer)
+        {
+            config.max_buyers_per_product_per_round = Some(v.max(0) as usize);
+        }
+        if let Some(v) = market
+            .get("word_of_mouth_neighbors")
+            .and_then(Value::as_integer)
+        {
+            config.word_of_mouth_neighbors = v.max(0) as usize;
+        }
+        if let Some(v) = market.get("word_of_mouth_boost").and_then(Value::as_float) {
+            config.word_of_mouth_boost = v.clamp(0.0, 1.0);
+        }
+        if let Some(v) = market.get("diff_capacity").and_then(Value::as_integer) {
+            config.diff_capacity = v.max(0) as usize;
+        }
+        if let Some(v) = market
+            .get("suppress_unsupplied_demand")
+            .and_then(Value::as_bool)
+        {
+            config.suppress_unsupplied_demand = v;
+        }
+        if let Some(v) = market.get("conversion_report").and_then(Value::as_bool) {
+            config.conversion_report = v;
+        }
+        if let Some(v) = market
+            .get("verbose_negotiation_log")
+            .and_then(Value::as_bool)
+        {
+            config.verbose_negotiation_log = v;
+        }
+        if let Some(v) = market
+            .get("price_index_base_round")
+            .and_then(Value::as_integer)
+        {
+            config.price_index_base_round = v.max(0) as u64;
+        }
+        if let Some(v) = market.get("metrics_path").and_then(Value::as_str) {
+            config.metrics_path = Some(v.to_string());
+        }
+        if let Some(shocks) = market.get("shock_schedule").and_then(Value::as_array) {
+            config.shock_schedule = shocks.iter().filter_map(Shock::from_toml).collect();
+        }
+
+        config
+    }
+}
+
+/// 供给冲击：某商品所有工厂的成本变为cost_factor倍，报价区间随之整体缩放，
+/// 对应[[market.shock_schedule]]中的一项
+#[derive(Clone, Debug, PartialEq)]
+pub struct Shock {
+    /// 生效的轮次
+    pub round: u64,
+    pub product_id: u64,
+    /// 成本倍数，例如3表示成本变为三倍
+    pub cost_factor: f64,
+    /// 持续轮数，到期后恢复；None表示永久
+    pub duration: Option<u64>,
+}
+
+impl Shock {
+    /// 缺少round、product_id或cost_factor的项忽略
+    fn from_toml(value: &Value) -> Option<Self> {
+        let round = value.get("round").and_then(Value::as_integer)?;
+        let product_id = value.get("product_id").and_then(Value::as_integer)?;
+        let cost_factor = value.get("cost_factor").and_then(Value::as_float)?;
+        let duration = value
+            .get("duration")
+            .and_then(Value::as_integer)
+            .filter(|d| *d > 0);
+        Some(Shock {
+            round: round.max(0) as u64,
+            product_id: product_id as u64,
+            cost_factor: cost_factor.max(0.01),
+            duration: duration.map(|d| d as u64),
+        })
+    }
+}
+
+/// 供需僵局的处理方式
+#[derive(Clone, Debug, PartialEq)]
+pub enum StallAction {
+    /// 所有工厂区间按该比例整体下调
+    Shift(f64),
+    /// 结束模拟
+    Terminate,
+}
+
+/// 商品的成交方式，对应[market]段的clearing
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClearingMode {
+    /// 逐个工厂、逐个agent依次撮合，先匹配成功者先成交
+    Sequential,
+    /// 统一价格拍卖：所有有需求的agent同时出价，库存分配给出价最高者，按同一价格成交
+    Auction,
+}
+
+/// 日志后台写入队列满时的处理方式，对应[market]段的log_overflow
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogOverflow {
+    /// 等待队列有空位，数据库变慢时模拟也随之变慢
+    Block,
+    /// 直接丢弃该批日志并计入丢弃行数，模拟速度不受数据库影响
+    Drop,
+}
+
+/// 报价相同的工厂之间的先后顺序，对应[market]段的tie_break。
+/// 逐个撮合时排在前面的工厂先接待所有agent，因此先成交
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TieBreak {
+    /// ID小的工厂优先
+    LowestId,
+    /// 本轮库存多的工厂优先，库存相同时ID小的优先
+    HighestStock,
+    /// 按轮次轮换，让报价相同的工厂轮流优先
+    RoundRobin,
+}
+
+/// 每轮agent参与撮合的先后顺序，对应[market]段的process_order。
+/// 逐个撮合时排在前面的agent先与每个工厂交易，因此先买到
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProcessOrder {
+    /// 每轮随机打乱
+    Shuffled,
+    /// 按ID从小到大，便于调试特定agent时复现撮合过程
+    ById,
+    /// 现金多的agent优先，现金相同时ID小的优先
+    ByCash,
+}
+
+/// agent与工厂的议价方式，对应[agent]段的negotiation
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NegotiationMode {
+    /// 明码标价：区间不相交时直接失败
+    Posted,
+    /// 讨价还价：agent区间整体略低于工厂区间时，双方每步各让出剩余差价的四分之一，
+    /// agent最多加价max_concession、工厂最多降价max_concession，steps步内差价不超过一分钱则成交
+    Bargaining { steps: u32, max_concession: f64 },
+}
+
+/// 收入模型，对应[agent]段的income_model
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IncomeModel {
+    /// 每个发薪日固定发放income
+    Fixed,
+    /// AR(1)随机游走：本次收入 = income + persistence * (上次收入 - income) + 噪声，
+    /// 噪声为均值0、标准差volatility * income的正态分布，收入不低于0
+    RandomWalk { persistence: f64, volatility: f64 },
+}
+
+/// agent偏好区间整体低于工厂成本下限、永远无法成交时的处理方式，对应[agent]段的unreachable_range
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnreachableRangeAction {
+    /// 不处理，继续产生需求并失败
+    Keep,
+    /// 删除需求并永久不再对该商品产生需求
+    Drop,
+    /// 把区间上界抬到成本下限之上，使之后能与工厂报价相交
+    Widen,
+}
+
+/// 需求季节性，对应[agent]段的seasonality_*：需求生效概率按周期变化，
+/// 每个周期第peak轮最高（不衰减），半个周期后最低（衰减amplitude）
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Seasonality {
+    /// 周期轮数
+    pub period: u64,
+    /// 周期内需求最高的轮次偏移
+    pub peak: u64,
+    /// 必需品的季节幅度(0~1)
+    pub essential_amplitude: f64,
+    /// 非必需品的季节幅度(0~1)
+    pub non_essential_amplitude: f64,
+}
+
+impl Seasonality {
+    /// 指定轮次的需求概率系数，在1-幅度到1之间按余弦变化
+    pub fn factor(&self, essential: bool, round: u64) -> f64 {
+        let amplitude = if essential {
+            self.essential_amplitude
+        } else {
+            self.non_essential_amplitude
+        };
+        let period = self.period.max(1);
+        let phase = (round + period - self.peak % period) % period;
+        let angle = 2.0 * std::f64::consts::PI * phase as f64 / period as f64;
+        1.0 - amplitude * (1.0 - angle.cos()) / 2.0
+    }
+}
+
+/// 消费者参数，对应config.toml中的[agent]段
+#[derive(Clone, Debug)]
+pub struct AgentConfig {
+    /// 初始偏好区间宽度占原始价格比例的正态分布(均值, 标准差)，每个agent抽样一次；
+    /// 窄的agent挑剔，宽的agent灵活。None时沿用随机区间
+    pub range_width: Option<(f64, f64)>,
+    /// 品牌忠诚度(0~1)：上次购买的工厂仍可选时，放弃其他工厂等待它的概率
+    pub brand_loyalty: f64,
+    /// 弹性随报价在区间内位置上升的斜率，0表示弹性恒定
+    pub elasticity_slope: f64,
+    /// 是否懒加载偏好：为true时agent首次对某商品产生需求时才生成preference，减少启动内存
+    pub lazy_preferences: bool,
+    /// 是否记录每次产生需求的日志，数据量很大，默认关闭
+    pub verbose_demand_log: bool,
+    /// 按轮生成需求：不启动需求线程，由市场每轮用全局种子的随机数生成器为每个agent生成一次需求，
+    /// 交易量不再依赖机器速度和线程调度
+    pub round_synced_demand: bool,
+    /// 需求季节性，None表示需求不随轮次变化
+    pub seasonality: Option<Seasonality>,
+    /// 每次成交购买的数量，大于1时模拟批发，工厂库存不足时部分成交
+    pub purchase_quantity: i16,
+    /// 每个家庭的人数，家庭成员共用资金；0或1表示不组建家庭
+    pub household_size: usize,
+    /// 需求到达模型
+    pub demand_model: DemandModelConfig,
+    /// 议价方式
+    pub negotiation: NegotiationMode,
+    /// 每次遇到缺货时不满增加的幅度(0~1)，不满会按比例降低该商品的需求概率，0表示不惩罚
+    pub stockout_penalty: f64,
+    /// 每次生成需求时不满恢复的幅度
+    pub stockout_recovery: f64,
+    /// 决策扰动幅度：每个agent按ID固定抽取[-幅度, 幅度]内的比例，判断能否成交时区间整体按该比例偏移，0表示不扰动
+    pub acceptance_jitter: f64,
+    /// 生成偏好时原始弹性的取值范围(最小, 最大)，避免弹性为0的商品交易失败后永远不放弃需求
+    pub elasticity_bounds: (f64, f64),
+    /// 可接受价格的最大累计涨幅：交易失败上移区间时，区间整体最多平移到上界等于心理价位的该倍数，
+    /// 已超过的区间不再上移。None表示不限
+    pub max_price_growth: Option<f64>,
+    /// 价格感知噪声的标准差：每次判断能否成交时agent看到的工厂报价为真实报价乘以(1+ε)，
+    /// ε服从均值为0的正态分布，成交价仍按真实报价计算，0表示没有噪声
+    pub price_perception_noise: f64,
+    /// 每次成功购买后该商品满足程度增加的幅度(0~1)，满足程度按比例降低再次产生需求的概率，0表示不启用
+    pub satiation_per_purchase: f64,
+    /// 每次生成需求时满足程度消退的幅度
+    pub satiation_decay: f64,
+    /// 创建时同步生成的初始需求期望数量，使第一轮就有需求可交易，0表示只依赖需求线程
+    pub initial_demands: f64,
+    /// 授信额度：现金不足时最多可借的金额，0表示不能借款。只用于个人资金，不用于家庭资金
+    pub credit_limit: f64,
+    /// 借款每轮的利率，利息计入借款余额
+    pub credit_interest_rate: f64,
+    /// 单轮消费上限：每个agent一轮内所有商品的花费合计不超过该金额，与总资金无关。None表示不限
+    pub round_spending_limit: Option<f64>,
+    /// 偏好相关度(0~1)：每个agent抽样一个节俭或大方的特质，按该比例同向缩放所有商品的偏好价格和弹性，0表示各商品独立
+    pub preference_correlation: f64,
+    /// 口碑带来的需求概率每次生成需求时消退的幅度
+    pub word_of_mouth_decay: f64,
+    /// 锚定强度(0~1)：每轮偏好区间中点向商品参考价（价格分布均值）移动的比例，0表示不锚定
+    pub anchoring_strength: f64,
+    /// 必需品溢价：工厂本轮库存不足产量一半时，必需品可接受的价格上界提高该比例，0表示不溢价
+    pub need_premium: f64,
+    /// 购买非必需品后需至少保留的资金，不足时放弃非必需品，0表示不保留
+    pub want_cash_reserve: f64,
+    /// 心理账户：初始资金和收入中划给必需品账户的比例(0~1)，其余划给非必需品账户，
+    /// 购买只能花对应账户的钱。None表示不分账户
+    pub essential_budget_share: Option<f64>,
+    /// 每个agent同时未满足的需求数上限，达到后不再产生新需求，None表示不限
+    pub max_outstanding_demands: Option<usize>,
+    /// 每个agent知道的商品比例(0~1]，创建时随机抽取，只对知道的商品产生需求和偏好；None表示知道所有商品
+    pub product_awareness: Option<f64>,
+    /// 每个发薪日发放的收入，0表示没有收入
+    pub income: f64,
+    /// 收入模型，默认每次发放固定收入
+    pub income_model: IncomeModel,
+    /// 发薪间隔轮数，收入在轮次为该值倍数的轮末到账
+    pub payday_interval: u64,
+    /// 消费倾向在两个发薪日之间下降的幅度(0~1)：发薪后第一轮总会尝试购买，临近下个发薪日时
+    /// 尝试购买的概率降到1减该值，0表示不随发薪周期变化
+    pub payday_spending_decline: f64,
+    /// 偏好区间上界低于该商品所有工厂的最低售价（单位成本和保留价中较高者）时的处理方式
+    pub unreachable_range: UnreachableRangeAction,
+    /// 现金低于该值时按折扣卖掉持有的全部耐用品换取现金，0表示不抛售
+    pub panic_sell_threshold: f64,
+    /// 抛售耐用品的折扣(0~1)，售价为买入价乘以1减折扣
+    pub panic_sell_discount: f64,
+}
+
+/// 需求到达模型配置，对应[agent]段的demand_model
+#[derive(Clone, Debug, PartialEq)]
+pub enum DemandModelConfig {
+    /// 每个tick随机挑选一个商品，以probability的概率产生需求
+    Bernoulli { probability: f64 },
+    /// 每个商品每个tick的需求到达次数服从参数为rate的泊松分布
+    Poisson { rate: f64 },
+}
+
+impl Default for AgentConfig {
+    fn default() -> Self {
+        AgentConfig {
+            range_width: None,
+            brand_loyalty: 0.0,
+            elasticity_slope: 0.0,
+            lazy_preferences: false,
+            verbose_demand_log: false,
+            round_synced_demand: false,
+            seasonality: None,
+            purchase_quantity: 1,
+            household_size: 0,
+            demand_model: DemandModelConfig::Bernoulli { probability: 1.0 },
+            negotiation: NegotiationMode::Posted,
+            stockout_penalty: 0.0,
+            stockout_recovery: 0.01,
+            acceptance_jitter: 0.0,
+            price_perception_noise: 0.0,
+            elasticity_bounds: (0.0, 1.0),
+            max_price_growth: None,
+            round_spending_limit: None,
+            initial_demands: 0.0,
+            credit_limit: 0.0,
+            credit_interest_rate: 0.0,
+            satiation_per_purchase: 0.0,
+            satiation_decay: 0.05,
+            need_premium: 0.0,
+            anchoring_strength: 0.0,
+            word_of_mouth_decay: 0.05,
+            preference_correlation: 0.0,
+            want_cash_reserve: 0.0,
+            essential_budget_share: None,
+            max_outstanding_demands: None,
+            product_awareness: None,
+            income: 0.0,
+            income_model: IncomeModel::Fixed,
+            payday_interval: 1,
+            payday_spending_decline: 0.0,
+            unreachable_range: UnreachableRangeAction::Keep,
+            panic_sell_threshold: 0.0,
+            panic_sell_discount: 0.5,
+        }
+    }
+}
+
+impl AgentConfig {
+    /// 从解析后的toml读取[agent]段，缺省的字段使用默认值
+    pub fn from_toml(value: &Value) -> Self {
+        let mut config = AgentConfig::default();
+        let Some(agent) = value.get("agent") else {
+            return config;
+        };
+
+        let width_mean = agent.get("range_width_mean").and_then(Value::as_float);
+        let width_std_dev = agent.get("range_width_std_dev").and_then(Value::as_float);
+        if let Some(mean) = width_mean {
+            config.range_width = Some((mean, width_std_dev.unwrap_or(0.0).max(0.0)));
+        }
+        if let Some(v) = agent.get("brand_loyalty").and_then(Value::as_float) {
+            config.brand_loyalty = v.clamp(0.0, 1.0);
+        }
+        if let Some(v) = agent.get("elasticity_slope").and_then(Value::as_float) {
+            config.elasticity_slope = v.max(0.0);
+        }
+        if let Some(v) = agent.get("lazy_preferences").and_then(Value::as_bool) {
+            config.lazy_preferences = v;
+        }
+        if let Some(v) = agent.get("verbose_demand_log").and_then(Value::as_bool) {
+            config.verbose_demand_log = v;
+        }
+        if let Some(v) = agent.get("round_synced_demand").and_then(Value::as_bool) {
+            config.round_synced_demand = v;
+        }
+        if let Some(period) = agent
+            .get("seasonality_period")
+            .and_then(Value::as_integer)
+            .filter(|v| *v > 0)
+        {
+            let amplitude = |key: &str| {
+                agent
+                    .get(key)
+                    .and_then(Value::as_float)
+                    .unwrap_or(0.0)
+                    .clamp(0.0, 1.0)
+            };
+            config.seasonality = Some(Seasonality {
+                period: period as u64,
+                peak: agent
+                    .get("seasonality_peak")
+                    .and_then(Value::as_integer)
+                    .unwrap_or(0)
+                    .max(0) as u64,
+                essential_amplitude: amplitude("essential_seasonality"),
+                non_essential_amplitude: amplitude("non_essential_seasonality"),
+            });
+        }
+        if let Some(v) = agent.get("purchase_quantity").and_then(Value::as_integer) {
+            config.purchase_quantity = v.clamp(1, i16::MAX as i64) as i16;
+        }
+        if let Some(v) = agent.get("household_size").and_then(Value::as_integer) {
+            config.household_size = v.max(0) as usize;
+        }
+        if let Some(v) = agent.get("stockout_penalty").and_then(Value::as_float) {
+            config.stockout_penalty = v.clamp(0.0, 1.0);
+        }
+        if let Some(v) = agent.get("stockout_recovery").and_then(Value::as_float) {
+            config.stockout_recovery = v.max(0.0);
+        }
+        if let Some(v) = agent.get("acceptance_jitter").and_then(Value::as_float) {
+            config.acceptance_jitter = v.clamp(0.0, 0.5);
+        }
+        if let Some(v) = agent
+            .get("price_perception_noise")
+            .and_then(Value::as_float)
+        {
+            config.price_perception_noise = v.clamp(0.0, 0.5);
+        }
+        let min = agent
+            .get("min_elasticity")
+            .and_then(Value::as_float)
+            .unwrap_or(config.elasticity_bounds.0)
+            .clamp(0.0, 1.0);
+        let max = agent
+            .get("max_elasticity")
+            .and_then(Value::as_float)
+            .unwrap_or(config.elasticity_bounds.1)
+            .clamp(min, 1.0);
+        config.elasticity_bounds = (min, max);
+        if let Some(v) = agent.get("max_price_growth").and_then(Value::as_float) {
+            config.max_price_growth = Some(v.max(1.0));
+        }
+        if let Some(v) = agent
+            .get("satiation_per_purchase")
+            .and_then(Value::as_float)
+        {
+            config.satiation_per_purchase = v.clamp(0.0, 1.0);
+        }
+        if let Some(v) = agent.get("satiation_decay").and_then(Value::as_float) {
+            config.satiation_decay = v.max(0.0);
+        }
+        if let Some(v) = agent.get("initial_demands").and_then(Value::as_float) {
+            config.initial_demands = v.max(0.0);
+        }
+        if let Some(v) = agent.get("credit_limit").and_then(Value::as_float) {
+            config.credit_limit = v.max(0.0);
+        }
+        if let Some(v) = agent.get("credit_interest_rate").and_then(Value::as_float) {
+            config.credit_interest_rate = v.max(0.0);
+        }
+        if let Some(v) = agent.get("round_spending_limit").and_then(Value::as_float) {
+            config.round_spending_limit = Some(v.max(0.0));
+        }
+        if let Some(v) = agent
+            .get("preference_correlation")
+            .and_then(Value::as_float)
+        {
+            config.preference_correlation = v.clamp(0.0, 1.0);
+        }
+        if let Some(v) = agent.get("word_of_mouth_decay").and_then(Value::as_float) {
+            config.word_of_mouth_decay = v.max(0.0);
+        }
+        if let Some(v) = agent.get("anchoring_strength").and_then(Value::as_float) {
+            config.anchoring_strength = v.clamp(0.0, 1.0);
+        }
+        if let Some(v) = agent.get("need_premium").and_then(Value::as_float) {
+            config.need_premium = v.max(0.0);
+        }
+        if let Some(v) = agent.get("want_cash_reserve").and_then(Value::as_float) {
+            config.want_cash_reserve = v.max(0.0);
+        }
+        if let Some(v) = agent
+            .get("essential_budget_share")
+            .and_then(Value::as_float)
+        {
+            config.essential_budget_share = Some(v.clamp(0.0, 1.0));
+        }
+        if let Some(v) = agent
+            .get("max_outstanding_demands")
+            .and_then(Value::as_integer)
+        {
+            config.max_outstanding_demands = Some(v.max(0) as usize);
+        }
+        if let Some(v) = agent.get("product_awareness").and_then(Value::as_float) {
+            config.product_awareness = Some(v.clamp(0.0, 1.0));
+        }
+        if let Some(v) = agent.get("income").and_then(Value::as_float) {
+            config.income = v.max(0.0);
+        }
+        if let Some("random_walk") = agent.get("income_model").and_then(Value::as_str) {
+            let persistence = agent
+                .get("income_persistence")
+                .and_then(Value::as_float)
+                .unwrap_or(0.8);
+            let volatility = agent
+                .get("income_volatility")
+                .and_then(Value::as_float)
+                .unwrap_or(0.1);
+            config.income_model = IncomeModel::RandomWalk {
+                persistence: persistence.clamp(0.0, 1.0),
+                volatility: volatility.max(0.0),
+            };
+        }
+        if let Some(v) = agent.get("payday_interval").and_then(Value::as_integer) {
+            config.payday_interval = v.max(1) as u64;
+        }
+        if let Some(v) = agent
+            .get("payday_spending_decline")
+            .and_then(Value::as_float)
+        {
+            config.payday_spending_decline = v.clamp(0.0, 1.0);
+        }
+        match agent.get("unreachable_range").and_then(Value::as_str) {
+            Some("drop") => config.unreachable_range = UnreachableRangeAction::Drop,
+            Some("widen") => config.unreachable_range = UnreachableRangeAction::Widen,
+            _ => {}
+        }
+        if let Some(v) = agent.get("panic_sell_threshold").and_then(Value::as_float) {
+            config.panic_sell_threshold = v.max(0.0);
+        }
+        if let Some(v) = agent.get("panic_sell_discount").and_then(Value::as_float) {
+            config.panic_sell_discount = v.clamp(0.0, 1.0);
+        }
+        if let Some("bargaining") = agent.get("negotiation").and_then(Value::as_str) {
+            let steps = agent
+                .get("bargaining_steps")
+                .and_then(Value::as_integer)
+                .unwrap_or(3);
+            let max_concession = agent
+                .get("bargaining_concession")
+                .and_then(Value::as_float)
+                .unwrap_or(0.05);
+            config.negotiation = NegotiationMode::Bargaining {
+                steps: steps.max(0) as u32,
+                max_concession: max_concession.clamp(0.0, 1.0),
+            };
+        }
+        match agent.get("demand_model").and_then(Value::as_str) {
+            Some("poisson") => {
+                let rate = agent
+                    .get("demand_rate")
+                    .and_then(Value::as_float)
+                    .unwrap_or(0.1);
+                config.demand_model = DemandModelConfig::Poisson { rate };
+            }
+            _ => {
+                let probability = agent
+                    .get("demand_probability")
+                    .and_then(Value::as_float)
+                    .unwrap_or(1.0);
+                config.demand_model = DemandModelConfig::Bernoulli { probability };
+            }
+        }
+
+        config
+    }
+}
+
+/// 工厂初始报价区间的生成方式，对应[factory]段的range_init
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RangeInitStrategy {
+    /// 从价格分布抽样一个参考价，下界随机取0到参考价，上界随机取下界到1.5倍参考价
+    RandomAroundReference,
+    /// 以价格分布的中间mass(0~1)概率质量为区间，上下界为对称的分位数
+    QuantileBand { mass: f64 },
+    /// 下界为单位成本，上界为单位成本乘以markup
+    CostPlusMarkup { markup: f64 },
+}
+
+/// 工厂参数，对应config.toml中的[factory]段
+#[derive(Clone, Debug)]
+pub struct FactoryConfig {
+    /// 每轮最大产能的正态分布(均值, 标准差)，每个工厂抽样一次。None时不限产能
+    pub max_capacity: Option<(f64, f64)>,
+    /// 每轮开始时向同商品其他工厂的平均报价靠拢的比例，负数表示远离，0表示不参考竞争对手
+    pub competitor_factor: f64,
+    /// 报价对库存的敏感度(0~1)：库存少时报价向区间上界偏移，库存多时向下界偏移，0表示按区间中点报价
+    pub scarcity_weight: f64,
+    /// 成交或agent出价高于报价区间时，报价区间整体上移的比例
+    pub price_increase_ratio: f64,
+    /// 交易失败时报价区间整体下移的比例
+    pub price_decrease_ratio: f64,
+    /// 报价区间的最小宽度，区间调整后窄于该宽度（或上下界倒置）时修复
+    pub min_range_width: f64,
+    /// 每轮分红比例(0~1)：工厂留存收入超出dividend_reserve的部分按该比例分给所有者，0表示不分红
+    pub dividend_ratio: f64,
+    /// 分红时工厂至少保留的留存收入
+    pub dividend_reserve: f64,
+    /// 缩水比例(0~1)：成交时不涨价而是把单位规格缩小该比例，0表示直接涨价
+    pub shrink_ratio: f64,
+    /// 单位规格的下限，缩到下限后恢复涨价
+    pub min_unit_size: f64,
+    /// 连续成交涨价的次数上限，达到后保持报价试探市场，直到出现交易失败；None表示不限
+    pub max_consecutive_increases: Option<u32>,
+    /// 自适应调价参考的最近交易结果数，0表示不启用，调价比例固定
+    pub adaptive_step_window: usize,
+    /// 自适应调价时调整比例的倍数范围(最小, 最大)：最近全部成交时取最小倍数，全部失败时取最大倍数
+    pub adaptive_step_bounds: (f64, f64),
+    /// 初始报价区间的生成方式
+    pub range_init: RangeInitStrategy,
+    /// 单位成本：初始报价区间的下界不低于该值，0表示没有成本下限
+    pub unit_cost: f64,
+    /// 先发优势(0~1)：每个商品第一个创建的工厂单位成本降低该比例，报价区间下界同比例下移，0表示没有
+    pub first_mover_bonus: f64,
+    /// 保留价系数：工厂不以低于单位成本乘以该系数的价格出售，即使报价区间更低；0表示没有保留价
+    pub reservation_factor: f64,
+    /// 报价上限分位数：区间上移时上界不超过商品价格分布的该分位数，None表示不限。
+    /// 取值限制在0.5~0.999，超出时取最近的边界
+    pub price_ceiling_quantile: Option<f64>,
+}
+
+impl Default for FactoryConfig {
+    fn default() -> Self {
+        FactoryConfig {
+            max_capacity: None,
+            competitor_factor: 0.0,
+            scarcity_weight: 0.0,
+            price_increase_ratio: 0.01,
+            price_decrease_ratio: 0.01,
+            min_range_width: 0.01,
+            dividend_ratio: 0.0,
+            dividend_reserve: 0.0,
+            shrink_ratio: 0.0,
+            min_unit_size: 0.5,
+            max_consecutive_increases: None,
+            adaptive_step_window: 0,
+            adaptive_step_bounds: (0.5, 3.0),
+            range_init: RangeInitStrategy::RandomAroundReference,
+            unit_cost: 0.0,
+            first_mover_bonus: 0.0,
+            reservation_factor: 0.0,
+            price_ceiling_quantile: None,
+        }
+    }
+}
+
+impl FactoryConfig {
+    /// 从解析后的toml读取[factory]段，缺省的字段使用默认值
+    pub fn from_toml(value: &Value) -> Self {
+        let mut config = FactoryConfig::default();
+        let Some(factory) = value.get("factory") else {
+            return config;
+        };
+
+        let capacity_mean = factory.get("max_capacity_mean").and_then(Value::as_float);
+        let capacity_std_dev = factory
+            .get("max_capacity_std_dev")
+            .and_then(Value::as_float);
+        if let Some(mean) = capacity_mean {
+            config.max_capacity = Some((mean, capacity_std_dev.unwrap_or(0.0).max(0.0)));
+        }
+        if let Some(v) = factory.get("competitor_factor").and_then(Value::as_float) {
+            config.competitor_factor = v.clamp(-1.0, 1.0);
+        }
+        if let Some(v) = factory.get("scarcity_weight").and_then(Value::as_float) {
+            config.scarcity_weight = v.clamp(0.0, 1.0);
+        }
+        if let Some(v) = factory
+            .get("price_increase_ratio")
+            .and_then(Value::as_float)
+        {
+            config.price_increase_ratio = v.clamp(0.0, 1.0);
+        }
+        if let Some(v) = factory
+            .get("price_decrease_ratio")
+            .and_then(Value::as_float)
+        {
+            config.price_decrease_ratio = v.clamp(0.0, 1.0);
+        }
+        if let Some(v) = factory.get("min_range_width").and_then(Value::as_float) {
+            config.min_range_width = v.max(0.0);
+        }
+        if let Some(v) = factory.get("dividend_ratio").and_then(Value::as_float) {
+            config.dividend_ratio = v.clamp(0.0, 1.0);
+        }
+        if let Some(v) = factory.get("dividend_reserve").and_then(Value::as_float) {
+            config.dividend_reserve = v.max(0.0);
+        }
+        if let Some(v) = factory.get("shrink_ratio").and_then(Value::as_float) {
+            config.shrink_ratio = v.clamp(0.0, 1.0);
+        }
+        if let Some(v) = factory.get("min_unit_size").and_then(Value::as_float) {
+            config.min_unit_size = v.clamp(0.0, 1.0);
+        }
+        if let Some(v) = factory
+            .get("max_consecutive_increases")
+            .and_then(Value::as_integer)
+        {
+            config.max_consecutive_increases = Some(v.max(0) as u32);
+        }
+        if let Some(v) = factory
+            .get("adaptive_step_window")
+            .and_then(Value::as_integer)
+        {
+            config.adaptive_step_window = v.max(0) as usize;
+        }
+        let min = factory
+            .get("adaptive_step_min")
+            .and_then(Value::as_float)
+            .unwrap_or(config.adaptive_step_bounds.0)
+            .max(0.0);
+        let max = factory
+            .get("adaptive_step_max")
+            .and_then(Value::as_float)
+            .unwrap_or(config.adaptive_step_bounds.1)
+            .max(min);
+        config.adaptive_step_bounds = (min, max);
+        if let Some(v) = factory.get("unit_cost").and_then(Value::as_float) {
+            config.unit_cost = v.max(0.0);
+        }
+        if let Some(v) = factory.get("first_mover_bonus").and_then(Value::as_float) {
+            config.first_mover_bonus = v.clamp(0.0, 1.0);
+        }
+        if let Some(v) = factory.get("reservation_factor").and_then(Value::as_float) {
+            config.reservation_factor = v.max(0.0);
+        }
+        if let Some(v) = factory
+            .get("price_ceiling_quantile")
+            .and_then(Value::as_float)
+        {
+            config.price_ceiling_quantile = Some(v.clamp(0.5, 0.999));
+        }
+        match factory.get("range_init").and_then(Value::as_str) {
+            Some("quantile_band") => {
+                let mass = factory
+                    .get("quantile_band_mass")
+                    .and_then(Value::as_float)
+                    .unwrap_or(0.5);
+                config.range_init = RangeInitStrategy::QuantileBand {
+                    mass: mass.clamp(0.0, 0.999),
+                };
+            }
+            Some("cost_plus_markup") => {
+                let markup = factory
+                    .get("markup")
+                    .and_then(Value::as_float)
+                    .unwrap_or(1.5);
+                config.range_init = RangeInitStrategy::CostPlusMarkup {
+                    markup: markup.max(1.0),
+                };
+            }
+            _ => {}
+        }
+
+        config
+    }
+}
+
+/// 解析前替换配置文本中的${VAR}为环境变量的值。整个引号字符串只有一个占位符且值是数字或布尔时
+/// 去掉引号，使`count = "${AGENTS}"`得到数值；变量未设置或占位符未闭合时返回错误。
+/// 注释（引号外`#`到行尾）原样保留，不做替换
+pub fn substitute_env(contents: &str) -> Result<String, String> {
+    let mut result = String::with_capacity(contents.len());
+    for line in contents.split_inclusive('\n') {
+        let (code, comment) = line.split_at(comment_start(line));
+        result.push_str(&substitute_line(code)?);
+        result.push_str(comment);
+    }
+    Ok(result)
+}
+
+/// 一行中注释开始的位置：第一个不在双引号字符串内的`#`，没有注释时为行长度
+fn comment_start(line: &str) -> usize {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return index,
+            _ => {}
+        }
+    }
+    line.len()
+}
+
+fn substitute_line(contents: &str) -> Result<String, String> {
+    let mut result = String::with_capacity(contents.len());
+    let mut rest = contents;
+    while let Some(start) = rest.find("${") {
+        let Some(len) = rest[start + 2..].find('}') else {
+            return Err(format!("unclosed placeholder: {}", &rest[start..]));
+        };
+        let name = &rest[start + 2..start + 2 + len];
+        let value =
+            std::env::var(name).map_err(|_| format!("environment variable {} is not set", name))?;
+        let end = start + 2 + len + 1;
+        let quoted = rest[..start].ends_with('"') && rest[end..].starts_with('"');
+        let bare = value.parse::<f64>().is_ok() || value == "true" || value == "false";
+        if quoted && bare {
+            // "${VAR}" 整体替换为不带引号的值
+            result.push_str(&rest[..start - 1]);
+            result.push_str(&value);
+            rest = &rest[end + 1..];
+        } else {
+            result.push_str(&rest[..start]);
+            result.push_str(&value);
+            rest = &rest[end..];
+        }
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_market_config_from_toml() {
+        let value = "[market]\nmax_round = 500\nmin_viable_factories = 3\nseed = 42\nstall_rounds = 5\nstall_action = \"terminate\"\ndiff_capacity = 16\nhandle_interrupt = true\nlog_sample_ratio = 0.1\nlog_batch_size = 500\nlog_queue_capacity = 64\nlog_overflow = \"drop\"\nclearing = \"auction\"\ntie_break = \"round_robin\"\nprocess_order = \"by_cash\"\nmax_buyers_per_product_per_round = 2\nword_of_mouth_neighbors = 4\nword_of_mouth_boost = 0.3\nconvergence_rounds = 10\nconvergence_tolerance = 0.25\nsuppress_unsupplied_demand = true\nconversion_report = true\nverbose_negotiation_log = true\nprice_index_base_round = 20\nmetrics_path = \"metrics.prom\"\n[[market.shock_schedule]]\nround = 500\nproduct_id = 2\ncost_factor = 3.0\nduration = 100\n[[market.shock_schedule]]\nround = 800\nproduct_id = 1\ncost_factor = 0.5\n"
+            .parse::<Value>()
+            .unwrap();
+        let config = MarketConfig::from_toml(&value);
+        assert_eq!(config.max_round, 500);
+        assert_eq!(config.min_viable_factories, 3);
+        assert_eq!(config.seed, Some(42));
+        assert_eq!(config.stall_rounds, 5);
+        assert_eq!(config.stall_action, StallAction::Terminate);
+        assert_eq!(config.diff_capacity, 16);
+        assert!(config.handle_interrupt);
+        assert_eq!(config.log_sample_ratio, 0.1);
+        assert_eq!(config.log_batch_size, 500);
+        assert_eq!(config.log_queue_capacity, 64);
+        assert_eq!(config.log_overflow, LogOverflow::Drop);
+        assert_eq!(config.clearing, ClearingMode::Auction);
+        assert_eq!(config.tie_break, TieBreak::RoundRobin);
+        assert_eq!(config.process_order, ProcessOrder::ByCash);
+        assert_eq!(config.max_buyers_per_product_per_round, Some(2));
+        assert_eq!(config.word_of_mouth_neighbors, 4);
+        assert_eq!(config.word_of_mouth_boost, 0.3);
+        assert_eq!(config.convergence_rounds, 10);
+        assert_eq!(config.convergence_tolerance, 0.25);
+        assert!(config.suppress_unsupplied_demand);
+        assert!(config.conversion_report);
+        assert!(config.verbose_negotiation_log);
+        assert_eq!(config.price_index_base_round, 20);
+        assert_eq!(config.metrics_path.as_deref(), Some("metrics.prom"));
+        assert_eq!(
+            config.shock_schedule,
+            vec![
+                Shock {
+                    round: 500,
+                    product_id: 2,
+                    cost_factor: 3.0,
+                    duration: Some(100),
+                },
+                Shock {
+                    round: 800,
+                    product_id: 1,
+                    cost_factor: 0.5,
+                    duration: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_substitute_env() {
+        // SAFETY: 变量名只在本测试中使用
+        unsafe {
+            std::env::set_var("MARKET_SIM_TEST_SEED", "7");
+            std::env::set_var("MARKET_SIM_TEST_MODE", "terminate");
+        }
+        let contents = "[market]\nseed = \"${MARKET_SIM_TEST_SEED}\"\nstall_action = \"${MARKET_SIM_TEST_MODE}\"\ndiff_capacity = ${MARKET_SIM_TEST_SEED}\n";
+        let value = substitute_env(contents).unwrap().parse::<Value>().unwrap();
+        let config = MarketConfig::from_toml(&value);
+        assert_eq!(config.seed, Some(7));
+        assert_eq!(config.stall_action, StallAction::Terminate);
+        assert_eq!(config.diff_capacity, 7);
+
+        let err = substitute_env("seed = \"${MARKET_SIM_TEST_UNSET}\"").unwrap_err();
+        assert!(err.contains("MARKET_SIM_TEST_UNSET"));
+        assert!(substitute_env("seed = ${MARKET_SIM_TEST_SEED").is_err());
+
+        // 注释中的占位符不替换，引号内的#不是注释
+        let contents = "# ${MARKET_SIM_TEST_UNSET}\nname = \"a#${MARKET_SIM_TEST_SEED}\" # ${X}\n";
+        assert_eq!(
+            substitute_env(contents).unwrap(),
+            "# ${MARKET_SIM_TEST_UNSET}\nname = \"a#7\" # ${X}\n"
+        );
+    }
+
+    #[test]
+    fn test_substitute_env_shipped_config() {
+        // 随仓库发布的配置不依赖任何环境变量
+        let contents = include_str!("../config.toml");
+        let substituted = substitute_env(contents).unwrap();
+        assert_eq!(substituted, contents);
+        assert!(substituted.parse::<Value>().is_ok());
+    }
+
+    #[test]
+    fn test_market_config_defaults() {
+        let value = "".parse::<Value>().unwrap();
+        let config = MarketConfig::from_toml(&value);
+        assert_eq!(config.min_viable_factories, 1);
+        assert!(config.seed.is_none());
+        assert_eq!(config.stall_rounds, 0);
+        assert_eq!(config.stall_action, StallAction::Shift(0.1));
+        assert!(config.agent.range_width.is_none());
+        assert_eq!(config.agent.purchase_quantity, 1);
+        assert_eq!(
+            config.agent.demand_model,
+            DemandModelConfig::Bernoulli { probability: 1.0 }
+        );
+        assert!(config.factory.max_capacity.is_none());
+    }
+
+    #[test]
+    fn test_agent_config_from_toml() {
+        let value =
+            "[agent]\nrange_width_mean = 0.4\nrange_width_std_dev = 0.1\nbrand_loyalty = 0.8\nhousehold_size = 3\nelasticity_slope = 0.5\nlazy_preferences = true\npurchase_quantity = 4\ndemand_model = \"poisson\"\ndemand_rate = 0.25\nstockout_penalty = 0.3\nacceptance_jitter = 0.05\nprice_perception_noise = 0.02\nmin_elasticity = 0.05\nmax_elasticity = 0.95\nmax_price_growth = 1.5\nround_spending_limit = 80.0\nsatiation_per_purchase = 0.9\ninitial_demands = 2.5\ncredit_limit = 200.0\ncredit_interest_rate = 0.02\nneed_premium = 0.2\nwant_cash_reserve = 50.0\nessential_budget_share = 0.6\npreference_correlation = 0.7\nword_of_mouth_decay = 0.1\nanchoring_strength = 0.05\nincome = 30.0\nincome_model = \"random_walk\"\nincome_persistence = 0.9\npayday_interval = 10\npayday_spending_decline = 0.6\npanic_sell_threshold = 20.0\nunreachable_range = \"widen\"\npanic_sell_discount = 0.7\nverbose_demand_log = true\nround_synced_demand = true\nseasonality_period = 7\nseasonality_peak = 5\nnon_essential_seasonality = 0.6\nmax_outstanding_demands = 3\nproduct_awareness = 0.5\nnegotiation = \"bargaining\"\nbargaining_steps = 6\n"
+                .parse::<Value>()
+                .unwrap();
+        let config = MarketConfig::from_toml(&value);
+        assert_eq!(config.agent.range_width, Some((0.4, 0.1)));
+        assert_eq!(config.agent.brand_loyalty, 0.8);
+        assert_eq!(config.agent.household_size, 3);
+        assert_eq!(config.agent.elasticity_slope, 0.5);
+        assert!(config.agent.lazy_preferences);
+        assert!(config.agent.round_synced_demand);
+        assert_eq!(
+            config.agent.seasonality,
+            Some(Seasonality {
+                period: 7,
+                peak: 5,
+                essential_amplitude: 0.0,
+                non_essential_amplitude: 0.6,
+            })
+        );
+        assert!(config.agent.verbose_demand_log);
+        assert_eq!(config.agent.max_outstanding_demands, Some(3));
+        assert_eq!(config.agent.product_awareness, Some(0.5));
+        assert_eq!(
+            config.agent.negotiation,
+            NegotiationMode::Bargaining {
+                steps: 6,
+                max_concession: 0.05
+            }
+        );
+        assert_eq!(config.agent.purchase_quantity, 4);
+        assert_eq!(
+            config.agent.demand_model,
+            DemandModelConfig::Poisson { rate: 0.25 }
+        );
+        assert_eq!(config.agent.stockout_penalty, 0.3);
+        assert_eq!(config.agent.stockout_recovery, 0.01);
+        assert_eq!(config.agent.acceptance_jitter, 0.05);
+        assert_eq!(config.agent.price_perception_noise, 0.02);
+        assert_eq!(config.agent.elasticity_bounds, (0.05, 0.95));
+        assert_eq!(config.agent.max_price_growth, Some(1.5));
+        assert_eq!(config.agent.round_spending_limit, Some(80.0));
+        assert_eq!(config.agent.satiation_per_purchase, 0.9);
+        assert_eq!(config.agent.satiation_decay, 0.05);
+        assert_eq!(config.agent.initial_demands, 2.5);
+        assert_eq!(config.agent.credit_limit, 200.0);
+        assert_eq!(config.agent.credit_interest_rate, 0.02);
+        assert_eq!(config.agent.need_premium, 0.2);
+        assert_eq!(config.agent.want_cash_reserve, 50.0);
+        assert_eq!(config.agent.essential_budget_share, Some(0.6));
+        assert_eq!(config.agent.preference_correlation, 0.7);
+        assert_eq!(config.agent.word_of_mouth_decay, 0.1);
+        assert_eq!(config.agent.anchoring_strength, 0.05);
+        assert_eq!(config.agent.income, 30.0);
+        assert_eq!(
+            config.agent.income_model,
+            IncomeModel::RandomWalk {
+                persistence: 0.9,
+                volatility: 0.1
+            }
+        );
+        assert_eq!(config.agent.payday_interval, 10);
+        assert_eq!(config.agent.payday_spending_decline, 0.6);
+        assert_eq!(config.agent.panic_sell_threshold, 20.0);
+        assert_eq!(
+            config.agent.unreachable_range,
+            UnreachableRangeAction::Widen
+        );
+        assert_eq!(config.agent.panic_sell_discount, 0.7);
+    }
+
+    #[test]
+    fn test_factory_config_from_toml() {
+        let value = "[factory]\nmax_capacity_mean = 6.0\nmax_capacity_std_dev = 1.5\ncompetitor_factor = 0.2\nscarcity_weight = 0.7\nprice_decrease_ratio = 0.05\ndividend_ratio = 0.25\ndividend_reserve = 100.0\nshrink_ratio = 0.02\nmax_consecutive_increases = 5\nadaptive_step_window = 20\nadaptive_step_min = 0.25\nadaptive_step_max = 4.0\nrange_init = \"cost_plus_markup\"\nmarkup = 2.0\nunit_cost = 8.0\nfirst_mover_bonus = 0.3\nreservation_factor = 1.1\nprice_ceiling_quantile = 0.99\n"
+            .parse::<Value>()
+            .unwrap();
+        let config = MarketConfig::from_toml(&value);
+        assert_eq!(config.factory.max_capacity, Some((6.0, 1.5)));
+        assert_eq!(config.factory.competitor_factor, 0.2);
+        assert_eq!(config.factory.scarcity_weight, 0.7);
+        assert_eq!(config.factory.price_increase_ratio, 0.01);
+        assert_eq!(config.factory.price_decrease_ratio, 0.05);
+        assert_eq!(config.factory.min_range_width, 0.01);
+        assert_eq!(config.factory.dividend_ratio, 0.25);
+        assert_eq!(config.factory.dividend_reserve, 100.0);
+        assert_eq!(config.factory.shrink_ratio, 0.02);
+        assert_eq!(config.factory.min_unit_size, 0.5);
+        assert_eq!(
+            config.factory.range_init,
+            RangeInitStrategy::CostPlusMarkup { markup: 2.0 }
+        );
+        assert_eq!(config.factory.unit_cost, 8.0);
+        assert_eq!(config.factory.first_mover_bonus, 0.3);
+        assert_eq!(config.factory.reservation_factor, 1.1);
+        assert_eq!(config.factory.price_ceiling_quantile, Some(0.99));
+        assert_eq!(config.factory.max_consecutive_increases, Some(5));
+        assert_eq!(config.factory.adaptive_step_window, 20);
+        assert_eq!(config.factory.adaptive_step_bounds, (0.25, 4.0));
+    }
+
+    #[test]
+    fn test_negative_std_dev_clamped() {
+        let value = "[agent]\nrange_width_mean = 0.4\nrange_width_std_dev = -0.1\n\
+                     [factory]\nmax_capacity_mean = 6.0\nmax_capacity_std_dev = -1.5\n"
+            .parse::<Value>()
+            .unwrap();
+        let config = MarketConfig::from_toml(&value);
+        assert_eq!(config.agent.range_width, Some((0.4, 0.0)));
+        assert_eq!(config.factory.max_capacity, Some((6.0, 0.0)));
+    }
+
+    #[test]
+    fn test_price_ceiling_quantile_clamped() {
+        for (raw, expected) in [(0.2, 0.5), (0.9, 0.9), (1.0, 0.999)] {
+            let value = format!("[factory]\nprice_ceiling_quantile = {:?}\n", raw)
+                .parse::<Value>()
+                .unwrap();
+            let config = MarketConfig::from_toml(&value);
+            assert_eq!(config.factory.price_ceiling_quantile, Some(expected));
+        }
+    }
+}
@@ -51,8 +51,70 @@ impl NormalDistribution {
         }
     }
 
+    /// 分位数：累计概率为p(0~1)时的取值，使用Acklam有理逼近，相对误差约1e-9。
+    /// p取0或1时返回负无穷或正无穷
+    pub fn quantile(&self, p: f64) -> f64 {
+        const A: [f64; 6] = [
+            -3.969683028665376e1,
+            2.209460984245205e2,
+            -2.759285104469687e2,
+            1.38357751867269e2,
+            -3.066479806614716e1,
+            2.506628277459239,
+        ];
+        const B: [f64; 5] = [
+            -5.447609879822406e1,
+            1.615858368580409e2,
+            -1.556989798598866e2,
+            6.680131188771972e1,
+            -1.328068155288572e1,
+        ];
+        const C: [f64; 6] = [
+            -7.784894002430293e-3,
+            -3.223964580411365e-1,
+            -2.400758277161838,
+            -2.549732539343734,
+            4.374664141464968,
+            2.938163982698783,
+        ];
+        const D: [f64; 4] = [
+            7.784695709041462e-3,
+            3.224671290700398e-1,
+            2.445134137142996,
+            3.754408661907416,
+        ];
+        const P_LOW: f64 = 0.02425;
+
+        if p <= 0.0 {
+            return f64::NEG_INFINITY;
+        }
+        if p >= 1.0 {
+            return f64::INFINITY;
+        }
+        // 标准正态分布的分位数，尾部和中部分别逼近
+        let z = if p < P_LOW {
+            let q = (-2.0 * p.ln()).sqrt();
+            (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+                / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+        } else if p <= 1.0 - P_LOW {
+            let q = p - 0.5;
+            let r = q * q;
+            (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+                / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+        } else {
+            let q = (-2.0 * (1.0 - p).ln()).sqrt();
+            -(((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+                / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+        };
+        self.mean + self.std_dev * z
+    }
+
     pub fn sample(&self, range: Option<(f64, f64)>) -> f64 {
-        let mut rng = rand::thread_rng();
+        self.sample_with(&mut rand::thread_rng(), range)
+    }
+
+    /// 使用指定的随机数生成器抽样，便于用固定种子复现
+    pub fn sample_with<R: Rng + ?Sized>(&self, rng: &mut R, range: Option<(f64, f64)>) -> f64 {
         let normal = Normal::new(self.mean, self.std_dev).unwrap();
 
         match range {
@@ -175,6 +237,42 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_quantile() {
+        let dist = NormalDistribution::new(50.0, 8, "quantile_distribution".to_string(), 10.0);
+
+        assert!((dist.quantile(0.5) - 50.0).abs() < 1e-9);
+        assert!((dist.quantile(0.975) - 69.599_639_845).abs() < 1e-6);
+        assert!((dist.quantile(0.01) - 26.736_521_259).abs() < 1e-6);
+        // 关于均值对称
+        for p in [0.001, 0.1, 0.25, 0.4] {
+            assert!((dist.quantile(p) + dist.quantile(1.0 - p) - 100.0).abs() < 1e-6);
+        }
+        assert_eq!(dist.quantile(0.0), f64::NEG_INFINITY);
+        assert_eq!(dist.quantile(1.0), f64::INFINITY);
+    }
+
+    #[test]
+    fn test_sample_with_seeded_rng() {
+        use rand::SeedableRng;
+        use rand::rngs::StdRng;
+
+        let dist = NormalDistribution::new(50.0, 7, "seeded_distribution".to_string(), 10.0);
+        let draw = |seed: u64| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            (0..10)
+                .map(|i| {
+                    let range = (i % 2 == 0).then_some((40.0, 60.0));
+                    dist.sample_with(&mut rng, range)
+                })
+                .collect::<Vec<f64>>()
+        };
+
+        // 相同种子得到相同的样本序列，不同种子不同
+        assert_eq!(draw(42), draw(42));
+        assert_ne!(draw(42), draw(43));
+    }
+
     #[test]
     fn test_sample_with_range() {
         let mean = 50.0;
@@ -1,3 +1,4 @@
+use crate::config::LogOverflow;
 use crate::model::agent::Agent;
 use crate::model::agent::TradeResult;
 use crate::model::factory::Factory;
@@ -5,9 +6,22 @@ use crate::model::product::Product;
 use lazy_static::lazy_static;
 use mysql::prelude::{FromRow, Queryable};
 use mysql::{OptsBuilder, Pool};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use std::env;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
 use std::sync::{Arc, Mutex, OnceLock, RwLock};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// 写入失败时的最大重试次数
+const MAX_INSERT_RETRIES: u32 = 3;
+// 首次重试前的等待时间，之后每次翻倍
+const INSERT_RETRY_BACKOFF_MS: u64 = 50;
+// 抽样日志时agent和工厂的种子区分值，与实体ID异或后播种
+const AGENT_SAMPLE_SEED: u64 = 0xA6E7_5A3D_1C4F_0B29;
+const FACTORY_SAMPLE_SEED: u64 = 0xF4C7_0291_8E3B_6D57;
 
 // 初始化MySQL连接池
 lazy_static! {
@@ -54,101 +68,194 @@ pub fn init_mysql_client() {
 }
 
 // 交易日志结构体
+#[derive(Clone)]
 pub struct TradeLog {
-    timestamp: i64,
-    round: u64,
-    trade_id: u64,
-    task_id: String,
-    agent_id: u64,
-    agent_name: String,
-    agent_cash: f64,
-    factory_id: u64,
-    factory_name: String,
-    product_id: u64,
-    product_name: String,
-    trade_result: String,
-    interval_relation: String,
-    price: Option<f64>,
-    factory_supply_range_lower: f64,
-    factory_supply_range_upper: f64,
-    factory_stock: i16,
-    agent_pref_original_price: Option<f64>,
-    agent_pref_original_elastic: Option<f64>,
-    agent_pref_current_price: Option<f64>,
-    agent_pref_current_range_lower: Option<f64>,
-    agent_pref_current_range_upper: Option<f64>,
+    pub timestamp: i64,
+    pub round: u64,
+    pub trade_id: u64,
+    pub task_id: String,
+    pub agent_id: u64,
+    pub agent_name: String,
+    pub agent_cash: f64,
+    pub factory_id: u64,
+    pub factory_name: String,
+    pub product_id: u64,
+    pub product_name: String,
+    pub trade_result: String,
+    pub interval_relation: String,
+    pub price: Option<f64>,
+    pub net_price: Option<f64>, // 扣除生产者税后工厂实际入账的价格
+    pub factory_supply_range_lower: f64,
+    pub factory_supply_range_upper: f64,
+    pub factory_stock: i16,
+    pub agent_pref_original_price: Option<f64>,
+    pub agent_pref_original_elastic: Option<f64>,
+    pub agent_pref_current_price: Option<f64>,
+    pub agent_pref_current_range_lower: Option<f64>,
+    pub agent_pref_current_range_upper: Option<f64>,
 }
 
 // 工厂范围优化日志结构体
+#[derive(Clone)]
 pub struct FactoryRangeOptimizationLog {
-    timestamp: i64,
-    round: u64,
-    task_id: String,
-    factory_id: u64,
-    factory_name: String,
-    product_id: u64,
-    old_range_lower: f64,
-    old_range_upper: f64,
-    new_range_lower: f64,
-    new_range_upper: f64,
-    lower_change: f64,
-    upper_change: f64,
-    total_change: f64,
-    lower_change_ratio: f64,
-    upper_change_ratio: f64,
-    trade_result: String,
+    pub timestamp: i64,
+    pub round: u64,
+    pub task_id: String,
+    pub factory_id: u64,
+    pub factory_name: String,
+    pub product_id: u64,
+    pub old_range_lower: f64,
+    pub old_range_upper: f64,
+    pub new_range_lower: f64,
+    pub new_range_upper: f64,
+    pub lower_change: f64,
+    pub upper_change: f64,
+    pub total_change: f64,
+    pub lower_change_ratio: f64,
+    pub upper_change_ratio: f64,
+    pub trade_result: String,
 }
 
 // Agent范围调整日志结构体
+#[derive(Clone)]
 pub struct AgentRangeAdjustmentLog {
-    timestamp: i64,
-    round: u64,
-    task_id: String,
-    agent_id: u64,
-    agent_name: String,
-    product_id: u64,
-    old_range_lower: f64,
-    old_range_upper: f64,
-    new_range_lower: f64,
-    new_range_upper: f64,
-    lower_change: f64,
-    upper_change: f64,
-    min_change_ratio: f64,
-    max_change_ratio: f64,
-    center: f64,
-    adjustment_type: String, // "trade_success" 或 "trade_failed"
-    price: Option<f64>,      // 仅在交易成功时有值
+    pub timestamp: i64,
+    pub round: u64,
+    pub task_id: String,
+    pub agent_id: u64,
+    pub agent_name: String,
+    pub product_id: u64,
+    pub old_range_lower: f64,
+    pub old_range_upper: f64,
+    pub new_range_lower: f64,
+    pub new_range_upper: f64,
+    pub lower_change: f64,
+    pub upper_change: f64,
+    pub min_change_ratio: f64,
+    pub max_change_ratio: f64,
+    pub center: f64,
+    pub adjustment_type: String, // "trade_success" 或 "trade_failed"
+    pub price: Option<f64>,      // 仅在交易成功时有值
 }
 
 // Agent现金日志结构体
+#[derive(Clone)]
 pub struct AgentCashLog {
-    timestamp: i64,
-    round: u64,
-    task_id: String,
-    agent_id: u64,
-    agent_name: String,
-    cash: f64,         // 主体现金
-    total_trades: u64, // 累计交易数
+    pub timestamp: i64,
+    pub round: u64,
+    pub task_id: String,
+    pub agent_id: u64,
+    pub agent_name: String,
+    pub cash: f64,         // 主体现金
+    pub real_cash: f64,    // 按市场价格指数平减后的实际现金
+    pub total_trades: u64, // 累计交易数
+}
+
+// 工厂每轮结束时的报价日志结构体
+#[derive(Clone)]
+pub struct FactoryOfferLog {
+    pub timestamp: i64,
+    pub round: u64,
+    pub task_id: String,
+    pub factory_id: u64,
+    pub factory_name: String,
+    pub product_id: u64,
+    pub supply_range_lower: f64,
+    pub supply_range_upper: f64,
+    pub offer_price: f64, // 当前实际报价
+    pub stock: i16,       // 本轮剩余库存
+}
+
+// agent与某个工厂的一次撮合尝试日志结构体，开启verbose_negotiation_log时记录
+#[derive(Clone)]
+pub struct NegotiationAttemptLog {
+    pub timestamp: i64,
+    pub round: u64,
+    pub task_id: String,
+    pub agent_id: u64,
+    pub factory_id: u64,
+    pub product_id: u64,
+    pub attempt: u32, // 本轮该agent对该商品的第几次尝试，从1开始
+    pub offer_price: f64,
+    pub supply_range_lower: f64,
+    pub supply_range_upper: f64,
+    pub interval_relation: String, // 偏好区间与工厂报价区间的关系
+}
+
+// 模拟结束时的汇总日志结构体，每个task_id一行
+#[derive(Clone)]
+pub struct RunEndLog {
+    pub timestamp: i64,
+    pub task_id: String,
+    pub final_round: u64,
+    pub reason: String, // 结束原因
+    pub total_trades: u64,
+}
+
+// Agent需求产生日志结构体
+#[derive(Clone)]
+pub struct AgentDemandCreationLog {
+    pub timestamp: i64,
+    pub round: u64,
+    pub task_id: String,
+    pub agent_id: u64,
+    pub agent_name: String,
+    pub product_id: u64,
+    pub agent_pref_original_elastic: Option<f64>, // 该商品偏好抽样得到的弹性
 }
 
 // Agent需求删除日志结构体
+#[derive(Clone)]
 pub struct AgentDemandRemovalLog {
-    timestamp: i64,
-    round: u64,
-    task_id: String,
-    agent_id: u64,
-    agent_name: String,
-    product_id: u64,
-    agent_cash: f64,
-    agent_pref_original_price: Option<f64>,
-    agent_pref_original_elastic: Option<f64>,
-    agent_pref_current_price: Option<f64>,
-    agent_pref_current_range_lower: Option<f64>,
-    agent_pref_current_range_upper: Option<f64>,
-    removal_reason: String,
+    pub timestamp: i64,
+    pub round: u64,
+    pub task_id: String,
+    pub agent_id: u64,
+    pub agent_name: String,
+    pub product_id: u64,
+    pub agent_cash: f64,
+    pub agent_pref_original_price: Option<f64>,
+    pub agent_pref_original_elastic: Option<f64>,
+    pub agent_pref_current_price: Option<f64>,
+    pub agent_pref_current_range_lower: Option<f64>,
+    pub agent_pref_current_range_upper: Option<f64>,
+    pub removal_reason: String,
 }
 
 impl TradeLog {
+    /// 生成GreptimeDB的建表语句，表已存在时不做修改
+    pub fn create_table_sql() -> &'static str {
+        r#"
+                CREATE TABLE IF NOT EXISTS trade_logs (
+                    `timestamp` TIMESTAMP NOT NULL,
+                    round BIGINT,
+                    trade_id BIGINT,
+                    task_id STRING INVERTED INDEX,
+                    agent_id BIGINT INVERTED INDEX,
+                    agent_name STRING,
+                    agent_cash DOUBLE,
+                    factory_id BIGINT INVERTED INDEX,
+                    factory_name STRING,
+                    product_id BIGINT INVERTED INDEX,
+                    product_name STRING,
+                    trade_result STRING,
+                    interval_relation STRING,
+                    price DOUBLE,
+                    net_price DOUBLE,
+                    factory_supply_range_lower DOUBLE,
+                    factory_supply_range_upper DOUBLE,
+                    factory_stock INT,
+                    agent_pref_original_price DOUBLE,
+                    agent_pref_original_elastic DOUBLE,
+                    agent_pref_current_price DOUBLE,
+                    agent_pref_current_range_lower DOUBLE,
+                    agent_pref_current_range_upper DOUBLE,
+                    TIME INDEX (`timestamp`),
+                    PRIMARY KEY (task_id, agent_id, factory_id, product_id, trade_id)
+                )
+            "#
+    }
+
     pub fn new(
         round: u64,
         trade_id: u64,
@@ -208,6 +315,7 @@ impl TradeLog {
             trade_result: result_str.to_string(),
             interval_relation: interval_relation.to_string(),
             price,
+            net_price: price.map(|p| p * (1.0 - product.producer_tax_rate())),
             factory_supply_range_lower: lower,
             factory_supply_range_upper: upper,
             factory_stock: factory.get_stock(round),
@@ -218,9 +326,79 @@ impl TradeLog {
             agent_pref_current_range_upper,
         }
     }
+
+    /// 生成写入MySQL的INSERT语句
+    pub fn insert_sql(&self) -> String {
+        format!(
+            r#"
+                INSERT INTO trade_logs (
+                    timestamp, round, trade_id, task_id, agent_id, agent_name, agent_cash,
+                    factory_id, factory_name, product_id, product_name, trade_result, interval_relation, price,
+                    net_price, factory_supply_range_lower, factory_supply_range_upper, factory_stock,
+                    agent_pref_original_price, agent_pref_original_elastic, agent_pref_current_price,
+                    agent_pref_current_range_lower, agent_pref_current_range_upper
+                ) VALUES (
+                    {}, {}, {}, '{}', {}, '{}', {},
+                    {}, '{}', {}, '{}', '{}', '{}', {},
+                    {}, {}, {}, {},
+                    {}, {}, {},
+                    {}, {}
+                )
+            "#,
+            self.timestamp,
+            self.round,
+            self.trade_id,
+            self.task_id,
+            self.agent_id,
+            self.agent_name,
+            self.agent_cash,
+            self.factory_id,
+            self.factory_name,
+            self.product_id,
+            self.product_name,
+            self.trade_result,
+            self.interval_relation,
+            self.price.unwrap_or(-1.0),
+            self.net_price.unwrap_or(-1.0),
+            self.factory_supply_range_lower,
+            self.factory_supply_range_upper,
+            self.factory_stock,
+            self.agent_pref_original_price.unwrap_or(-1.0),
+            self.agent_pref_original_elastic.unwrap_or(-1.0),
+            self.agent_pref_current_price.unwrap_or(-1.0),
+            self.agent_pref_current_range_lower.unwrap_or(-1.0),
+            self.agent_pref_current_range_upper.unwrap_or(-1.0),
+        )
+    }
 }
 
 impl FactoryRangeOptimizationLog {
+    /// 生成GreptimeDB的建表语句，表已存在时不做修改
+    pub fn create_table_sql() -> &'static str {
+        r#"
+                CREATE TABLE IF NOT EXISTS factory_range_optimization_logs (
+                    `timestamp` TIMESTAMP NOT NULL,
+                    round BIGINT,
+                    task_id STRING INVERTED INDEX,
+                    factory_id BIGINT INVERTED INDEX,
+                    factory_name STRING,
+                    product_id BIGINT INVERTED INDEX,
+                    old_range_lower DOUBLE NOT NULL,
+                    old_range_upper DOUBLE NOT NULL,
+                    new_range_lower DOUBLE NOT NULL,
+                    new_range_upper DOUBLE NOT NULL,
+                    lower_change DOUBLE NOT NULL,
+                    upper_change DOUBLE NOT NULL,
+                    total_change DOUBLE NOT NULL,
+                    lower_change_ratio DOUBLE NOT NULL,
+                    upper_change_ratio DOUBLE NOT NULL,
+                    trade_result STRING,
+                    TIME INDEX (`timestamp`),
+                    PRIMARY KEY (task_id, factory_id, product_id, round)
+                )
+            "#
+    }
+
     pub fn new(
         round: u64,
         task_id: String,
@@ -260,9 +438,71 @@ impl FactoryRangeOptimizationLog {
             trade_result: trade_result.to_string(),
         }
     }
+
+    /// 生成写入MySQL的INSERT语句
+    pub fn insert_sql(&self) -> String {
+        format!(
+            r#"
+                INSERT INTO factory_range_optimization_logs (
+                    timestamp, round, task_id, factory_id, factory_name, product_id,
+                    old_range_lower, old_range_upper, new_range_lower, new_range_upper,
+                    lower_change, upper_change, total_change,
+                    lower_change_ratio, upper_change_ratio, trade_result
+                ) VALUES (
+                    {}, {}, '{}', {}, '{}', {},
+                    {}, {}, {}, {},
+                    {}, {}, {},
+                    {}, {}, '{}'
+                )
+            "#,
+            self.timestamp,
+            self.round,
+            self.task_id,
+            self.factory_id,
+            self.factory_name,
+            self.product_id,
+            self.old_range_lower,
+            self.old_range_upper,
+            self.new_range_lower,
+            self.new_range_upper,
+            self.lower_change,
+            self.upper_change,
+            self.total_change,
+            self.lower_change_ratio * 100.0, // 转换为百分比
+            self.upper_change_ratio * 100.0, // 转换为百分比
+            self.trade_result
+        )
+    }
 }
 
 impl AgentRangeAdjustmentLog {
+    /// 生成GreptimeDB的建表语句，表已存在时不做修改
+    pub fn create_table_sql() -> &'static str {
+        r#"
+                CREATE TABLE IF NOT EXISTS agent_range_adjustment_logs (
+                    `timestamp` TIMESTAMP NOT NULL,
+                    round BIGINT,
+                    task_id STRING INVERTED INDEX,
+                    agent_id BIGINT INVERTED INDEX,
+                    agent_name STRING,
+                    product_id BIGINT INVERTED INDEX,
+                    old_range_lower DOUBLE NOT NULL,
+                    old_range_upper DOUBLE NOT NULL,
+                    new_range_lower DOUBLE NOT NULL,
+                    new_range_upper DOUBLE NOT NULL,
+                    lower_change DOUBLE NOT NULL,
+                    upper_change DOUBLE NOT NULL,
+                    min_change_ratio DOUBLE NOT NULL,
+                    max_change_ratio DOUBLE NOT NULL,
+                    center DOUBLE NOT NULL,
+                    adjustment_type STRING,
+                    price DOUBLE,
+                    TIME INDEX (`timestamp`),
+                    PRIMARY KEY (task_id, agent_id, product_id, round)
+                )
+            "#
+    }
+
     pub fn new(
         round: u64,
         task_id: String,
@@ -304,16 +544,71 @@ impl AgentRangeAdjustmentLog {
             price,
         }
     }
+
+    /// 生成写入MySQL的INSERT语句
+    pub fn insert_sql(&self) -> String {
+        format!(
+            r#"
+                INSERT INTO agent_range_adjustment_logs (
+                    timestamp, round, task_id, agent_id, agent_name, product_id,
+                    old_range_lower, old_range_upper, new_range_lower, new_range_upper,
+                    lower_change, upper_change, min_change_ratio, max_change_ratio,
+                    center, adjustment_type, price
+                ) VALUES (
+                    {}, {}, '{}', {}, '{}', {},
+                    {}, {}, {}, {},
+                    {}, {}, {}, {},
+                    {}, '{}', {}
+                )
+            "#,
+            self.timestamp,
+            self.round,
+            self.task_id,
+            self.agent_id,
+            self.agent_name,
+            self.product_id,
+            self.old_range_lower,
+            self.old_range_upper,
+            self.new_range_lower,
+            self.new_range_upper,
+            self.lower_change,
+            self.upper_change,
+            self.min_change_ratio * 100.0, // 转换为百分比
+            self.max_change_ratio * 100.0, // 转换为百分比
+            self.center,
+            self.adjustment_type,
+            self.price.unwrap_or(-1.0) // -1.0表示未设置
+        )
+    }
 }
 
 impl AgentCashLog {
+    /// 生成GreptimeDB的建表语句，表已存在时不做修改
+    pub fn create_table_sql() -> &'static str {
+        r#"
+                CREATE TABLE IF NOT EXISTS agent_cash_logs (
+                    `timestamp` TIMESTAMP NOT NULL,
+                    round BIGINT,
+                    task_id STRING INVERTED INDEX,
+                    agent_id BIGINT INVERTED INDEX,
+                    agent_name STRING,
+                    cash DOUBLE NOT NULL,
+                    real_cash DOUBLE NOT NULL,
+                    total_trades BIGINT NOT NULL,
+                    TIME INDEX (`timestamp`),
+                    PRIMARY KEY (task_id, agent_id, round)
+                )
+            "#
+    }
+
+    /// cash为(名义现金, 按价格指数折算的实际现金)
     pub fn new(
         timestamp: i64,
         round: u64,
         task_id: String,
         agent_id: u64,
         agent_name: String,
-        cash: f64,
+        (cash, real_cash): (f64, f64),
         total_trades: u64,
     ) -> Self {
         AgentCashLog {
@@ -323,12 +618,308 @@ impl AgentCashLog {
             agent_id,
             agent_name,
             cash,
+            real_cash,
+            total_trades,
+        }
+    }
+
+    /// 生成写入MySQL的INSERT语句
+    pub fn insert_sql(&self) -> String {
+        format!(
+            r#"
+                INSERT INTO agent_cash_logs (
+                    timestamp, round, task_id, agent_id, agent_name, cash, real_cash, total_trades
+                ) VALUES (
+                    {}, {}, '{}', {}, '{}', {}, {}, {}
+                )
+            "#,
+            self.timestamp,
+            self.round,
+            self.task_id,
+            self.agent_id,
+            self.agent_name,
+            self.cash,
+            self.real_cash,
+            self.total_trades
+        )
+    }
+}
+
+impl FactoryOfferLog {
+    /// 生成GreptimeDB的建表语句，表已存在时不做修改
+    pub fn create_table_sql() -> &'static str {
+        r#"
+                CREATE TABLE IF NOT EXISTS factory_offer_logs (
+                    `timestamp` TIMESTAMP NOT NULL,
+                    round BIGINT,
+                    task_id STRING INVERTED INDEX,
+                    factory_id BIGINT INVERTED INDEX,
+                    factory_name STRING,
+                    product_id BIGINT INVERTED INDEX,
+                    supply_range_lower DOUBLE NOT NULL,
+                    supply_range_upper DOUBLE NOT NULL,
+                    offer_price DOUBLE NOT NULL,
+                    stock INT NOT NULL,
+                    TIME INDEX (`timestamp`),
+                    PRIMARY KEY (task_id, factory_id, round)
+                )
+            "#
+    }
+
+    pub fn new(timestamp: i64, round: u64, task_id: String, factory: &Factory) -> Self {
+        let (supply_range_lower, supply_range_upper) = factory.supply_price_range();
+        FactoryOfferLog {
+            timestamp,
+            round,
+            task_id,
+            factory_id: factory.id(),
+            factory_name: factory.name().to_string(),
+            product_id: factory.product_id(),
+            supply_range_lower,
+            supply_range_upper,
+            offer_price: factory.current_offer(round),
+            stock: factory.get_stock(round),
+        }
+    }
+
+    /// 生成写入MySQL的INSERT语句
+    pub fn insert_sql(&self) -> String {
+        format!(
+            r#"
+                INSERT INTO factory_offer_logs (
+                    timestamp, round, task_id, factory_id, factory_name, product_id,
+                    supply_range_lower, supply_range_upper, offer_price, stock
+                ) VALUES (
+                    {}, {}, '{}', {}, '{}', {},
+                    {}, {}, {}, {}
+                )
+            "#,
+            self.timestamp,
+            self.round,
+            self.task_id,
+            self.factory_id,
+            self.factory_name,
+            self.product_id,
+            self.supply_range_lower,
+            self.supply_range_upper,
+            self.offer_price,
+            self.stock
+        )
+    }
+}
+
+impl NegotiationAttemptLog {
+    /// 生成GreptimeDB的建表语句，表已存在时不做修改
+    pub fn create_table_sql() -> &'static str {
+        r#"
+                CREATE TABLE IF NOT EXISTS negotiation_attempt_logs (
+                    `timestamp` TIMESTAMP NOT NULL,
+                    round BIGINT,
+                    task_id STRING INVERTED INDEX,
+                    agent_id BIGINT INVERTED INDEX,
+                    factory_id BIGINT INVERTED INDEX,
+                    product_id BIGINT INVERTED INDEX,
+                    attempt INT NOT NULL,
+                    offer_price DOUBLE NOT NULL,
+                    supply_range_lower DOUBLE NOT NULL,
+                    supply_range_upper DOUBLE NOT NULL,
+                    interval_relation STRING,
+                    TIME INDEX (`timestamp`),
+                    PRIMARY KEY (task_id, agent_id, product_id, factory_id, round, attempt)
+                )
+            "#
+    }
+
+    pub fn new(
+        round: u64,
+        task_id: String,
+        agent_id: u64,
+        factory: &Factory,
+        attempt: u32,
+        interval_relation: &str,
+    ) -> Self {
+        let (supply_range_lower, supply_range_upper) = factory.supply_price_range();
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Failed to get system time")
+            .as_millis() as i64;
+        NegotiationAttemptLog {
+            timestamp,
+            round,
+            task_id,
+            agent_id,
+            factory_id: factory.id(),
+            product_id: factory.product_id(),
+            attempt,
+            offer_price: factory.current_offer(round),
+            supply_range_lower,
+            supply_range_upper,
+            interval_relation: interval_relation.to_string(),
+        }
+    }
+
+    /// 生成写入MySQL的INSERT语句
+    pub fn insert_sql(&self) -> String {
+        format!(
+            r#"
+                INSERT INTO negotiation_attempt_logs (
+                    timestamp, round, task_id, agent_id, factory_id, product_id, attempt,
+                    offer_price, supply_range_lower, supply_range_upper, interval_relation
+                ) VALUES (
+                    {}, {}, '{}', {}, {}, {}, {},
+                    {}, {}, {}, '{}'
+                )
+            "#,
+            self.timestamp,
+            self.round,
+            self.task_id,
+            self.agent_id,
+            self.factory_id,
+            self.product_id,
+            self.attempt,
+            self.offer_price,
+            self.supply_range_lower,
+            self.supply_range_upper,
+            self.interval_relation
+        )
+    }
+}
+
+impl RunEndLog {
+    /// 生成GreptimeDB的建表语句，表已存在时不做修改
+    pub fn create_table_sql() -> &'static str {
+        r#"
+                CREATE TABLE IF NOT EXISTS simulation_runs (
+                    `timestamp` TIMESTAMP NOT NULL,
+                    task_id STRING INVERTED INDEX,
+                    final_round BIGINT NOT NULL,
+                    reason STRING NOT NULL,
+                    total_trades BIGINT NOT NULL,
+                    TIME INDEX (`timestamp`),
+                    PRIMARY KEY (task_id)
+                )
+            "#
+    }
+
+    pub fn new(task_id: String, final_round: u64, reason: &str, total_trades: u64) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Failed to get system time")
+            .as_millis() as i64;
+
+        RunEndLog {
+            timestamp,
+            task_id,
+            final_round,
+            reason: reason.to_string(),
             total_trades,
         }
     }
+
+    /// 生成写入MySQL的INSERT语句
+    pub fn insert_sql(&self) -> String {
+        format!(
+            r#"
+                INSERT INTO simulation_runs (
+                    timestamp, task_id, final_round, reason, total_trades
+                ) VALUES (
+                    {}, '{}', {}, '{}', {}
+                )
+            "#,
+            self.timestamp, self.task_id, self.final_round, self.reason, self.total_trades
+        )
+    }
+}
+
+impl AgentDemandCreationLog {
+    /// 生成GreptimeDB的建表语句，表已存在时不做修改
+    pub fn create_table_sql() -> &'static str {
+        r#"
+                CREATE TABLE IF NOT EXISTS agent_demand_creation_logs (
+                    `timestamp` TIMESTAMP NOT NULL,
+                    round BIGINT NOT NULL,
+                    task_id STRING NOT NULL INVERTED INDEX,
+                    agent_id BIGINT NOT NULL INVERTED INDEX,
+                    agent_name STRING NOT NULL,
+                    product_id BIGINT NOT NULL INVERTED INDEX,
+                    agent_pref_original_elastic DOUBLE NULL,
+                    TIME INDEX (`timestamp`),
+                    PRIMARY KEY (task_id, agent_id, round)
+                )
+            "#
+    }
+
+    pub fn new(
+        round: u64,
+        task_id: String,
+        agent_id: u64,
+        agent_name: String,
+        product_id: u64,
+        agent_pref_original_elastic: Option<f64>,
+    ) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Failed to get system time")
+            .as_millis() as i64;
+
+        AgentDemandCreationLog {
+            timestamp,
+            round,
+            task_id,
+            agent_id,
+            agent_name,
+            product_id,
+            agent_pref_original_elastic,
+        }
+    }
+
+    /// 生成写入MySQL的INSERT语句
+    pub fn insert_sql(&self) -> String {
+        format!(
+            r#"
+                INSERT INTO agent_demand_creation_logs (
+                    timestamp, round, task_id, agent_id, agent_name, product_id,
+                    agent_pref_original_elastic
+                ) VALUES (
+                    {}, {}, '{}', {}, '{}', {},
+                    {}
+                )
+            "#,
+            self.timestamp,
+            self.round,
+            self.task_id,
+            self.agent_id,
+            self.agent_name,
+            self.product_id,
+            self.agent_pref_original_elastic.unwrap_or(-1.0)
+        )
+    }
 }
 
 impl AgentDemandRemovalLog {
+    /// 生成GreptimeDB的建表语句，表已存在时不做修改
+    pub fn create_table_sql() -> &'static str {
+        r#"
+                CREATE TABLE IF NOT EXISTS agent_demand_removal_logs (
+                    `timestamp` TIMESTAMP NOT NULL,
+                    round BIGINT NOT NULL INVERTED INDEX,
+                    task_id STRING NOT NULL INVERTED INDEX,
+                    agent_id BIGINT NOT NULL INVERTED INDEX,
+                    agent_name STRING NOT NULL,
+                    product_id BIGINT NOT NULL INVERTED INDEX,
+                    agent_cash DOUBLE NOT NULL,
+                    agent_pref_original_price DOUBLE NULL,
+                    agent_pref_original_elastic DOUBLE NULL,
+                    agent_pref_current_price DOUBLE NULL,
+                    agent_pref_current_range_lower DOUBLE NULL,
+                    agent_pref_current_range_upper DOUBLE NULL,
+                    removal_reason STRING NOT NULL,
+                    TIME INDEX (`timestamp`),
+                    PRIMARY KEY (task_id, agent_id, round)
+                )
+            "#
+    }
+
     pub fn new(
         round: u64,
         task_id: String,
@@ -364,23 +955,379 @@ impl AgentDemandRemovalLog {
             removal_reason: removal_reason.to_string(),
         }
     }
-}
-
-// 日志记录器
-#[derive(Clone)]
-pub struct Logger {
-    trade_counter: Arc<Mutex<u64>>,
-    task_id: String,
-}
 
-impl Logger {
-    pub fn new(_file_path: &str, task_id: String) -> Result<Self, Box<dyn std::error::Error>> {
+    /// 生成写入MySQL的INSERT语句
+    pub fn insert_sql(&self) -> String {
+        format!(
+            r#"
+                INSERT INTO agent_demand_removal_logs (
+                    timestamp, round, task_id, agent_id, agent_name, product_id, agent_cash,
+                    agent_pref_original_price, agent_pref_original_elastic, agent_pref_current_price,
+                    agent_pref_current_range_lower, agent_pref_current_range_upper, removal_reason
+                ) VALUES (
+                    {}, {}, '{}', {}, '{}', {}, {},
+                    {}, {}, {},
+                    {}, {}, '{}'
+                )
+            "#,
+            self.timestamp,
+            self.round,
+            self.task_id,
+            self.agent_id,
+            self.agent_name,
+            self.product_id,
+            self.agent_cash,
+            self.agent_pref_original_price.unwrap_or(-1.0),
+            self.agent_pref_original_elastic.unwrap_or(-1.0),
+            self.agent_pref_current_price.unwrap_or(-1.0),
+            self.agent_pref_current_range_lower.unwrap_or(-1.0),
+            self.agent_pref_current_range_upper.unwrap_or(-1.0),
+            self.removal_reason
+        )
+    }
+}
+
+// 一条已记录的日志，保留结构化字段而不是SQL字符串
+#[derive(Clone)]
+pub enum LoggedRow {
+    Trade(TradeLog),
+    FactoryRangeOptimization(FactoryRangeOptimizationLog),
+    AgentRangeAdjustment(AgentRangeAdjustmentLog),
+    AgentCash(AgentCashLog),
+    AgentDemandRemoval(AgentDemandRemovalLog),
+    AgentDemandCreation(AgentDemandCreationLog),
+    FactoryOffer(FactoryOfferLog),
+    NegotiationAttempt(NegotiationAttemptLog),
+    RunEnd(RunEndLog),
+}
+
+impl LoggedRow {
+    /// 该行所属的任务ID
+    pub fn task_id(&self) -> &str {
+        match self {
+            LoggedRow::Trade(log) => &log.task_id,
+            LoggedRow::FactoryRangeOptimization(log) => &log.task_id,
+            LoggedRow::AgentRangeAdjustment(log) => &log.task_id,
+            LoggedRow::AgentCash(log) => &log.task_id,
+            LoggedRow::AgentDemandRemoval(log) => &log.task_id,
+            LoggedRow::AgentDemandCreation(log) => &log.task_id,
+            LoggedRow::FactoryOffer(log) => &log.task_id,
+            LoggedRow::NegotiationAttempt(log) => &log.task_id,
+            LoggedRow::RunEnd(log) => &log.task_id,
+        }
+    }
+
+    /// 所有日志表的建表语句，每张表一条
+    pub fn create_table_sqls() -> [&'static str; 9] {
+        [
+            TradeLog::create_table_sql(),
+            FactoryRangeOptimizationLog::create_table_sql(),
+            AgentRangeAdjustmentLog::create_table_sql(),
+            AgentCashLog::create_table_sql(),
+            AgentDemandRemovalLog::create_table_sql(),
+            AgentDemandCreationLog::create_table_sql(),
+            FactoryOfferLog::create_table_sql(),
+            NegotiationAttemptLog::create_table_sql(),
+            RunEndLog::create_table_sql(),
+        ]
+    }
+
+    /// 生成写入MySQL的INSERT语句
+    pub fn insert_sql(&self) -> String {
+        match self {
+            LoggedRow::Trade(log) => log.insert_sql(),
+            LoggedRow::FactoryRangeOptimization(log) => log.insert_sql(),
+            LoggedRow::AgentRangeAdjustment(log) => log.insert_sql(),
+            LoggedRow::AgentCash(log) => log.insert_sql(),
+            LoggedRow::AgentDemandRemoval(log) => log.insert_sql(),
+            LoggedRow::AgentDemandCreation(log) => log.insert_sql(),
+            LoggedRow::FactoryOffer(log) => log.insert_sql(),
+            LoggedRow::NegotiationAttempt(log) => log.insert_sql(),
+            LoggedRow::RunEnd(log) => log.insert_sql(),
+        }
+    }
+}
+
+// 日志写入目标
+pub trait LogSink: Send + Sync {
+    fn write(&self, row: &LoggedRow) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// 按顺序写入一批日志行，默认逐行写入
+    fn write_batch(&self, rows: &[LoggedRow]) -> Result<(), Box<dyn std::error::Error>> {
+        for row in rows {
+            self.write(row)?;
+        }
+        Ok(())
+    }
+
+    /// 执行建表等不产生日志行的语句，默认忽略
+    fn execute(&self, _sql: &str) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
+}
+
+// MySQL写入目标，每次写入都从连接池获取新连接，重试时即相当于重连
+pub struct MySqlSink;
+
+impl LogSink for MySqlSink {
+    fn write(&self, row: &LoggedRow) -> Result<(), Box<dyn std::error::Error>> {
+        // 如果MySQL池未初始化，直接返回成功
+        let Some(pool) = MYSQL_POOL.get() else {
+            return Ok(());
+        };
+
+        let mut conn = pool.get_conn()?;
+        conn.query_drop(row.insert_sql())?;
+        Ok(())
+    }
+
+    // 一批日志在同一个连接的同一个事务中写入，只在提交时落盘
+    fn write_batch(&self, rows: &[LoggedRow]) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(pool) = MYSQL_POOL.get() else {
+            return Ok(());
+        };
+
+        let mut conn = pool.get_conn()?;
+        let mut tx = conn.start_transaction(Default::default())?;
+        for row in rows {
+            tx.query_drop(row.insert_sql())?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn execute(&self, sql: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(pool) = MYSQL_POOL.get() else {
+            return Ok(());
+        };
+
+        let mut conn = pool.get_conn()?;
+        conn.query_drop(sql)?;
+        Ok(())
+    }
+}
+
+// 内存写入目标，保存所有日志行，供单元测试断言和实时看板读取
+#[derive(Clone, Default)]
+pub struct MemorySink {
+    rows: Arc<Mutex<Vec<LoggedRow>>>,
+}
+
+impl MemorySink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 返回目前记录的所有日志行的副本
+    pub fn rows(&self) -> Vec<LoggedRow> {
+        self.rows.lock().unwrap().clone()
+    }
+}
+
+impl LogSink for MemorySink {
+    fn write(&self, row: &LoggedRow) -> Result<(), Box<dyn std::error::Error>> {
+        self.rows.lock().unwrap().push(row.clone());
+        Ok(())
+    }
+}
+
+// 丢弃所有日志的写入目标，用于性能测试
+pub struct NullSink;
+
+impl LogSink for NullSink {
+    fn write(&self, _row: &LoggedRow) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
+}
+
+// 发给后台写入线程的消息
+enum LogMessage {
+    Rows(Vec<LoggedRow>),
+    // 之前入队的日志全部写完后回复
+    Flush(SyncSender<()>),
+}
+
+// 日志记录器
+#[derive(Clone)]
+pub struct Logger {
+    trade_counter: Arc<Mutex<u64>>,
+    task_id: String,
+    sink: Arc<dyn LogSink>,
+    dropped_rows: Arc<AtomicU64>, // 重试耗尽或队列满时丢弃的行数
+    sample_ratio: f64,            // 记录逐个agent/工厂日志的实体比例
+    batch: Arc<Mutex<Vec<LoggedRow>>>, // 等待批量写入的日志行
+    batch_size: usize,                 // 攒够多少行写入一次，0或1表示逐行写入
+    queue: Option<SyncSender<LogMessage>>, // 后台写入队列，None时在调用线程同步写入
+    overflow: LogOverflow,                 // 队列满时等待还是丢弃
+}
+
+impl Logger {
+    pub fn new(_file_path: &str, task_id: String) -> Result<Self, Box<dyn std::error::Error>> {
         init_mysql_client();
 
-        Ok(Logger {
+        Ok(Self::with_sink(task_id, Arc::new(MySqlSink)))
+    }
+
+    pub fn with_sink(task_id: String, sink: Arc<dyn LogSink>) -> Self {
+        Logger {
             trade_counter: Arc::new(Mutex::new(0)),
             task_id,
-        })
+            sink,
+            dropped_rows: Arc::new(AtomicU64::new(0)),
+            sample_ratio: 1.0,
+            batch: Arc::new(Mutex::new(Vec::new())),
+            batch_size: 0,
+            queue: None,
+            overflow: LogOverflow::Block,
+        }
+    }
+
+    /// 攒够batch_size行后在一个事务中批量写入，减少与数据库的往返；
+    /// 不足一批的日志行需要调用flush写入。0或1表示逐行写入
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// 创建所有日志表（已存在的表保持不变），返回执行的建表语句数。
+    /// 新数据库没有建表时所有写入都会失败，应在模拟开始前调用
+    pub fn ensure_schema(&self) -> Result<usize, Box<dyn std::error::Error>> {
+        let statements = LoggedRow::create_table_sqls();
+        for sql in statements {
+            self.sink.execute(sql)?;
+        }
+        Ok(statements.len())
+    }
+
+    /// 由后台线程写入日志，调用线程只把日志放入容量为capacity的队列。队列满时按overflow等待
+    /// 或丢弃（计入丢弃行数），后台写入失败时打印错误。0表示在调用线程同步写入
+    pub fn with_queue(mut self, capacity: usize, overflow: LogOverflow) -> Self {
+        if capacity == 0 {
+            return self;
+        }
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        let writer = Logger {
+            queue: None,
+            ..self.clone()
+        };
+        thread::spawn(move || {
+            for message in receiver {
+                match message {
+                    LogMessage::Rows(rows) => {
+                        if let Err(e) = writer.write_with_retry(&rows) {
+                            eprintln!("Failed to write queued logs: {}", e);
+                        }
+                    }
+                    LogMessage::Flush(done) => {
+                        let _ = done.send(());
+                    }
+                }
+            }
+        });
+        self.queue = Some(sender);
+        self.overflow = overflow;
+        self
+    }
+
+    /// 写入所有尚未写入的日志行，使用后台队列时等待队列中的日志全部写完
+    pub fn flush(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let rows = std::mem::take(&mut *self.batch.lock().unwrap());
+        if !rows.is_empty() {
+            self.submit(rows)?;
+        }
+        if let Some(queue) = &self.queue {
+            let (done, wait) = mpsc::sync_channel(1);
+            queue.send(LogMessage::Flush(done))?;
+            wait.recv()?;
+        }
+        Ok(())
+    }
+
+    /// 交给后台队列，没有队列时直接写入
+    fn submit(&self, rows: Vec<LoggedRow>) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(queue) = &self.queue else {
+            return self.write_with_retry(&rows);
+        };
+        match self.overflow {
+            LogOverflow::Block => queue.send(LogMessage::Rows(rows))?,
+            LogOverflow::Drop => match queue.try_send(LogMessage::Rows(rows)) {
+                Ok(()) => {}
+                Err(TrySendError::Full(LogMessage::Rows(rows))) => {
+                    self.dropped_rows.fetch_add(rows.len() as u64, Ordering::Relaxed);
+                }
+                Err(e) => return Err(e.to_string().into()),
+            },
+        }
+        Ok(())
+    }
+
+    /// 只记录sample_ratio(0~1)比例的agent/工厂的逐实体日志（现金、区间调整、需求删除），
+    /// 每个实体是否入选只由ID决定，整个模拟期间保持不变。成交日志不受影响
+    pub fn with_sample_ratio(mut self, ratio: f64) -> Self {
+        self.sample_ratio = ratio.clamp(0.0, 1.0);
+        self
+    }
+
+    /// ID对应的实体是否在抽样范围内。kind区分agent和工厂，同一ID的agent和工厂各自抽样，
+    /// 也不与按agent ID播种的其他随机数（如决策扰动）共用同一序列
+    fn is_sampled(&self, kind: u64, entity_id: u64) -> bool {
+        if self.sample_ratio >= 1.0 {
+            return true;
+        }
+        StdRng::seed_from_u64(entity_id ^ kind).r#gen::<f64>() < self.sample_ratio
+    }
+
+    pub fn task_id(&self) -> &str {
+        &self.task_id
+    }
+
+    /// 重试耗尽或后台队列满时被丢弃的日志行数
+    pub fn dropped_rows(&self) -> u64 {
+        self.dropped_rows.load(Ordering::Relaxed)
+    }
+
+    /// 写入一行日志；开启批量写入时先放入缓冲区，攒够一批再写入。
+    /// 写入时持有缓冲区的锁，保证各批按记录顺序写入
+    fn write_row(&self, row: LoggedRow) -> Result<(), Box<dyn std::error::Error>> {
+        if self.batch_size <= 1 {
+            return self.submit(vec![row]);
+        }
+        let mut batch = self.batch.lock().unwrap();
+        batch.push(row);
+        if batch.len() < self.batch_size {
+            return Ok(());
+        }
+        let rows = std::mem::take(&mut *batch);
+        self.submit(rows)
+    }
+
+    /// 写入一批日志，失败时按指数退避重试整批，重试耗尽后整批计入丢弃行数并返回最后一次错误
+    fn write_with_retry(&self, rows: &[LoggedRow]) -> Result<(), Box<dyn std::error::Error>> {
+        let mut attempt = 0;
+        loop {
+            let result = match rows {
+                [row] => self.sink.write(row),
+                rows => self.sink.write_batch(rows),
+            };
+            match result {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    if attempt >= MAX_INSERT_RETRIES {
+                        self.dropped_rows.fetch_add(rows.len() as u64, Ordering::Relaxed);
+                        return Err(e);
+                    }
+                    let backoff = INSERT_RETRY_BACKOFF_MS * 2u64.pow(attempt);
+                    eprintln!(
+                        "Log insert failed (attempt {}), retrying in {}ms: {}",
+                        attempt + 1,
+                        backoff,
+                        e
+                    );
+                    thread::sleep(Duration::from_millis(backoff));
+                    attempt += 1;
+                }
+            }
+        }
     }
 
     pub fn log_trade(
@@ -404,75 +1351,7 @@ impl Logger {
             interval_relation,
         );
 
-        // 如果MySQL池未初始化，直接返回成功
-        let Some(pool) = MYSQL_POOL.get() else {
-            return Ok(());
-        };
-
-        // 准备SQL语句
-        let sql = r#"
-            INSERT INTO trade_logs (
-                timestamp, round, trade_id, task_id, agent_id, agent_name, agent_cash,
-                factory_id, factory_name, product_id, product_name, trade_result, interval_relation, price,
-                factory_supply_range_lower, factory_supply_range_upper, factory_stock,
-                agent_pref_original_price, agent_pref_original_elastic, agent_pref_current_price,
-                agent_pref_current_range_lower, agent_pref_current_range_upper
-            ) VALUES (
-                ?, ?, ?, ?, ?, ?, ?,
-                ?, ?, ?, ?, ?, ?, ?,
-                ?, ?, ?,
-                ?, ?, ?,
-                ?, ?
-            )
-        "#;
-
-        // 获取连接并执行SQL插入
-        let mut conn = pool.get_conn()?;
-        // 使用exec方法，MySQL的exec方法不支持超过20个参数的元组，所以我们使用字符串格式化来构建SQL
-        let sql = format!(
-            r#"
-                INSERT INTO trade_logs (
-                    timestamp, round, trade_id, task_id, agent_id, agent_name, agent_cash,
-                    factory_id, factory_name, product_id, product_name, trade_result, interval_relation, price,
-                    factory_supply_range_lower, factory_supply_range_upper, factory_stock,
-                    agent_pref_original_price, agent_pref_original_elastic, agent_pref_current_price,
-                    agent_pref_current_range_lower, agent_pref_current_range_upper
-                ) VALUES (
-                    {}, {}, {}, '{}', {}, '{}', {},
-                    {}, '{}', {}, '{}', '{}', '{}', {},
-                    {}, {}, {},
-                    {}, {}, {},
-                    {}, {}
-                )
-            "#,
-            log.timestamp,
-            log.round,
-            log.trade_id,
-            log.task_id,
-            log.agent_id,
-            log.agent_name,
-            log.agent_cash,
-            log.factory_id,
-            log.factory_name,
-            log.product_id,
-            log.product_name,
-            log.trade_result,
-            log.interval_relation,
-            log.price.unwrap_or(-1.0),
-            log.factory_supply_range_lower,
-            log.factory_supply_range_upper,
-            log.factory_stock,
-            log.agent_pref_original_price.unwrap_or(-1.0),
-            log.agent_pref_original_elastic.unwrap_or(-1.0),
-            log.agent_pref_current_price.unwrap_or(-1.0),
-            log.agent_pref_current_range_lower.unwrap_or(-1.0),
-            log.agent_pref_current_range_upper.unwrap_or(-1.0),
-        );
-
-        // 使用query方法执行SQL
-        conn.query_drop(&sql)?;
-
-        Ok(())
+        self.write_row(LoggedRow::Trade(log))
     }
 
     pub fn log_factory_range_optimization(
@@ -490,6 +1369,9 @@ impl Logger {
         upper_change_ratio: f64,
         trade_result: &str,
     ) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.is_sampled(FACTORY_SAMPLE_SEED, factory_id) {
+            return Ok(());
+        }
         let log = FactoryRangeOptimizationLog::new(
             round,
             self.task_id.clone(),
@@ -506,49 +1388,7 @@ impl Logger {
             trade_result,
         );
 
-        // 如果MySQL池未初始化，直接返回成功
-        let Some(pool) = MYSQL_POOL.get() else {
-            return Ok(());
-        };
-
-        // 准备SQL语句
-        let sql = format!(
-            r#"
-                INSERT INTO factory_range_optimization_logs (
-                    timestamp, round, task_id, factory_id, factory_name, product_id,
-                    old_range_lower, old_range_upper, new_range_lower, new_range_upper,
-                    lower_change, upper_change, total_change,
-                    lower_change_ratio, upper_change_ratio, trade_result
-                ) VALUES (
-                    {}, {}, '{}', {}, '{}', {},
-                    {}, {}, {}, {},
-                    {}, {}, {},
-                    {}, {}, '{}'
-                )
-            "#,
-            log.timestamp,
-            log.round,
-            log.task_id,
-            log.factory_id,
-            log.factory_name,
-            log.product_id,
-            log.old_range_lower,
-            log.old_range_upper,
-            log.new_range_lower,
-            log.new_range_upper,
-            log.lower_change,
-            log.upper_change,
-            log.total_change,
-            log.lower_change_ratio * 100.0, // 转换为百分比
-            log.upper_change_ratio * 100.0, // 转换为百分比
-            log.trade_result
-        );
-
-        // 使用query方法执行SQL
-        let mut conn = pool.get_conn()?;
-        conn.query_drop(&sql)?;
-
-        Ok(())
+        self.write_row(LoggedRow::FactoryRangeOptimization(log))
     }
 
     pub fn log_agent_range_adjustment(
@@ -567,6 +1407,9 @@ impl Logger {
         adjustment_type: &str,
         price: Option<f64>,
     ) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.is_sampled(AGENT_SAMPLE_SEED, agent_id) {
+            return Ok(());
+        }
         let log = AgentRangeAdjustmentLog::new(
             round,
             self.task_id.clone(),
@@ -584,50 +1427,7 @@ impl Logger {
             price,
         );
 
-        // 如果MySQL池未初始化，直接返回成功
-        let Some(pool) = MYSQL_POOL.get() else {
-            return Ok(());
-        };
-
-        // 准备SQL语句
-        let sql = format!(
-            r#"
-                INSERT INTO agent_range_adjustment_logs (
-                    timestamp, round, task_id, agent_id, agent_name, product_id,
-                    old_range_lower, old_range_upper, new_range_lower, new_range_upper,
-                    lower_change, upper_change, min_change_ratio, max_change_ratio,
-                    center, adjustment_type, price
-                ) VALUES (
-                    {}, {}, '{}', {}, '{}', {},
-                    {}, {}, {}, {},
-                    {}, {}, {}, {},
-                    {}, '{}', {}
-                )
-            "#,
-            log.timestamp,
-            log.round,
-            log.task_id,
-            log.agent_id,
-            log.agent_name,
-            log.product_id,
-            log.old_range_lower,
-            log.old_range_upper,
-            log.new_range_lower,
-            log.new_range_upper,
-            log.lower_change,
-            log.upper_change,
-            log.min_change_ratio * 100.0, // 转换为百分比
-            log.max_change_ratio * 100.0, // 转换为百分比
-            log.center,
-            log.adjustment_type,
-            log.price.unwrap_or(-1.0) // -1.0表示未设置
-        );
-
-        // 使用query方法执行SQL
-        let mut conn = pool.get_conn()?;
-        conn.query_drop(&sql)?;
-
-        Ok(())
+        self.write_row(LoggedRow::AgentRangeAdjustment(log))
     }
 
     pub fn log_agent_cash(
@@ -636,9 +1436,12 @@ impl Logger {
         round: u64,
         agent_id: u64,
         agent_name: String,
-        cash: f64,
+        cash: (f64, f64),
         total_trades: u64,
     ) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.is_sampled(AGENT_SAMPLE_SEED, agent_id) {
+            return Ok(());
+        }
         let log = AgentCashLog::new(
             timestamp,
             round,
@@ -649,34 +1452,76 @@ impl Logger {
             total_trades,
         );
 
-        // 如果MySQL池未初始化，直接返回成功
-        let Some(pool) = MYSQL_POOL.get() else {
+        self.write_row(LoggedRow::AgentCash(log))
+    }
+
+    pub fn log_factory_offer(
+        &self,
+        timestamp: i64,
+        round: u64,
+        factory: &Factory,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.is_sampled(FACTORY_SAMPLE_SEED, factory.id()) {
             return Ok(());
-        };
+        }
+        let log = FactoryOfferLog::new(timestamp, round, self.task_id.clone(), factory);
+        self.write_row(LoggedRow::FactoryOffer(log))
+    }
 
-        // 准备SQL语句
-        let sql = format!(
-            r#"
-                INSERT INTO agent_cash_logs (
-                    timestamp, round, task_id, agent_id, agent_name, cash, total_trades
-                ) VALUES (
-                    {}, {}, '{}', {}, '{}', {}, {}
-                )
-            "#,
-            log.timestamp,
-            log.round,
-            log.task_id,
-            log.agent_id,
-            log.agent_name,
-            log.cash,
-            log.total_trades
+    pub fn log_negotiation_attempt(
+        &self,
+        round: u64,
+        agent_id: u64,
+        factory: &Factory,
+        attempt: u32,
+        interval_relation: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.is_sampled(AGENT_SAMPLE_SEED, agent_id) {
+            return Ok(());
+        }
+        let log = NegotiationAttemptLog::new(
+            round,
+            self.task_id.clone(),
+            agent_id,
+            factory,
+            attempt,
+            interval_relation,
         );
+        self.write_row(LoggedRow::NegotiationAttempt(log))
+    }
 
-        // 使用query方法执行SQL
-        let mut conn = pool.get_conn()?;
-        conn.query_drop(&sql)?;
+    /// 记录本次模拟的结束信息，不受抽样比例影响
+    pub fn log_run_end(
+        &self,
+        final_round: u64,
+        reason: &str,
+        total_trades: u64,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let log = RunEndLog::new(self.task_id.clone(), final_round, reason, total_trades);
+        self.write_row(LoggedRow::RunEnd(log))
+    }
 
-        Ok(())
+    pub fn log_agent_demand_creation(
+        &self,
+        round: u64,
+        agent_id: u64,
+        agent_name: String,
+        product_id: u64,
+        agent_pref_original_elastic: Option<f64>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.is_sampled(AGENT_SAMPLE_SEED, agent_id) {
+            return Ok(());
+        }
+        let log = AgentDemandCreationLog::new(
+            round,
+            self.task_id.clone(),
+            agent_id,
+            agent_name,
+            product_id,
+            agent_pref_original_elastic,
+        );
+
+        self.write_row(LoggedRow::AgentDemandCreation(log))
     }
 
     pub fn log_agent_demand_removal(
@@ -693,6 +1538,9 @@ impl Logger {
         agent_pref_current_range_upper: Option<f64>,
         removal_reason: &str,
     ) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.is_sampled(AGENT_SAMPLE_SEED, agent_id) {
+            return Ok(());
+        }
         let log = AgentDemandRemovalLog::new(
             round,
             self.task_id.clone(),
@@ -708,61 +1556,15 @@ impl Logger {
             removal_reason,
         );
 
-        // 如果MySQL池未初始化，直接返回成功
-        let Some(pool) = MYSQL_POOL.get() else {
-            return Ok(());
-        };
-
-        // 准备SQL语句
-        let sql = format!(
-            r#"
-                INSERT INTO agent_demand_removal_logs (
-                    timestamp, round, task_id, agent_id, agent_name, product_id, agent_cash,
-                    agent_pref_original_price, agent_pref_original_elastic, agent_pref_current_price,
-                    agent_pref_current_range_lower, agent_pref_current_range_upper, removal_reason
-                ) VALUES (
-                    {}, {}, '{}', {}, '{}', {}, {},
-                    {}, {}, {},
-                    {}, {}, '{}'
-                )
-            "#,
-            log.timestamp,
-            log.round,
-            log.task_id,
-            log.agent_id,
-            log.agent_name,
-            log.product_id,
-            log.agent_cash,
-            log.agent_pref_original_price.unwrap_or(-1.0),
-            log.agent_pref_original_elastic.unwrap_or(-1.0),
-            log.agent_pref_current_price.unwrap_or(-1.0),
-            log.agent_pref_current_range_lower.unwrap_or(-1.0),
-            log.agent_pref_current_range_upper.unwrap_or(-1.0),
-            log.removal_reason
-        );
-
-        // 使用query方法执行SQL
-        let mut conn = pool.get_conn()?;
-        conn.query_drop(&sql)?;
-
-        Ok(())
+        self.write_row(LoggedRow::AgentDemandRemoval(log))
     }
 }
 
-// 全局日志记录器
-lazy_static! {
-    pub static ref LOGGER: Mutex<Option<Logger>> = Mutex::new(None);
-}
-
-// 初始化日志记录器
-pub fn init_logger(file_path: &str, task_id: String) -> Result<(), Box<dyn std::error::Error>> {
-    let logger = Logger::new(file_path, task_id)?;
-    *LOGGER.lock().unwrap() = Some(logger);
-    Ok(())
-}
+// 以下log_*函数写入调用方传入的日志记录器（通常是所属市场的），logger为None时不记录
 
 // 记录交易日志
 pub fn log_trade(
+    logger: Option<&Logger>,
     round: u64,
     agent: Arc<RwLock<Agent>>,
     factory: &Factory,
@@ -770,7 +1572,7 @@ pub fn log_trade(
     trade_result: &TradeResult,
     interval_relation: &str,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    if let Some(logger) = &mut *LOGGER.lock().unwrap() {
+    if let Some(logger) = logger {
         // 生成trade_id
         let mut counter = logger.trade_counter.lock().unwrap();
         *counter += 1;
@@ -794,6 +1596,7 @@ pub fn log_trade(
 
 // 记录工厂范围优化日志
 pub fn log_factory_range_optimization(
+    logger: Option<&Logger>,
     round: u64,
     factory_id: u64,
     factory_name: String,
@@ -807,7 +1610,7 @@ pub fn log_factory_range_optimization(
     upper_change_ratio: f64,
     trade_result: &str,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    if let Some(logger) = &mut *LOGGER.lock().unwrap() {
+    if let Some(logger) = logger {
         // 调用logger的log_factory_range_optimization方法
         if let Err(e) = logger.log_factory_range_optimization(
             round,
@@ -831,6 +1634,7 @@ pub fn log_factory_range_optimization(
 
 // 记录Agent范围调整日志
 pub fn log_agent_range_adjustment(
+    logger: Option<&Logger>,
     round: u64,
     agent_id: u64,
     agent_name: String,
@@ -845,7 +1649,7 @@ pub fn log_agent_range_adjustment(
     adjustment_type: &str,
     price: Option<f64>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    if let Some(logger) = &mut *LOGGER.lock().unwrap() {
+    if let Some(logger) = logger {
         // 调用logger的log_agent_range_adjustment方法
         if let Err(e) = logger.log_agent_range_adjustment(
             round,
@@ -868,28 +1672,69 @@ pub fn log_agent_range_adjustment(
     Ok(())
 }
 
-// 记录Agent现金日志
+// 记录Agent现金日志，cash为(名义现金, 实际现金)
 pub fn log_agent_cash(
+    logger: Option<&Logger>,
     timestamp: i64,
     round: u64,
     agent_id: u64,
     agent_name: String,
-    cash: f64,
+    cash: (f64, f64),
     total_trades: u64,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    if let Some(logger) = &mut *LOGGER.lock().unwrap() {
+    if let Some(logger) = logger {
         // 调用logger的log_agent_cash方法
-        if let Err(e) = 
-            logger.log_agent_cash(timestamp, round, agent_id, agent_name, cash, total_trades)
-        {
+        if let Err(e) = logger.log_agent_cash(
+            timestamp,
+            round,
+            agent_id,
+            agent_name,
+            cash,
+            total_trades,
+        ) {
             eprintln!("Failed to log agent cash to MySQL: {}", e);
         }
     }
     Ok(())
 }
 
+// 记录工厂每轮结束时的报价
+pub fn log_factory_offer(
+    logger: Option<&Logger>,
+    timestamp: i64,
+    round: u64,
+    factory: &Factory,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(logger) = logger {
+        if let Err(e) = logger.log_factory_offer(timestamp, round, factory) {
+            eprintln!("Failed to log factory offer to MySQL: {}", e);
+        }
+    }
+    Ok(())
+}
+
+// 记录agent与工厂的一次撮合尝试
+pub fn log_negotiation_attempt(
+    logger: Option<&Logger>,
+    round: u64,
+    agent_id: u64,
+    factory: &Factory,
+    attempt: u32,
+    interval_relation: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(logger) = logger {
+        if let Err(e) =
+            logger.log_negotiation_attempt(round, agent_id, factory, attempt, interval_relation)
+        {
+            eprintln!("Failed to log negotiation attempt to MySQL: {}", e);
+        }
+    }
+    Ok(())
+}
+
 // 记录Agent需求删除日志
 pub fn log_agent_demand_removal(
+    logger: Option<&Logger>,
     round: u64,
     agent_id: u64,
     agent_name: String,
@@ -902,7 +1747,7 @@ pub fn log_agent_demand_removal(
     agent_pref_current_range_upper: Option<f64>,
     removal_reason: &str,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    if let Some(logger) = &mut *LOGGER.lock().unwrap() {
+    if let Some(logger) = logger {
         // 调用logger的log_agent_demand_removal方法
         if let Err(e) = 
             logger.log_agent_demand_removal(round, agent_id, agent_name, product_id, agent_cash,
@@ -914,3 +1759,362 @@ pub fn log_agent_demand_removal(
     }
     Ok(())
 }
+
+// 写入当前日志记录器中尚未写入的批量日志
+pub fn flush_logs(logger: Option<&Logger>) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(logger) = logger {
+        if let Err(e) = logger.flush() {
+            eprintln!("Failed to flush logs to MySQL: {}", e);
+        }
+    }
+    Ok(())
+}
+
+// 记录模拟结束信息
+pub fn log_run_end(
+    logger: Option<&Logger>,
+    final_round: u64,
+    reason: &str,
+    total_trades: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(logger) = logger {
+        if let Err(e) = logger.log_run_end(final_round, reason, total_trades) {
+            eprintln!("Failed to log run end to MySQL: {}", e);
+        }
+    }
+    Ok(())
+}
+
+// 记录Agent需求产生日志
+pub fn log_agent_demand_creation(
+    logger: Option<&Logger>,
+    round: u64,
+    agent_id: u64,
+    agent_name: String,
+    product_id: u64,
+    agent_pref_original_elastic: Option<f64>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(logger) = logger {
+        if let Err(e) = logger.log_agent_demand_creation(
+            round,
+            agent_id,
+            agent_name,
+            product_id,
+            agent_pref_original_elastic,
+        ) {
+            eprintln!("Failed to log agent demand creation to MySQL: {}", e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 前几次写入失败、之后成功的模拟写入目标
+    struct FlakySink {
+        failures_left: Mutex<u32>,
+        written: Mutex<Vec<String>>,
+    }
+
+    impl FlakySink {
+        fn new(failures: u32) -> Self {
+            FlakySink {
+                failures_left: Mutex::new(failures),
+                written: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    impl LogSink for FlakySink {
+        fn write(&self, row: &LoggedRow) -> Result<(), Box<dyn std::error::Error>> {
+            let mut failures_left = self.failures_left.lock().unwrap();
+            if *failures_left > 0 {
+                *failures_left -= 1;
+                return Err("connection reset".into());
+            }
+            self.written.lock().unwrap().push(row.insert_sql());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_retries_until_success() {
+        let sink = Arc::new(FlakySink::new(2));
+        let logger = Logger::with_sink("task".to_string(), sink.clone());
+
+        let result = logger.log_agent_cash(0, 1, 1, "agent_1".to_string(), (100.0, 100.0), 0);
+
+        assert!(result.is_ok(), "Row should be written after retries");
+        assert_eq!(sink.written.lock().unwrap().len(), 1);
+        assert_eq!(logger.dropped_rows(), 0);
+    }
+
+    #[test]
+    fn test_write_drops_row_after_retries_exhausted() {
+        let sink = Arc::new(FlakySink::new(MAX_INSERT_RETRIES + 1));
+        let logger = Logger::with_sink("task".to_string(), sink.clone());
+
+        let result = logger.log_agent_cash(0, 1, 1, "agent_1".to_string(), (100.0, 100.0), 0);
+
+        assert!(result.is_err(), "Row should be dropped after retries are exhausted");
+        assert!(sink.written.lock().unwrap().is_empty());
+        assert_eq!(logger.dropped_rows(), 1);
+    }
+
+    #[test]
+    fn test_memory_sink_keeps_typed_rows() {
+        let sink = MemorySink::new();
+        let logger = Logger::with_sink("task".to_string(), Arc::new(sink.clone()));
+
+        logger
+            .log_agent_cash(0, 7, 3, "agent_3".to_string(), (42.0, 42.0), 5)
+            .unwrap();
+
+        let rows = sink.rows();
+        assert_eq!(rows.len(), 1);
+        match &rows[0] {
+            LoggedRow::AgentCash(log) => {
+                assert_eq!(log.round, 7);
+                assert_eq!(log.agent_id, 3);
+                assert_eq!(log.cash, 42.0);
+                assert_eq!(log.task_id, "task");
+            }
+            _ => panic!("Expected an agent cash row"),
+        }
+    }
+
+    // 统计逐行写入和批量写入调用次数的写入目标
+    #[derive(Default)]
+    struct CountingSink {
+        write_calls: Mutex<usize>,
+        batch_calls: Mutex<usize>,
+        rounds: Mutex<Vec<u64>>,
+    }
+
+    impl LogSink for CountingSink {
+        fn write(&self, row: &LoggedRow) -> Result<(), Box<dyn std::error::Error>> {
+            *self.write_calls.lock().unwrap() += 1;
+            if let LoggedRow::AgentCash(log) = row {
+                self.rounds.lock().unwrap().push(log.round);
+            }
+            Ok(())
+        }
+
+        fn write_batch(&self, rows: &[LoggedRow]) -> Result<(), Box<dyn std::error::Error>> {
+            *self.batch_calls.lock().unwrap() += 1;
+            for row in rows {
+                if let LoggedRow::AgentCash(log) = row {
+                    self.rounds.lock().unwrap().push(log.round);
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_batched_writes() {
+        let sink = Arc::new(CountingSink::default());
+        let logger = Logger::with_sink("task".to_string(), sink.clone()).with_batch_size(10);
+
+        for round in 0..25 {
+            logger
+                .log_agent_cash(0, round, 1, "agent_1".to_string(), (100.0, 100.0), 0)
+                .unwrap();
+        }
+        // 两个满批已写入，剩余5行在flush时写入
+        assert_eq!(*sink.batch_calls.lock().unwrap(), 2);
+        assert_eq!(sink.rounds.lock().unwrap().len(), 20);
+        logger.flush().unwrap();
+        logger.flush().unwrap();
+
+        let calls = *sink.write_calls.lock().unwrap() + *sink.batch_calls.lock().unwrap();
+        assert!(calls < 25);
+        assert_eq!(calls, 3);
+        assert_eq!(*sink.rounds.lock().unwrap(), (0..25).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn test_run_end_row() {
+        let sink = MemorySink::new();
+        let logger = Logger::with_sink("run_task".to_string(), Arc::new(sink.clone()))
+            .with_sample_ratio(0.0);
+
+        logger.log_run_end(1234, "Converged", 56).unwrap();
+
+        let rows = sink.rows();
+        assert_eq!(rows.len(), 1);
+        match &rows[0] {
+            LoggedRow::RunEnd(log) => {
+                assert_eq!(log.task_id, "run_task");
+                assert_eq!(log.final_round, 1234);
+                assert_eq!(log.reason, "Converged");
+                assert_eq!(log.total_trades, 56);
+                let sql = log.insert_sql();
+                assert!(sql.contains("INSERT INTO simulation_runs"));
+                assert!(sql.contains("'run_task', 1234, 'Converged', 56"));
+            }
+            _ => panic!("Expected a run end row"),
+        }
+    }
+
+    #[test]
+    fn test_sample_agents_and_factories_separately() {
+        let logger = Logger::with_sink("sample_task".to_string(), Arc::new(MemorySink::new()))
+            .with_sample_ratio(0.5);
+        let sampled = |kind: u64| -> Vec<bool> {
+            (1..=100).map(|id| logger.is_sampled(kind, id)).collect()
+        };
+
+        // 同一ID的agent和工厂分别抽样，入选结果不完全相同
+        let agents = sampled(AGENT_SAMPLE_SEED);
+        let factories = sampled(FACTORY_SAMPLE_SEED);
+        assert_ne!(agents, factories);
+        assert!(agents.iter().any(|&s| s) && agents.iter().any(|&s| !s));
+        assert!(factories.iter().any(|&s| s) && factories.iter().any(|&s| !s));
+    }
+
+    // 记录执行过的建表语句的写入目标
+    #[derive(Default)]
+    struct SchemaSink {
+        statements: Mutex<Vec<String>>,
+    }
+
+    impl LogSink for SchemaSink {
+        fn write(&self, _row: &LoggedRow) -> Result<(), Box<dyn std::error::Error>> {
+            Ok(())
+        }
+
+        fn execute(&self, sql: &str) -> Result<(), Box<dyn std::error::Error>> {
+            self.statements.lock().unwrap().push(sql.to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_ensure_schema() {
+        let sink = Arc::new(SchemaSink::default());
+        let logger = Logger::with_sink("schema_task".to_string(), sink.clone());
+        assert_eq!(logger.ensure_schema().unwrap(), 9);
+
+        // 每张日志表一条建表语句，包括trade_logs和agent_range_adjustment_logs
+        let statements = sink.statements.lock().unwrap();
+        let tables = [
+            "trade_logs",
+            "factory_range_optimization_logs",
+            "agent_range_adjustment_logs",
+            "agent_cash_logs",
+            "agent_demand_removal_logs",
+            "agent_demand_creation_logs",
+            "factory_offer_logs",
+            "negotiation_attempt_logs",
+            "simulation_runs",
+        ];
+        assert_eq!(statements.len(), tables.len());
+        for table in tables {
+            let prefix = format!("CREATE TABLE IF NOT EXISTS {} (", table);
+            assert_eq!(statements.iter().filter(|sql| sql.contains(&prefix)).count(), 1);
+        }
+    }
+
+    #[test]
+    fn test_create_table_sql_columns() {
+        let trade_columns = [
+            "timestamp",
+            "round",
+            "trade_id",
+            "task_id",
+            "agent_id",
+            "agent_name",
+            "agent_cash",
+            "factory_id",
+            "factory_name",
+            "product_id",
+            "product_name",
+            "trade_result",
+            "interval_relation",
+            "price",
+            "net_price",
+            "factory_supply_range_lower",
+            "factory_supply_range_upper",
+            "factory_stock",
+            "agent_pref_original_price",
+            "agent_pref_original_elastic",
+            "agent_pref_current_price",
+            "agent_pref_current_range_lower",
+            "agent_pref_current_range_upper",
+        ];
+        let adjustment_columns = [
+            "timestamp",
+            "round",
+            "task_id",
+            "agent_id",
+            "agent_name",
+            "product_id",
+            "old_range_lower",
+            "old_range_upper",
+            "new_range_lower",
+            "new_range_upper",
+            "lower_change",
+            "upper_change",
+            "min_change_ratio",
+            "max_change_ratio",
+            "center",
+            "adjustment_type",
+            "price",
+        ];
+
+        // 建表语句包含结构体的每个字段，且列定义各占一行
+        for (sql, columns) in [
+            (TradeLog::create_table_sql(), &trade_columns[..]),
+            (AgentRangeAdjustmentLog::create_table_sql(), &adjustment_columns[..]),
+        ] {
+            let defined: Vec<&str> = sql
+                .lines()
+                .filter_map(|line| line.split_whitespace().next())
+                .map(|name| name.trim_matches('`'))
+                .collect();
+            for column in columns {
+                assert!(defined.contains(column), "missing column {}", column);
+            }
+        }
+    }
+
+    // 每次写入都很慢的写入目标
+    #[derive(Default)]
+    struct SlowSink {
+        written: Mutex<usize>,
+    }
+
+    impl LogSink for SlowSink {
+        fn write(&self, _row: &LoggedRow) -> Result<(), Box<dyn std::error::Error>> {
+            thread::sleep(Duration::from_millis(50));
+            *self.written.lock().unwrap() += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_queue_drops_when_full() {
+        let sink = Arc::new(SlowSink::default());
+        let logger =
+            Logger::with_sink("task".to_string(), sink.clone()).with_queue(1, LogOverflow::Drop);
+
+        // 写入目标很慢时队列很快写满，后续日志直接丢弃而不是等待
+        let start = std::time::Instant::now();
+        for round in 0..20 {
+            logger
+                .log_agent_cash(0, round, 1, "agent_1".to_string(), (100.0, 100.0), 0)
+                .unwrap();
+        }
+        assert!(start.elapsed() < Duration::from_millis(500));
+        assert!(logger.dropped_rows() > 0);
+
+        // flush等待队列中的日志写完，写入的和丢弃的合计为全部日志
+        logger.flush().unwrap();
+        let written = *sink.written.lock().unwrap() as u64;
+        assert!(written > 0);
+        assert_eq!(written + logger.dropped_rows(), 20);
+    }
+}
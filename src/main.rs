@@ -2,24 +2,34 @@ mod model;
 mod entity;
 mod logging;
 mod util;
+mod config;
 
 use rand::{distributions::Alphanumeric, Rng};
 use std::fs::File;
 use std::io::Read;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use toml::Value;
+use crate::config::MarketConfig;
 use crate::entity::normal_distribute::NormalDistribution;
-use crate::logging::init_logger;
+use crate::logging::Logger;
+use crate::model::equilibrium::{EquilibriumParams, competitive_equilibrium};
 
-/// 从config.toml文件初始化产品列表
-fn init_products() -> Vec<crate::model::product::Product> {
+/// 读取并解析config.toml文件
+fn load_config() -> Value {
     // 读取config.toml文件
     let mut file = File::open("config.toml").expect("Failed to open config.toml");
     let mut contents = String::new();
     file.read_to_string(&mut contents).expect("Failed to read config.toml");
     
-    // 解析toml
-    let value = contents.parse::<Value>().expect("Failed to parse config.toml");
-    
+    // 替换${VAR}环境变量后解析toml
+    let contents = crate::config::substitute_env(&contents)
+        .unwrap_or_else(|e| panic!("Failed to resolve config.toml: {}", e));
+    contents.parse::<Value>().expect("Failed to parse config.toml")
+}
+
+/// 从config.toml文件初始化产品列表
+fn init_products(value: &Value) -> Vec<crate::model::product::Product> {
     // 提取products数组
     let products_array = value.get("products").and_then(Value::as_array).expect("Failed to get products array");
     
@@ -40,16 +50,123 @@ fn init_products() -> Vec<crate::model::product::Product> {
         
         // 创建弹性分布
         let elastic_distribution = NormalDistribution::new(mean_elastic, id, format!("{}_elastic_dist", name), std_dev_elastic);
+
+        let seed_offset = product_value.get("seed_offset").and_then(Value::as_integer).unwrap_or(0) as u64;
+        let producer_tax_rate = product_value.get("producer_tax_rate").and_then(Value::as_float).unwrap_or(0.0);
+        let essential = product_value.get("tier").and_then(Value::as_str) == Some("need");
+        let supply_elasticity = product_value.get("supply_elasticity").and_then(Value::as_float).unwrap_or(1.0);
+        let durable = product_value.get("durable").and_then(Value::as_bool).unwrap_or(false);
+        let subscription_rounds = product_value.get("subscription_rounds").and_then(Value::as_integer).unwrap_or(0).max(0) as u32;
         
         // 创建Product对象
-        let product = crate::model::product::Product::from(id, name, price_distribution, elastic_distribution);
+        let product = crate::model::product::Product::from(id, name, price_distribution, elastic_distribution)
+            .with_seed_offset(seed_offset)
+            .with_producer_tax_rate(producer_tax_rate)
+            .with_essential(essential)
+            .with_supply_elasticity(supply_elasticity)
+            .with_durable(durable)
+            .with_subscription_rounds(subscription_rounds);
         products.push(product);
     }
     
     products
 }
 
+/// 打印config.toml中每个商品的理论竞争均衡
+/// 用法：equilibrium <unit_cost> [factory_count]
+fn print_equilibrium(args: &[String]) {
+    let Some(unit_cost) = args.first().and_then(|v| v.parse::<f64>().ok()) else {
+        eprintln!("Usage: equilibrium <unit_cost> [factory_count]");
+        return;
+    };
+    let factory_count = args.get(1).and_then(|v| v.parse::<usize>().ok()).unwrap_or(4);
+
+    let config = load_config();
+    for product in init_products(&config) {
+        let params = EquilibriumParams::from_product(&product, unit_cost, factory_count);
+        match competitive_equilibrium(&params) {
+            Some(eq) => println!(
+                "Product {} ({}): price = {:.2}, quantity = {:.2}",
+                product.id(),
+                product.name(),
+                eq.price,
+                eq.quantity
+            ),
+            None => println!(
+                "Product {} ({}): no finite equilibrium",
+                product.id(),
+                product.name()
+            ),
+        }
+    }
+}
+
+/// 用config.toml的商品和参数测量核心循环的速度，不写数据库、轮间不等待
+/// 用法：bench [rounds]
+fn run_bench(args: &[String]) {
+    let rounds = args.first().and_then(|v| v.parse::<u64>().ok()).unwrap_or(100);
+    let config = load_config();
+    let products = init_products(&config);
+    // 需求按轮生成且固定种子，不同次bench之间的成交数可比
+    let mut market_config = MarketConfig::from_toml(&config);
+    market_config.agent.round_synced_demand = true;
+    market_config.seed.get_or_insert(0);
+    let mut market = crate::model::market::Market::with_config(products, market_config);
+    let report = market.bench(rounds);
+    println!(
+        "{} rounds, {} trades in {:.3}s ({:.1} rounds/sec)",
+        report.rounds,
+        report.total_trades,
+        report.elapsed.as_secs_f64(),
+        report.rounds_per_sec
+    );
+}
+
+/// 解析命令行中的--rounds N，用于冒烟测试时只跑少量轮次。没有该参数时返回Ok(None)
+fn parse_rounds(args: &[String]) -> Result<Option<u64>, String> {
+    let Some(index) = args.iter().position(|arg| arg == "--rounds") else {
+        return Ok(None);
+    };
+    match args.get(index + 1).map(|v| v.parse::<u64>()) {
+        Some(Ok(rounds)) if rounds > 0 => Ok(Some(rounds)),
+        Some(_) => Err(format!("invalid value for --rounds: {}", args[index + 1])),
+        None => Err("missing value for --rounds".to_string()),
+    }
+}
+
+/// 解析命令行中的--export-preferences PATH，模拟结束后把agent偏好导出到该文件。
+/// 没有该参数时返回Ok(None)，不导出
+fn parse_export_path(args: &[String]) -> Result<Option<String>, String> {
+    let Some(index) = args.iter().position(|arg| arg == "--export-preferences") else {
+        return Ok(None);
+    };
+    match args.get(index + 1) {
+        Some(path) if !path.starts_with("--") => Ok(Some(path.clone())),
+        _ => Err("missing path for --export-preferences".to_string()),
+    }
+}
+
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("equilibrium") {
+        print_equilibrium(&args[2..]);
+        return;
+    }
+    if args.get(1).map(String::as_str) == Some("bench") {
+        run_bench(&args[2..]);
+        return;
+    }
+    let (rounds, export_path) = match (parse_rounds(&args), parse_export_path(&args)) {
+        (Ok(rounds), Ok(export_path)) => (rounds, export_path),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!(
+                "{}\nUsage: austrian_market_sim [--rounds N] [--export-preferences PATH]",
+                e
+            );
+            return;
+        }
+    };
+
     // 生成随机task_id
     let task_id: String = rand::thread_rng()
         .sample_iter(&Alphanumeric)
@@ -57,26 +174,99 @@ fn main() {
         .map(char::from)
         .collect();
     
-    // 初始化日志记录器，传递task_id
-    if let Err(e) = init_logger("trade_logs.csv", task_id.clone()) {
-        eprintln!("Failed to initialize logger: {}", e);
-        return;
+    // 初始化本次市场的日志记录器，传递task_id
+    let logger = match Logger::new("trade_logs.csv", task_id.clone()) {
+        Ok(logger) => logger,
+        Err(e) => {
+            eprintln!("Failed to initialize logger: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = logger.ensure_schema() {
+        eprintln!("Failed to create log tables: {}", e);
     }
     
     println!("Initializing products from config.toml...");
-    let products = init_products();
+    let config = load_config();
+    let products = init_products(&config);
     println!("Successfully initialized {} products!", products.len());
+    let mut market_config = MarketConfig::from_toml(&config);
+    if let Some(rounds) = rounds {
+        market_config.max_round = rounds;
+    }
+    let handle_interrupt = market_config.handle_interrupt;
     
     // 创建市场对象
     println!("Creating market...");
-    let mut market = crate::model::market::Market::new(products);
+    let mut market =
+        crate::model::market::Market::with_config(products, market_config).with_logger(logger);
     println!("Market created successfully!");
+    if handle_interrupt {
+        install_interrupt_handler(market.stop_handle());
+    }
     
     // 运行市场模拟
     println!("Starting market simulation...");
     println!("Task ID: {}", task_id);
     println!("Pausing for 5 seconds...");
     std::thread::sleep(std::time::Duration::from_secs(5));
-    market.run();
-    println!("Market simulation {:?} completed!", task_id);
+    let outcome = market.run();
+    println!(
+        "Market simulation {:?} completed: {:?} after {} rounds, {} trades",
+        task_id, outcome.reason, outcome.rounds, outcome.total_trades
+    );
+    println!("Profits distributed to factory owners: {:.2}", market.distributed_profits());
+    println!("Producer tax collected: {:.2}", market.tax_revenue());
+    if let Some(path) = export_path {
+        match market.export_preferences(&path) {
+            Ok(rows) => println!("Exported {} agent preferences to {}", rows, path),
+            Err(e) => eprintln!("Failed to export preferences: {}", e),
+        }
+    }
+}
+
+/// 收到Ctrl-C时设置停止标志，由run在当前轮结束后退出
+fn install_interrupt_handler(stop: Arc<AtomicBool>) {
+    std::thread::spawn(move || {
+        let runtime = match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                eprintln!("Failed to install interrupt handler: {}", e);
+                return;
+            }
+        };
+        if runtime.block_on(tokio::signal::ctrl_c()).is_ok() {
+            println!("Interrupt received, finishing current round...");
+            stop.store(true, Ordering::SeqCst);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_rounds() {
+        assert_eq!(parse_rounds(&args(&["sim", "--rounds", "5"])), Ok(Some(5)));
+        assert_eq!(parse_rounds(&args(&["sim"])), Ok(None));
+        assert!(parse_rounds(&args(&["sim", "--rounds"])).is_err());
+        assert!(parse_rounds(&args(&["sim", "--rounds", "abc"])).is_err());
+        assert!(parse_rounds(&args(&["sim", "--rounds", "0"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_export_path() {
+        assert_eq!(
+            parse_export_path(&args(&["sim", "--export-preferences", "prefs.csv"])),
+            Ok(Some("prefs.csv".to_string()))
+        );
+        assert_eq!(parse_export_path(&args(&["sim", "--rounds", "5"])), Ok(None));
+        assert!(parse_export_path(&args(&["sim", "--export-preferences"])).is_err());
+        assert!(parse_export_path(&args(&["sim", "--export-preferences", "--rounds"])).is_err());
+    }
 }
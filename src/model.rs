@@ -1,5 +1,7 @@
 pub mod product;
 pub mod agent;
 pub mod factory;
+pub mod household;
 pub mod util;
-pub mod market;
\ No newline at end of file
+pub mod market;
+pub mod equilibrium;
\ No newline at end of file
@@ -1,26 +1,191 @@
-use crate::logging::log_agent_range_adjustment;
+use crate::config::{
+    AgentConfig, IncomeModel, NegotiationMode, Seasonality, UnreachableRangeAction,
+};
+use crate::entity::normal_distribute::NormalDistribution;
+use crate::logging::{Logger, log_agent_range_adjustment};
+use crate::model::agent::demand::{DemandModel, build_demand_model};
 use crate::model::agent::preference::Preference;
 use crate::model::factory::Factory;
+use crate::model::household::Household;
 use crate::model::product::Product;
 use crate::model::util::{
-    gen_new_range_with_price, gen_price_in_range, interval_intersection, round_to_nearest_cent,
+    gen_new_range_with_price, gen_purchase_in_range, interval_intersection, round_to_nearest_cent,
 };
 use mysql::prelude::{TextQuery, WithParams};
-use rand::Rng;
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, RngCore, SeedableRng};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
 use std::time::Duration;
 use tokio::io::AsyncWriteExt;
 
+pub mod demand;
 mod preference;
 
+/// 区间上界低于成本下限时，抬高后的上界比成本下限高出的比例
+const UNREACHABLE_WIDEN_MARGIN: f64 = 0.1;
+
 pub struct Agent {
     id: u64,
     name: String,
     preferences: Arc<RwLock<HashMap<u64, Preference>>>,
     cash: f64,
     demand: Arc<RwLock<HashMap<u64, bool>>>,
+    last_factory: HashMap<u64, u64>, // 每个商品上次成功购买的工厂ID
+    config: AgentConfig,
+    household: Option<Arc<Household>>, // 所属家庭，加入后使用家庭的共享资金
+    products: Arc<Vec<Product>>,        // 市场的全部商品，由同一市场的agent共用
+    known_products: Option<Arc<HashSet<u64>>>, // 配置product_awareness时知道的商品，None表示都知道
+    frustration: Arc<RwLock<HashMap<u64, f64>>>, // 每个商品因缺货积累的不满(0~1)，降低产生需求的概率
+    satiation: Arc<RwLock<HashMap<u64, f64>>>, // 每个商品购买后的满足程度(0~1)，降低再次产生需求的概率
+    word_of_mouth: Arc<RwLock<HashMap<u64, f64>>>, // 邻居推荐带来的额外需求概率(0~1)
+    width_ratio: Option<f64>,           // 初始区间宽度比例，懒加载preference时使用
+    jitter: f64,                        // 判断能否成交时区间整体偏移的比例，每个agent固定
+    spending_factor: f64,               // 消费倾向，所有商品的偏好价格按该倍数缩放
+    round_spent: (u64, f64),            // (轮次, 该轮已花费的金额)，用于单轮消费上限
+    debt: f64,                          // 借款余额（含累计利息）
+    last_income: f64,                   // 上次发放的收入，随机游走收入模型使用
+    holdings: HashMap<u64, (u32, f64)>, // 持有的耐用品：商品ID -> (件数, 买入总价)
+    category_budgets: Option<(f64, f64)>, // 心理账户余额(必需品, 非必需品)，None表示不分账户
+    subscriptions: HashMap<u64, Subscription>, // 订阅中的商品：商品ID -> 订阅合约
+    round: Arc<AtomicU64>,              // 当前轮次，需求线程记录需求产生日志时使用
+    refused_demands: Arc<AtomicU64>,    // 因未满足需求数达到上限而放弃的新需求数
+    unavailable: Arc<RwLock<HashSet<u64>>>, // 没有工厂供应、不再产生需求的商品
+    abandoned: Arc<RwLock<HashSet<u64>>>, // 偏好区间低于成本下限而永久放弃的商品
+    interest_accrued: f64,              // 累计产生的利息
+    logger: Arc<RwLock<Option<Logger>>>, // 所属市场的日志记录器，需求线程共用，None时不记录日志
+    rng: Mutex<StdRng>, // 抽样个性和偏好的随机数生成器，市场设置种子时按种子和agent ID播种
+}
+
+/// 订阅合约：在剩余轮数内每轮按约定价格向同一工厂续购1件
+#[derive(Clone, Copy, Debug)]
+struct Subscription {
+    factory_id: u64,
+    price: f64,
+    rounds_left: u32,
+    last_round: u64, // 最近一次付款的轮次，同一轮不重复续购
+}
+
+/// 生成需求所需的agent状态，都是共享句柄，需求线程和按轮生成需求共用
+struct DemandGenerator {
+    demand: Arc<RwLock<HashMap<u64, bool>>>,
+    preferences: Arc<RwLock<HashMap<u64, Preference>>>,
+    products: Arc<Vec<Product>>,
+    known_products: Option<Arc<HashSet<u64>>>,
+    width_ratio: Option<f64>,
+    spending_factor: f64,
+    elasticity_bounds: (f64, f64),
+    seasonality: Option<Seasonality>,
+    demand_model: Box<dyn DemandModel>,
+    frustration: Arc<RwLock<HashMap<u64, f64>>>,
+    recovery: f64,
+    satiation: Arc<RwLock<HashMap<u64, f64>>>,
+    satiation_decay: f64,
+    word_of_mouth: Arc<RwLock<HashMap<u64, f64>>>,
+    word_of_mouth_decay: f64,
+    verbose_demand_log: bool,
+    max_demands: Option<usize>,
+    refused_demands: Arc<AtomicU64>,
+    unavailable: Arc<RwLock<HashSet<u64>>>,
+    abandoned: Arc<RwLock<HashSet<u64>>>,
+    round: Arc<AtomicU64>,
+    logger: Arc<RwLock<Option<Logger>>>,
+    name: String,
+    user_id: u64,
+}
+
+impl DemandGenerator {
+    /// 执行一次需求生成
+    fn tick(&self, rng: &mut dyn RngCore) {
+        let product_ids: Vec<u64> = self
+            .products
+            .iter()
+            .map(|p| p.id())
+            .filter(|id| self.known_products.as_ref().is_none_or(|known| known.contains(id)))
+            .collect();
+        // 缺货带来的不满和购买后的满足都随时间消退
+        Agent::decay_levels(&self.frustration, self.recovery);
+        Agent::decay_levels(&self.satiation, self.satiation_decay);
+        Agent::decay_levels(&self.word_of_mouth, self.word_of_mouth_decay);
+
+        // 由需求模型决定本次新到达的需求
+        for product_id in self.demand_model.arrivals(&product_ids, rng) {
+            // 没有工厂供应或已放弃的商品不产生需求
+            if self.unavailable.read().unwrap().contains(&product_id)
+                || self.abandoned.read().unwrap().contains(&product_id)
+            {
+                continue;
+            }
+            // 因缺货不满或刚买过的商品以更低的概率产生需求，淡季的概率也更低
+            let mut probability =
+                Agent::arrival_probability(&self.frustration, &self.satiation, product_id);
+            if let Some(seasonality) = &self.seasonality {
+                let essential = self
+                    .products
+                    .iter()
+                    .any(|p| p.id() == product_id && p.is_essential());
+                probability *= seasonality.factor(essential, self.round.load(Ordering::Relaxed));
+            }
+            if !rng.gen_bool(probability) {
+                continue;
+            }
+
+            // 如果不在demand中，才添加
+            if !self.demand.read().unwrap().contains_key(&product_id) {
+                self.insert(product_id, rng);
+            }
+        }
+        // 邻居推荐的商品按口碑概率额外产生需求
+        let recommended: Vec<(u64, f64)> = self
+            .word_of_mouth
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(id, level)| (*id, *level))
+            .collect();
+        for (product_id, level) in recommended {
+            if !rng.gen_bool(level.min(1.0))
+                || self.demand.read().unwrap().contains_key(&product_id)
+                || self.unavailable.read().unwrap().contains(&product_id)
+                || self.abandoned.read().unwrap().contains(&product_id)
+            {
+                continue;
+            }
+            self.insert(product_id, rng);
+        }
+    }
+
+    /// 加入一个新需求，未满足的需求已达上限时放弃
+    fn insert(&self, product_id: u64, rng: &mut dyn RngCore) {
+        if Agent::backlog_full(&self.demand, self.max_demands) {
+            self.refused_demands.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        Agent::ensure_preference(
+            &self.preferences,
+            &self.products,
+            product_id,
+            self.width_ratio,
+            self.spending_factor,
+            self.elasticity_bounds,
+            rng,
+        );
+        self.demand.write().unwrap().insert(product_id, true);
+        if self.verbose_demand_log {
+            let round = self.round.load(Ordering::Relaxed);
+            Agent::log_demand_creation(
+                self.logger.read().unwrap().as_ref(),
+                &self.preferences,
+                round,
+                self.user_id,
+                &self.name,
+                product_id,
+            );
+        }
+    }
 }
 
 /// 区间关系枚举，表示两个区间之间的关系
@@ -48,11 +213,83 @@ pub enum TradeResult {
 
 impl Agent {
     pub fn new(id: u64, name: String, cash: f64, products: &[Product]) -> Self {
-        // 为每个商品生成preference
+        Self::with_config(id, name, cash, products, &AgentConfig::default())
+    }
+
+    pub fn with_config(
+        id: u64,
+        name: String,
+        cash: f64,
+        products: &[Product],
+        config: &AgentConfig,
+    ) -> Self {
+        let products = Arc::new(products.to_vec());
+        Self::with_shared_products(id, name, cash, products, config, StdRng::from_entropy())
+    }
+
+    /// 同with_config，商品列表由调用方共享：市场创建的所有agent共用一份，不各自复制。
+    /// 个性和偏好都从rng抽样，使用固定种子的rng时结果可复现
+    pub fn with_shared_products(
+        id: u64,
+        name: String,
+        cash: f64,
+        products: Arc<Vec<Product>>,
+        config: &AgentConfig,
+        mut rng: StdRng,
+    ) -> Self {
+        // 每个agent抽样一次区间宽度，所有商品共用，体现挑剔或灵活的个性
+        let width_ratio = config.range_width.map(|(mean, std_dev)| {
+            NormalDistribution::new(mean, id, format!("{}_range_width", name), std_dev)
+                .sample_with(&mut rng, None)
+                .max(0.01)
+        });
+
+        // 决策扰动由agent ID决定，同一agent每次运行一致，不同agent之间错开
+        let jitter = if config.acceptance_jitter > 0.0 {
+            StdRng::seed_from_u64(id)
+                .gen_range(-config.acceptance_jitter..=config.acceptance_jitter)
+        } else {
+            0.0
+        };
+
+        // 消费倾向：抽样一次节俭(-1)到大方(1)的特质，按相关度缩放所有商品的偏好
+        let spending_factor = if config.preference_correlation > 0.0 {
+            1.0 + 0.5 * config.preference_correlation * rng.gen_range(-1.0..=1.0)
+        } else {
+            1.0
+        };
+
+        // 只记录agent知道的商品ID，至少知道一个
+        let known_products = match config.product_awareness {
+            Some(awareness) if !products.is_empty() => {
+                let count = ((products.len() as f64 * awareness).ceil() as usize).max(1);
+                let known: HashSet<u64> = products
+                    .choose_multiple(&mut rng, count)
+                    .map(|p| p.id())
+                    .collect();
+                Some(Arc::new(known))
+            }
+            _ => None,
+        };
+
+        // 为每个知道的商品生成preference，懒加载时等到首次产生需求再生成
         let mut preferences_map = HashMap::new();
-        for product in products {
-            let preference = Preference::from_product(product);
-            preferences_map.insert(product.id(), preference);
+        if !config.lazy_preferences {
+            let known = products.iter().filter(|p| {
+                known_products.as_ref().is_none_or(|known| known.contains(&p.id()))
+            });
+            for product in known {
+                preferences_map.insert(
+                    product.id(),
+                    Self::new_preference(
+                        product,
+                        width_ratio,
+                        spending_factor,
+                        config.elasticity_bounds,
+                        &mut rng,
+                    ),
+                );
+            }
         }
 
         let mut agent = Agent {
@@ -61,11 +298,84 @@ impl Agent {
             preferences: Arc::new(RwLock::new(preferences_map)),
             cash,
             demand: Arc::new(RwLock::new(HashMap::new())),
+            last_factory: HashMap::new(),
+            frustration: Arc::new(RwLock::new(HashMap::new())),
+            satiation: Arc::new(RwLock::new(HashMap::new())),
+            word_of_mouth: Arc::new(RwLock::new(HashMap::new())),
+            config: config.clone(),
+            household: None,
+            products,
+            known_products,
+            width_ratio,
+            jitter,
+            spending_factor,
+            round_spent: (0, 0.0),
+            debt: 0.0,
+            last_income: config.income,
+            holdings: HashMap::new(),
+            subscriptions: HashMap::new(),
+            category_budgets: config
+                .essential_budget_share
+                .map(|share| (cash * share, cash * (1.0 - share))),
+            interest_accrued: 0.0,
+            round: Arc::new(AtomicU64::new(0)),
+            refused_demands: Arc::new(AtomicU64::new(0)),
+            unavailable: Arc::new(RwLock::new(HashSet::new())),
+            abandoned: Arc::new(RwLock::new(HashSet::new())),
+            logger: Arc::new(RwLock::new(None)),
+            rng: Mutex::new(rng),
         };
-        agent.desire();
+        agent.seed_initial_demands();
+        // 按轮生成需求时由市场驱动，不启动需求线程
+        if !config.round_synced_demand {
+            agent.desire();
+        }
         agent
     }
 
+    fn new_preference(
+        product: &Product,
+        width_ratio: Option<f64>,
+        spending_factor: f64,
+        elasticity_bounds: (f64, f64),
+        rng: &mut dyn RngCore,
+    ) -> Preference {
+        let preference = match width_ratio {
+            Some(ratio) => Preference::from_product_with_width(product, ratio, rng),
+            None => Preference::from_product_with(product, rng),
+        };
+        preference
+            .with_spending_factor(spending_factor)
+            .with_elasticity_bounds(elasticity_bounds)
+    }
+
+    /// 商品还没有preference时生成一个（懒加载模式下首次产生需求时调用）
+    fn ensure_preference(
+        preferences: &RwLock<HashMap<u64, Preference>>,
+        products: &[Product],
+        product_id: u64,
+        width_ratio: Option<f64>,
+        spending_factor: f64,
+        elasticity_bounds: (f64, f64),
+        rng: &mut dyn RngCore,
+    ) {
+        if preferences.read().unwrap().contains_key(&product_id) {
+            return;
+        }
+        if let Some(product) = products.iter().find(|p| p.id() == product_id) {
+            let mut preferences = preferences.write().unwrap();
+            preferences.entry(product_id).or_insert_with(|| {
+                Self::new_preference(
+                    product,
+                    width_ratio,
+                    spending_factor,
+                    elasticity_bounds,
+                    rng,
+                )
+            });
+        }
+    }
+
     pub fn id(&self) -> u64 {
         self.id
     }
@@ -78,40 +388,368 @@ impl Agent {
         self.preferences.read().unwrap()
     }
 
+    /// 可用资金，加入家庭后为家庭的共享资金
     pub fn cash(&self) -> f64 {
-        self.cash
+        match &self.household {
+            Some(household) => household.cash(),
+            None => self.cash,
+        }
     }
 
-    pub fn desire(&mut self) {
-        let d = self.demand.clone();
-        let p = self.preferences.clone();
-        let user_id = self.id;
-        thread::spawn(move || {
-            let mut rng = rand::thread_rng();
-            loop {
-                // 从preferences中随机选择一个商品ID
-                let product_id = {
-                    let preferences = p.read().unwrap();
-                    if preferences.is_empty() {
-                        continue; // 如果preferences为空，跳过本次循环
-                    }
+    /// 借款余额（含累计利息）
+    pub fn debt(&self) -> f64 {
+        self.debt
+    }
 
-                    // 随机选择一个商品ID
-                    let index = rng.gen_range(0..preferences.len());
-                    *preferences.keys().nth(index).unwrap()
-                };
+    /// 累计产生的利息
+    pub fn interest_accrued(&self) -> f64 {
+        self.interest_accrued
+    }
+
+    /// 可支付的金额：个人为现金加上剩余授信额度，加入家庭后为家庭资金（家庭资金不使用授信）
+    pub fn spendable(&self) -> f64 {
+        match &self.household {
+            Some(household) => household.cash(),
+            None => self.cash + (self.config.credit_limit - self.debt).max(0.0),
+        }
+    }
+
+    /// 用个人资金支付，现金不足的部分记为借款
+    fn pay(&mut self, amount: f64) {
+        let from_cash = amount.min(self.cash);
+        self.cash -= from_cash;
+        self.debt += amount - from_cash;
+    }
+
+    /// 借款按credit_interest_rate计息一轮，每轮结束时由市场调用
+    pub fn accrue_interest(&mut self) {
+        if self.debt <= 0.0 || self.config.credit_interest_rate <= 0.0 {
+            return;
+        }
+        let interest = self.debt * self.config.credit_interest_rate;
+        self.debt += interest;
+        self.interest_accrued += interest;
+    }
+
+    /// 发薪：轮次为payday_interval倍数的轮末收到income，加入家庭时存入家庭资金。
+    /// 每轮结束时由市场调用
+    pub fn receive_income(&mut self, round: u64) {
+        if self.config.income <= 0.0 || !round.is_multiple_of(self.config.payday_interval.max(1)) {
+            return;
+        }
+        let income = self.next_income();
+        // 先用收入偿还借款，剩余部分才计入心理账户和现金
+        let repayment = income.min(self.debt);
+        self.debt -= repayment;
+        let income = income - repayment;
+        self.fund_category_budgets(income);
+        match &self.household {
+            Some(household) => household.deposit(income),
+            None => self.cash += income,
+        }
+    }
+
+    /// 收入按essential_budget_share分别存入必需品和非必需品账户
+    fn fund_category_budgets(&mut self, income: f64) {
+        if let (Some(share), Some((essential, non_essential))) =
+            (self.config.essential_budget_share, self.category_budgets.as_mut())
+        {
+            *essential += income * share;
+            *non_essential += income * (1.0 - share);
+        }
+    }
+
+    /// 该商品所属心理账户的余额，不分账户时不限
+    pub fn category_budget(&self, product_id: u64) -> f64 {
+        match self.category_budgets {
+            Some((essential, _)) if self.is_essential(product_id) => essential,
+            Some((_, non_essential)) => non_essential,
+            None => f64::INFINITY,
+        }
+    }
 
-                // 检查该商品是否已经在demand中
-                let is_already_demanded = {
-                    let demand = d.read().unwrap();
-                    demand.contains_key(&product_id)
+    /// 按收入模型抽取本次发放的收入并记为上次收入
+    pub fn next_income(&mut self) -> f64 {
+        let mean = self.config.income;
+        let income = match self.config.income_model {
+            IncomeModel::Fixed => mean,
+            IncomeModel::RandomWalk {
+                persistence,
+                volatility,
+            } => {
+                let noise = if volatility > 0.0 && mean > 0.0 {
+                    rand::thread_rng()
+                        .sample(rand_distr::Normal::new(0.0, volatility * mean).unwrap())
+                } else {
+                    0.0
                 };
+                (mean + persistence * (self.last_income - mean) + noise).max(0.0)
+            }
+        };
+        self.last_income = income;
+        income
+    }
+
+    /// 买到耐用品后计入持有
+    fn record_holding(&mut self, product_id: u64, price: f64, quantity: i16) {
+        let durable = self
+            .products
+            .iter()
+            .any(|p| p.id() == product_id && p.is_durable());
+        if !durable || quantity <= 0 {
+            return;
+        }
+        let (units, cost) = self.holdings.entry(product_id).or_insert((0, 0.0));
+        *units += quantity as u32;
+        *cost += price * quantity as f64;
+    }
+
+    /// 订阅商品成交后签订合约，之后subscription_rounds-1轮按成交价自动续购
+    fn start_subscription(&mut self, product_id: u64, factory_id: u64, price: f64, round: u64) {
+        let rounds = self
+            .products
+            .iter()
+            .find(|p| p.id() == product_id)
+            .map_or(0, |p| p.subscription_rounds());
+        if rounds <= 1 || self.subscriptions.contains_key(&product_id) {
+            return;
+        }
+        let subscription = Subscription {
+            factory_id,
+            price,
+            rounds_left: rounds - 1,
+            last_round: round,
+        };
+        self.subscriptions.insert(product_id, subscription);
+    }
+
+    /// 订阅中的商品对应的(工厂ID, 约定价格, 剩余轮数)
+    pub fn subscription(&self, product_id: u64) -> Option<(u64, f64, u32)> {
+        self.subscriptions
+            .get(&product_id)
+            .map(|s| (s.factory_id, s.price, s.rounds_left))
+    }
+
+    /// 本轮待续购的订阅，按商品ID排序：(商品ID, 工厂ID, 约定价格)
+    pub fn due_subscriptions(&self, round: u64) -> Vec<(u64, u64, f64)> {
+        let mut due: Vec<(u64, u64, f64)> = self
+            .subscriptions
+            .iter()
+            .filter(|(_, s)| s.last_round < round)
+            .map(|(product_id, s)| (*product_id, s.factory_id, s.price))
+            .collect();
+        due.sort_by_key(|(product_id, _, _)| *product_id);
+        due
+    }
+
+    /// 按约定价格续购一轮订阅，资金不足时取消订阅并返回false。期满后订阅自动结束
+    pub fn renew_subscription(&mut self, product_id: u64, round: u64) -> bool {
+        let Some(subscription) = self.subscriptions.get(&product_id).copied() else {
+            return false;
+        };
+        let price = subscription.price;
+        let paid = match &self.household {
+            Some(household) => household.withdraw(price),
+            None if self.spendable() >= price => {
+                self.pay(price);
+                true
+            }
+            None => false,
+        };
+        if !paid {
+            self.cancel_subscription(product_id, round, "subscription_insufficient_cash");
+            return false;
+        }
+        self.record_spending(round, product_id, price);
+        self.record_holding(product_id, price, 1);
+        if subscription.rounds_left <= 1 {
+            self.subscriptions.remove(&product_id);
+        } else if let Some(s) = self.subscriptions.get_mut(&product_id) {
+            s.rounds_left -= 1;
+            s.last_round = round;
+        }
+        true
+    }
+
+    /// 取消订阅，之后该商品重新按需求逐轮撮合。取消原因记入需求删除日志
+    pub fn cancel_subscription(&mut self, product_id: u64, round: u64, reason: &str) {
+        if self.subscriptions.remove(&product_id).is_none() {
+            return;
+        }
+        let preferences = self.preferences.read().unwrap();
+        let preference = preferences.get(&product_id);
+        if let Err(e) = crate::logging::log_agent_demand_removal(
+            self.logger.read().unwrap().as_ref(),
+            round,
+            self.id,
+            self.name.clone(),
+            product_id,
+            self.cash(),
+            preference.map(|p| p.original_price),
+            preference.map(|p| p.original_elastic),
+            preference.map(|p| p.current_price),
+            preference.map(|p| p.current_range.0),
+            preference.map(|p| p.current_range.1),
+            reason,
+        ) {
+            eprintln!("Failed to log agent demand removal: {}", e);
+        }
+    }
+
+    /// 持有的某种耐用品件数
+    pub fn holdings(&self, product_id: u64) -> u32 {
+        self.holdings.get(&product_id).map_or(0, |(units, _)| *units)
+    }
+
+    /// 恐慌抛售：现金低于panic_sell_threshold时把持有的全部耐用品按买入价打panic_sell_discount折扣
+    /// 卖到二手市场，所得存入现金（加入家庭时存入家庭资金），返回所得。每轮结束时由市场调用
+    pub fn panic_sell(&mut self, round: u64) -> f64 {
+        if self.config.panic_sell_threshold <= 0.0
+            || self.holdings.is_empty()
+            || self.cash() >= self.config.panic_sell_threshold
+        {
+            return 0.0;
+        }
+        let cost: f64 = self.holdings.drain().map(|(_, (_, cost))| cost).sum();
+        let proceeds = cost * (1.0 - self.config.panic_sell_discount);
+        match &self.household {
+            Some(household) => household.deposit(proceeds),
+            None => self.cash += proceeds,
+        }
+        eprintln!(
+            "Agent {} panic sold durable goods for {:.2} at round {}",
+            self.id, proceeds, round
+        );
+        proceeds
+    }
+
+    /// 本轮尝试购买的概率：发薪后第一轮为1，随后线性下降，发薪前最后一轮降到1-payday_spending_decline
+    pub fn spending_probability(&self, round: u64) -> f64 {
+        let interval = self.config.payday_interval.max(1);
+        if interval == 1 || self.config.payday_spending_decline <= 0.0 {
+            return 1.0;
+        }
+        let elapsed = round.saturating_sub(1) % interval;
+        1.0 - self.config.payday_spending_decline * elapsed as f64 / (interval - 1) as f64
+    }
+
+    /// 按本轮的消费倾向抽样决定是否尝试购买，以agent ID和轮次为种子，同一轮内各商品的决定一致
+    fn willing_to_spend(&self, round: u64) -> bool {
+        let probability = self.spending_probability(round);
+        probability >= 1.0
+            || StdRng::seed_from_u64(self.id.wrapping_mul(1_000_003).wrapping_add(round))
+                .gen_bool(probability)
+    }
+
+    /// 锚定参考价：每轮把每个偏好区间的中点向商品价格分布的均值移动anchoring_strength比例，
+    /// 区间宽度不变，每轮结束时由市场调用
+    pub fn anchor_preferences(&self) {
+        let strength = self.config.anchoring_strength;
+        if strength <= 0.0 {
+            return;
+        }
+        let mut preferences = self.preferences.write().unwrap();
+        for product in self.products.iter() {
+            let Some(preference) = preferences.get_mut(&product.id()) else {
+                continue;
+            };
+            let reference = product.original_price_distribution().mean();
+            let (lower, upper) = preference.current_range;
+            let shift = (reference - (lower + upper) / 2.0) * strength;
+            let half_width = (upper - lower) / 2.0;
+            let lower = (lower + shift).max(0.0);
+            preference.current_range = (lower, lower + 2.0 * half_width);
+        }
+    }
+
+    /// 加入家庭，个人资金应由调用方并入家庭资金池，此处清零
+    pub fn join_household(&mut self, household: Arc<Household>) {
+        self.cash = 0.0;
+        self.household = Some(household);
+    }
+
+    pub fn household(&self) -> Option<&Arc<Household>> {
+        self.household.as_ref()
+    }
+
+    /// 所属家庭本轮是否已买过该商品，未加入家庭时为false
+    fn household_purchased(&self, product_id: u64, round: u64) -> bool {
+        self.household
+            .as_ref()
+            .is_some_and(|household| household.has_purchased(product_id, round))
+    }
 
-                // 如果不在demand中，才添加
-                if !is_already_demanded {
-                    let mut demand = d.write().unwrap();
-                    demand.insert(product_id, true);
+    /// 更新没有工厂供应的商品：这些商品不再产生新需求，已有的需求清除
+    pub fn set_unavailable_products(&mut self, products: &HashSet<u64>, round: u64) {
+        *self.unavailable.write().unwrap() = products.clone();
+        for product_id in products {
+            if self.has_demand(*product_id) {
+                self.remove_demand(*product_id, round, "no_supplier");
+            }
+        }
+    }
+
+    /// 处理偏好区间上界低于该商品工厂成本下限（cost_floors，商品ID -> 下限）、永远无法成交的需求：
+    /// 按unreachable_range删除需求并永久放弃该商品，或把区间上界抬到成本下限之上
+    /// （高出UNREACHABLE_WIDEN_MARGIN）。返回处理的商品数，每轮开始时由市场调用
+    pub fn handle_unreachable_ranges(
+        &mut self,
+        cost_floors: &HashMap<u64, f64>,
+        round: u64,
+    ) -> usize {
+        let action = self.config.unreachable_range;
+        if action == UnreachableRangeAction::Keep {
+            return 0;
+        }
+        let unreachable: Vec<(u64, f64)> = {
+            let demand = self.demand.read().unwrap();
+            let preferences = self.preferences.read().unwrap();
+            demand
+                .keys()
+                .filter_map(|id| {
+                    let floor = *cost_floors.get(id)?;
+                    let below = preferences
+                        .get(id)
+                        .is_some_and(|p| p.current_range.1 < floor);
+                    (floor > 0.0 && below).then_some((*id, floor))
+                })
+                .collect()
+        };
+        for &(product_id, cost_floor) in &unreachable {
+            match action {
+                UnreachableRangeAction::Drop => {
+                    self.abandoned.write().unwrap().insert(product_id);
+                    self.remove_demand(product_id, round, "unreachable_price");
                 }
+                UnreachableRangeAction::Widen => {
+                    let mut preferences = self.preferences.write().unwrap();
+                    if let Some(preference) = preferences.get_mut(&product_id) {
+                        preference.current_range.1 =
+                            cost_floor * (1.0 + UNREACHABLE_WIDEN_MARGIN);
+                    }
+                }
+                UnreachableRangeAction::Keep => {}
+            }
+        }
+        unreachable.len()
+    }
+
+    /// 是否因偏好区间低于成本下限而永久放弃了该商品
+    pub fn has_abandoned(&self, product_id: u64) -> bool {
+        self.abandoned.read().unwrap().contains(&product_id)
+    }
+
+    /// 进入新的一轮，由市场在每轮开始时调用
+    pub fn start_round(&self, round: u64) {
+        self.round.store(round, Ordering::Relaxed);
+    }
+
+    pub fn desire(&mut self) {
+        let generator = self.demand_generator();
+        thread::spawn(move || {
+            let mut rng = rand::thread_rng();
+            loop {
+                generator.tick(&mut rng);
                 // 随机等待0~500ms
                 let wait_time = rng.gen_range(0..500);
                 thread::sleep(Duration::from_millis(wait_time));
@@ -119,30 +757,327 @@ impl Agent {
         });
     }
 
+    /// 设置所属市场的日志记录器，由市场在agent加入时调用，已启动的需求线程随后也写入该记录器
+    pub fn set_logger(&self, logger: Option<Logger>) {
+        *self.logger.write().unwrap() = logger;
+    }
+
+    /// 按轮生成需求：用调用方的随机数生成器执行一次需求生成，与需求线程的一次tick相同。
+    /// 开启round_synced_demand时由市场每轮调用，代替需求线程，固定种子时结果可复现
+    pub fn generate_demands(&self, rng: &mut dyn RngCore) {
+        self.demand_generator().tick(rng);
+    }
+
+    fn demand_generator(&self) -> DemandGenerator {
+        DemandGenerator {
+            demand: self.demand.clone(),
+            preferences: self.preferences.clone(),
+            products: self.products.clone(),
+            known_products: self.known_products.clone(),
+            width_ratio: self.width_ratio,
+            spending_factor: self.spending_factor,
+            elasticity_bounds: self.config.elasticity_bounds,
+            seasonality: self.config.seasonality,
+            demand_model: build_demand_model(&self.config.demand_model),
+            frustration: self.frustration.clone(),
+            recovery: self.config.stockout_recovery,
+            satiation: self.satiation.clone(),
+            satiation_decay: self.config.satiation_decay,
+            word_of_mouth: self.word_of_mouth.clone(),
+            word_of_mouth_decay: self.config.word_of_mouth_decay,
+            verbose_demand_log: self.config.verbose_demand_log,
+            max_demands: self.config.max_outstanding_demands,
+            refused_demands: self.refused_demands.clone(),
+            unavailable: self.unavailable.clone(),
+            abandoned: self.abandoned.clone(),
+            round: self.round.clone(),
+            logger: self.logger.clone(),
+            name: self.name.clone(),
+            user_id: self.id,
+        }
+    }
+
+    /// 未满足的需求数是否已达到上限
+    fn backlog_full(demand: &RwLock<HashMap<u64, bool>>, max_demands: Option<usize>) -> bool {
+        max_demands.is_some_and(|max| demand.read().unwrap().len() >= max)
+    }
+
+    /// 因未满足需求数达到上限而放弃的新需求数
+    pub fn refused_demands(&self) -> u64 {
+        self.refused_demands.load(Ordering::Relaxed)
+    }
+
+    /// 记录产生需求的日志，包括该商品偏好抽样得到的弹性
+    fn log_demand_creation(
+        logger: Option<&Logger>,
+        preferences: &RwLock<HashMap<u64, Preference>>,
+        round: u64,
+        agent_id: u64,
+        agent_name: &str,
+        product_id: u64,
+    ) {
+        let elastic = preferences
+            .read()
+            .unwrap()
+            .get(&product_id)
+            .map(|p| p.original_elastic);
+        if let Err(e) = crate::logging::log_agent_demand_creation(
+            logger,
+            round,
+            agent_id,
+            agent_name.to_string(),
+            product_id,
+            elastic,
+        ) {
+            eprintln!("Failed to log agent demand creation: {}", e);
+        }
+    }
+
+    fn arrival_probability(
+        frustration: &RwLock<HashMap<u64, f64>>,
+        satiation: &RwLock<HashMap<u64, f64>>,
+        product_id: u64,
+    ) -> f64 {
+        let frustration = frustration.read().unwrap().get(&product_id).copied().unwrap_or(0.0);
+        let satiation = satiation.read().unwrap().get(&product_id).copied().unwrap_or(0.0);
+        (1.0 - frustration) * (1.0 - satiation)
+    }
+
+    /// 每个商品的程度值减去amount，降到0的移除
+    fn decay_levels(levels: &RwLock<HashMap<u64, f64>>, amount: f64) {
+        if amount <= 0.0 {
+            return;
+        }
+        let mut levels = levels.write().unwrap();
+        levels.retain(|_, level| {
+            *level -= amount;
+            *level > 0.0
+        });
+    }
+
+    /// 到达的需求真正生效的概率，缺货越频繁、刚买过越低
+    pub fn demand_probability(&self, product_id: u64) -> f64 {
+        Self::arrival_probability(&self.frustration, &self.satiation, product_id)
+    }
+
+    /// 是否知道该商品；未配置product_awareness时知道所有商品
+    pub fn knows_product(&self, product_id: u64) -> bool {
+        self.known_products.as_ref().is_none_or(|known| known.contains(&product_id))
+    }
+
+    /// 听到邻居推荐：该商品的口碑概率增加boost，按word_of_mouth_decay逐渐消退，不知道的商品忽略
+    pub fn hear_about(&self, product_id: u64, boost: f64) {
+        if boost <= 0.0 || !self.knows_product(product_id) {
+            return;
+        }
+        let mut levels = self.word_of_mouth.write().unwrap();
+        let level = levels.entry(product_id).or_insert(0.0);
+        *level = (*level + boost).min(1.0);
+    }
+
+    /// 邻居推荐带来的额外需求概率，没有推荐时为0
+    pub fn word_of_mouth(&self, product_id: u64) -> f64 {
+        self.word_of_mouth
+            .read()
+            .unwrap()
+            .get(&product_id)
+            .copied()
+            .unwrap_or(0.0)
+    }
+
+    /// 记录一次缺货：有需求但该商品所有工厂都已售罄，不满增加stockout_penalty
+    pub fn record_stockout(&self, product_id: u64) {
+        if self.config.stockout_penalty <= 0.0 {
+            return;
+        }
+        let mut frustration = self.frustration.write().unwrap();
+        let f = frustration.entry(product_id).or_insert(0.0);
+        *f = (*f + self.config.stockout_penalty).min(1.0);
+    }
+
+    /// 按initial_demands同步生成初始需求：每个商品以 期望数量/商品数 的概率产生需求，
+    /// 不必等需求线程，第一轮即可交易。使用agent的随机数生成器，市场设置种子时可复现
+    fn seed_initial_demands(&self) {
+        let known: Vec<u64> = self
+            .products
+            .iter()
+            .map(|p| p.id())
+            .filter(|id| self.knows_product(*id))
+            .collect();
+        if self.config.initial_demands <= 0.0 || known.is_empty() {
+            return;
+        }
+        let probability = (self.config.initial_demands / known.len() as f64).min(1.0);
+        let seeded: Vec<u64> = {
+            let mut rng = self.rng.lock().unwrap();
+            known.into_iter().filter(|_| rng.gen_bool(probability)).collect()
+        };
+        for product_id in seeded {
+            self.add_demand(product_id);
+        }
+    }
+
+    /// 直接为agent添加一个商品需求，用于场景设置
+    pub fn add_demand(&self, product_id: u64) {
+        if !self.knows_product(product_id) {
+            return;
+        }
+        if !self.has_demand(product_id)
+            && Self::backlog_full(&self.demand, self.config.max_outstanding_demands)
+        {
+            self.refused_demands.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        Self::ensure_preference(
+            &self.preferences,
+            &self.products,
+            product_id,
+            self.width_ratio,
+            self.spending_factor,
+            self.config.elasticity_bounds,
+            &mut *self.rng.lock().unwrap(),
+        );
+        let created = self.demand.write().unwrap().insert(product_id, true).is_none();
+        if created && self.config.verbose_demand_log {
+            let round = self.round.load(Ordering::Relaxed);
+            Self::log_demand_creation(
+                self.logger.read().unwrap().as_ref(),
+                &self.preferences,
+                round,
+                self.id,
+                &self.name,
+                product_id,
+            );
+        }
+    }
+
+    /// 该商品上次成功购买的工厂ID
+    pub fn last_factory(&self, product_id: u64) -> Option<u64> {
+        self.last_factory.get(&product_id).copied()
+    }
+
+    /// 品牌忠诚：上次购买的工厂本轮还没轮到且报价区间与自己有交集时，
+    /// 以brand_loyalty的概率放弃当前工厂，等待老工厂
+    pub fn waits_for_loyal_factory(&self, product_id: u64, upcoming: &[(u64, (f64, f64))]) -> bool {
+        if self.config.brand_loyalty <= 0.0 {
+            return false;
+        }
+        let Some(loyal_id) = self.last_factory(product_id) else {
+            return false;
+        };
+        let Some((_, loyal_range)) = upcoming.iter().find(|(id, _)| *id == loyal_id) else {
+            return false;
+        };
+
+        let agent_range = {
+            let preferences = self.preferences.read().unwrap();
+            match preferences.get(&product_id) {
+                Some(p) => p.current_range,
+                None => return false,
+            }
+        };
+        if interval_intersection(agent_range, *loyal_range).is_none() {
+            return false;
+        }
+
+        rand::thread_rng().gen_bool(self.config.brand_loyalty.min(1.0))
+    }
+
+    /// 直接设置某商品的偏好区间，用于场景实验；没有该商品的偏好时返回false
+    pub fn set_preference_range(&self, product_id: u64, range: (f64, f64)) -> bool {
+        match self.preferences.write().unwrap().get_mut(&product_id) {
+            Some(preference) => {
+                preference.current_range = range;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 把所有偏好恢复到生成时的初始值，清除交易中的学习结果，现金和需求不变
+    pub fn reset_preferences(&mut self) {
+        let mut preferences = self.preferences.write().unwrap();
+        for (product_id, preference) in preferences.iter_mut() {
+            let expected_price = self
+                .products
+                .iter()
+                .find(|p| p.id() == *product_id)
+                .map(|p| p.original_price_distribution().mean() * self.spending_factor)
+                .unwrap_or(preference.original_price);
+            preference.reset(expected_price, self.width_ratio);
+        }
+    }
+
+    /// 尚未满足的需求数量
+    pub fn demand_count(&self) -> usize {
+        self.demand.read().unwrap().len()
+    }
+
+    /// 当前有需求的商品ID，按ID升序
+    pub fn demanded_products(&self) -> Vec<u64> {
+        let mut ids: Vec<u64> = self.demand.read().unwrap().keys().copied().collect();
+        ids.sort();
+        ids
+    }
+
     pub fn has_demand(&self, product_id: u64) -> bool {
         let demand = self.demand.read().unwrap();
         demand.contains_key(&product_id)
     }
 
-    fn match_factory(&self, factory: &Factory) -> IntervalRelation {
+    fn is_essential(&self, product_id: u64) -> bool {
+        self.products
+            .iter()
+            .any(|p| p.id() == product_id && p.is_essential())
+    }
+
+    fn match_factory(&self, factory: &Factory, round: u64) -> IntervalRelation {
         let product_id = factory.product_id();
 
+        // 必需品在工厂本轮库存不足产量一半时，可接受的上界提高need_premium
+        let premium = if self.is_essential(product_id)
+            && factory.get_stock(round) * 2 <= factory.production()
+        {
+            1.0 + self.config.need_premium
+        } else {
+            1.0
+        };
+
         let pg = self.preferences.read().unwrap();
         let p = pg.get(&product_id).unwrap();
 
-        let agent_range = p.current_range;
-        let factory_range = factory.supply_price_range();
+        let agent_range = (
+            p.current_range.0 * (1.0 + self.jitter),
+            p.current_range.1 * (1.0 + self.jitter) * premium,
+        );
+        // agent看到的是带感知噪声的报价，按看到的报价决定是否成交，成交价再换算回真实报价
+        let perception = self.perceived_price_factor();
+        let (real_min, real_max) = factory.asking_range();
+        let factory_range = (real_min * perception, real_max * perception);
+        let to_real = |(lower, upper): (f64, f64)| {
+            (
+                (lower / perception).clamp(real_min, real_max),
+                (upper / perception).clamp(real_min, real_max),
+            )
+        };
 
         match interval_intersection(agent_range, factory_range) {
-            Some(overlap) => IntervalRelation::Overlapping(overlap),
+            Some(overlap) => IntervalRelation::Overlapping(to_real(overlap)),
             None => {
                 // 判断区间关系
                 let (agent_min, agent_max) = agent_range;
                 let (factory_min, factory_max) = factory_range;
 
                 if agent_max < factory_min {
-                    // 代理的价格区间整体低于工厂的价格区间
-                    IntervalRelation::AgentBelowFactory
+                    // 代理的价格区间整体低于工厂的价格区间，讨价还价成功时按谈定的价格成交
+                    match self.bargain(agent_max, factory_min) {
+                        // 谈定的价格可以低于报价区间，但不低于工厂的保留价
+                        Some(price) if price / perception >= factory.reservation_price() => {
+                            let price = price / perception;
+                            IntervalRelation::Overlapping((price, price))
+                        }
+                        _ => IntervalRelation::AgentBelowFactory,
+                    }
                 } else {
                     // 代理的价格区间整体高于工厂的价格区间
                     IntervalRelation::AgentAboveFactory
@@ -151,6 +1086,41 @@ impl Agent {
         }
     }
 
+    /// 本次观察工厂报价的感知系数1+ε，ε~N(0, price_perception_noise)，系数不低于0.5
+    fn perceived_price_factor(&self) -> f64 {
+        let noise = self.config.price_perception_noise;
+        if noise <= 0.0 {
+            return 1.0;
+        }
+        let epsilon =
+            rand::thread_rng().sample(rand_distr::Normal::new(0.0, noise).unwrap());
+        (1.0 + epsilon).max(0.5)
+    }
+
+    /// 讨价还价：agent最高出价bid低于工厂最低要价ask时，每步双方各让出剩余差价的四分之一，
+    /// 不超过各自max_concession的让步上限。steps步内差价不超过一分钱时返回成交价，明码标价时返回None
+    fn bargain(&self, bid: f64, ask: f64) -> Option<f64> {
+        let NegotiationMode::Bargaining {
+            steps,
+            max_concession,
+        } = self.config.negotiation
+        else {
+            return None;
+        };
+        let bid_limit = bid * (1.0 + max_concession);
+        let ask_limit = ask * (1.0 - max_concession);
+        let (mut bid, mut ask) = (bid, ask);
+        for _ in 0..steps {
+            let gap = ask - bid;
+            if gap <= 0.01 {
+                break;
+            }
+            bid = (bid + gap / 4.0).min(bid_limit);
+            ask = (ask - gap / 4.0).max(ask_limit);
+        }
+        (ask - bid <= 0.01).then(|| round_to_nearest_cent(ask))
+    }
+
     /// 处理交易失败的逻辑
     /// - `is_agent_below_factory`: 如果为true，表示代理价格低于工厂（商家售价太高），需要上移范围
     /// - 如果为false，表示代理价格高于工厂或余额不足，需要下移范围
@@ -166,8 +1136,11 @@ impl Agent {
 
         let mut g = self.preferences.write().unwrap();
         if let Some(preference) = g.get_mut(&product_id) {
-            // 计算概率：弹性值本身，弹性越大，越容易删除需求
-            let delete_probability = preference.original_elastic;
+            // 计算概率：以工厂报价中点计算的有效弹性，弹性越大，越容易删除需求
+            let (factory_min, factory_max) = factory.supply_price_range();
+            let offered_price = (factory_min + factory_max) / 2.0;
+            let delete_probability =
+                preference.effective_elastic(offered_price, self.config.elasticity_slope);
 
             // 生成随机数（0.0到1.0）
             let random_value = rng.gen_range(0.0..1.0);
@@ -180,11 +1153,12 @@ impl Agent {
                     // 记录需求删除日志
                     let preference = g.get(&product_id).unwrap();
                     if let Err(e) = crate::logging::log_agent_demand_removal(
+                        self.logger.read().unwrap().as_ref(),
                         round,
                         self.id,
                         self.name.clone(),
                         product_id,
-                        self.cash,
+                        self.cash(),
                         Some(preference.original_price),
                         Some(preference.original_elastic),
                         Some(preference.current_price),
@@ -230,6 +1204,17 @@ impl Agent {
                 // 确保最小值不小于0.0
                 new_min = new_min.max(0.0);
 
+                // 上移时上界不超过心理价位的max_price_growth倍，不能无限上漂：
+                // 整个区间最多平移到上界触顶，宽度不变，已在上限之上的区间不再上移
+                if let Some(growth) = self.config.max_price_growth {
+                    let ceiling = round_to_nearest_cent(preference.original_price * growth);
+                    if new_max > old_max && new_max > ceiling {
+                        let shift = (ceiling - old_max).max(0.0);
+                        new_min = round_to_nearest_cent(old_min + shift);
+                        new_max = round_to_nearest_cent(old_max + shift);
+                    }
+                }
+
                 // 确保max大于min，且至少有0.01的差距
                 let new_max = if new_max <= new_min {
                     new_min + 0.01
@@ -264,6 +1249,7 @@ impl Agent {
 
                     // 调用日志记录函数
                     if let Err(e) = log_agent_range_adjustment(
+                        self.logger.read().unwrap().as_ref(),
                         round, // 使用传入的round参数
                         self.id,
                         self.name.clone(),
@@ -295,11 +1281,12 @@ impl Agent {
         let preferences = self.preferences.read().unwrap();
         if let Some(preference) = preferences.get(&product_id) {
             if let Err(e) = crate::logging::log_agent_demand_removal(
+                self.logger.read().unwrap().as_ref(),
                 round,
                 self.id,
                 self.name.clone(),
                 product_id,
-                self.cash,
+                self.cash(),
                 Some(preference.original_price),
                 Some(preference.original_elastic),
                 Some(preference.current_price),
@@ -312,87 +1299,275 @@ impl Agent {
         }
     }
 
+    /// 本轮还能花的钱：未设置单轮消费上限时不限，与现金无关
+    pub fn round_budget(&self, round: u64) -> f64 {
+        let Some(limit) = self.config.round_spending_limit else {
+            return f64::INFINITY;
+        };
+        let (spent_round, spent) = self.round_spent;
+        if spent_round == round {
+            (limit - spent).max(0.0)
+        } else {
+            limit
+        }
+    }
+
+    fn record_spending(&mut self, round: u64, product_id: u64, amount: f64) {
+        if self.round_spent.0 != round {
+            self.round_spent = (round, 0.0);
+        }
+        self.round_spent.1 += amount;
+        // 从对应的心理账户扣除
+        let essential = self.is_essential(product_id);
+        if let Some((essential_budget, non_essential_budget)) = self.category_budgets.as_mut() {
+            let budget = if essential {
+                essential_budget
+            } else {
+                non_essential_budget
+            };
+            *budget = (*budget - amount).max(0.0);
+        }
+    }
+
+    /// 成交后的记账：清除需求、记录购买的工厂，并以成交价为中心收窄偏好区间。
+    /// 资金由调用方扣减
+    fn settle_purchase(&mut self, factory: &Factory, round: u64, price: f64) {
+        let product_id = factory.product_id();
+        self.remove_demand(product_id, round, "successful_trade");
+        self.last_factory.insert(product_id, factory.id());
+        self.start_subscription(product_id, factory.id(), price, round);
+        // 满足程度按工厂的单位规格折算，缩水的商品带来的满足更少
+        if self.config.satiation_per_purchase > 0.0 {
+            let mut satiation = self.satiation.write().unwrap();
+            let level = satiation.entry(product_id).or_insert(0.0);
+            *level = (*level + self.config.satiation_per_purchase * factory.unit_size()).min(1.0);
+        }
+        let mut g = self.preferences.write().unwrap();
+        let preference = g.get_mut(&product_id).unwrap();
+        preference.current_price = price;
+        let (new_min, new_max) = gen_new_range_with_price(price, preference.current_range, 0.9);
+        let (old_min, old_max) = preference.current_range;
+        // 计算变化量，如果小于0.01，则不更新
+        let min_change = (new_min - old_min).abs();
+        let max_change = (new_max - old_max).abs();
+
+        if min_change >= 0.01 || max_change >= 0.01 {
+            // 计算变化比例（基于原范围长度）
+            let old_length = old_max - old_min;
+            let min_change_value = new_min - old_min;
+            let max_change_value = new_max - old_max;
+            let min_change_ratio = if old_length > 0.0 {
+                min_change_value / old_length
+            } else {
+                0.0
+            };
+            let max_change_ratio = if old_length > 0.0 {
+                max_change_value / old_length
+            } else {
+                0.0
+            };
+
+            // 调用日志记录函数
+            if let Err(e) = log_agent_range_adjustment(
+                self.logger.read().unwrap().as_ref(),
+                round, // 使用传入的round参数
+                self.id(),
+                self.name().to_string(),
+                product_id,
+                (old_min, old_max),
+                (new_min, new_max),
+                min_change_value,
+                max_change_value,
+                min_change_ratio,
+                max_change_ratio,
+                price, // 交易成功，以成交价格为中心
+                "trade_success",
+                Some(price), // 交易成功，有价格
+            ) {
+                eprintln!("Failed to log agent range adjustment: {}", e);
+            }
+
+            preference.current_range = (new_min, new_max);
+        }
+    }
+
+    /// 按指定价格与工厂强制成交1件，跳过区间匹配，用于搭建测试或场景的初始状态。
+    /// 工厂库存或agent资金不足时返回错误，agent状态不变
+    pub fn force_trade(
+        &mut self,
+        factory: &Factory,
+        round: u64,
+        price: f64,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let product_id = factory.product_id();
+        if factory.get_stock(round) <= 0 {
+            return Err(format!("factory {} is out of stock", factory.id()).into());
+        }
+        Self::ensure_preference(
+            &self.preferences,
+            &self.products,
+            product_id,
+            self.width_ratio,
+            self.spending_factor,
+            self.config.elasticity_bounds,
+            &mut *self.rng.lock().unwrap(),
+        );
+        if !self.preferences.read().unwrap().contains_key(&product_id) {
+            return Err(format!("agent {} has no preference for product {}", self.id, product_id).into());
+        }
+
+        match &self.household {
+            Some(household) => {
+                if household
+                    .purchase(product_id, round, (price, price), 1, f64::INFINITY)
+                    .is_none()
+                {
+                    return Err(format!("household {} cannot pay {:.2}", household.id(), price).into());
+                }
+            }
+            None => {
+                if self.spendable() < price {
+                    return Err(format!("agent {} cannot pay {:.2}", self.id, price).into());
+                }
+                self.pay(price);
+            }
+        }
+        self.record_spending(round, product_id, price);
+        self.settle_purchase(factory, round, price);
+        self.record_holding(product_id, price, 1);
+        Ok(())
+    }
+
+    /// 本轮购买该商品最多能花的金额：受本轮消费上限和所属心理账户余额约束，
+    /// 非必需品还需保留want_cash_reserve的资金
+    fn purchase_budget(&self, product_id: u64, round: u64) -> f64 {
+        let budget = self.round_budget(round).min(self.category_budget(product_id));
+        if self.is_essential(product_id) || self.config.want_cash_reserve <= 0.0 {
+            return budget;
+        }
+        let available = match &self.household {
+            Some(household) => household.cash(),
+            None => self.spendable(),
+        };
+        budget.min((available - self.config.want_cash_reserve).max(0.0))
+    }
+
+    /// 拍卖出价：愿意支付的最高价格，即偏好区间上界，不超过本轮可花的资金。
+    /// 没有需求、家庭本轮已买过或出不起价时返回None
+    pub fn auction_bid(&self, product_id: u64, round: u64) -> Option<f64> {
+        if !self.has_demand(product_id) || !self.willing_to_spend(round) {
+            return None;
+        }
+        let available = match &self.household {
+            Some(household) => {
+                if household.has_purchased(product_id, round) {
+                    return None;
+                }
+                household.cash()
+            }
+            None => self.spendable(),
+        };
+        let upper = self.preferences.read().unwrap().get(&product_id)?.current_range.1;
+        let bid = (upper * (1.0 + self.jitter))
+            .min(self.purchase_budget(product_id, round))
+            .min(available);
+        (bid >= 0.01).then_some(bid)
+    }
+
+    /// 拍卖中出价低于成交价而未买到，与报价过高时一样上移区间或放弃需求
+    pub fn auction_lost(&mut self, factory: &Factory, round: u64) {
+        self.handle_trade_failure(factory, factory.product_id(), round, true);
+    }
+
+    /// 每次成交希望购买的数量
+    pub fn purchase_quantity(&self) -> i16 {
+        self.config.purchase_quantity
+    }
+
     pub fn trade(
         &mut self,
         factory: &Factory,
         round: u64,
     ) -> (TradeResult, Option<IntervalRelation>) {
+        let (result, interval_relation, _) = self.trade_units(factory, round, 1);
+        (result, interval_relation)
+    }
+
+    /// 批量交易：成交时按单价购买最多quantity件，不超过工厂本轮库存和可负担的数量，
+    /// 现金扣减单价×数量。返回值的第三项为实际成交数量
+    pub fn trade_units(
+        &mut self,
+        factory: &Factory,
+        round: u64,
+        quantity: i16,
+    ) -> (TradeResult, Option<IntervalRelation>, i16) {
         let has_demand = self.has_demand(factory.product_id());
         if !has_demand {
-            return (TradeResult::NotMatched, None);
+            return (TradeResult::NotMatched, None, 0);
         }
-        let interval_relation = self.match_factory(factory);
-
         let product_id = factory.product_id();
 
+        // 订阅中的商品由续购满足，不再逐轮撮合
+        if self.subscriptions.contains_key(&product_id) {
+            self.remove_demand(product_id, round, "subscribed");
+            return (TradeResult::NotMatched, None, 0);
+        }
+
+        // 离发薪日越远越不愿意消费，本轮暂不购买但保留需求
+        if !self.willing_to_spend(round) {
+            return (TradeResult::NotMatched, None, 0);
+        }
+
+        // 家庭成员本轮已买过该商品，需求由家庭满足
+        if self.household_purchased(product_id, round) {
+            self.remove_demand(product_id, round, "household_purchased");
+            return (TradeResult::NotMatched, None, 0);
+        }
+
+        let interval_relation = self.match_factory(factory, round);
+
         match interval_relation {
             IntervalRelation::Overlapping(range) => {
-                let price = gen_price_in_range(range, self.cash);
-                if price.is_none() {
+                let quantity = quantity.min(factory.get_stock(round)).max(1);
+                // 花费同时受现金和本轮消费上限约束
+                let budget = self.purchase_budget(product_id, round);
+                let purchase = match &self.household {
+                    Some(household) => {
+                        household.purchase(product_id, round, range, quantity, budget)
+                    }
+                    None => gen_purchase_in_range(range, self.spendable().min(budget), quantity),
+                };
+                if purchase.is_none() {
+                    // 其他家庭成员在本次撮合期间先买到了，不算资金不足
+                    if self.household_purchased(product_id, round) {
+                        self.remove_demand(product_id, round, "household_purchased");
+                        return (TradeResult::NotMatched, None, 0);
+                    }
                     self.handle_trade_failure(factory, product_id, round, false);
-                    return (TradeResult::Failed, Some(interval_relation));
+                    return (TradeResult::Failed, Some(interval_relation), 0);
                 }
-                self.remove_demand(product_id, round, "successful_trade");
-                let price = price.unwrap();
-                self.cash -= price;
-                let mut g = self.preferences.write().unwrap();
-                let preference = g.get_mut(&product_id).unwrap();
-                preference.current_price = price;
-                let (new_min, new_max) =
-                    gen_new_range_with_price(price, preference.current_range, 0.9);
-                let (old_min, old_max) = preference.current_range;
-                // 计算变化量，如果小于0.01，则不更新
-                let min_change = (new_min - old_min).abs();
-                let max_change = (new_max - old_max).abs();
-
-                if min_change >= 0.01 || max_change >= 0.01 {
-                    // 计算变化比例（基于原范围长度）
-                    let old_length = old_max - old_min;
-                    let min_change_value = new_min - old_min;
-                    let max_change_value = new_max - old_max;
-                    let min_change_ratio = if old_length > 0.0 {
-                        min_change_value / old_length
-                    } else {
-                        0.0
-                    };
-                    let max_change_ratio = if old_length > 0.0 {
-                        max_change_value / old_length
-                    } else {
-                        0.0
-                    };
-
-                    // 调用日志记录函数
-                    if let Err(e) = log_agent_range_adjustment(
-                        round, // 使用传入的round参数
-                        self.id(),
-                        self.name().to_string(),
-                        product_id,
-                        (old_min, old_max),
-                        (new_min, new_max),
-                        min_change_value,
-                        max_change_value,
-                        min_change_ratio,
-                        max_change_ratio,
-                        price, // 交易成功，以成交价格为中心
-                        "trade_success",
-                        Some(price), // 交易成功，有价格
-                    ) {
-                        eprintln!("Failed to log agent range adjustment: {}", e);
-                    }
-
-                    preference.current_range = (new_min, new_max);
+                let (price, quantity) = purchase.unwrap();
+                if self.household.is_none() {
+                    self.pay(price * quantity as f64);
                 }
-                return (TradeResult::Success(price), Some(interval_relation));
+                self.record_spending(round, product_id, price * quantity as f64);
+                self.settle_purchase(factory, round, price);
+                self.record_holding(product_id, price, quantity);
+                return (
+                    TradeResult::Success(price),
+                    Some(interval_relation),
+                    quantity,
+                );
             }
             IntervalRelation::AgentBelowFactory => {
                 // 代理价格低于工厂，商家售价太高，上移3%
                 self.handle_trade_failure(factory, product_id, round, true);
-                return (TradeResult::Failed, Some(interval_relation));
+                return (TradeResult::Failed, Some(interval_relation), 0);
             }
             IntervalRelation::AgentAboveFactory => {
                 // 代理价格高于工厂，商家售价太低，下移3%
                 self.handle_trade_failure(factory, product_id, round, false);
-                return (TradeResult::Failed, Some(interval_relation));
+                return (TradeResult::Failed, Some(interval_relation), 0);
             }
         }
     }
@@ -402,6 +1577,25 @@ impl Agent {
 mod tests {
     use super::*;
 
+    fn test_product(id: u64, price: f64) -> Product {
+        Product::from(
+            id,
+            format!("product_{}", id),
+            crate::entity::normal_distribute::NormalDistribution::new(
+                price,
+                id,
+                "price_dist".to_string(),
+                5.0,
+            ),
+            crate::entity::normal_distribute::NormalDistribution::new(
+                0.5,
+                id,
+                "elastic_dist".to_string(),
+                0.1,
+            ),
+        )
+    }
+
     #[test]
     fn test_new() {
         let id = 1;
@@ -1004,4 +2198,1312 @@ mod tests {
             product_id
         );
     }
+
+    #[test]
+    fn test_range_width_config() {
+        let product = test_product(1, 50.0);
+        let products = vec![product];
+
+        let narrow = AgentConfig {
+            range_width: Some((0.1, 0.01)),
+            ..Default::default()
+        };
+        let wide = AgentConfig {
+            range_width: Some((1.0, 0.01)),
+            ..Default::default()
+        };
+
+        // 统计0~100之间每隔0.5的价格有多少落在区间内
+        let accepted_prices = |agent: &Agent| {
+            let range = agent.preferences().get(&1).unwrap().current_range;
+            (0..200)
+                .map(|i| i as f64 * 0.5)
+                .filter(|price| *price >= range.0 && *price <= range.1)
+                .count()
+        };
+
+        for i in 0..10 {
+            let narrow_agent =
+                Agent::with_config(i, "narrow".to_string(), 100.0, &products, &narrow);
+            let wide_agent = Agent::with_config(i, "wide".to_string(), 100.0, &products, &wide);
+            assert!(
+                accepted_prices(&wide_agent) > accepted_prices(&narrow_agent),
+                "Wide agents should accept more prices than narrow agents"
+            );
+
+            // 区间以原始价格为中心
+            let preferences = narrow_agent.preferences();
+            let preference = preferences.get(&1).unwrap();
+            let center = (preference.current_range.0 + preference.current_range.1) / 2.0;
+            assert!((center - preference.original_price).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_brand_loyalty() {
+        let product = test_product(1, 50.0);
+        let products = vec![product.clone()];
+        let loyal = AgentConfig {
+            brand_loyalty: 1.0,
+            ..Default::default()
+        };
+        let mut agent = Agent::with_config(1, "loyal".to_string(), 1000.0, &products, &loyal);
+        {
+            let mut preferences = agent.preferences.write().unwrap();
+            preferences.get_mut(&1).unwrap().current_range = (0.0, 1000.0);
+        }
+
+        let factory_a = crate::model::factory::Factory::new(1, "a".to_string(), &product);
+        let factory_b = crate::model::factory::Factory::new(2, "b".to_string(), &product);
+        let upcoming = vec![
+            (factory_a.id(), factory_a.supply_price_range()),
+            (factory_b.id(), factory_b.supply_price_range()),
+        ];
+
+        // 没有购买记录时不等待
+        assert!(!agent.waits_for_loyal_factory(1, &upcoming));
+
+        agent.add_demand(1);
+        let (result, _) = agent.trade(&factory_a, 0);
+        assert!(matches!(result, TradeResult::Success(_)));
+        assert_eq!(agent.last_factory(1), Some(1));
+
+        // 老工厂还没轮到时等待它，老工厂不在列表中时不等待
+        assert!(agent.waits_for_loyal_factory(1, &upcoming));
+        assert!(!agent.waits_for_loyal_factory(1, &upcoming[1..]));
+
+        // 忠诚度为0的agent从不等待
+        let mut fickle = Agent::new(2, "fickle".to_string(), 1000.0, &products);
+        {
+            let mut preferences = fickle.preferences.write().unwrap();
+            preferences.get_mut(&1).unwrap().current_range = (0.0, 1000.0);
+        }
+        fickle.add_demand(1);
+        let (result, _) = fickle.trade(&factory_a, 0);
+        assert!(matches!(result, TradeResult::Success(_)));
+        assert!(!fickle.waits_for_loyal_factory(1, &upcoming));
+    }
+
+    #[test]
+    fn test_household_shares_cash() {
+        let product = test_product(1, 50.0);
+        let products = vec![product.clone()];
+        let mut first = Agent::new(1, "first".to_string(), 100.0, &products);
+        let mut second = Agent::new(2, "second".to_string(), 100.0, &products);
+        let household = Arc::new(Household::new(1, vec![1, 2], 200.0));
+        first.join_household(household.clone());
+        second.join_household(household.clone());
+
+        for agent in [&first, &second] {
+            let mut preferences = agent.preferences.write().unwrap();
+            preferences.get_mut(&1).unwrap().current_range = (0.0, 1000.0);
+        }
+        let mut factory = crate::model::factory::Factory::new(1, "factory".to_string(), &product);
+
+        // 两个成员从同一个资金池扣款
+        first.add_demand(1);
+        let (result, _) = first.trade(&factory, 1);
+        let TradeResult::Success(first_price) = result else {
+            panic!("First member should buy");
+        };
+        assert!((second.cash() - (200.0 - first_price)).abs() < 1e-9);
+
+        // 同一轮家庭不重复购买同一商品，第二个成员的需求被满足
+        second.add_demand(1);
+        let (result, _) = second.trade(&factory, 1);
+        assert!(matches!(result, TradeResult::NotMatched));
+        assert!(!second.has_demand(1));
+
+        // 下一轮第二个成员继续花同一笔钱，直到资金耗尽
+        let mut spent = first_price;
+        for round in 2..100 {
+            factory.start_round(round);
+            second.add_demand(1);
+            if let (TradeResult::Success(price), _) = second.trade(&factory, round) {
+                spent += price;
+            }
+        }
+        assert!((first.cash() - (200.0 - spent)).abs() < 1e-6);
+        assert!(first.cash() < 200.0 - first_price);
+        assert_eq!(first.cash(), second.cash());
+    }
+
+    #[test]
+    fn test_effective_elastic() {
+        let mut preference = Preference::new(50.0, 0.3);
+        preference.current_range = (40.0, 60.0);
+
+        // 斜率为0时弹性恒定
+        assert_eq!(preference.effective_elastic(42.0, 0.0), 0.3);
+        assert_eq!(preference.effective_elastic(58.0, 0.0), 0.3);
+
+        // 区间内报价越高越敏感，高于区间时更敏感
+        let low = preference.effective_elastic(42.0, 1.0);
+        let high = preference.effective_elastic(58.0, 1.0);
+        let above = preference.effective_elastic(70.0, 1.0);
+        assert!(high > low);
+        assert!(above > high);
+        assert!(above <= 1.0);
+    }
+
+    #[test]
+    fn test_lazy_preferences() {
+        let products: Vec<Product> = (1..=200).map(|id| test_product(id, 50.0)).collect();
+        let lazy = AgentConfig {
+            lazy_preferences: true,
+            round_synced_demand: true,
+            demand_model: crate::config::DemandModelConfig::Bernoulli { probability: 1.0 },
+            ..Default::default()
+        };
+
+        let eager_agent = Agent::new(1, "eager".to_string(), 100.0, &products);
+        assert_eq!(eager_agent.preferences().len(), 200);
+
+        // 创建时不生成任何preference
+        let agent = Agent::with_config(2, "lazy".to_string(), 100.0, &products, &lazy);
+        assert!(agent.preferences().is_empty());
+
+        // 只有产生过需求的商品才有preference
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..5 {
+            agent.generate_demands(&mut rng);
+        }
+        let initialized: Vec<u64> = agent.preferences().keys().copied().collect();
+        assert!(!initialized.is_empty() && initialized.len() < 200);
+        {
+            let demand = agent.demand.read().unwrap();
+            assert!(initialized.iter().all(|id| demand.contains_key(id)));
+        }
+
+        // 手动添加需求时生成preference
+        let missing = (1..=200)
+            .find(|id| !agent.preferences().contains_key(id))
+            .unwrap();
+        agent.add_demand(missing);
+        assert!(agent.preferences().contains_key(&missing));
+        assert!(agent.has_demand(missing));
+    }
+
+    #[test]
+    fn test_shared_products() {
+        let products = Arc::new((1..=10).map(|id| test_product(id, 50.0)).collect::<Vec<_>>());
+        let config = AgentConfig {
+            product_awareness: Some(0.3),
+            round_synced_demand: true,
+            ..Default::default()
+        };
+        let agent = |id: u64| {
+            let rng = StdRng::seed_from_u64(id);
+            let name = format!("agent_{}", id);
+            Agent::with_shared_products(id, name, 100.0, products.clone(), &config, rng)
+        };
+        let (first, second) = (agent(1), agent(2));
+
+        // 两个agent共用同一份商品列表，各自只记录知道的商品ID
+        assert!(Arc::ptr_eq(&first.products, &products));
+        assert!(Arc::ptr_eq(&second.products, &products));
+        assert_eq!(first.known_products.as_ref().unwrap().len(), 3);
+        assert_eq!(first.preferences().len(), 3);
+    }
+
+    #[test]
+    fn test_trade_units() {
+        let product = test_product(1, 50.0);
+        let products = vec![product.clone()];
+        let bulk = AgentConfig {
+            purchase_quantity: 5,
+            ..Default::default()
+        };
+        let mut agent = Agent::with_config(1, "bulk".to_string(), 10000.0, &products, &bulk);
+        {
+            let mut preferences = agent.preferences.write().unwrap();
+            preferences.get_mut(&1).unwrap().current_range = (0.0, 1000.0);
+        }
+        let mut factory = crate::model::factory::Factory::new(1, "factory".to_string(), &product);
+        let round = 1;
+        factory.start_round(round);
+
+        // 买5件：库存减5，现金减5倍单价
+        agent.add_demand(1);
+        let (result, relation, quantity) =
+            agent.trade_units(&factory, round, agent.purchase_quantity());
+        let TradeResult::Success(price) = result else {
+            panic!("Bulk trade should succeed");
+        };
+        assert_eq!(quantity, 5);
+        assert!((agent.cash() - (10000.0 - price * 5.0)).abs() < 1e-6);
+        factory.deal_units(&result, round, relation, quantity);
+        assert_eq!(factory.get_stock(round), 5);
+
+        // 库存只剩5件时，要8件只能成交5件
+        agent.add_demand(1);
+        let cash_before = agent.cash();
+        let (result, relation, quantity) = agent.trade_units(&factory, round, 8);
+        let TradeResult::Success(price) = result else {
+            panic!("Partial trade should succeed");
+        };
+        assert_eq!(quantity, 5);
+        assert!((agent.cash() - (cash_before - price * 5.0)).abs() < 1e-6);
+        factory.deal_units(&result, round, relation, quantity);
+        assert_eq!(factory.get_stock(round), 0);
+    }
+
+    #[test]
+    fn test_round_spending_limit() {
+        let product = test_product(1, 50.0);
+        let products = vec![product.clone()];
+        let config = AgentConfig {
+            round_spending_limit: Some(100.0),
+            ..Default::default()
+        };
+        let mut agent = Agent::with_config(1, "thrifty".to_string(), 10000.0, &products, &config);
+        let factory = crate::model::factory::Factory::new(1, "factory".to_string(), &product);
+
+        // 现金充足，但一轮内花满上限后就买不到了
+        let trade = |agent: &mut Agent, round: u64| {
+            agent
+                .preferences
+                .write()
+                .unwrap()
+                .get_mut(&1)
+                .unwrap()
+                .current_range = (0.0, 1000.0);
+            agent.add_demand(1);
+            agent.trade(&factory, round).0
+        };
+        let mut limited = false;
+        for _ in 0..50 {
+            if !matches!(trade(&mut agent, 1), TradeResult::Success(_)) {
+                limited = true;
+                break;
+            }
+        }
+        assert!(limited, "Purchases should stop once the round limit is reached");
+        assert!(agent.cash() >= 10000.0 - 100.0 - 1e-9);
+        // 剩余额度已不够报价区间的下限
+        assert!(agent.round_budget(1) < factory.supply_price_range().0);
+
+        // 下一轮额度恢复
+        assert_eq!(agent.round_budget(2), 100.0);
+        assert!(matches!(trade(&mut agent, 2), TradeResult::Success(_)));
+    }
+
+    #[test]
+    fn test_cash_tight_agent_buys_need_and_skips_want() {
+        let need = test_product(1, 50.0).with_essential(true);
+        let want = test_product(2, 50.0);
+        let need_factory = Factory::new(1, "need_factory".to_string(), &need);
+        let want_factory = Factory::new(2, "want_factory".to_string(), &want);
+        let midpoint = |(lower, upper): (f64, f64)| (lower + upper) / 2.0;
+        let need_price = midpoint(need_factory.supply_price_range());
+        let want_price = midpoint(want_factory.supply_price_range());
+
+        // 现金只够买必需品，再买非必需品就会跌破保留资金
+        let reserve = 100.0;
+        let config = AgentConfig {
+            demand_model: crate::config::DemandModelConfig::Bernoulli { probability: 0.0 },
+            want_cash_reserve: reserve,
+            ..Default::default()
+        };
+        let cash = reserve + need_price + want_price - 0.01;
+        let products = vec![need, want];
+        let mut agent = Agent::with_config(1, "tight".to_string(), cash, &products, &config);
+        for (product_id, price) in [(1, need_price), (2, want_price)] {
+            agent
+                .preferences
+                .write()
+                .unwrap()
+                .get_mut(&product_id)
+                .unwrap()
+                .current_range = (price, price + 0.01);
+            agent.add_demand(product_id);
+        }
+        // 保留资金之外的部分原本够买非必需品
+        assert!(cash - reserve > want_price + 0.01);
+
+        assert!(matches!(
+            agent.trade(&need_factory, 1).0,
+            TradeResult::Success(_)
+        ));
+        let cash_after_need = agent.cash();
+        assert!(matches!(agent.trade(&want_factory, 1).0, TradeResult::Failed));
+        assert_eq!(agent.cash(), cash_after_need);
+        assert!(agent.cash() >= reserve);
+    }
+
+    #[test]
+    fn test_category_budgets() {
+        let food = test_product(1, 50.0).with_essential(true);
+        let toy = test_product(2, 50.0);
+        let food_factory = Factory::new(1, "food_factory".to_string(), &food);
+        let toy_factory = Factory::new(2, "toy_factory".to_string(), &toy);
+        let config = AgentConfig {
+            demand_model: crate::config::DemandModelConfig::Bernoulli { probability: 0.0 },
+            essential_budget_share: Some(0.25),
+            income: 100.0,
+            payday_interval: 1,
+            ..Default::default()
+        };
+        let products = vec![food, toy];
+        let mut agent = Agent::with_config(1, "accountant".to_string(), 1000.0, &products, &config);
+        assert_eq!(agent.category_budget(1), 250.0);
+        assert_eq!(agent.category_budget(2), 750.0);
+
+        // 必需品账户花光后，总资金充足也买不了必需品，非必需品照常购买
+        agent.category_budgets = Some((0.0, 750.0));
+        for (factory, product_id) in [(&food_factory, 1), (&toy_factory, 2)] {
+            let (lower, upper) = factory.supply_price_range();
+            agent
+                .preferences
+                .write()
+                .unwrap()
+                .get_mut(&product_id)
+                .unwrap()
+                .current_range = (lower, upper);
+            agent.add_demand(product_id);
+        }
+        assert!(matches!(agent.trade(&food_factory, 1).0, TradeResult::Failed));
+        assert_eq!(agent.cash(), 1000.0);
+        let (result, _) = agent.trade(&toy_factory, 1);
+        let TradeResult::Success(price) = result else {
+            panic!("expected a purchase from the non-essential budget");
+        };
+        assert!((agent.category_budget(2) - (750.0 - price)).abs() < 1e-9);
+
+        // 收入按比例补充两个账户
+        agent.receive_income(1);
+        assert_eq!(agent.category_budget(1), 25.0);
+        assert!((agent.category_budget(2) - (825.0 - price)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_reset_preferences() {
+        let product = test_product(1, 50.0);
+        let products = vec![product.clone()];
+        let config = AgentConfig {
+            demand_model: crate::config::DemandModelConfig::Bernoulli { probability: 0.0 },
+            ..Default::default()
+        };
+        let mut agent = Agent::with_config(1, "learner".to_string(), 10000.0, &products, &config);
+        let factory = Factory::new(1, "factory".to_string(), &product);
+        let original_price = agent.preferences.read().unwrap()[&1].original_price;
+        let initial_range = agent.preferences.read().unwrap()[&1].current_range;
+
+        // 多次交易后区间已偏离初始值
+        for round in 1..=5 {
+            agent.add_demand(1);
+            agent.trade(&factory, round);
+        }
+        agent
+            .preferences
+            .write()
+            .unwrap()
+            .get_mut(&1)
+            .unwrap()
+            .current_range = (1.0, 2.0);
+        agent.add_demand(1);
+        let cash = agent.cash();
+
+        agent.reset_preferences();
+        let preference = agent.preferences.read().unwrap()[&1].clone();
+        assert_eq!(preference.original_price, original_price);
+        assert_eq!(preference.current_price, original_price);
+        // 区间恢复为生成偏好时的初始区间
+        assert_eq!(preference.current_range, initial_range);
+        // 现金和需求保持不变
+        assert_eq!(agent.cash(), cash);
+        assert!(agent.has_demand(1));
+
+        // 按宽度比例生成的区间同样恢复原样
+        let narrow = AgentConfig {
+            range_width: Some((0.2, 0.0)),
+            ..config
+        };
+        let mut agent = Agent::with_config(2, "narrow".to_string(), 10000.0, &products, &narrow);
+        let initial_range = agent.preferences.read().unwrap()[&1].current_range;
+        agent.set_preference_range(1, (1.0, 2.0));
+        agent.reset_preferences();
+        assert_eq!(agent.preferences.read().unwrap()[&1].current_range, initial_range);
+    }
+
+    #[test]
+    fn test_preference_correlation() {
+        let products: Vec<Product> = (1..=2).map(|id| test_product(id, 100.0)).collect();
+        // 各agent两种商品区间中点的相关系数
+        let range_correlation = |correlation: f64| {
+            let config = AgentConfig {
+                range_width: Some((0.2, 0.0)),
+                demand_model: crate::config::DemandModelConfig::Bernoulli { probability: 0.0 },
+                preference_correlation: correlation,
+                ..Default::default()
+            };
+            let pairs: Vec<(f64, f64)> = (0..200)
+                .map(|id| {
+                    let agent =
+                        Agent::with_config(id, format!("agent_{}", id), 100.0, &products, &config);
+                    let preferences = agent.preferences();
+                    let midpoint = |product_id: u64| {
+                        let (lower, upper) = preferences[&product_id].current_range;
+                        (lower + upper) / 2.0
+                    };
+                    (midpoint(1), midpoint(2))
+                })
+                .collect();
+            let n = pairs.len() as f64;
+            let mean_a = pairs.iter().map(|p| p.0).sum::<f64>() / n;
+            let mean_b = pairs.iter().map(|p| p.1).sum::<f64>() / n;
+            let cov: f64 = pairs.iter().map(|p| (p.0 - mean_a) * (p.1 - mean_b)).sum();
+            let var_a: f64 = pairs.iter().map(|p| (p.0 - mean_a).powi(2)).sum();
+            let var_b: f64 = pairs.iter().map(|p| (p.1 - mean_b).powi(2)).sum();
+            cov / (var_a * var_b).sqrt()
+        };
+
+        // 高相关度时同一agent的两个商品区间同向偏移，不相关时各自独立
+        let correlated = range_correlation(1.0);
+        let independent = range_correlation(0.0);
+        assert!(correlated > 0.8, "correlation was {}", correlated);
+        assert!(independent.abs() < 0.4, "correlation was {}", independent);
+        assert!(correlated > independent);
+    }
+
+    #[test]
+    fn test_demand_count() {
+        let products: Vec<Product> = (1..=3).map(|id| test_product(id, 10.0)).collect();
+        let config = AgentConfig {
+            demand_model: crate::config::DemandModelConfig::Bernoulli { probability: 0.0 },
+            ..Default::default()
+        };
+        let mut agent = Agent::with_config(1, "counter".to_string(), 100.0, &products, &config);
+        assert_eq!(agent.demand_count(), 0);
+
+        for product in &products {
+            agent.add_demand(product.id());
+        }
+        assert_eq!(agent.demand_count(), 3);
+
+        // 成交后该商品的需求被满足
+        let factory = Factory::new(1, "factory".to_string(), &products[0]);
+        agent.force_trade(&factory, 1, 10.0).unwrap();
+        assert_eq!(agent.demand_count(), 2);
+        assert!(!agent.has_demand(1));
+    }
+
+    #[test]
+    fn test_anchoring() {
+        let product = test_product(1, 50.0);
+        // 每轮区间整体上漂5，返回最终区间中点
+        let drift = |strength: f64| {
+            let config = AgentConfig {
+                demand_model: crate::config::DemandModelConfig::Bernoulli { probability: 0.0 },
+                anchoring_strength: strength,
+                ..Default::default()
+            };
+            let agent = Agent::with_config(
+                1,
+                "drifter".to_string(),
+                100.0,
+                std::slice::from_ref(&product),
+                &config,
+            );
+            agent.set_preference_range(1, (40.0, 60.0));
+            for _ in 0..30 {
+                let (lower, upper) = agent.preferences()[&1].current_range;
+                agent.set_preference_range(1, (lower + 5.0, upper + 5.0));
+                agent.anchor_preferences();
+            }
+            let (lower, upper) = agent.preferences()[&1].current_range;
+            assert!((upper - lower - 20.0).abs() < 1e-9);
+            (lower + upper) / 2.0
+        };
+
+        // 不锚定时随漂移一路上升，锚定后停留在参考价附近
+        assert!((drift(0.0) - 200.0).abs() < 1e-9);
+        assert!((drift(0.5) - 50.0).abs() < 10.0);
+    }
+
+    #[test]
+    fn test_demanded_products() {
+        let products: Vec<Product> = (1..=3).map(|id| test_product(id, 10.0)).collect();
+        let config = AgentConfig {
+            demand_model: crate::config::DemandModelConfig::Bernoulli { probability: 0.0 },
+            ..Default::default()
+        };
+        let agent = Agent::with_config(1, "shopper".to_string(), 100.0, &products, &config);
+        assert!(agent.demanded_products().is_empty());
+
+        agent.add_demand(3);
+        agent.add_demand(1);
+        assert_eq!(agent.demanded_products(), vec![1, 3]);
+    }
+
+    #[test]
+    fn test_payday_spending_cycle() {
+        let product = test_product(1, 10.0);
+        let config = AgentConfig {
+            demand_model: crate::config::DemandModelConfig::Bernoulli { probability: 0.0 },
+            income: 50.0,
+            payday_interval: 5,
+            payday_spending_decline: 0.8,
+            ..Default::default()
+        };
+        let mut agent = Agent::with_config(
+            1,
+            "worker".to_string(),
+            100.0,
+            std::slice::from_ref(&product),
+            &config,
+        );
+
+        // 第5轮末发薪，第6轮的消费倾向高于第9、10轮（下个发薪日之前）
+        for round in 1..=5 {
+            agent.receive_income(round);
+        }
+        assert_eq!(agent.cash(), 150.0);
+        assert_eq!(agent.spending_probability(6), 1.0);
+        assert!(agent.spending_probability(6) > agent.spending_probability(9));
+        assert!(agent.spending_probability(9) > agent.spending_probability(10));
+        assert!((agent.spending_probability(10) - 0.2).abs() < 1e-9);
+
+        // 多个agent统计：发薪后一轮尝试购买的比例高于发薪前一轮
+        let products = [product];
+        let willing = |round: u64| {
+            (1..=200)
+                .filter(|id| {
+                    let agent =
+                        Agent::with_config(*id, "worker".to_string(), 100.0, &products, &config);
+                    agent.willing_to_spend(round)
+                })
+                .count()
+        };
+        assert_eq!(willing(6), 200);
+        assert!(willing(6) > willing(10));
+    }
+
+    #[test]
+    fn test_income_random_walk() {
+        let product = test_product(1, 10.0);
+        // 相邻两次收入的相关系数
+        let lag_correlation = |persistence: f64| {
+            let config = AgentConfig {
+                demand_model: crate::config::DemandModelConfig::Bernoulli { probability: 0.0 },
+                income: 100.0,
+                income_model: IncomeModel::RandomWalk {
+                    persistence,
+                    volatility: 0.1,
+                },
+                ..Default::default()
+            };
+            let mut agent = Agent::with_config(
+                1,
+                "worker".to_string(),
+                0.0,
+                std::slice::from_ref(&product),
+                &config,
+            );
+            let incomes: Vec<f64> = (0..2000).map(|_| agent.next_income()).collect();
+            let (xs, ys) = (&incomes[..incomes.len() - 1], &incomes[1..]);
+            let n = xs.len() as f64;
+            let (mean_x, mean_y) = (xs.iter().sum::<f64>() / n, ys.iter().sum::<f64>() / n);
+            let cov: f64 = xs.iter().zip(ys).map(|(x, y)| (x - mean_x) * (y - mean_y)).sum();
+            let var_x: f64 = xs.iter().map(|x| (x - mean_x).powi(2)).sum();
+            let var_y: f64 = ys.iter().map(|y| (y - mean_y).powi(2)).sum();
+            cov / (var_x * var_y).sqrt()
+        };
+        assert!(lag_correlation(0.95) > 0.8);
+        assert!(lag_correlation(0.0).abs() < 0.1);
+
+        // 默认固定收入
+        let config = AgentConfig {
+            demand_model: crate::config::DemandModelConfig::Bernoulli { probability: 0.0 },
+            income: 100.0,
+            ..Default::default()
+        };
+        let mut agent = Agent::with_config(2, "worker".to_string(), 0.0, &[product], &config);
+        assert_eq!(agent.next_income(), 100.0);
+        agent.receive_income(1);
+        assert_eq!(agent.cash(), 100.0);
+    }
+
+    #[test]
+    fn test_product_awareness() {
+        let products: Vec<Product> = (1..=10).map(|id| test_product(id, 10.0)).collect();
+        let config = AgentConfig {
+            demand_model: crate::config::DemandModelConfig::Bernoulli { probability: 0.9 },
+            satiation_decay: 1.0,
+            product_awareness: Some(0.3),
+            ..Default::default()
+        };
+        let agent = Agent::with_config(1, "niche".to_string(), 1000.0, &products, &config);
+        let known: Vec<u64> = products
+            .iter()
+            .map(|p| p.id())
+            .filter(|id| agent.knows_product(*id))
+            .collect();
+        assert_eq!(known.len(), 3);
+        assert_eq!(agent.preferences.read().unwrap().len(), 3);
+
+        // 推荐和直接添加的不知道的商品都被忽略
+        let unknown = products.iter().map(|p| p.id()).find(|id| !known.contains(id)).unwrap();
+        agent.hear_about(unknown, 1.0);
+        agent.add_demand(unknown);
+        assert_eq!(agent.word_of_mouth(unknown), 0.0);
+
+        // 需求线程运行多次后，需求只出现在知道的商品上
+        thread::sleep(Duration::from_secs(1));
+        let demanded = agent.demanded_products();
+        assert!(!demanded.is_empty());
+        assert!(demanded.iter().all(|id| known.contains(id)));
+    }
+
+    #[test]
+    fn test_panic_sell_durables() {
+        let product = test_product(1, 90.0).with_durable(true);
+        let config = AgentConfig {
+            demand_model: crate::config::DemandModelConfig::Bernoulli { probability: 0.0 },
+            panic_sell_threshold: 20.0,
+            panic_sell_discount: 0.6,
+            ..Default::default()
+        };
+        let mut agent = Agent::with_config(
+            1,
+            "owner".to_string(),
+            100.0,
+            std::slice::from_ref(&product),
+            &config,
+        );
+        let mut factory = Factory::new(1, "furniture".to_string(), &product);
+        factory.start_round(1);
+
+        // 现金充足时不抛售
+        assert_eq!(agent.panic_sell(1), 0.0);
+
+        // 买下耐用品后现金只剩10，低于阈值，按四折卖出
+        agent.force_trade(&factory, 1, 90.0).unwrap();
+        assert_eq!(agent.holdings(1), 1);
+        assert_eq!(agent.cash(), 10.0);
+        let proceeds = agent.panic_sell(1);
+        assert!((proceeds - 36.0).abs() < 1e-9);
+        assert!((agent.cash() - 46.0).abs() < 1e-9);
+        assert_eq!(agent.holdings(1), 0);
+        assert_eq!(agent.panic_sell(2), 0.0);
+    }
+
+    #[test]
+    fn test_unreachable_range() {
+        let product = test_product(1, 60.0);
+        let stingy = |id: u64, action: UnreachableRangeAction| {
+            let config = AgentConfig {
+                demand_model: crate::config::DemandModelConfig::Bernoulli { probability: 1.0 },
+                satiation_decay: 1.0,
+                unreachable_range: action,
+                ..Default::default()
+            };
+            let agent = Agent::with_config(
+                id,
+                "stingy".to_string(),
+                1000.0,
+                std::slice::from_ref(&product),
+                &config,
+            );
+            agent.add_demand(1);
+            agent.set_preference_range(1, (0.0, 10.0));
+            agent
+        };
+
+        let floors = HashMap::from([(1, 50.0)]);
+
+        // 不处理时需求保留
+        let mut keep = stingy(1, UnreachableRangeAction::Keep);
+        assert_eq!(keep.handle_unreachable_ranges(&floors, 1), 0);
+        assert!(keep.has_demand(1));
+
+        // 删除需求后需求线程多次运行也不再产生该商品的需求
+        let mut drop = stingy(2, UnreachableRangeAction::Drop);
+        assert_eq!(drop.handle_unreachable_ranges(&floors, 1), 1);
+        assert!(drop.has_abandoned(1));
+        thread::sleep(Duration::from_millis(1000));
+        assert!(!drop.has_demand(1));
+        assert_eq!(drop.handle_unreachable_ranges(&floors, 2), 0);
+
+        // 抬高区间上界后可以与成本之上的报价相交
+        let mut widen = stingy(3, UnreachableRangeAction::Widen);
+        assert_eq!(widen.handle_unreachable_ranges(&floors, 1), 1);
+        assert!(widen.has_demand(1));
+        assert!((widen.preferences()[&1].current_range.1 - 55.0).abs() < 1e-9);
+        assert_eq!(widen.handle_unreachable_ranges(&floors, 2), 0);
+    }
+
+    #[test]
+    fn test_max_outstanding_demands() {
+        let products: Vec<Product> = (1..=6).map(|id| test_product(id, 10.0)).collect();
+        let config = AgentConfig {
+            max_outstanding_demands: Some(3),
+            initial_demands: 6.0,
+            ..Default::default()
+        };
+        let agent = Agent::with_config(1, "backlogged".to_string(), 100.0, &products, &config);
+
+        // 初始需求和需求线程都不会超过上限
+        for _ in 0..10 {
+            assert!(agent.demand_count() <= 3);
+            thread::sleep(Duration::from_millis(20));
+        }
+        for product in &products {
+            agent.add_demand(product.id());
+        }
+        assert_eq!(agent.demand_count(), 3);
+        assert!(agent.refused_demands() >= 3);
+    }
+
+    #[test]
+    fn test_bargaining_near_miss() {
+        let product = test_product(1, 50.0);
+        let factory = Factory::new(1, "seller".to_string(), &product);
+        let (factory_min, _) = factory.supply_price_range();
+
+        let try_trade = |negotiation: NegotiationMode| {
+            let config = AgentConfig {
+                demand_model: crate::config::DemandModelConfig::Bernoulli { probability: 0.0 },
+                negotiation,
+                ..Default::default()
+            };
+            let mut agent = Agent::with_config(
+                1,
+                "haggler".to_string(),
+                10000.0,
+                std::slice::from_ref(&product),
+                &config,
+            );
+            // agent的区间上界比工厂下界低2%
+            agent.set_preference_range(1, (factory_min * 0.8, factory_min * 0.98));
+            agent.add_demand(1);
+            agent.trade(&factory, 1).0
+        };
+
+        // 明码标价时差一点也无法成交
+        assert!(matches!(try_trade(NegotiationMode::Posted), TradeResult::Failed));
+
+        // 讨价还价时双方让步后在区间之间成交
+        let bargaining = NegotiationMode::Bargaining {
+            steps: 20,
+            max_concession: 0.05,
+        };
+        match try_trade(bargaining) {
+            TradeResult::Success(price) => {
+                assert!(price > factory_min * 0.98 - 0.01 && price < factory_min + 0.01);
+            }
+            _ => panic!("Expected the near-miss to settle by bargaining"),
+        }
+
+        // 差价超出双方的让步上限时仍然失败
+        let stingy = NegotiationMode::Bargaining {
+            steps: 20,
+            max_concession: 0.001,
+        };
+        assert!(matches!(try_trade(stingy), TradeResult::Failed));
+    }
+
+    #[test]
+    fn test_stockout_penalty() {
+        let products: Vec<Product> = (1..=2).map(|id| test_product(id, 50.0)).collect();
+        let config = AgentConfig {
+            stockout_penalty: 0.2,
+            stockout_recovery: 0.0,
+            ..Default::default()
+        };
+        let agent = Agent::with_config(1, "frustrated".to_string(), 100.0, &products, &config);
+
+        // 商品1长期缺货，需求概率逐步下降，商品2不受影响
+        let mut last = agent.demand_probability(1);
+        for _ in 0..3 {
+            agent.record_stockout(1);
+            let probability = agent.demand_probability(1);
+            assert!(probability < last);
+            last = probability;
+        }
+        assert!((agent.demand_probability(1) - 0.4).abs() < 1e-9);
+        assert_eq!(agent.demand_probability(2), 1.0);
+
+        // 不满随时间恢复
+        Agent::decay_levels(&agent.frustration, 0.25);
+        assert!((agent.demand_probability(1) - 0.65).abs() < 1e-9);
+        Agent::decay_levels(&agent.frustration, 1.0);
+        assert_eq!(agent.demand_probability(1), 1.0);
+
+        // 未开启惩罚时缺货没有影响
+        let calm = Agent::new(2, "calm".to_string(), 100.0, &products);
+        calm.record_stockout(1);
+        assert_eq!(calm.demand_probability(1), 1.0);
+    }
+
+    #[test]
+    fn test_acceptance_jitter() {
+        let product = test_product(1, 50.0);
+        let products = vec![product.clone()];
+        let factory = crate::model::factory::Factory::new(1, "factory".to_string(), &product);
+        let (_, factory_max) = factory.supply_price_range();
+        let config = AgentConfig {
+            acceptance_jitter: 0.1,
+            ..Default::default()
+        };
+
+        // 偏好完全相同、区间略高于工厂报价的agent，只有扰动向下的能成交
+        let mut successes = 0;
+        let mut failures = 0;
+        for id in 1..=20 {
+            let mut agent =
+                Agent::with_config(id, format!("agent_{}", id), 10000.0, &products, &config);
+            {
+                let mut preferences = agent.preferences.write().unwrap();
+                preferences.get_mut(&1).unwrap().current_range =
+                    (factory_max * 1.01, factory_max * 2.0);
+            }
+            agent.add_demand(1);
+            match agent.trade(&factory, 0).0 {
+                TradeResult::Success(_) => successes += 1,
+                _ => failures += 1,
+            }
+        }
+        assert!(successes > 0 && failures > 0);
+
+        // 同一agent的扰动可复现，未开启时没有扰动
+        let a = Agent::with_config(3, "a".to_string(), 100.0, &products, &config);
+        let b = Agent::with_config(3, "b".to_string(), 100.0, &products, &config);
+        assert_eq!(a.jitter, b.jitter);
+        assert_eq!(Agent::new(3, "c".to_string(), 100.0, &products).jitter, 0.0);
+    }
+
+    #[test]
+    fn test_price_perception_noise() {
+        let product = test_product(1, 50.0);
+        let products = vec![product.clone()];
+        let factory = crate::model::factory::Factory::new(1, "factory".to_string(), &product);
+        let (factory_min, factory_max) = factory.supply_price_range();
+
+        // 偏好上界恰好略低于工厂最低报价的边缘情况，统计多次判断的结果
+        let decide = |config: &AgentConfig| {
+            let agent = Agent::with_config(1, "agent".to_string(), 10000.0, &products, config);
+            {
+                let mut preferences = agent.preferences.write().unwrap();
+                preferences.get_mut(&1).unwrap().current_range =
+                    (factory_min * 0.5, factory_min * 0.99);
+            }
+            let mut accepted = 0;
+            for _ in 0..200 {
+                if let IntervalRelation::Overlapping((lower, upper)) =
+                    agent.match_factory(&factory, 0)
+                {
+                    // 成交价仍在工厂真实报价范围内
+                    assert!(lower >= factory_min && upper <= factory_max);
+                    accepted += 1;
+                }
+            }
+            accepted
+        };
+
+        let quiet = AgentConfig {
+            demand_model: crate::config::DemandModelConfig::Bernoulli { probability: 0.0 },
+            ..Default::default()
+        };
+        assert_eq!(decide(&quiet), 0);
+
+        let noisy = AgentConfig {
+            price_perception_noise: 0.05,
+            ..quiet.clone()
+        };
+        let accepted = decide(&noisy);
+        assert!(accepted > 0 && accepted < 200);
+    }
+
+    #[test]
+    fn test_elasticity_bounds() {
+        // 弹性分布集中在0附近的商品
+        let product = Product::from(
+            1,
+            "test_product".to_string(),
+            crate::entity::normal_distribute::NormalDistribution::new(
+                50.0,
+                1,
+                "price_dist".to_string(),
+                5.0,
+            ),
+            crate::entity::normal_distribute::NormalDistribution::new(
+                0.0,
+                1,
+                "elastic_dist".to_string(),
+                0.0001,
+            ),
+        );
+        let products = vec![product.clone()];
+        let factory = crate::model::factory::Factory::new(1, "factory".to_string(), &product);
+        let (factory_min, _) = factory.supply_price_range();
+
+        // 出价整体低于工厂报价，交易失败后按弹性决定是否放弃需求
+        let dropped = |config: &AgentConfig| {
+            let mut dropped = 0;
+            for id in 1..=50 {
+                let mut agent =
+                    Agent::with_config(id, format!("agent_{}", id), 10000.0, &products, config);
+                {
+                    let mut preferences = agent.preferences.write().unwrap();
+                    let preference = preferences.get_mut(&1).unwrap();
+                    preference.current_range = (factory_min * 0.1, factory_min * 0.2);
+                }
+                agent.add_demand(1);
+                agent.trade(&factory, 0);
+                if !agent.has_demand(1) {
+                    dropped += 1;
+                }
+            }
+            dropped
+        };
+
+        let unbounded = AgentConfig {
+            demand_model: crate::config::DemandModelConfig::Bernoulli { probability: 0.0 },
+            ..Default::default()
+        };
+        assert_eq!(dropped(&unbounded), 0);
+
+        let bounded = AgentConfig {
+            elasticity_bounds: (0.5, 1.0),
+            ..unbounded.clone()
+        };
+        let agent = Agent::with_config(1, "agent".to_string(), 100.0, &products, &bounded);
+        assert_eq!(agent.preferences().get(&1).unwrap().original_elastic, 0.5);
+        let count = dropped(&bounded);
+        assert!(count > 0 && count < 50);
+    }
+
+    #[test]
+    fn test_reservation_price_rejects_bid() {
+        let product = test_product(1, 50.0);
+        let products = vec![product.clone()];
+        let factory_config = crate::config::FactoryConfig {
+            range_init: crate::config::RangeInitStrategy::CostPlusMarkup { markup: 1.5 },
+            unit_cost: 40.0,
+            reservation_factor: 1.25,
+            ..Default::default()
+        };
+        let factory = Factory::with_config(1, "factory".to_string(), &product, &factory_config);
+        assert_eq!(factory.supply_price_range(), (40.0, 60.0));
+
+        // 出价落在报价区间内但低于保留价50，不能成交
+        let config = AgentConfig {
+            demand_model: crate::config::DemandModelConfig::Bernoulli { probability: 0.0 },
+            ..Default::default()
+        };
+        let mut agent = Agent::with_config(1, "agent".to_string(), 1000.0, &products, &config);
+        agent.preferences.write().unwrap().get_mut(&1).unwrap().current_range = (42.0, 48.0);
+        agent.add_demand(1);
+        assert!(matches!(agent.trade(&factory, 0).0, TradeResult::Failed));
+        assert_eq!(agent.cash(), 1000.0);
+
+        // 出价达到保留价时按不低于保留价的价格成交
+        agent.preferences.write().unwrap().get_mut(&1).unwrap().current_range = (48.0, 55.0);
+        agent.add_demand(1);
+        let TradeResult::Success(price) = agent.trade(&factory, 0).0 else {
+            panic!("expected a trade above the reservation price");
+        };
+        assert!(price >= 50.0);
+    }
+
+    #[test]
+    fn test_promotion_enables_trade() {
+        let product = test_product(1, 50.0);
+        let products = vec![product.clone()];
+        let factory_config = crate::config::FactoryConfig {
+            range_init: crate::config::RangeInitStrategy::CostPlusMarkup { markup: 1.5 },
+            unit_cost: 40.0,
+            ..Default::default()
+        };
+        let mut factory = Factory::with_config(1, "factory".to_string(), &product, &factory_config);
+        factory.start_round(0);
+        let config = AgentConfig {
+            demand_model: crate::config::DemandModelConfig::Bernoulli { probability: 0.0 },
+            elasticity_bounds: (0.0, 0.0),
+            ..Default::default()
+        };
+        let mut agent = Agent::with_config(1, "agent".to_string(), 1000.0, &products, &config);
+
+        // 区间(28, 35)低于报价区间(40, 60)，不打折时无法成交
+        agent.preferences.write().unwrap().get_mut(&1).unwrap().current_range = (28.0, 35.0);
+        agent.add_demand(1);
+        assert!(matches!(agent.trade(&factory, 0).0, TradeResult::Failed));
+
+        // 打五折后成交区间为(20, 30)，与agent的区间重叠，按折后价格成交
+        factory.start_promotion(0.5, 1);
+        assert_eq!(factory.asking_range(), (20.0, 30.0));
+        agent.preferences.write().unwrap().get_mut(&1).unwrap().current_range = (28.0, 35.0);
+        agent.add_demand(1);
+        let TradeResult::Success(price) = agent.trade(&factory, 0).0 else {
+            panic!("expected the promotion to enable a trade");
+        };
+        assert!((28.0..=30.0).contains(&price));
+    }
+
+    #[test]
+    fn test_seasonality() {
+        let seasonality = Seasonality {
+            period: 8,
+            peak: 2,
+            essential_amplitude: 0.0,
+            non_essential_amplitude: 0.8,
+        };
+        assert!((seasonality.factor(false, 2) - 1.0).abs() < 1e-9);
+        assert!((seasonality.factor(false, 10) - 1.0).abs() < 1e-9);
+        assert!((seasonality.factor(false, 6) - 0.2).abs() < 1e-9);
+        assert_eq!(seasonality.factor(true, 6), 1.0);
+
+        let product = test_product(1, 50.0);
+        let config = AgentConfig {
+            demand_model: crate::config::DemandModelConfig::Bernoulli { probability: 1.0 },
+            round_synced_demand: true,
+            seasonality: Some(seasonality),
+            ..Default::default()
+        };
+        let agent = Agent::with_config(1, "agent".to_string(), 100.0, &[product], &config);
+
+        // 旺季每次都产生需求，淡季只有约两成
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut demands_at = |round: u64| {
+            agent.start_round(round);
+            let mut count = 0;
+            for _ in 0..200 {
+                agent.demand.write().unwrap().clear();
+                agent.generate_demands(&mut rng);
+                count += agent.demand_count();
+            }
+            count
+        };
+        let peak = demands_at(2);
+        let trough = demands_at(6);
+        assert_eq!(peak, 200);
+        assert!(trough > 0 && trough < 100);
+    }
+
+    #[test]
+    fn test_satiation() {
+        let product = test_product(1, 50.0);
+        let products = vec![product.clone()];
+        let config = AgentConfig {
+            satiation_per_purchase: 0.9,
+            // 由测试手动消退，避免需求线程干扰
+            satiation_decay: 0.0,
+            ..Default::default()
+        };
+        let mut agent = Agent::with_config(1, "sated".to_string(), 1000.0, &products, &config);
+        {
+            let mut preferences = agent.preferences.write().unwrap();
+            preferences.get_mut(&1).unwrap().current_range = (0.0, 1000.0);
+        }
+        let factory = crate::model::factory::Factory::new(1, "factory".to_string(), &product);
+        assert_eq!(agent.demand_probability(1), 1.0);
+
+        // 刚买过的商品再次产生需求的概率大幅下降
+        agent.add_demand(1);
+        assert!(matches!(agent.trade(&factory, 1).0, TradeResult::Success(_)));
+        assert!((agent.demand_probability(1) - 0.1).abs() < 1e-9);
+
+        // 满足程度逐步消退，需求概率逐步恢复
+        let mut last = agent.demand_probability(1);
+        for _ in 0..4 {
+            Agent::decay_levels(&agent.satiation, 0.2);
+            let probability = agent.demand_probability(1);
+            assert!(probability > last);
+            last = probability;
+        }
+        Agent::decay_levels(&agent.satiation, 0.2);
+        assert_eq!(agent.demand_probability(1), 1.0);
+    }
+
+    #[test]
+    fn test_initial_demands() {
+        let products: Vec<Product> = (1..=3).map(|id| test_product(id, 50.0)).collect();
+        // 关闭需求线程的到达，只看初始需求
+        let quiet = AgentConfig {
+            demand_model: crate::config::DemandModelConfig::Bernoulli { probability: 0.0 },
+            ..Default::default()
+        };
+        let seeded = AgentConfig {
+            initial_demands: 3.0,
+            lazy_preferences: true,
+            ..quiet.clone()
+        };
+
+        let agent = Agent::with_config(1, "seeded".to_string(), 100.0, &products, &seeded);
+        for product in &products {
+            assert!(agent.has_demand(product.id()));
+            // 懒加载时初始需求也会生成preference
+            assert!(agent.preferences().contains_key(&product.id()));
+        }
+
+        let agent = Agent::with_config(2, "quiet".to_string(), 100.0, &products, &quiet);
+        assert!(products.iter().all(|p| !agent.has_demand(p.id())));
+    }
+
+    #[test]
+    fn test_credit_limit() {
+        let product = test_product(1, 50.0);
+        let products = vec![product.clone()];
+        let mut factory = crate::model::factory::Factory::new(1, "factory".to_string(), &product);
+        factory.start_round(1);
+
+        // 没有授信时现金不足无法购买
+        let mut cash_only = Agent::new(1, "cash_only".to_string(), 10.0, &products);
+        assert!(cash_only.force_trade(&factory, 1, 50.0).is_err());
+        assert_eq!(cash_only.cash(), 10.0);
+
+        let config = AgentConfig {
+            credit_limit: 100.0,
+            credit_interest_rate: 0.1,
+            ..Default::default()
+        };
+        let mut borrower = Agent::with_config(2, "borrower".to_string(), 10.0, &products, &config);
+        borrower.force_trade(&factory, 1, 50.0).unwrap();
+        // 先用完现金，不足部分记为借款
+        assert_eq!(borrower.cash(), 0.0);
+        assert!((borrower.debt() - 40.0).abs() < 1e-9);
+
+        // 每轮计息
+        borrower.accrue_interest();
+        assert!((borrower.debt() - 44.0).abs() < 1e-9);
+        assert!((borrower.interest_accrued() - 4.0).abs() < 1e-9);
+
+        // 超出剩余额度时不能再借
+        assert!(borrower.force_trade(&factory, 1, 60.0).is_err());
+        assert!((borrower.debt() - 44.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_income_repays_debt() {
+        let product = test_product(1, 50.0);
+        let products = vec![product.clone()];
+        let mut factory = Factory::new(1, "factory".to_string(), &product);
+        factory.start_round(1);
+        let config = AgentConfig {
+            demand_model: crate::config::DemandModelConfig::Bernoulli { probability: 0.0 },
+            credit_limit: 100.0,
+            income: 30.0,
+            payday_interval: 1,
+            ..Default::default()
+        };
+        let mut borrower = Agent::with_config(1, "borrower".to_string(), 10.0, &products, &config);
+        borrower.force_trade(&factory, 1, 50.0).unwrap();
+        assert!((borrower.debt() - 40.0).abs() < 1e-9);
+        // 现金用完但还有授信额度，仍可支付
+        assert_eq!(borrower.cash(), 0.0);
+        assert!((borrower.spendable() - 60.0).abs() < 1e-9);
+
+        // 收入先还清借款，剩余部分计入现金
+        borrower.receive_income(1);
+        assert!((borrower.debt() - 10.0).abs() < 1e-9);
+        assert_eq!(borrower.cash(), 0.0);
+        borrower.receive_income(2);
+        assert_eq!(borrower.debt(), 0.0);
+        assert!((borrower.cash() - 20.0).abs() < 1e-9);
+
+        // 分心理账户时，还款部分不计入账户，账户只增加还款后的收入
+        let budgeted = AgentConfig {
+            essential_budget_share: Some(0.5),
+            ..config
+        };
+        let mut borrower =
+            Agent::with_config(2, "budgeted".to_string(), 10.0, &products, &budgeted);
+        borrower.force_trade(&factory, 1, 50.0).unwrap();
+        let budget = borrower.category_budget(1);
+        borrower.receive_income(1);
+        assert!((borrower.debt() - 10.0).abs() < 1e-9);
+        assert_eq!(borrower.category_budget(1), budget);
+        borrower.receive_income(2);
+        assert_eq!(borrower.debt(), 0.0);
+        assert!((borrower.category_budget(1) - (budget + 10.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_subscription() {
+        let product = test_product(1, 50.0).with_subscription_rounds(3);
+        let mut factory = Factory::new(7, "publisher".to_string(), &product);
+        factory.start_round(1);
+        let config = AgentConfig {
+            demand_model: crate::config::DemandModelConfig::Bernoulli { probability: 0.0 },
+            ..Default::default()
+        };
+        let products = vec![product];
+        let mut agent = Agent::with_config(1, "reader".to_string(), 100.0, &products, &config);
+        agent.force_trade(&factory, 1, 30.0).unwrap();
+        assert_eq!(agent.subscription(1), Some((7, 30.0, 2)));
+        // 成交当轮不重复续购
+        assert!(agent.due_subscriptions(1).is_empty());
+
+        // 订阅期内每轮按同一价格付款，工厂每轮收到同样的收入
+        for round in 2..=3 {
+            factory.start_round(round);
+            assert_eq!(agent.due_subscriptions(round), vec![(1, 7, 30.0)]);
+            let cash_before = agent.cash();
+            let revenue_before = factory.revenue();
+            assert!(agent.renew_subscription(1, round));
+            assert!(factory.deliver_subscription(30.0, round));
+            assert!((cash_before - agent.cash() - 30.0).abs() < 1e-9);
+            assert!((factory.revenue() - revenue_before - 30.0).abs() < 1e-9);
+            assert_eq!(factory.get_stock(round), 9);
+        }
+        // 期满后订阅结束
+        assert!(agent.subscription(1).is_none());
+        assert!(agent.due_subscriptions(4).is_empty());
+
+        // 资金不足时取消订阅
+        factory.start_round(5);
+        agent.force_trade(&factory, 5, 8.0).unwrap();
+        assert!((agent.cash() - 2.0).abs() < 1e-9);
+        assert!(!agent.renew_subscription(1, 6));
+        assert!(agent.subscription(1).is_none());
+        assert!((agent.cash() - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_max_price_growth() {
+        let product = test_product(1, 50.0);
+        let factory = Factory::new(1, "landlord".to_string(), &product);
+        let config = AgentConfig {
+            demand_model: crate::config::DemandModelConfig::Bernoulli { probability: 0.0 },
+            elasticity_bounds: (0.0, 0.0),
+            max_price_growth: Some(1.2),
+            ..Default::default()
+        };
+        let mut agent = Agent::with_config(1, "tenant".to_string(), 1000.0, &[product], &config);
+        let original_price = agent.preferences.read().unwrap()[&1].original_price;
+        let ceiling = (original_price * 1.2 * 100.0).round() / 100.0;
+        let start = (
+            (original_price * 90.0).round() / 100.0,
+            (original_price * 100.0).round() / 100.0,
+        );
+        agent.preferences.write().unwrap().get_mut(&1).unwrap().current_range = start;
+
+        // 反复因报价太高而失败，区间持续上移，但上界停在心理价位的1.2倍
+        for round in 1..=200 {
+            agent.handle_trade_failure(&factory, 1, round, true);
+            let (lower, upper) = agent.preferences.read().unwrap()[&1].current_range;
+            assert!(upper <= ceiling + 1e-9);
+            assert!(lower < upper);
+        }
+        let (_, upper) = agent.preferences.read().unwrap()[&1].current_range;
+        assert!((upper - ceiling).abs() < 1e-9);
+
+        // 上移会越过上限时整个区间只平移到上界触顶，宽度不变
+        let near = (ceiling - 30.0, ceiling - 0.5);
+        agent.preferences.write().unwrap().get_mut(&1).unwrap().current_range = near;
+        agent.handle_trade_failure(&factory, 1, 201, true);
+        let (lower, upper) = agent.preferences.read().unwrap()[&1].current_range;
+        assert!((upper - ceiling).abs() < 1e-9);
+        assert!(((upper - lower) - (near.1 - near.0)).abs() < 1e-6);
+
+        // 已在上限之上的区间不再上移，下移不受限制
+        let above = (ceiling + 10.0, ceiling + 20.0);
+        agent.preferences.write().unwrap().get_mut(&1).unwrap().current_range = above;
+        agent.handle_trade_failure(&factory, 1, 202, true);
+        assert_eq!(agent.preferences.read().unwrap()[&1].current_range, above);
+        agent.handle_trade_failure(&factory, 1, 203, false);
+        let (_, upper) = agent.preferences.read().unwrap()[&1].current_range;
+        assert!(upper < above.1);
+    }
 }
@@ -0,0 +1,107 @@
+use crate::config::DemandModelConfig;
+use rand::{Rng, RngCore};
+use rand_distr::{Distribution, Poisson as PoissonDistribution};
+
+/// 需求生成模型：每个tick产生新的需求到达
+pub trait DemandModel: Send + Sync {
+    /// 本tick到达的需求，每次到达对应一个商品ID（同一商品可能出现多次）
+    fn arrivals(&self, product_ids: &[u64], rng: &mut dyn RngCore) -> Vec<u64>;
+}
+
+/// 伯努利到达：每个tick随机挑选一个商品，以probability的概率产生需求。
+/// probability为1时即原来每个tick必然产生一个需求的行为
+pub struct Bernoulli {
+    probability: f64,
+}
+
+impl Bernoulli {
+    pub fn new(probability: f64) -> Self {
+        Bernoulli {
+            probability: probability.clamp(0.0, 1.0),
+        }
+    }
+}
+
+impl DemandModel for Bernoulli {
+    fn arrivals(&self, product_ids: &[u64], rng: &mut dyn RngCore) -> Vec<u64> {
+        if product_ids.is_empty() || !rng.gen_bool(self.probability) {
+            return Vec::new();
+        }
+        vec![product_ids[rng.gen_range(0..product_ids.len())]]
+    }
+}
+
+/// 泊松到达：每个商品每个tick的到达次数服从参数为rate的泊松分布
+pub struct Poisson {
+    distribution: Option<PoissonDistribution<f64>>,
+}
+
+impl Poisson {
+    pub fn new(rate: f64) -> Self {
+        // rate不为正时没有到达
+        Poisson {
+            distribution: PoissonDistribution::new(rate).ok(),
+        }
+    }
+}
+
+impl DemandModel for Poisson {
+    fn arrivals(&self, product_ids: &[u64], rng: &mut dyn RngCore) -> Vec<u64> {
+        let Some(distribution) = &self.distribution else {
+            return Vec::new();
+        };
+        let mut arrivals = Vec::new();
+        for product_id in product_ids {
+            let count = distribution.sample(rng) as usize;
+            arrivals.extend(std::iter::repeat_n(*product_id, count));
+        }
+        arrivals
+    }
+}
+
+/// 根据配置创建需求模型
+pub fn build_demand_model(config: &DemandModelConfig) -> Box<dyn DemandModel> {
+    match config {
+        DemandModelConfig::Bernoulli { probability } => Box::new(Bernoulli::new(*probability)),
+        DemandModelConfig::Poisson { rate } => Box::new(Poisson::new(*rate)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_poisson_arrival_rate() {
+        let model = Poisson::new(0.3);
+        let product_ids = vec![1, 2, 3, 4];
+        let mut rng = rand::thread_rng();
+
+        let ticks = 20000;
+        let total: usize = (0..ticks)
+            .map(|_| model.arrivals(&product_ids, &mut rng).len())
+            .sum();
+        let observed_rate = total as f64 / (ticks * product_ids.len()) as f64;
+        assert!(
+            (observed_rate - 0.3).abs() < 0.02,
+            "Observed arrival rate {} should be close to 0.3",
+            observed_rate
+        );
+    }
+
+    #[test]
+    fn test_bernoulli_arrivals() {
+        let product_ids = vec![1, 2, 3];
+        let mut rng = rand::thread_rng();
+
+        let always = Bernoulli::new(1.0);
+        for _ in 0..100 {
+            let arrivals = always.arrivals(&product_ids, &mut rng);
+            assert_eq!(arrivals.len(), 1);
+            assert!(product_ids.contains(&arrivals[0]));
+        }
+
+        let never = Bernoulli::new(0.0);
+        assert!(never.arrivals(&product_ids, &mut rng).is_empty());
+    }
+}
@@ -1,43 +1,184 @@
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 use crate::model::product::Product;
 
+/// agent对某个商品的偏好
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Preference {
+    /// 心理价位，由商品价格分布抽样得到，不随交易变化
     pub original_price: f64,
+    /// 价格弹性(0~1)，越大越容易在交易失败时放弃需求
     pub original_elastic: f64,
+    /// 最近一次成交价格，初始为心理价位
     pub(crate) current_price: f64,
+    /// 当前可接受的价格区间，初始时包含心理价位
     pub(crate) current_range: (f64, f64),
 }
 
 impl Preference {
+    /// 以心理价位的0.5~1.5倍作为初始区间
     pub fn new(original_price: f64, original_elastic: f64) -> Self {
         Preference {
             original_price,
             original_elastic,
-            current_price: 0.0,
-            current_range: (0.0, 0.0),
+            current_price: original_price,
+            current_range: Self::initial_range(original_price, original_price, None),
         }
     }
     
-    pub fn from_product(product: &Product) -> Self {
+    /// 商品的默认初始区间：以价格分布均值（期望价格）为中心的0.5~1.5倍，与new的约定一致
+    pub fn default_range_for(product: &Product) -> (f64, f64) {
+        let expected_price = product.original_price_distribution().mean();
+        Self::initial_range(expected_price, expected_price, None)
+    }
+
+    /// 生成偏好和reset共用的初始区间：width_ratio为None时取期望价格的0.5~1.5倍，
+    /// 心理价位落在其外时扩展到包含心理价位；否则以心理价位为中心按比例取宽度
+    pub fn initial_range(
+        expected_price: f64,
+        original_price: f64,
+        width_ratio: Option<f64>,
+    ) -> (f64, f64) {
+        match width_ratio {
+            Some(ratio) => {
+                let half_width = original_price * ratio.max(0.0) / 2.0;
+                ((original_price - half_width).max(0.0), original_price + half_width)
+            }
+            None => {
+                let expected_price = expected_price.max(0.0);
+                (
+                    (expected_price * 0.5).min(original_price),
+                    (expected_price * 1.5).max(original_price),
+                )
+            }
+        }
+    }
+
+    /// 使用指定的随机数生成器从商品的分布抽样生成偏好，便于用固定种子复现
+    pub fn from_product_with<R: Rng + ?Sized>(product: &Product, rng: &mut R) -> Self {
         // 使用产品的价格分布生成原始价格
-        let original_price = product.original_price_distribution().sample(Some((0.0,1000000.0)));
+        let original_price = product.original_price_distribution().sample_with(rng, Some((0.0,1000000.0)));
         // 使用产品的弹性分布生成原始弹性，并限制在0~1之间
-        let original_elastic = product.original_elastic_distribution().sample(Some((0.0, 1.0)));
-        
-        // 随机生成current_range，min随机(0.0到max*0.5)，max随机(min到max*1.5)
-        let mut rng = rand::thread_rng();
-        let base_max = original_price * 1.5;
-        // 下限范围：0.0到base_max的一半
-        let min = rng.gen_range(0.0..(base_max * 0.5));
-        // 上限范围：下限到base_max
-        let max = rng.gen_range(min..base_max);
-        let current_range = (min, max);
+        let original_elastic = product.original_elastic_distribution().sample_with(rng, Some((0.0, 1.0)));
         
-        Preference {
-            original_price,
-            original_elastic,
-            current_price: original_price,
-            current_range,
+        // 初始区间取商品的默认区间，心理价位落在默认区间外时扩展到包含心理价位
+        let (lower, upper) = Self::default_range_for(product);
+        let mut preference = Self::new(original_price, original_elastic);
+        preference.current_range = (lower.min(original_price), upper.max(original_price));
+        preference
+    }
+
+    /// 以原始价格为中心、按给定宽度比例生成初始区间
+    /// width_ratio为区间总宽度占原始价格的比例，越小越挑剔
+    pub fn from_product_with_width<R: Rng + ?Sized>(
+        product: &Product,
+        width_ratio: f64,
+        rng: &mut R,
+    ) -> Self {
+        let mut preference = Self::from_product_with(product, rng);
+        preference.reset(0.0, Some(width_ratio));
+        preference
+    }
+
+    /// 按agent的消费倾向缩放：价格和区间乘以factor，弹性反向调整，
+    /// factor大于1的agent愿意出更高的价、对价格更不敏感
+    pub fn with_spending_factor(mut self, factor: f64) -> Self {
+        if factor == 1.0 {
+            return self;
         }
+        self.original_price *= factor;
+        self.current_price *= factor;
+        self.current_range = (self.current_range.0 * factor, self.current_range.1 * factor);
+        self.original_elastic = (self.original_elastic * (2.0 - factor)).clamp(0.0, 1.0);
+        self
+    }
+
+    /// 把原始弹性限制在[min, max]内，避免弹性为0的商品失败后永远不放弃需求
+    pub fn with_elasticity_bounds(mut self, (min, max): (f64, f64)) -> Self {
+        self.original_elastic = self.original_elastic.clamp(min, max);
+        self
+    }
+
+    /// 清除交易中学到的调整，当前价格恢复为心理价位，区间按initial_range重新推出，
+    /// 与生成偏好时的区间一致。expected_price为按消费倾向缩放后的商品期望价格
+    pub fn reset(&mut self, expected_price: f64, width_ratio: Option<f64>) {
+        self.current_price = self.original_price;
+        self.current_range = Self::initial_range(expected_price, self.original_price, width_ratio);
+    }
+
+    /// 随报价水平变化的有效弹性：报价在区间内越靠上（或高于区间）越敏感
+    /// slope为0时等于原始弹性，结果限制在0~1之间
+    pub fn effective_elastic(&self, offered_price: f64, slope: f64) -> f64 {
+        let (min, max) = self.current_range;
+        let position = if max > min {
+            ((offered_price - min) / (max - min)).max(0.0)
+        } else if offered_price > max {
+            1.0
+        } else {
+            0.0
+        };
+        (self.original_elastic * (1.0 + slope * position)).clamp(0.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::normal_distribute::NormalDistribution;
+
+    #[test]
+    fn test_initial_range_contains_original_price() {
+        let preference = Preference::new(40.0, 0.2);
+        assert_eq!(preference.current_range, (20.0, 60.0));
+        assert_eq!(preference.current_price, 40.0);
+
+        let product = Product::from(
+            1,
+            "test_product".to_string(),
+            NormalDistribution::new(40.0, 1, "price".to_string(), 10.0),
+            NormalDistribution::new(0.3, 1, "elastic".to_string(), 0.1),
+        );
+        for _ in 0..100 {
+            let preference = Preference::from_product_with(&product, &mut rand::thread_rng());
+            let (min, max) = preference.current_range;
+            assert!(min <= preference.original_price && preference.original_price <= max);
+            assert_eq!(preference.current_price, preference.original_price);
+        }
+    }
+
+    #[test]
+    fn test_default_range_for_product() {
+        let product = Product::from(
+            1,
+            "test_product".to_string(),
+            NormalDistribution::new(40.0, 1, "price".to_string(), 4.0),
+            NormalDistribution::new(0.3, 1, "elastic".to_string(), 0.1),
+        );
+        assert_eq!(Preference::default_range_for(&product), (20.0, 60.0));
+
+        // 心理价位在默认区间内时，初始区间以期望价格为中心
+        for _ in 0..100 {
+            let preference = Preference::from_product_with(&product, &mut rand::thread_rng());
+            if (20.0..=60.0).contains(&preference.original_price) {
+                assert_eq!(preference.current_range, (20.0, 60.0));
+            }
+            let (min, max) = preference.current_range;
+            assert!(min <= 40.0 && 40.0 <= max);
+        }
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let mut preference = Preference::new(40.0, 0.2);
+        preference.current_price = 42.5;
+        preference.current_range = (35.0, 48.0);
+
+        let json = serde_json::to_string(&preference).unwrap();
+        let restored: Preference = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(restored.original_price, 40.0);
+        assert_eq!(restored.original_elastic, 0.2);
+        assert_eq!(restored.current_price, 42.5);
+        assert_eq!(restored.current_range, (35.0, 48.0));
     }
 }
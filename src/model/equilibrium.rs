@@ -0,0 +1,120 @@
+use crate::model::product::Product;
+
+/// 计算理论均衡所需的参数
+#[derive(Clone, Debug)]
+pub struct EquilibriumParams {
+    /// 单位生产成本，完全竞争下工厂以成本价供货
+    pub unit_cost: f64,
+    /// 参考价格：消费者在该价格下的需求量为consumers
+    pub reference_price: f64,
+    /// 需求价格弹性（常弹性需求曲线）
+    pub elasticity: f64,
+    pub factory_count: usize,
+    /// 每家工厂每轮的产能
+    pub capacity_per_factory: f64,
+    /// 参考价格下每轮的需求量
+    pub consumers: f64,
+}
+
+impl EquilibriumParams {
+    /// 用商品的价格均值和弹性均值作为参考价格和弹性，产能和消费者数量与模拟一致
+    pub fn from_product(product: &Product, unit_cost: f64, factory_count: usize) -> Self {
+        EquilibriumParams {
+            unit_cost,
+            reference_price: product.original_price_distribution().mean(),
+            elasticity: product.original_elastic_distribution().mean(),
+            factory_count,
+            capacity_per_factory: 10.0,
+            consumers: 100.0,
+        }
+    }
+
+    /// 常弹性需求曲线：Q = consumers * (P / reference_price)^(-elasticity)
+    pub fn demand_at(&self, price: f64) -> f64 {
+        self.consumers * (price / self.reference_price).powf(-self.elasticity)
+    }
+
+    pub fn total_capacity(&self) -> f64 {
+        self.factory_count as f64 * self.capacity_per_factory
+    }
+}
+
+/// 理论竞争均衡
+#[derive(Clone, Debug, PartialEq)]
+pub struct Equilibrium {
+    pub price: f64,
+    pub quantity: f64,
+}
+
+/// 计算单个商品的理论竞争均衡，与随机模拟无关
+/// 供给曲线在总产能以内按成本价水平供给，达到总产能后垂直；
+/// 成本价下的需求不超过总产能时，均衡价格为成本价，否则价格上升到需求恰好等于总产能。
+/// 弹性不为正且需求超过产能时价格没有上限，返回None
+pub fn competitive_equilibrium(params: &EquilibriumParams) -> Option<Equilibrium> {
+    if params.reference_price <= 0.0 || params.unit_cost <= 0.0 {
+        return None;
+    }
+
+    let capacity = params.total_capacity();
+    let demand_at_cost = params.demand_at(params.unit_cost);
+    if demand_at_cost <= capacity {
+        return Some(Equilibrium {
+            price: params.unit_cost,
+            quantity: demand_at_cost,
+        });
+    }
+
+    if params.elasticity <= 0.0 || capacity <= 0.0 {
+        return None;
+    }
+
+    // 反解需求曲线：capacity = consumers * (P / P0)^(-e)
+    let price =
+        params.reference_price * (params.consumers / capacity).powf(1.0 / params.elasticity);
+    Some(Equilibrium {
+        price,
+        quantity: capacity,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(unit_cost: f64, elasticity: f64) -> EquilibriumParams {
+        EquilibriumParams {
+            unit_cost,
+            reference_price: 20.0,
+            elasticity,
+            factory_count: 4,
+            capacity_per_factory: 10.0,
+            consumers: 100.0,
+        }
+    }
+
+    #[test]
+    fn test_capacity_bound_equilibrium() {
+        // 成本价10时需求200 > 产能40，价格升到 20 * (100/40)^1 = 50
+        let eq = competitive_equilibrium(&params(10.0, 1.0)).unwrap();
+        assert!((eq.price - 50.0).abs() < 1e-9);
+        assert!((eq.quantity - 40.0).abs() < 1e-9);
+
+        // 弹性2时价格为 20 * sqrt(2.5)
+        let eq = competitive_equilibrium(&params(30.0, 2.0)).unwrap();
+        assert!((eq.price - 20.0 * 2.5f64.sqrt()).abs() < 1e-9);
+        assert!((eq.quantity - 40.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_cost_bound_equilibrium() {
+        // 成本价40时需求 100 * 2^-2 = 25，不超过产能，按成本价成交
+        let eq = competitive_equilibrium(&params(40.0, 2.0)).unwrap();
+        assert_eq!(eq.price, 40.0);
+        assert!((eq.quantity - 25.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_inelastic_demand_over_capacity() {
+        assert!(competitive_equilibrium(&params(10.0, 0.0)).is_none());
+    }
+}
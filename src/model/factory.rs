@@ -1,9 +1,86 @@
-use crate::logging::log_factory_range_optimization;
+use crate::config::{FactoryConfig, RangeInitStrategy};
+use crate::entity::normal_distribute::NormalDistribution;
+use crate::logging::{Logger, log_factory_range_optimization};
 use crate::model::agent::{IntervalRelation, TradeResult};
 use crate::model::product::Product;
 use rand::Rng;
-use std::collections::{HashMap, LinkedList};
-use crate::model::util::shift_range_by_ratio;
+use std::collections::{HashMap, LinkedList, VecDeque};
+use std::fmt;
+use crate::model::util::{round_to_nearest_cent, shift_range_by_ratio};
+
+/// 工厂清算结算单
+#[derive(Clone, Debug)]
+pub struct FinancialBill {
+    pub factory_id: u64,
+    pub round: u64,
+    /// 清算前的剩余库存
+    pub opening_stock: i16,
+    /// 清算时核销的库存
+    pub written_off_stock: i16,
+    /// 清算后的库存
+    pub ending_stock: i16,
+    /// 本轮产量占最大产能的比例，不限产能时为None
+    pub capacity_utilization: Option<f64>,
+    /// 累计税后销售收入
+    pub revenue: f64,
+    /// 累计缴纳的生产者税
+    pub tax_paid: f64,
+}
+
+/// 工厂诊断信息，用于排查长期不成交的工厂
+#[derive(Clone, Debug)]
+pub struct FactoryDiagnostics {
+    pub factory_id: u64,
+    pub name: String,
+    pub product_id: u64,
+    pub round: u64,
+    pub supply_price_range: (f64, f64),
+    /// 当前报价：报价区间的中点，按库存稀缺程度上下浮动
+    pub offer_price: f64,
+    pub stock: i16,
+    pub max_capacity: Option<i16>,
+    pub capacity_utilization: Option<f64>,
+    /// 最近几轮的剩余库存(轮次, 库存)，按轮次从早到晚
+    pub recent_stock: Vec<(u64, i16)>,
+    /// 当前单位规格，1表示未缩水
+    pub unit_size: f64,
+}
+
+impl fmt::Display for FactoryDiagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Factory {} ({}) product {} @ round {}",
+            self.factory_id, self.name, self.product_id, self.round
+        )?;
+        writeln!(
+            f,
+            "  supply range: [{:.2}, {:.2}], offer price: {:.2}",
+            self.supply_price_range.0, self.supply_price_range.1, self.offer_price
+        )?;
+        writeln!(
+            f,
+            "  stock: {}, max capacity: {:?}, utilization: {:?}",
+            self.stock, self.max_capacity, self.capacity_utilization
+        )?;
+        writeln!(f, "  recent stock: {:?}", self.recent_stock)?;
+        write!(f, "  unit size: {:.3}", self.unit_size)
+    }
+}
+
+/// 每轮的计划产量
+const ROUND_PRODUCTION: i16 = 10;
+
+/// 单位规格历史保留的轮数
+const UNIT_SIZE_HISTORY: usize = 100;
+
+/// 进行中的促销：报价按discount比例打折，持续rounds_left轮
+#[derive(Clone, Debug)]
+struct Promotion {
+    discount: f64,
+    rounds_left: u64,
+    started: bool, // 促销期的第一轮是否已开始，开始前的start_round不扣减剩余轮数
+}
 
 #[derive(Clone)]
 pub struct Factory {
@@ -13,29 +90,59 @@ pub struct Factory {
     supply_price_range: (f64, f64),
     amount: HashMap<u64, i16>,
     u64_list: LinkedList<u64>,
+    max_capacity: Option<i16>, // 每轮最大产能，None表示不限
+    scarcity_weight: f64,      // 报价对库存的敏感度(0~1)
+    tax_rate: f64,             // 商品的生产者税率
+    price_increase_ratio: f64, // 成交或报价过低时区间上移的比例
+    price_decrease_ratio: f64, // 交易失败时区间下移的比例
+    min_range_width: f64,      // 报价区间的最小宽度
+    revenue: f64,              // 累计税后销售收入
+    tax_paid: f64,             // 累计缴纳的生产者税
+    dividend_ratio: f64,       // 每轮分红占留存收入超出保留额部分的比例
+    dividend_reserve: f64,     // 分红时工厂至少保留的留存收入
+    dividends_paid: f64,       // 累计分红
+    shrink_ratio: f64,         // 成交时缩小单位规格的比例，0表示直接涨价
+    min_unit_size: f64,        // 单位规格的下限，缩到下限后恢复涨价
+    unit_size: f64,            // 当前单位规格，1表示未缩水
+    unit_size_history: VecDeque<(u64, f64)>, // 最近每轮开始时的单位规格(轮次, 规格)
+    promotion: Option<Promotion>, // 临时降价，不改变报价区间
+    max_consecutive_increases: Option<u32>, // 连续成交涨价的次数上限
+    consecutive_increases: u32,   // 自上次交易失败以来成交涨价的次数
+    unit_cost: f64,               // 单位成本，降价时区间下界不低于该值
+    reservation_factor: f64,      // 保留价为单位成本乘以该系数，0表示没有保留价
+    price_ceiling: Option<f64>,   // 区间上移时上界的上限，None表示不限
+    adaptive_step_window: usize,  // 自适应调价参考的最近交易结果数，0表示不启用
+    adaptive_step_bounds: (f64, f64), // 自适应调价的倍数范围(最小, 最大)
+    recent_outcomes: VecDeque<bool>,  // 最近的交易结果，true表示失败
+    logger: Option<Logger>,           // 所属市场的日志记录器，None时不记录日志
 }
 
 impl Factory {
     pub fn new(id: u64, name: String, product: &Product) -> Self {
-        // 根据商品的价格正态分布，随机生成一个参考价格点
-        let reference_price = product.original_price_distribution().sample(None);
-
-        // 随机生成区间，上下界都是随机的，下界不能小于0.0
-        let mut rng = rand::thread_rng();
-
-        // 处理特殊情况，确保reference_price是有限值
-        let reference_price = reference_price.min(f64::MAX / 2.0);
-
-        // 计算区间范围，基于参考价格
-        let range_scale = reference_price * 0.5;
+        Self::with_config(id, name, product, &FactoryConfig::default())
+    }
 
-        // 生成下界：0.0到reference_price
-        let lower = rng.gen_range(0.0..reference_price);
+    pub fn with_config(id: u64, name: String, product: &Product, config: &FactoryConfig) -> Self {
+        Self::with_rng(id, name, product, config, &mut rand::thread_rng())
+    }
 
-        // 确保upper_bound是有限值
-        let upper_bound = (reference_price + range_scale).min(f64::MAX / 2.0);
-        // 生成上界：lower到upper_bound
-        let upper = rng.gen_range(lower..upper_bound);
+    /// 使用指定的随机数生成器创建工厂，固定种子时工厂区间可复现
+    pub fn with_rng<R: Rng + ?Sized>(
+        id: u64,
+        name: String,
+        product: &Product,
+        config: &FactoryConfig,
+        rng: &mut R,
+    ) -> Self {
+        let (lower, upper) = Self::initial_range(product, config, rng);
+
+        // 每个工厂抽样一次最大产能，至少能生产1件
+        let max_capacity = config.max_capacity.map(|(mean, std_dev)| {
+            NormalDistribution::new(mean, id, format!("{}_max_capacity", name), std_dev)
+                .sample_with(rng, None)
+                .round()
+                .clamp(1.0, i16::MAX as f64) as i16
+        });
 
         Self {
             id,
@@ -44,9 +151,93 @@ impl Factory {
             supply_price_range: (lower, upper),
             amount: HashMap::new(),
             u64_list: LinkedList::new(),
+            max_capacity,
+            scarcity_weight: config.scarcity_weight,
+            tax_rate: product.producer_tax_rate(),
+            // 基础调整比例按商品的供给弹性缩放
+            price_increase_ratio: config.price_increase_ratio * product.supply_elasticity(),
+            price_decrease_ratio: config.price_decrease_ratio * product.supply_elasticity(),
+            min_range_width: config.min_range_width,
+            revenue: 0.0,
+            tax_paid: 0.0,
+            dividend_ratio: config.dividend_ratio,
+            dividend_reserve: config.dividend_reserve,
+            dividends_paid: 0.0,
+            shrink_ratio: config.shrink_ratio,
+            min_unit_size: config.min_unit_size,
+            unit_size: 1.0,
+            unit_size_history: VecDeque::new(),
+            promotion: None,
+            max_consecutive_increases: config.max_consecutive_increases,
+            consecutive_increases: 0,
+            unit_cost: config.unit_cost,
+            reservation_factor: config.reservation_factor,
+            price_ceiling: config
+                .price_ceiling_quantile
+                .map(|q| product.original_price_distribution().quantile(q)),
+            adaptive_step_window: config.adaptive_step_window,
+            adaptive_step_bounds: config.adaptive_step_bounds,
+            recent_outcomes: VecDeque::new(),
+            logger: None,
         }
     }
 
+    /// 设置所属市场的日志记录器，由市场在工厂加入时调用
+    pub fn set_logger(&mut self, logger: Option<Logger>) {
+        self.logger = logger;
+    }
+
+    /// 先发优势：单位成本降低bonus比例，报价区间下界同比例下移（不低于新的成本），上界不变
+    pub fn with_first_mover_bonus(mut self, bonus: f64) -> Self {
+        let factor = 1.0 - bonus.clamp(0.0, 1.0);
+        let (lower, upper) = self.supply_price_range;
+        self.unit_cost *= factor;
+        self.supply_price_range = (round_to_nearest_cent(lower * factor).max(self.unit_cost), upper);
+        self
+    }
+
+    /// 按range_init生成初始报价区间，下界不低于单位成本，宽度不小于min_range_width
+    fn initial_range<R: Rng + ?Sized>(
+        product: &Product,
+        config: &FactoryConfig,
+        rng: &mut R,
+    ) -> (f64, f64) {
+        let distribution = product.original_price_distribution();
+        let (lower, upper) = match config.range_init {
+            RangeInitStrategy::RandomAroundReference => {
+                // 根据商品的价格正态分布，随机生成一个参考价格点
+                let reference_price = distribution.sample_with(rng, None);
+
+                // 处理特殊情况，确保reference_price是有限值
+                let reference_price = reference_price.min(f64::MAX / 2.0);
+
+                // 计算区间范围，基于参考价格
+                let range_scale = reference_price * 0.5;
+
+                // 生成下界：0.0到reference_price
+                let lower = rng.gen_range(0.0..reference_price);
+
+                // 确保upper_bound是有限值
+                let upper_bound = (reference_price + range_scale).min(f64::MAX / 2.0);
+                // 生成上界：lower到upper_bound
+                let upper = rng.gen_range(lower..upper_bound);
+                (lower, upper)
+            }
+            RangeInitStrategy::QuantileBand { mass } => {
+                let tail = (1.0 - mass) / 2.0;
+                (
+                    distribution.quantile(tail).max(0.0),
+                    distribution.quantile(1.0 - tail).max(0.0),
+                )
+            }
+            RangeInitStrategy::CostPlusMarkup { markup } => {
+                (config.unit_cost, config.unit_cost * markup)
+            }
+        };
+        let lower = lower.max(config.unit_cost);
+        (lower, upper.max(lower + config.min_range_width))
+    }
+
     pub fn id(&self) -> u64 {
         self.id
     }
@@ -63,14 +254,182 @@ impl Factory {
         self.supply_price_range
     }
 
+    /// 单位成本，0表示没有成本下限
+    pub fn unit_cost(&self) -> f64 {
+        self.unit_cost
+    }
+
+    /// 保留价：低于该价格不出售，没有设置时为0
+    pub fn reservation_price(&self) -> f64 {
+        self.unit_cost * self.reservation_factor
+    }
+
+    /// 实际愿意成交的价格区间：促销期间报价区间按折扣打折，低于保留价的部分不出售，
+    /// 整个区间都低于保留价时只按保留价出售
+    pub fn asking_range(&self) -> (f64, f64) {
+        let (lower, upper) = self.supply_price_range;
+        let discount = self.promotion.as_ref().map_or(0.0, |p| p.discount);
+        let (lower, upper) = (lower * (1.0 - discount), upper * (1.0 - discount));
+        let lower = lower.max(self.reservation_price());
+        (lower, upper.max(lower))
+    }
+
+    /// 按比例整体平移报价区间，负数为下调
+    pub fn shift_supply_price_range(&mut self, ratio: f64) {
+        self.supply_price_range = shift_range_by_ratio(self.supply_price_range, ratio);
+    }
+
+    /// 成本冲击：单位成本乘以factor，报价区间按同一倍数整体缩放
+    pub fn scale_cost(&mut self, factor: f64) {
+        self.unit_cost *= factor;
+        self.shift_supply_price_range(factor - 1.0);
+    }
+
+    /// 当前报价：以区间中点为基准，按scarcity_weight向区间边界偏移。
+    /// 库存越少越接近上界，库存接近满产时越接近下界，权重为0时即为中点
+    /// 促销期间再按折扣降低
+    pub fn offer_price(&self) -> f64 {
+        self.offer_with_stock(self.current_stock())
+    }
+
+    /// 指定轮次的实际报价，按该轮的剩余库存计算稀缺度，用于轮末记录对外挂出的价格
+    pub fn current_offer(&self, round: u64) -> f64 {
+        self.offer_with_stock(self.get_stock(round))
+    }
+
+    fn offer_with_stock(&self, stock: i16) -> f64 {
+        let (lower, upper) = self.supply_price_range;
+        let midpoint = (lower + upper) / 2.0;
+        let discount = self.promotion.as_ref().map_or(0.0, |p| p.discount);
+        if self.scarcity_weight == 0.0 {
+            return midpoint * (1.0 - discount);
+        }
+        // 稀缺度：满库存为-1，无库存为1
+        let fill = stock.max(0) as f64 / self.production() as f64;
+        let scarcity = (1.0 - 2.0 * fill).clamp(-1.0, 1.0);
+        (midpoint + self.scarcity_weight * scarcity * (upper - lower) / 2.0) * (1.0 - discount)
+    }
+
+    /// 开始促销：接下来rounds轮（含当前轮）报价按discount(0~1)比例打折，到期自动恢复。
+    /// 报价和实际成交区间(asking_range)都打折，不移动报价区间；rounds为0时取消进行中的促销
+    pub fn start_promotion(&mut self, discount: f64, rounds: u64) {
+        // 已有轮次在进行时当前轮计入促销期
+        let started = !self.u64_list.is_empty();
+        self.promotion = (rounds > 0).then(|| Promotion {
+            discount: discount.clamp(0.0, 1.0),
+            rounds_left: rounds,
+            started,
+        });
+    }
+
+    /// 促销剩余轮数，没有促销时为0
+    pub fn promotion_rounds_left(&self) -> u64 {
+        self.promotion.as_ref().map_or(0, |p| p.rounds_left)
+    }
+
+    /// 最近开始的一轮的剩余库存，还没开始任何一轮时视为满库存
+    fn current_stock(&self) -> i16 {
+        match self.u64_list.back() {
+            Some(round) => self.get_stock(*round),
+            None => self.production(),
+        }
+    }
+
+    /// 参考竞争对手的平均报价，把报价区间整体向其移动两者差距的factor比例，
+    /// factor为负时远离平均报价，下界不低于0
+    pub fn observe_competitors(&mut self, competitor_average: f64, factor: f64) {
+        if factor == 0.0 {
+            return;
+        }
+        let (lower, upper) = self.supply_price_range;
+        let mut shift = (competitor_average - self.offer_price()) * factor;
+        if lower + shift < 0.0 {
+            shift = -lower;
+        }
+        self.supply_price_range = (
+            round_to_nearest_cent(lower + shift),
+            round_to_nearest_cent(upper + shift),
+        );
+    }
+
     pub fn get_stock(&self, round: u64) -> i16 {
         *self.amount.get(&round).unwrap_or(&10) // 默认库存为10
     }
 
+    /// 保留窗口内（最近3轮）每轮的(轮次, 库存)，按轮次升序
+    pub fn stock_history(&self) -> Vec<(u64, i16)> {
+        let mut history: Vec<(u64, i16)> = self
+            .u64_list
+            .iter()
+            .filter_map(|r| self.amount.get(r).map(|amount| (*r, *amount)))
+            .collect();
+        history.sort_by_key(|(round, _)| *round);
+        history
+    }
+
+    pub fn max_capacity(&self) -> Option<i16> {
+        self.max_capacity
+    }
+
+    /// 每轮实际产量：计划产量，不超过最大产能
+    pub fn production(&self) -> i16 {
+        match self.max_capacity {
+            Some(capacity) => ROUND_PRODUCTION.min(capacity),
+            None => ROUND_PRODUCTION,
+        }
+    }
+
+    /// 产能利用率：实际产量 / 最大产能
+    pub fn capacity_utilization(&self) -> Option<f64> {
+        self.max_capacity
+            .map(|capacity| self.production() as f64 / capacity as f64)
+    }
+
+    /// 累计税后销售收入
+    pub fn revenue(&self) -> f64 {
+        self.revenue
+    }
+
+    /// 累计缴纳的生产者税
+    pub fn tax_paid(&self) -> f64 {
+        self.tax_paid
+    }
+
+    /// 当前单位规格，1表示未缩水，越小每件带给agent的满足越少
+    pub fn unit_size(&self) -> f64 {
+        self.unit_size
+    }
+
+    /// 最近每轮开始时的单位规格(轮次, 规格)，按轮次从早到晚
+    pub fn unit_size_history(&self) -> &VecDeque<(u64, f64)> {
+        &self.unit_size_history
+    }
+
+    /// 留存收入：累计税后销售收入扣除已分红的部分
+    pub fn retained_revenue(&self) -> f64 {
+        self.revenue - self.dividends_paid
+    }
+
+    /// 向所有者分红：取出留存收入超出dividend_reserve部分的dividend_ratio比例，返回本次分红金额
+    pub fn pay_dividend(&mut self) -> f64 {
+        let surplus = self.retained_revenue() - self.dividend_reserve;
+        if self.dividend_ratio <= 0.0 || surplus <= 0.0 {
+            return 0.0;
+        }
+        let dividend = surplus * self.dividend_ratio;
+        self.dividends_paid += dividend;
+        dividend
+    }
+
+    /// 是否已开始指定轮次，即已为该轮生产库存
+    pub fn is_round_started(&self, round: u64) -> bool {
+        self.amount.contains_key(&round)
+    }
+
     /// 开始新一轮
     pub fn start_round(&mut self, round: u64) {
-        // 给hashmap创建一个以round为键，值为10的数字
-        self.amount.insert(round, 10);
+        // 给hashmap创建一个以round为键，值为本轮产量的数字
+        self.amount.insert(round, self.production());
 
         // 把round插入到队尾
         self.u64_list.push_back(round);
@@ -82,6 +441,80 @@ impl Factory {
                 self.amount.remove(&v);
             }
         }
+
+        // 记录本轮的单位规格，规格变化时写入区间优化日志，报价区间不变
+        if let Some((_, last)) = self.unit_size_history.back()
+            && *last != self.unit_size
+        {
+            let range = self.supply_price_range;
+            if let Err(e) = log_factory_range_optimization(
+                self.logger.as_ref(),
+                round,
+                self.id(),
+                self.name().to_string(),
+                self.product_id(),
+                range,
+                range,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                &format!("UnitSize {:.3} -> {:.3}", last, self.unit_size),
+            ) {
+                eprintln!("Failed to log factory range optimization: {}", e);
+            }
+        }
+        self.unit_size_history.push_back((round, self.unit_size));
+        if self.unit_size_history.len() > UNIT_SIZE_HISTORY {
+            self.unit_size_history.pop_front();
+        }
+
+        // 促销每过一轮扣减剩余轮数，到期后恢复原价
+        if let Some(promotion) = self.promotion.as_mut() {
+            if !promotion.started {
+                promotion.started = true;
+            } else {
+                promotion.rounds_left -= 1;
+                if promotion.rounds_left == 0 {
+                    self.promotion = None;
+                }
+            }
+        }
+    }
+
+    /// 汇总工厂当前状态，便于打印排查
+    pub fn diagnostics(&self, round: u64) -> FactoryDiagnostics {
+        FactoryDiagnostics {
+            factory_id: self.id,
+            name: self.name.clone(),
+            product_id: self.product_id,
+            round,
+            supply_price_range: self.supply_price_range,
+            offer_price: self.offer_price(),
+            stock: self.get_stock(round),
+            max_capacity: self.max_capacity,
+            capacity_utilization: self.capacity_utilization(),
+            recent_stock: self.stock_history(),
+            unit_size: self.unit_size,
+        }
+    }
+
+    /// 清算工厂：核销当前轮次的剩余库存并返回结算单，工厂退出市场前调用
+    pub fn liquidate(&mut self, round: u64) -> FinancialBill {
+        let opening_stock = self.amount.get(&round).copied().unwrap_or(0).max(0);
+        self.amount.insert(round, 0);
+
+        FinancialBill {
+            factory_id: self.id,
+            round,
+            opening_stock,
+            written_off_stock: opening_stock,
+            ending_stock: self.get_stock(round),
+            capacity_utilization: self.capacity_utilization(),
+            revenue: self.revenue,
+            tax_paid: self.tax_paid,
+        }
     }
 
     pub fn deal(
@@ -89,6 +522,35 @@ impl Factory {
         result: &TradeResult,
         round: u64,
         interval_relation: Option<IntervalRelation>,
+    ) {
+        self.deal_units(result, round, interval_relation, 1);
+    }
+
+    /// 按税后金额入账，税款单独累计
+    fn book_sale(&mut self, gross: f64) {
+        let tax = gross * self.tax_rate;
+        self.revenue += gross - tax;
+        self.tax_paid += tax;
+    }
+
+    /// 交付一件订阅商品：按约定价格入账并扣减本轮库存，不调整报价区间。
+    /// 本轮库存为0时不交付也不入账，返回false
+    pub fn deliver_subscription(&mut self, price: f64, round: u64) -> bool {
+        if self.get_stock(round) <= 0 {
+            return false;
+        }
+        self.book_sale(price);
+        self.amount.entry(round).and_modify(|e| *e -= 1);
+        true
+    }
+
+    /// 同deal，成交时库存按实际成交数量扣减
+    pub fn deal_units(
+        &mut self,
+        result: &TradeResult,
+        round: u64,
+        interval_relation: Option<IntervalRelation>,
+        quantity: i16,
     ) {
         // 检查指定轮次的库存，如果为0则退出
         if let Some(amount) = self.amount.get(&round) {
@@ -103,26 +565,37 @@ impl Factory {
                 return;
             }
             TradeResult::Failed => {
+                // 交易失败后重新允许成交涨价
+                self.consecutive_increases = 0;
+                let step = self.record_outcome(true);
                 let mut ratio = 0.0;
                 if interval_relation.is_none() {
-                    ratio = -0.01;
+                    ratio = -self.price_decrease_ratio;
                 }else {
                     let interval_rel = interval_relation.unwrap();
                     match interval_rel {
                         IntervalRelation::Overlapping(_) => {
-                            ratio = -0.01;
+                            ratio = -self.price_decrease_ratio;
                         }
                         IntervalRelation::AgentBelowFactory => {
-                            ratio = -0.01;
+                            ratio = -self.price_decrease_ratio;
                         }
                         IntervalRelation::AgentAboveFactory => {
-                            ratio = 0.01;
+                            ratio = self.price_increase_ratio;
                         }
                     }
                 }
                 let (lower,upper) = self.supply_price_range;
                 let range_length = upper - lower;
-                let (new_lower, new_upper) = shift_range_by_ratio(self.supply_price_range,ratio);
+                let (mut new_lower, mut new_upper) = self.cap_at_ceiling(shift_range_by_ratio(
+                    self.supply_price_range,
+                    ratio * step,
+                ));
+                // 降价不低于单位成本，下界碰到成本时区间整体停在成本之上
+                if new_lower < self.unit_cost {
+                    new_upper += self.unit_cost - new_lower;
+                    new_lower = self.unit_cost;
+                }
 
                 // 计算修改幅度
                 let lower_change = new_lower - lower;
@@ -143,6 +616,7 @@ impl Factory {
 
                 // 调用日志记录函数
                 if let Err(e) = log_factory_range_optimization(
+                    self.logger.as_ref(),
                     round,
                     self.id(),
                     self.name().to_string(),
@@ -161,10 +635,29 @@ impl Factory {
 
                 self.supply_price_range = (new_lower, new_upper);
             }
-            TradeResult::Success(_price) => {
-                // 交易成功，区间整体上移1%
+            TradeResult::Success(price) => {
+                self.book_sale(price * quantity as f64);
+                let step = self.record_outcome(false);
+
+                // 交易成功，区间整体上移price_increase_ratio；开启缩水时先缩小单位规格、标价不变，
+                // 规格缩到下限后才涨价。连续涨价达到上限后保持报价，等交易失败再恢复
+                let increase_ratio = if self.shrink_ratio > 0.0 && self.unit_size > self.min_unit_size {
+                    self.unit_size = (self.unit_size * (1.0 - self.shrink_ratio)).max(self.min_unit_size);
+                    0.0
+                } else if self
+                    .max_consecutive_increases
+                    .is_some_and(|cap| self.consecutive_increases >= cap)
+                {
+                    0.0
+                } else {
+                    self.consecutive_increases += 1;
+                    self.price_increase_ratio
+                };
                 let (lower, upper) = self.supply_price_range;
-                let (new_lower,new_upper) = shift_range_by_ratio(self.supply_price_range,0.01);
+                let (new_lower, new_upper) = self.cap_at_ceiling(shift_range_by_ratio(
+                    self.supply_price_range,
+                    increase_ratio * step,
+                ));
                 let range_length = upper - lower;
                 // 计算修改幅度
                 let lower_change = new_lower - lower;
@@ -185,6 +678,7 @@ impl Factory {
 
                 // 调用日志记录函数
                 if let Err(e) = log_factory_range_optimization(
+                    self.logger.as_ref(),
                     round,
                     self.id(),
                     self.name().to_string(),
@@ -203,12 +697,63 @@ impl Factory {
 
                 self.supply_price_range = (new_lower, new_upper);
 
-                // 库存减1
-                // 更新指定轮次的库存
-                self.amount.entry(round).and_modify(|e| *e -= 1);
+                // 库存减去成交数量
+                // 更新指定轮次的库存，超卖时库存停在0，不会溢出
+                self.amount
+                    .entry(round)
+                    .and_modify(|e| *e = e.saturating_sub(quantity).max(0));
+            }
+        }
+
+        // 调整后保证区间不倒置且不窄于min_range_width
+        self.repair_range(round);
+    }
+
+    /// 上移后的区间上界超过price_ceiling时，只上移到上界等于price_ceiling为止；
+    /// 原区间已经高于上限时不再上移。下移的区间不受影响
+    fn cap_at_ceiling(&self, (new_lower, new_upper): (f64, f64)) -> (f64, f64) {
+        let (lower, upper) = self.supply_price_range;
+        match self.price_ceiling {
+            Some(ceiling) if new_upper > upper && new_upper > ceiling => {
+                let shift = (ceiling - upper).max(0.0);
+                (
+                    round_to_nearest_cent(lower + shift),
+                    round_to_nearest_cent(upper + shift),
+                )
             }
+            _ => (new_lower, new_upper),
         }
     }
+
+    /// 记录一次交易结果并返回本次调价比例的倍数：按最近adaptive_step_window次交易的失败率
+    /// 在adaptive_step_bounds之间线性插值，未启用时为1
+    fn record_outcome(&mut self, failed: bool) -> f64 {
+        if self.adaptive_step_window == 0 {
+            return 1.0;
+        }
+        self.recent_outcomes.push_back(failed);
+        while self.recent_outcomes.len() > self.adaptive_step_window {
+            self.recent_outcomes.pop_front();
+        }
+        let failures = self.recent_outcomes.iter().filter(|failed| **failed).count();
+        let failure_rate = failures as f64 / self.recent_outcomes.len() as f64;
+        let (min, max) = self.adaptive_step_bounds;
+        min + (max - min) * failure_rate
+    }
+
+    /// 区间宽度小于min_range_width（包括上界低于下界）时，以下界为准修复上界并打印警告
+    fn repair_range(&mut self, round: u64) {
+        let (lower, upper) = self.supply_price_range;
+        if upper >= lower + self.min_range_width {
+            return;
+        }
+        let repaired = (lower, round_to_nearest_cent(lower + self.min_range_width));
+        eprintln!(
+            "Warning: factory {} range [{:.2}, {:.2}] narrower than {:.2} at round {}, repaired to [{:.2}, {:.2}]",
+            self.id, lower, upper, self.min_range_width, round, repaired.0, repaired.1
+        );
+        self.supply_price_range = repaired;
+    }
 }
 
 #[cfg(test)]
@@ -505,4 +1050,623 @@ mod tests {
         // 验证价格区间没有变化
         assert_eq!(factory.supply_price_range, initial_range);
     }
+
+    #[test]
+    fn test_liquidate() {
+        let product = Product::new(1, "test_product".to_string());
+        let mut factory = Factory::new(1, "test_factory".to_string(), &product);
+        factory.supply_price_range = (100.0, 200.0);
+
+        let current_round = 1;
+        factory.start_round(current_round);
+        factory.deal(&TradeResult::Success(150.0), current_round, None);
+        factory.deal(&TradeResult::Success(150.0), current_round, None);
+
+        let bill = factory.liquidate(current_round);
+
+        assert_eq!(bill.factory_id, 1);
+        assert_eq!(bill.round, current_round);
+        assert_eq!(bill.opening_stock, 8);
+        assert_eq!(bill.written_off_stock, 8);
+        assert_eq!(bill.ending_stock, 0);
+        assert_eq!(bill.capacity_utilization, None);
+        assert_eq!(factory.get_stock(current_round), 0);
+    }
+
+    #[test]
+    fn test_max_capacity() {
+        let product = Product::new(1, "test_product".to_string());
+        let config = FactoryConfig {
+            max_capacity: Some((4.0, 0.0)),
+            ..Default::default()
+        };
+        let mut factory = Factory::with_config(1, "test_factory".to_string(), &product, &config);
+        factory.supply_price_range = (100.0, 200.0);
+        assert_eq!(factory.max_capacity(), Some(4));
+
+        // 需求再多，每轮产量也不超过最大产能
+        let current_round = 1;
+        factory.start_round(current_round);
+        assert_eq!(factory.get_stock(current_round), 4);
+        for _ in 0..20 {
+            factory.deal(&TradeResult::Success(150.0), current_round, None);
+        }
+        assert_eq!(factory.get_stock(current_round), 0);
+
+        let bill = factory.liquidate(current_round);
+        assert_eq!(bill.capacity_utilization, Some(1.0));
+
+        // 产能大于计划产量时按计划生产
+        let config = FactoryConfig {
+            max_capacity: Some((20.0, 0.0)),
+            ..Default::default()
+        };
+        let mut factory = Factory::with_config(2, "big_factory".to_string(), &product, &config);
+        factory.start_round(current_round);
+        assert_eq!(factory.get_stock(current_round), 10);
+        assert_eq!(factory.capacity_utilization(), Some(0.5));
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let product = Product::new(1, "test_product".to_string());
+        let config = FactoryConfig {
+            max_capacity: Some((8.0, 0.0)),
+            ..Default::default()
+        };
+        let mut factory = Factory::with_config(7, "test_factory".to_string(), &product, &config);
+        factory.supply_price_range = (100.0, 200.0);
+
+        factory.start_round(1);
+        factory.start_round(2);
+        factory.deal(&TradeResult::Success(150.0), 2, None);
+
+        let diagnostics = factory.diagnostics(2);
+        assert_eq!(diagnostics.factory_id, 7);
+        assert_eq!(diagnostics.product_id, 1);
+        assert_eq!(diagnostics.round, 2);
+        // 成交后区间上移1%
+        assert_eq!(diagnostics.supply_price_range, (101.0, 202.0));
+        assert_eq!(diagnostics.offer_price, 151.5);
+        assert_eq!(diagnostics.stock, 7);
+        assert_eq!(diagnostics.max_capacity, Some(8));
+        assert_eq!(diagnostics.capacity_utilization, Some(1.0));
+        assert_eq!(diagnostics.recent_stock, vec![(1, 8), (2, 7)]);
+        assert!(diagnostics.to_string().contains("Factory 7 (test_factory)"));
+    }
+
+    #[test]
+    fn test_observe_competitors() {
+        let product = Product::new(1, "test_product".to_string());
+        let mut factory = Factory::new(1, "test_factory".to_string(), &product);
+        factory.supply_price_range = (180.0, 220.0);
+
+        // 报价远高于竞争对手平均价，向平均价靠拢
+        factory.observe_competitors(100.0, 0.5);
+        assert_eq!(factory.supply_price_range(), (130.0, 170.0));
+        assert_eq!(factory.offer_price(), 150.0);
+
+        // 系数为0时不变
+        factory.observe_competitors(100.0, 0.0);
+        assert_eq!(factory.supply_price_range(), (130.0, 170.0));
+
+        // 系数为负时远离平均价
+        factory.observe_competitors(100.0, -0.5);
+        assert_eq!(factory.supply_price_range(), (155.0, 195.0));
+    }
+
+    #[test]
+    fn test_promotion() {
+        let product = Product::new(1, "test_product".to_string());
+        let mut factory = Factory::new(1, "promo".to_string(), &product);
+        factory.supply_price_range = (100.0, 200.0);
+        factory.start_round(1);
+        assert_eq!(factory.offer_price(), 150.0);
+
+        // 促销两轮：当前轮和下一轮打八折
+        factory.start_promotion(0.2, 2);
+        assert_eq!(factory.offer_price(), 120.0);
+        factory.start_round(2);
+        assert_eq!(factory.offer_price(), 120.0);
+        assert_eq!(factory.promotion_rounds_left(), 1);
+
+        // 到期后恢复，报价区间始终未变
+        factory.start_round(3);
+        assert_eq!(factory.offer_price(), 150.0);
+        assert_eq!(factory.promotion_rounds_left(), 0);
+        assert_eq!(factory.supply_price_range(), (100.0, 200.0));
+    }
+
+    #[test]
+    fn test_offer_price_scarcity_weight() {
+        let product = Product::new(1, "test_product".to_string());
+        let current_round = 1;
+
+        // 权重为0时无论库存多少都按区间中点报价
+        let mut flat = Factory::new(1, "flat".to_string(), &product);
+        flat.supply_price_range = (100.0, 200.0);
+        flat.start_round(current_round);
+        for stock in [10, 5, 0] {
+            flat.amount.insert(current_round, stock);
+            assert_eq!(flat.offer_price(), 150.0);
+        }
+
+        // 权重为1时满库存报下界，半库存报中点，售罄报上界
+        let config = FactoryConfig {
+            scarcity_weight: 1.0,
+            ..Default::default()
+        };
+        let mut reactive = Factory::with_config(2, "reactive".to_string(), &product, &config);
+        reactive.supply_price_range = (100.0, 200.0);
+        reactive.start_round(current_round);
+        let prices: Vec<f64> = [10, 5, 0]
+            .iter()
+            .map(|stock| {
+                reactive.amount.insert(current_round, *stock);
+                reactive.offer_price()
+            })
+            .collect();
+        assert_eq!(prices, vec![100.0, 150.0, 200.0]);
+    }
+
+    #[test]
+    fn test_producer_tax() {
+        let product = Product::new(1, "test_product".to_string());
+        let taxed_product = product.clone().with_producer_tax_rate(0.1);
+        let mut untaxed = Factory::new(1, "untaxed".to_string(), &product);
+        let mut taxed = Factory::new(2, "taxed".to_string(), &taxed_product);
+        let current_round = 1;
+        untaxed.start_round(current_round);
+        taxed.start_round(current_round);
+
+        untaxed.deal(&TradeResult::Success(100.0), current_round, None);
+        taxed.deal(&TradeResult::Success(100.0), current_round, None);
+
+        // 10%的税让每笔销售的实得收入减少10%
+        assert_eq!(untaxed.revenue(), 100.0);
+        assert_eq!(untaxed.tax_paid(), 0.0);
+        assert!((taxed.revenue() - 90.0).abs() < 1e-9);
+        assert!((taxed.tax_paid() - 10.0).abs() < 1e-9);
+
+        // 批量成交按数量计税
+        taxed.deal_units(&TradeResult::Success(50.0), current_round, None, 4);
+        assert!((taxed.revenue() - 270.0).abs() < 1e-9);
+        assert!((taxed.tax_paid() - 30.0).abs() < 1e-9);
+
+        let bill = taxed.liquidate(current_round);
+        assert!((bill.tax_paid - 30.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_asymmetric_price_reaction() {
+        let product = Product::new(1, "test_product".to_string());
+        let config = FactoryConfig {
+            price_increase_ratio: 0.01,
+            price_decrease_ratio: 0.05,
+            ..Default::default()
+        };
+        let mut factory = Factory::with_config(1, "test_factory".to_string(), &product, &config);
+        let current_round = 1;
+        factory.start_round(current_round);
+
+        // 成交时缓慢涨价
+        factory.supply_price_range = (100.0, 200.0);
+        factory.deal(&TradeResult::Success(150.0), current_round, None);
+        assert_eq!(factory.supply_price_range(), (101.0, 202.0));
+
+        // 失败时快速降价
+        factory.supply_price_range = (100.0, 200.0);
+        factory.deal(
+            &TradeResult::Failed,
+            current_round,
+            Some(IntervalRelation::AgentBelowFactory),
+        );
+        assert_eq!(factory.supply_price_range(), (95.0, 190.0));
+
+        // agent出价高于报价区间时按涨价比例上移
+        factory.supply_price_range = (100.0, 200.0);
+        factory.deal(
+            &TradeResult::Failed,
+            current_round,
+            Some(IntervalRelation::AgentAboveFactory),
+        );
+        assert_eq!(factory.supply_price_range(), (101.0, 202.0));
+    }
+
+    #[test]
+    fn test_max_consecutive_increases() {
+        let product = Product::new(1, "test_product".to_string());
+        let config = FactoryConfig {
+            price_increase_ratio: 0.01,
+            max_consecutive_increases: Some(3),
+            ..Default::default()
+        };
+        let mut factory = Factory::with_config(1, "test_factory".to_string(), &product, &config);
+        factory.supply_price_range = (100.0, 200.0);
+        factory.start_round(1);
+
+        // 连续成交3次涨价，之后保持不变
+        let mut lowers = Vec::new();
+        for _ in 0..5 {
+            factory.deal(&TradeResult::Success(150.0), 1, None);
+            lowers.push(factory.supply_price_range().0);
+        }
+        assert!(lowers[0] < lowers[1] && lowers[1] < lowers[2]);
+        assert_eq!(lowers[2], lowers[3]);
+        assert_eq!(lowers[3], lowers[4]);
+
+        // 交易失败后计数清零，成交又会涨价
+        factory.deal(
+            &TradeResult::Failed,
+            1,
+            Some(IntervalRelation::AgentBelowFactory),
+        );
+        let after_failure = factory.supply_price_range().0;
+        factory.deal(&TradeResult::Success(150.0), 1, None);
+        assert!(factory.supply_price_range().0 > after_failure);
+    }
+
+    #[test]
+    fn test_stock_over_subtraction() {
+        let product = Product::new(1, "test_product".to_string());
+        let mut factory = Factory::new(1, "test_factory".to_string(), &product);
+        factory.start_round(1);
+
+        // 成交数量远超库存时库存停在0，不会变成负数或回绕
+        factory.deal_units(&TradeResult::Success(10.0), 1, None, i16::MAX);
+        assert_eq!(factory.get_stock(1), 0);
+
+        // 库存接近下限时继续扣减也不会溢出
+        factory.amount.insert(2, 1);
+        factory.deal_units(&TradeResult::Success(10.0), 2, None, i16::MAX);
+        assert_eq!(factory.get_stock(2), 0);
+
+        let bill = factory.liquidate(1);
+        assert_eq!(bill.opening_stock, 0);
+        assert_eq!(bill.ending_stock, 0);
+    }
+
+    #[test]
+    fn test_deliver_subscription() {
+        let product = Product::new(1, "test_product".to_string());
+        let mut factory = Factory::new(1, "test_factory".to_string(), &product);
+        factory.start_round(1);
+        let stock = factory.get_stock(1);
+        assert!(factory.deliver_subscription(30.0, 1));
+        assert_eq!(factory.get_stock(1), stock - 1);
+        assert!((factory.revenue() - 30.0).abs() < 1e-9);
+
+        // 本轮售罄后不交付也不入账
+        factory.amount.insert(1, 0);
+        assert!(!factory.deliver_subscription(30.0, 1));
+        assert_eq!(factory.get_stock(1), 0);
+        assert!((factory.revenue() - 30.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_adaptive_step() {
+        let product = Product::new(1, "test_product".to_string());
+        let config = FactoryConfig {
+            price_decrease_ratio: 0.01,
+            adaptive_step_window: 4,
+            adaptive_step_bounds: (0.5, 3.0),
+            ..Default::default()
+        };
+        let failed = |factory: &mut Factory| {
+            factory.deal(
+                &TradeResult::Failed,
+                1,
+                Some(IntervalRelation::AgentBelowFactory),
+            );
+        };
+
+        // 一直失败的工厂
+        let mut failing = Factory::with_config(1, "failing".to_string(), &product, &config);
+        failing.supply_price_range = (100.0, 200.0);
+        failing.start_round(1);
+        for _ in 0..3 {
+            failed(&mut failing);
+        }
+        let before = failing.supply_price_range().0;
+        failed(&mut failing);
+        let failing_step = 1.0 - failing.supply_price_range().0 / before;
+
+        // 成交与失败交替的工厂
+        let mut mixed = Factory::with_config(2, "mixed".to_string(), &product, &config);
+        mixed.supply_price_range = (100.0, 200.0);
+        mixed.start_round(1);
+        for _ in 0..2 {
+            mixed.deal(&TradeResult::Success(150.0), 1, None);
+            failed(&mut mixed);
+        }
+        mixed.deal(&TradeResult::Success(150.0), 1, None);
+        let before = mixed.supply_price_range().0;
+        failed(&mut mixed);
+        let mixed_step = 1.0 - mixed.supply_price_range().0 / before;
+
+        // 全部失败时按最大倍数调整，失败率一半时步长较小
+        assert!((failing_step - 0.03).abs() < 1e-3);
+        assert!(mixed_step < failing_step);
+        assert!((mixed_step - 0.0175).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_price_decrease_respects_unit_cost() {
+        let product = Product::new(1, "test_product".to_string());
+        let config = FactoryConfig {
+            price_decrease_ratio: 0.5,
+            unit_cost: 80.0,
+            ..Default::default()
+        };
+        let mut factory = Factory::with_config(1, "test_factory".to_string(), &product, &config);
+        factory.supply_price_range = (100.0, 150.0);
+        factory.start_round(1);
+
+        factory.deal(
+            &TradeResult::Failed,
+            1,
+            Some(IntervalRelation::AgentBelowFactory),
+        );
+        assert_eq!(factory.supply_price_range(), (80.0, 105.0));
+    }
+
+    #[test]
+    fn test_supply_elasticity() {
+        let product = Product::new(1, "test_product".to_string());
+        let config = FactoryConfig {
+            price_increase_ratio: 0.01,
+            price_decrease_ratio: 0.01,
+            ..Default::default()
+        };
+        let total_move = |elasticity: f64| {
+            let product = product.clone().with_supply_elasticity(elasticity);
+            let mut factory = Factory::with_config(1, "test_factory".to_string(), &product, &config);
+            factory.supply_price_range = (100.0, 200.0);
+            let mut moved = 0.0;
+            for round in 1..=6 {
+                factory.start_round(round);
+                let before = factory.supply_price_range();
+                if round % 2 == 0 {
+                    factory.deal(&TradeResult::Success(150.0), round, None);
+                } else {
+                    factory.deal(
+                        &TradeResult::Failed,
+                        round,
+                        Some(IntervalRelation::AgentBelowFactory),
+                    );
+                }
+                moved += (factory.supply_price_range().0 - before.0).abs();
+            }
+            moved
+        };
+
+        // 相同的交易结果序列，供给弹性高的工厂每次调价幅度更大
+        let sticky = total_move(0.5);
+        let neutral = total_move(1.0);
+        let elastic = total_move(3.0);
+        assert!(sticky < neutral && neutral < elastic);
+        assert!((neutral - 6.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_stock_history() {
+        let product = Product::new(1, "test_product".to_string());
+        let mut factory = Factory::new(1, "test_factory".to_string(), &product);
+        assert!(factory.stock_history().is_empty());
+
+        for round in 1..=5 {
+            factory.start_round(round);
+            factory.deal_units(&TradeResult::Success(10.0), round, None, round as i16);
+        }
+
+        // 只保留最近3轮，按轮次升序
+        assert_eq!(factory.stock_history(), vec![(3, 7), (4, 6), (5, 5)]);
+    }
+
+    #[test]
+    fn test_reservation_price() {
+        let product = Product::new(1, "test_product".to_string());
+        let config = FactoryConfig {
+            unit_cost: 40.0,
+            reservation_factor: 1.25,
+            ..Default::default()
+        };
+        let mut factory = Factory::with_config(1, "test_factory".to_string(), &product, &config);
+        assert_eq!(factory.reservation_price(), 50.0);
+
+        factory.supply_price_range = (45.0, 60.0);
+        assert_eq!(factory.asking_range(), (50.0, 60.0));
+        factory.supply_price_range = (41.0, 48.0);
+        assert_eq!(factory.asking_range(), (50.0, 50.0));
+
+        // 没有保留价时按报价区间出售
+        let plain = Factory::new(2, "plain".to_string(), &product);
+        assert_eq!(plain.asking_range(), plain.supply_price_range());
+    }
+
+    #[test]
+    fn test_price_ceiling() {
+        let product = Product::from(
+            1,
+            "test_product".to_string(),
+            NormalDistribution::new(50.0, 1, "price_dist".to_string(), 10.0),
+            NormalDistribution::new(0.5, 1, "elastic_dist".to_string(), 0.1),
+        );
+        let config = FactoryConfig {
+            price_increase_ratio: 0.05,
+            price_ceiling_quantile: Some(0.99),
+            ..Default::default()
+        };
+        let mut factory = Factory::with_config(1, "test_factory".to_string(), &product, &config);
+        factory.supply_price_range = (40.0, 60.0);
+
+        // 连续成交，上界停在价格分布的99%分位数(约73.26)
+        let ceiling = product.original_price_distribution().quantile(0.99);
+        for round in 1..=20 {
+            factory.start_round(round);
+            factory.deal(&TradeResult::Success(50.0), round, None);
+        }
+        let (lower, upper) = factory.supply_price_range();
+        assert!((upper - ceiling).abs() < 0.01);
+        assert!(lower > 40.0 && lower < upper);
+
+        // 失败时照常下移
+        factory.deal(
+            &TradeResult::Failed,
+            20,
+            Some(IntervalRelation::AgentBelowFactory),
+        );
+        assert!(factory.supply_price_range().1 < upper);
+    }
+
+    #[test]
+    fn test_first_mover_bonus() {
+        let product = Product::from(
+            1,
+            "test_product".to_string(),
+            NormalDistribution::new(50.0, 1, "price_dist".to_string(), 10.0),
+            NormalDistribution::new(0.5, 1, "elastic_dist".to_string(), 0.1),
+        );
+        let config = FactoryConfig {
+            range_init: RangeInitStrategy::CostPlusMarkup { markup: 1.5 },
+            unit_cost: 40.0,
+            ..Default::default()
+        };
+        let entrant = Factory::with_config(2, "entrant".to_string(), &product, &config);
+        let incumbent = Factory::with_config(1, "incumbent".to_string(), &product, &config)
+            .with_first_mover_bonus(0.25);
+
+        // 先发工厂成本更低、区间向下加宽，上界与后来者相同
+        assert_eq!(entrant.unit_cost, 40.0);
+        assert_eq!(entrant.supply_price_range(), (40.0, 60.0));
+        assert_eq!(incumbent.unit_cost, 30.0);
+        assert_eq!(incumbent.supply_price_range(), (30.0, 60.0));
+    }
+
+    #[test]
+    fn test_range_init_strategies() {
+        let product = Product::from(
+            1,
+            "test_product".to_string(),
+            NormalDistribution::new(50.0, 1, "price_dist".to_string(), 10.0),
+            NormalDistribution::new(0.5, 1, "elastic_dist".to_string(), 0.1),
+        );
+        let range = |range_init: RangeInitStrategy, unit_cost: f64| {
+            let config = FactoryConfig {
+                range_init,
+                unit_cost,
+                ..Default::default()
+            };
+            Factory::with_config(1, "test_factory".to_string(), &product, &config)
+                .supply_price_range()
+        };
+        let valid = |(lower, upper): (f64, f64), unit_cost: f64| {
+            lower >= unit_cost && upper >= lower + 0.01 && upper.is_finite()
+        };
+
+        // 随机区间：成本高于随机下界时下界被抬到成本
+        for _ in 0..50 {
+            assert!(valid(range(RangeInitStrategy::RandomAroundReference, 0.0), 0.0));
+            assert!(valid(range(RangeInitStrategy::RandomAroundReference, 40.0), 40.0));
+        }
+
+        // 分位数区间：中间50%为均值±0.674个标准差
+        let (lower, upper) = range(RangeInitStrategy::QuantileBand { mass: 0.5 }, 0.0);
+        assert!((lower - 43.255).abs() < 0.01);
+        assert!((upper - 56.745).abs() < 0.01);
+        let floored = range(RangeInitStrategy::QuantileBand { mass: 0.5 }, 45.0);
+        assert!(valid(floored, 45.0));
+        assert_eq!(floored.0, 45.0);
+        // 成本高于整个区间时，区间收窄到成本之上的最小宽度
+        let above = range(RangeInitStrategy::QuantileBand { mass: 0.5 }, 80.0);
+        assert!(valid(above, 80.0));
+
+        // 成本加成
+        assert_eq!(
+            range(RangeInitStrategy::CostPlusMarkup { markup: 1.5 }, 20.0),
+            (20.0, 30.0)
+        );
+        assert!(valid(range(RangeInitStrategy::CostPlusMarkup { markup: 1.0 }, 20.0), 20.0));
+    }
+
+    #[test]
+    fn test_shrinkflation() {
+        let product = Product::new(1, "test_product".to_string());
+        let config = FactoryConfig {
+            price_increase_ratio: 0.01,
+            shrink_ratio: 0.1,
+            min_unit_size: 0.5,
+            ..Default::default()
+        };
+        let mut factory = Factory::with_config(1, "test_factory".to_string(), &product, &config);
+        let sink = crate::logging::MemorySink::new();
+        factory.set_logger(Some(Logger::with_sink(
+            "shrinkflation_test".to_string(),
+            std::sync::Arc::new(sink.clone()),
+        )));
+        factory.supply_price_range = (100.0, 200.0);
+
+        // 持续成交的压力下缩小规格，报价区间保持不变
+        for round in 1..=5 {
+            factory.start_round(round);
+            factory.deal(&TradeResult::Success(150.0), round, None);
+        }
+        assert!((factory.unit_size() - 0.9_f64.powi(5)).abs() < 1e-9);
+        let (lower, upper) = factory.supply_price_range();
+        assert!((lower - 100.0).abs() < 1e-9 && (upper - 200.0).abs() < 1e-9);
+        assert_eq!(factory.unit_size_history().len(), 5);
+        assert!(factory.unit_size_history().back().unwrap().1 < 1.0);
+        // 第2轮起每轮开始时记录上一轮缩小的规格
+        let changes: Vec<String> = sink
+            .rows()
+            .into_iter()
+            .filter_map(|row| match row {
+                crate::logging::LoggedRow::FactoryRangeOptimization(log)
+                    if log.trade_result.starts_with("UnitSize") =>
+                {
+                    Some(log.trade_result)
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(changes.len(), 4);
+        assert_eq!(changes[0], "UnitSize 1.000 -> 0.900");
+
+        // 缩到下限后恢复涨价
+        for round in 6..=20 {
+            factory.start_round(round);
+            factory.deal(&TradeResult::Success(150.0), round, None);
+        }
+        assert_eq!(factory.unit_size(), 0.5);
+        assert!(factory.supply_price_range().0 > 100.0);
+    }
+
+    #[test]
+    fn test_repair_narrow_range() {
+        let product = Product::new(1, "test_product".to_string());
+        let config = FactoryConfig {
+            min_range_width: 0.5,
+            ..Default::default()
+        };
+        let mut factory = Factory::with_config(1, "test_factory".to_string(), &product, &config);
+        let current_round = 1;
+        factory.start_round(current_round);
+
+        // 几乎为0的区间下移后仍保持最小宽度
+        factory.supply_price_range = (0.02, 0.02);
+        factory.deal(
+            &TradeResult::Failed,
+            current_round,
+            Some(IntervalRelation::AgentBelowFactory),
+        );
+        let (lower, upper) = factory.supply_price_range();
+        assert!(lower >= 0.0);
+        assert!(upper >= lower + 0.5 - 1e-9);
+
+        // 已倒置的区间在调整后被修复
+        factory.supply_price_range = (10.0, 5.0);
+        factory.deal(&TradeResult::Success(8.0), current_round, None);
+        let (lower, upper) = factory.supply_price_range();
+        assert!(upper >= lower + 0.5 - 1e-9);
+    }
 }
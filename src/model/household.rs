@@ -0,0 +1,108 @@
+use crate::model::util::gen_purchase_in_range;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// 家庭：多个agent共用一个资金池，同一轮内同一商品只买一次
+pub struct Household {
+    id: u64,
+    agent_ids: Vec<u64>,
+    cash: Mutex<f64>,
+    purchases: Mutex<HashMap<u64, u64>>, // 商品ID -> 最近一次购买的轮次
+}
+
+impl Household {
+    pub fn new(id: u64, agent_ids: Vec<u64>, cash: f64) -> Self {
+        Household {
+            id,
+            agent_ids,
+            cash: Mutex::new(cash),
+            purchases: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn agent_ids(&self) -> &[u64] {
+        &self.agent_ids
+    }
+
+    pub fn cash(&self) -> f64 {
+        *self.cash.lock().unwrap()
+    }
+
+    /// 向共享资金存入一笔收入
+    pub fn deposit(&self, amount: f64) {
+        *self.cash.lock().unwrap() += amount;
+    }
+
+    /// 从共享资金中支取一笔钱，余额不足时不扣款并返回false
+    pub fn withdraw(&self, amount: f64) -> bool {
+        let mut cash = self.cash.lock().unwrap();
+        if *cash < amount {
+            return false;
+        }
+        *cash -= amount;
+        true
+    }
+
+    /// 本轮是否已有家庭成员买过该商品
+    pub fn has_purchased(&self, product_id: u64, round: u64) -> bool {
+        self.purchases.lock().unwrap().get(&product_id) == Some(&round)
+    }
+
+    /// 用共享资金在成交区间内购买最多quantity件，花费不超过budget，成功时扣款并记录本轮已购买，
+    /// 返回(单价, 数量)。加锁完成出价与扣款，避免不同商品线程同时花同一笔钱
+    pub fn purchase(
+        &self,
+        product_id: u64,
+        round: u64,
+        range: (f64, f64),
+        quantity: i16,
+        budget: f64,
+    ) -> Option<(f64, i16)> {
+        let mut purchases = self.purchases.lock().unwrap();
+        if purchases.get(&product_id) == Some(&round) {
+            return None;
+        }
+
+        let mut cash = self.cash.lock().unwrap();
+        let (price, quantity) = gen_purchase_in_range(range, cash.min(budget), quantity)?;
+        *cash -= price * quantity as f64;
+        purchases.insert(product_id, round);
+        Some((price, quantity))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_purchase() {
+        let household = Household::new(1, vec![1, 2], 100.0);
+
+        let (price, _) = household
+            .purchase(1, 1, (30.0, 40.0), 1, f64::INFINITY)
+            .unwrap();
+        assert!((household.cash() - (100.0 - price)).abs() < 1e-9);
+        assert!(household.has_purchased(1, 1));
+
+        // 同一轮同一商品不重复购买
+        assert!(
+            household
+                .purchase(1, 1, (30.0, 40.0), 1, f64::INFINITY)
+                .is_none()
+        );
+        // 超出预算时不购买
+        assert!(household.purchase(1, 2, (30.0, 40.0), 1, 20.0).is_none());
+        // 下一轮可以再买
+        assert!(
+            household
+                .purchase(1, 2, (30.0, 40.0), 1, f64::INFINITY)
+                .is_some()
+        );
+        assert!(!household.has_purchased(1, 3));
+    }
+}
@@ -1,282 +1,3636 @@
-use crate::logging::{log_agent_cash, log_trade};
-use crate::model::agent::{Agent, TradeResult};
-use crate::model::factory::Factory;
+use crate::config::{ClearingMode, MarketConfig, ProcessOrder, Shock, StallAction, TieBreak};
+use crate::logging::{
+    Logger, NullSink, flush_logs, log_agent_cash, log_factory_offer, log_negotiation_attempt,
+    log_run_end, log_trade,
+};
+use crate::model::agent::{Agent, IntervalRelation, TradeResult};
+use crate::model::factory::{Factory, FinancialBill};
+use crate::model::household::Household;
 use crate::model::product::Product;
-use rand::Rng;
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
+use rand::{Rng, RngCore, SeedableRng};
 use rayon::prelude::*;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::sync::Arc;
 use std::sync::RwLock;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
 use std::thread::JoinHandle;
 
+/// 一次区间变化。agent的区间按商品记录，工厂的product_id即其商品
+#[derive(Clone, Debug, PartialEq)]
+pub struct RangeChange {
+    pub id: u64,
+    pub product_id: u64,
+    pub before: (f64, f64),
+    pub after: (f64, f64),
+}
+
+/// 一轮中发生变化的agent偏好区间和工厂报价区间
+#[derive(Clone, Debug)]
+pub struct RoundDiff {
+    pub round: u64,
+    pub agents: Vec<RangeChange>,
+    pub factories: Vec<RangeChange>,
+}
+
+/// 模拟结束的原因
+#[derive(Clone, Debug, PartialEq)]
+pub enum EndReason {
+    /// 达到最大轮数
+    MaxRounds,
+    /// 所有agent资金耗尽
+    AllAgentsBroke,
+    /// 连续多轮没有成交
+    NoTrades,
+    /// 供需僵局
+    Stalled,
+    /// 停止标志被设置，例如收到Ctrl-C
+    Interrupted,
+    /// 所有商品的成交价连续多轮保持稳定
+    Converged,
+}
+
+/// run的结果
+#[derive(Clone, Debug)]
+pub struct SimulationOutcome {
+    pub reason: EndReason,
+    /// 最后完成的轮次
+    pub rounds: u64,
+    pub total_trades: u64,
+}
+
+/// bench的结果
+#[derive(Clone, Debug)]
+pub struct BenchReport {
+    pub rounds: u64,
+    pub total_trades: u64,
+    pub elapsed: std::time::Duration,
+    /// 每秒完成的轮数
+    pub rounds_per_sec: f64,
+}
+
+/// 需求到成交的转化漏斗，按撮合次数统计：同一个有需求的agent依次遇到多个工厂时每次都计一次结果
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ConversionFunnel {
+    /// 撮合开始时有需求的agent数
+    pub demands: u64,
+    /// 成交
+    pub successes: u64,
+    /// 有需求但本次没有参与撮合（等待老工厂、临近发薪日等）
+    pub not_matched: u64,
+    /// agent区间整体低于工厂报价
+    pub price_too_high: u64,
+    /// agent区间整体高于工厂报价
+    pub price_too_low: u64,
+    /// 价格区间有交集但现金或消费上限不足
+    pub cash_shortfall: u64,
+    /// 所有工厂售罄时仍有需求的agent数
+    pub stockouts: u64,
+}
+
+impl ConversionFunnel {
+    /// 累加另一个漏斗的计数
+    pub fn add(&mut self, other: &ConversionFunnel) {
+        self.demands += other.demands;
+        self.successes += other.successes;
+        self.not_matched += other.not_matched;
+        self.price_too_high += other.price_too_high;
+        self.price_too_low += other.price_too_low;
+        self.cash_shortfall += other.cash_shortfall;
+        self.stockouts += other.stockouts;
+    }
+
+    /// 成交占需求的比例，没有需求时为0
+    pub fn conversion_rate(&self) -> f64 {
+        if self.demands == 0 {
+            return 0.0;
+        }
+        self.successes as f64 / self.demands as f64
+    }
+}
+
+/// 每个商品保留的最近成交价数量
+const PRICE_HISTORY: usize = 100;
+
+/// 收敛判定时计算波动所用的最近成交笔数
+const CONVERGENCE_SAMPLE: usize = 20;
+/// 报价相差不超过该值的工厂视为同价
+const PRICE_TIE_EPSILON: f64 = 1e-9;
+
 pub struct Market {
-    factories: HashMap<u64, Arc<RwLock<Vec<Factory>>>>,
+    factories: BTreeMap<u64, Arc<RwLock<Vec<Factory>>>>, // 按商品ID有序，遍历顺序在多次运行间一致
     products: Vec<Product>,
     agents: Arc<RwLock<Vec<Arc<RwLock<Agent>>>>>,
     consecutive_zero_trades: u32, // 跟踪连续0成交量的轮次数
+    total_trades: u64,            // 累计成交数
+    round: u64,                   // 当前轮次
+    config: MarketConfig,
+    untradable_products: HashSet<u64>, // 在营工厂数不足、暂停交易的商品
+    stalled_rounds: u32,               // 连续处于供需僵局的轮次数
+    converged_rounds: u32,             // 连续所有商品成交价都稳定的轮次数
+    logger: Option<Logger>,            // 市场专属日志记录器，None时不记录日志
+    removed_tax_revenue: f64,          // 已退出市场的工厂缴纳的生产者税
+    distributed_profits: f64,          // 工厂累计分给所有者的利润
+    round_diffs: VecDeque<RoundDiff>,  // 最近几轮的区间变化，最多保留config.diff_capacity轮
+    stop: Arc<AtomicBool>,             // 停止标志，run在当前轮结束后检查
+    recent_prices: Arc<RwLock<BTreeMap<u64, VecDeque<f64>>>>, // 每个商品最近的成交价
+    active_shocks: Vec<(Shock, u64)>,  // 尚未到期的临时冲击及其到期轮次
+    funnel: ConversionFunnel,          // 累计的需求转化漏斗
+    demand_rng: StdRng,                // 按轮生成需求使用的随机数生成器，设置了seed时可复现
+    base_prices: BTreeMap<u64, f64>,   // 价格指数一篮子商品的基期均价
+    price_index: f64,                  // 最近一轮结束时的价格指数，基期为1
 }
 
 impl Market {
     pub fn new(products: Vec<Product>) -> Self {
-        let mut factories = HashMap::new();
-        let mut agents_vec = Vec::new();
-        let mut rng = rand::thread_rng();
+        Self::with_config(products, MarketConfig::default())
+    }
+
+    pub fn with_config(products: Vec<Product>, config: MarketConfig) -> Self {
+        let mut factories = Vec::new();
+        let mut agents = Vec::new();
         let mut factory_id_counter = 1;
 
         // 为每个产品创建3或4个工厂
         for product in &products {
-            let factory_count = if rng.gen_bool(0.5) { 3 } else { 4 };
-            let mut product_factories = Vec::with_capacity(factory_count);
+            // 设置了全局种子时每个商品使用独立的种子，改变一个商品的seed_offset不影响其他商品
+            let mut product_rng: Box<dyn RngCore> = match config.seed {
+                Some(seed) => Box::new(StdRng::seed_from_u64(Self::product_seed(seed, product))),
+                None => Box::new(rand::thread_rng()),
+            };
+            let factory_count = if product_rng.gen_bool(0.5) { 3 } else { 4 };
 
             for i in 0..factory_count {
-                let factory = Factory::new(
+                let factory = Factory::with_rng(
                     factory_id_counter,
                     format!("{}_{}", product.name(), i),
                     product,
+                    &config.factory,
+                    &mut product_rng,
                 );
-                product_factories.push(factory);
+                // 每个商品第一个创建的工厂是在位者，可获得先发优势
+                let factory = if i == 0 && config.factory.first_mover_bonus > 0.0 {
+                    factory.with_first_mover_bonus(config.factory.first_mover_bonus)
+                } else {
+                    factory
+                };
+                factories.push(factory);
                 factory_id_counter += 1;
             }
-
-            // 将工厂列表包装为Arc<RwLock<Vec<Factory>>>
-            factories.insert(product.id(), Arc::new(RwLock::new(product_factories)));
         }
         println!("before agent created");
-        // 生成100个消费者，每个消费者初始有10万块钱
+        // 生成100个消费者，每个消费者初始有10万块钱，所有消费者共用一份商品列表
+        let shared_products = Arc::new(products.clone());
+        // 设置了全局种子时每个agent按种子和agent ID播种，个性和偏好可复现
         for agent_id in 1..=100 {
-            let agent = Agent::new(
+            let rng = match config.seed {
+                Some(seed) => StdRng::seed_from_u64(Self::agent_seed(seed, agent_id)),
+                None => StdRng::from_entropy(),
+            };
+            let agent = Agent::with_shared_products(
                 agent_id,
                 format!("Consumer_{}", agent_id),
                 1000.0,
-                &products,
+                shared_products.clone(),
+                &config.agent,
+                rng,
             );
-            agents_vec.push(Arc::new(RwLock::new(agent)));
+            agents.push(agent);
         }
         println!("after agents created");
+        Self::from_parts_with_config(products, agents, factories, config)
+    }
+
+    /// 用调用方构造好的agent和工厂创建市场，例如指定的消费者群体或垄断工厂，使用默认参数
+    pub fn from_parts(products: Vec<Product>, agents: Vec<Agent>, factories: Vec<Factory>) -> Self {
+        Self::from_parts_with_config(products, agents, factories, MarketConfig::default())
+    }
+
+    /// 同from_parts，使用指定的市场参数。工厂按所属商品分组，agent按household_size组建家庭
+    pub fn from_parts_with_config(
+        products: Vec<Product>,
+        agents: Vec<Agent>,
+        factories: Vec<Factory>,
+        config: MarketConfig,
+    ) -> Self {
+        // 每个商品都有一个工厂列表，没有工厂的商品为空列表
+        let mut grouped: BTreeMap<u64, Vec<Factory>> =
+            products.iter().map(|p| (p.id(), Vec::new())).collect();
+        for factory in factories {
+            grouped
+                .entry(factory.product_id())
+                .or_default()
+                .push(factory);
+        }
+        let factories = grouped
+            .into_iter()
+            .map(|(product_id, list)| (product_id, Arc::new(RwLock::new(list))))
+            .collect();
+
+        let agents_vec: Vec<Arc<RwLock<Agent>>> = agents
+            .into_iter()
+            .map(|a| Arc::new(RwLock::new(a)))
+            .collect();
+        Self::form_households(&agents_vec, config.agent.household_size);
+        let demand_rng = match config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
         Market {
             factories,
             products,
             agents: Arc::new(RwLock::new(agents_vec)),
             consecutive_zero_trades: 0, // 初始化连续0成交量轮次为0
+            total_trades: 0,
+            round: 0,
+            config,
+            untradable_products: HashSet::new(),
+            stalled_rounds: 0,
+            converged_rounds: 0,
+            logger: None,
+            removed_tax_revenue: 0.0,
+            distributed_profits: 0.0,
+            round_diffs: VecDeque::new(),
+            stop: Arc::new(AtomicBool::new(false)),
+            recent_prices: Arc::new(RwLock::new(BTreeMap::new())),
+            active_shocks: Vec::new(),
+            funnel: ConversionFunnel::default(),
+            demand_rng,
+            base_prices: BTreeMap::new(),
+            price_index: 1.0,
         }
     }
 
-    pub fn run(&mut self) {
-        let mut rng = rand::thread_rng();
-        let mut round = 1;
-        let mut total_trades = 0;
-        const MAX_ROUND: u64 = 8000;
+    /// 按agent ID顺序为每个agent生成一次需求，顺序不受本轮打乱的影响，固定种子时结果可复现
+    pub fn generate_round_demands(&mut self) {
+        let mut agents: Vec<Arc<RwLock<Agent>>> = self.agents.read().unwrap().clone();
+        agents.sort_by_key(|agent| agent.read().unwrap().id());
+        for agent in agents {
+            agent.read().unwrap().generate_demands(&mut self.demand_rng);
+        }
+    }
 
-        loop {
-            println!("Starting round {}, Total trades: {}", round, total_trades);
-            let mut factories = self.factories.clone();
-            // 打乱所有工厂的顺序
-            for (_product_id, factory_list_arc) in factories.iter_mut() {
-                let mut factory_list = factory_list_arc.write().unwrap();
-                factory_list.shuffle(&mut rng);
+    /// 使用市场专属的日志记录器（独立的task_id和写入目标），多个市场可以并发运行
+    /// 按log_sample_ratio只记录部分agent/工厂的逐实体日志，按log_batch_size批量写入，
+    /// log_queue_capacity大于0时由后台线程写入
+    pub fn with_logger(mut self, logger: Logger) -> Self {
+        self.logger = Some(
+            logger
+                .with_sample_ratio(self.config.log_sample_ratio)
+                .with_batch_size(self.config.log_batch_size)
+                .with_queue(self.config.log_queue_capacity, self.config.log_overflow),
+        );
+        self.share_logger();
+        self
+    }
+
+    /// 把市场的日志记录器交给所有agent和工厂，它们的逐实体日志写入同一个记录器
+    fn share_logger(&self) {
+        for agent in self.agents.read().unwrap().iter() {
+            agent.read().unwrap().set_logger(self.logger.clone());
+        }
+        for list in self.factories.values() {
+            for factory in list.write().unwrap().iter_mut() {
+                factory.set_logger(self.logger.clone());
             }
+        }
+    }
 
-            // 打乱所有消费者的顺序
-            {
-                let mut agents = self.agents.write().unwrap();
-                agents.shuffle(&mut rng);
+    /// 由全局种子、商品ID和商品的seed_offset组合出该商品的种子
+    fn product_seed(seed: u64, product: &Product) -> u64 {
+        seed.wrapping_add(product.id().wrapping_mul(0x9E37_79B9_7F4A_7C15))
+            .wrapping_add(product.seed_offset())
+    }
+
+    /// 由全局种子和agent ID组合出该agent的种子，与商品的种子错开
+    fn agent_seed(seed: u64, agent_id: u64) -> u64 {
+        seed.wrapping_add(agent_id.wrapping_mul(0xC2B2_AE3D_27D4_EB4F)) ^ 0xA5A5_A5A5_A5A5_A5A5
+    }
+
+    /// 按顺序把agent分组为家庭，成员的资金并入家庭资金池
+    fn form_households(agents: &[Arc<RwLock<Agent>>], household_size: usize) {
+        if household_size <= 1 {
+            return;
+        }
+        for (index, members) in agents.chunks(household_size).enumerate() {
+            let mut members: Vec<_> = members.iter().map(|a| a.write().unwrap()).collect();
+            let agent_ids = members.iter().map(|a| a.id()).collect();
+            let cash = members.iter().map(|a| a.cash()).sum();
+            let household = Arc::new(Household::new(index as u64 + 1, agent_ids, cash));
+            for member in members.iter_mut() {
+                member.join_household(household.clone());
             }
+        }
+    }
 
-            // 获取产品ID列表
-            let product_ids: Vec<u64> = self.products.iter().map(|p| p.id()).collect();
-            let mut handles: Vec<JoinHandle<_>> = Vec::new();
-            let round_trades: Arc<RwLock<u64>> = Arc::new(RwLock::new(0));
-            for i in 0..product_ids.len() {
-                let product_id = product_ids[i];
-                let products = self.products.clone();
-                let f = factories.get(&product_id);
-                if f.is_none() {
-                    continue;
-                }
-                let f_list = f.unwrap().clone();
-                let agents = self.agents.clone();
-                let mut counter = round_trades.clone();
-                let h = thread::spawn(move || {
-                    let count = process_product_trades(products, f_list, agents, round, product_id);
-                    let mut c = counter.write().unwrap();
-                    *c += count;
-                });
-                handles.push(h);
+    /// 商品当前的在营工厂数量
+    pub fn factory_count(&self, product_id: u64) -> usize {
+        self.factories
+            .get(&product_id)
+            .map(|list| list.read().unwrap().len())
+            .unwrap_or(0)
+    }
+
+    /// 商品在所有在营工厂的指定轮次库存之和，没有工厂时为0
+    pub fn total_stock(&self, product_id: u64, round: u64) -> u64 {
+        self.factories.get(&product_id).map_or(0, |list| {
+            list.read()
+                .unwrap()
+                .iter()
+                .map(|f| f.get_stock(round).max(0) as u64)
+                .sum()
+        })
+    }
+
+    /// 价格指数：一篮子商品最近成交均价相对基期均价之比的平均值，基期之前和没有成交时为1
+    pub fn price_index(&self) -> f64 {
+        self.price_index
+    }
+
+    /// 按价格指数平减的实际现金
+    pub fn real_cash(&self, cash: f64) -> f64 {
+        cash / self.price_index
+    }
+
+    /// 每轮成交结束后更新价格指数：到达基期后有成交的商品记录基期均价，
+    /// 指数为各商品当前均价与基期均价之比的平均值
+    fn update_price_index(&mut self, round: u64) {
+        if round < self.config.price_index_base_round {
+            return;
+        }
+        let prices = self.recent_prices.read().unwrap();
+        let mut ratios = Vec::new();
+        for (product_id, history) in prices.iter().filter(|(_, h)| !h.is_empty()) {
+            let mean = history.iter().sum::<f64>() / history.len() as f64;
+            let base = *self.base_prices.entry(*product_id).or_insert(mean);
+            if base > 0.0 {
+                ratios.push(mean / base);
             }
+        }
+        if !ratios.is_empty() {
+            self.price_index = ratios.iter().sum::<f64>() / ratios.len() as f64;
+        }
+    }
 
-            // 等待所有线程完成
-            for h in handles {
-                h.join().expect("error ");
+    /// 商品在营工厂的平均加成率：指定轮次实际报价与单位成本之比的均值。
+    /// 没有成本的工厂不参与计算，没有可计算的工厂时为0
+    pub fn average_markup(&self, product_id: u64, round: u64) -> f64 {
+        let Some(list) = self.factories.get(&product_id) else {
+            return 0.0;
+        };
+        let markups: Vec<f64> = list
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|f| f.unit_cost() > 0.0)
+            .map(|f| f.current_offer(round) / f.unit_cost())
+            .collect();
+        if markups.is_empty() {
+            return 0.0;
+        }
+        markups.iter().sum::<f64>() / markups.len() as f64
+    }
+
+    /// 新工厂进入市场
+    pub fn add_factory(&mut self, mut factory: Factory) {
+        factory.set_logger(self.logger.clone());
+        let list = self
+            .factories
+            .entry(factory.product_id())
+            .or_insert_with(|| Arc::new(RwLock::new(Vec::new())));
+        list.write().unwrap().push(factory);
+    }
+
+    /// 工厂退出市场：先清算剩余库存，再从列表中移除，返回清算结算单
+    pub fn remove_factory(&mut self, factory_id: u64) -> Option<FinancialBill> {
+        for list in self.factories.values() {
+            let mut list = list.write().unwrap();
+            if let Some(index) = list.iter().position(|f| f.id() == factory_id) {
+                let bill = list[index].liquidate(self.round);
+                println!(
+                    "Factory {} liquidated at round {}, written off stock: {}",
+                    factory_id, bill.round, bill.written_off_stock
+                );
+                self.removed_tax_revenue += bill.tax_paid;
+                list.remove(index);
+                return Some(bill);
             }
+        }
+        None
+    }
 
-            // 汇总本轮交易数
-            let current_round_trades = {
-                let r = round_trades.read().unwrap();
-                *r
-            };
-            total_trades += current_round_trades;
+    /// 在当前轮次让指定agent以price向指定工厂买入1件商品，不经过随机撮合，
+    /// 用于把市场置于已知的成交后状态。工厂库存和agent资金都会校验，成交照常记录交易日志
+    pub fn force_trade(
+        &mut self,
+        agent_id: u64,
+        factory_id: u64,
+        product_id: u64,
+        price: f64,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let round = self.round;
+        let product = self
+            .products
+            .iter()
+            .find(|p| p.id() == product_id)
+            .ok_or_else(|| format!("product {} not found", product_id))?
+            .clone();
+        let factory_list = self
+            .factories
+            .get(&product_id)
+            .ok_or_else(|| format!("no factories for product {}", product_id))?
+            .clone();
+        let agent = self
+            .agents
+            .read()
+            .unwrap()
+            .iter()
+            .find(|a| a.read().unwrap().id() == agent_id)
+            .cloned()
+            .ok_or_else(|| format!("agent {} not found", agent_id))?;
 
-            // 更新连续0成交量轮次计数
-            if current_round_trades == 0 {
-                self.consecutive_zero_trades += 1;
-            } else {
-                self.consecutive_zero_trades = 0;
+        let mut factory_list = factory_list.write().unwrap();
+        let factory = factory_list
+            .iter_mut()
+            .find(|f| f.id() == factory_id)
+            .ok_or_else(|| {
+                format!(
+                    "factory {} not found for product {}",
+                    factory_id, product_id
+                )
+            })?;
+        if !factory.is_round_started(round) {
+            factory.start_round(round);
+        }
+
+        agent.write().unwrap().force_trade(factory, round, price)?;
+        let trade_result = TradeResult::Success(price);
+        factory.deal_units(&trade_result, round, None, 1);
+        log_trade(
+            self.logger.as_ref(),
+            round,
+            agent.clone(),
+            factory,
+            &product,
+            &trade_result,
+            "Forced",
+        )?;
+        record_trade_price(&self.recent_prices, product_id, price);
+        self.total_trades += 1;
+        Ok(())
+    }
+
+    /// agent资金分布：把[最低资金, 最高资金]等分为bucket_count个区间，返回每个区间的(下限, agent数)。
+    /// 所有agent资金相同时只返回一个区间。家庭成员按家庭共享资金计
+    pub fn wealth_histogram(&self, bucket_count: usize) -> Vec<(f64, usize)> {
+        let cash: Vec<f64> = self
+            .agents
+            .read()
+            .unwrap()
+            .iter()
+            .map(|a| a.read().unwrap().cash())
+            .collect();
+        histogram(&cash, bucket_count)
+    }
+
+    /// 工厂累计分给所有者的利润
+    pub fn distributed_profits(&self) -> f64 {
+        self.distributed_profits
+    }
+
+    /// 每轮结束时所有工厂按dividend_ratio分红，计入distributed_profits
+    fn pay_dividends(&mut self) {
+        for list in self.factories.values() {
+            for factory in list.write().unwrap().iter_mut() {
+                self.distributed_profits += factory.pay_dividend();
             }
+        }
+    }
 
-            // 记录每个agent的现金情况
-            {
-                // 生成一个共享的timestamp，确保同一轮的所有log_cash事件使用同一个时间戳
-                let timestamp = std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .expect("Failed to get system time")
-                    .as_millis() as i64;
-
-                let agents = self.agents.read().unwrap();
-                for agent in agents.iter() {
-                    let a = agent.read().unwrap();
-                    if let Err(e) = log_agent_cash(
-                        timestamp,
-                        round,
-                        a.id(),
-                        a.name().to_string(),
-                        a.cash(),
-                        total_trades,
-                    ) {
-                        eprintln!("Failed to log agent cash: {}", e);
-                    }
-                }
+    /// 所有agent尚未满足的需求总数，反映未被满足的消费需要
+    pub fn outstanding_demand(&self) -> usize {
+        self.agents
+            .read()
+            .unwrap()
+            .iter()
+            .map(|a| a.read().unwrap().demand_count())
+            .sum()
+    }
+
+    /// 累计征收的生产者税，包括已退出市场的工厂缴纳的部分
+    pub fn tax_revenue(&self) -> f64 {
+        let current: f64 = self
+            .factories
+            .values()
+            .flat_map(|list| {
+                list.read()
+                    .unwrap()
+                    .iter()
+                    .map(|f| f.tax_paid())
+                    .collect::<Vec<_>>()
+            })
+            .sum();
+        current + self.removed_tax_revenue
+    }
+
+    /// 商品最近window笔成交价的标准差，成交不足两笔时为0。
+    /// 只保留最近PRICE_HISTORY笔成交价，window超过时按已有的计算
+    pub fn price_volatility(&self, product_id: u64, window: usize) -> f64 {
+        let prices = self.recent_prices.read().unwrap();
+        let Some(history) = prices.get(&product_id) else {
+            return 0.0;
+        };
+        let recent: Vec<f64> = history.iter().rev().take(window).cloned().collect();
+        if recent.len() < 2 {
+            return 0.0;
+        }
+        let mean = recent.iter().sum::<f64>() / recent.len() as f64;
+        let variance = recent.iter().map(|p| (p - mean).powi(2)).sum::<f64>() / recent.len() as f64;
+        variance.sqrt()
+    }
+
+    /// 最近几轮的区间变化记录，按轮次从早到晚。diff_capacity为0时始终为空
+    pub fn round_diffs(&self) -> &VecDeque<RoundDiff> {
+        &self.round_diffs
+    }
+
+    /// 当前所有agent每个商品的偏好区间，键为(agent ID, 商品ID)
+    fn snapshot_agent_ranges(&self) -> HashMap<(u64, u64), (f64, f64)> {
+        let mut ranges = HashMap::new();
+        for agent in self.agents.read().unwrap().iter() {
+            let agent = agent.read().unwrap();
+            for (product_id, preference) in agent.preferences().iter() {
+                ranges.insert((agent.id(), *product_id), preference.current_range);
             }
+        }
+        ranges
+    }
 
-            // 检查是否所有agent的余额为0
-            let agents = self.agents.read().unwrap();
-            let all_agents_broke = agents.iter().all(|agent| {
-                let a = agent.read().unwrap();
-                a.cash() < 0.01
-            });
+    /// 当前所有工厂的报价区间，键为(工厂ID, 商品ID)
+    fn snapshot_factory_ranges(&self) -> HashMap<(u64, u64), (f64, f64)> {
+        let mut ranges = HashMap::new();
+        for (product_id, list) in self.factories.iter() {
+            for factory in list.read().unwrap().iter() {
+                ranges.insert((factory.id(), *product_id), factory.supply_price_range());
+            }
+        }
+        ranges
+    }
 
-            // 检查退出条件
-            if round > MAX_ROUND || all_agents_broke || self.consecutive_zero_trades >= 20 {
-                println!("Simulation ending...");
-                if round > MAX_ROUND {
-                    println!("Reason: Reached maximum rounds ({})\n", MAX_ROUND);
-                }
-                if all_agents_broke {
-                    println!("Reason: All agents have zero or negative cash.\n");
-                }
-                if self.consecutive_zero_trades >= 20 {
+    /// 对比本轮前后的区间，记录发生变化的部分，超出容量时丢弃最早的一轮
+    fn record_round_diff(
+        &mut self,
+        round: u64,
+        agents_before: HashMap<(u64, u64), (f64, f64)>,
+        factories_before: HashMap<(u64, u64), (f64, f64)>,
+    ) {
+        let changes = |before: HashMap<(u64, u64), (f64, f64)>,
+                       after: HashMap<(u64, u64), (f64, f64)>| {
+            let mut changes: Vec<RangeChange> = after
+                .into_iter()
+                .filter_map(|(key, after)| {
+                    // 本轮新生成的偏好没有之前的区间，不算变化
+                    let before = *before.get(&key)?;
+                    (before != after).then_some(RangeChange {
+                        id: key.0,
+                        product_id: key.1,
+                        before,
+                        after,
+                    })
+                })
+                .collect();
+            changes.sort_by_key(|c| (c.id, c.product_id));
+            changes
+        };
+        let diff = RoundDiff {
+            round,
+            agents: changes(agents_before, self.snapshot_agent_ranges()),
+            factories: changes(factories_before, self.snapshot_factory_ranges()),
+        };
+
+        self.round_diffs.push_back(diff);
+        while self.round_diffs.len() > self.config.diff_capacity {
+            self.round_diffs.pop_front();
+        }
+    }
+
+    /// 商品是否属于必需品档位
+    fn is_essential(&self, product_id: u64) -> bool {
+        self.products
+            .iter()
+            .any(|p| p.id() == product_id && p.is_essential())
+    }
+
+    /// 当前因在营工厂数不足而暂停交易的商品
+    pub fn untradable_products(&self) -> Vec<u64> {
+        let mut ids: Vec<u64> = self.untradable_products.iter().cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    /// 按min_viable_factories检查每个商品是否可交易，更新暂停标记，返回本轮可交易的商品ID
+    pub fn refresh_viability(&mut self) -> Vec<u64> {
+        let mut tradable = Vec::new();
+        for product in &self.products {
+            let product_id = product.id();
+            let count = self.factory_count(product_id);
+            if count < self.config.min_viable_factories {
+                if self.untradable_products.insert(product_id) {
                     println!(
-                        "Reason: No trades for {} consecutive rounds.\n",
-                        self.consecutive_zero_trades
+                        "Product {} is untradable: {} factories, at least {} required",
+                        product_id, count, self.config.min_viable_factories
                     );
                 }
-                break;
+            } else {
+                if self.untradable_products.remove(&product_id) {
+                    println!("Product {} is tradable again with {} factories", product_id, count);
+                }
+                tradable.push(product_id);
             }
-
-            round += 1;
-            thread::sleep(std::time::Duration::from_millis(100));
         }
+        tradable
     }
-}
 
-/// 处理单个商品的交易逻辑（线程安全版本）
-fn process_product_trades(
-    products: Vec<Product>,
-    factories: Arc<RwLock<Vec<Factory>>>,
-    agents: Arc<RwLock<Vec<Arc<RwLock<Agent>>>>>,
-    round: u64,
-    product_id: u64,
-) -> u64 {
-    println!("dealing:{:?}", product_id);
-    let mut trades_count = 0;
-    let p = products.iter().find(|p| p.id() == product_id);
-    if p.is_none() {
-        return 0;
+    /// 当前没有任何在营工厂的商品ID，从小到大
+    pub fn unsupplied_products(&self) -> Vec<u64> {
+        let mut ids: Vec<u64> = self
+            .products
+            .iter()
+            .map(|p| p.id())
+            .filter(|id| self.factory_count(*id) == 0)
+            .collect();
+        ids.sort();
+        ids
     }
-    let product = p.unwrap();
-    // 查找产品
-    // 获取工厂列表的Arc副本
-    let mut factory_list_arc = factories.write().unwrap();
-    // 克隆Arc，以便在闭包中使用
-    let factory_list_arc_clone = factory_list_arc;
-    let agents_clone = agents.clone();
-    let product_clone = product.clone();
 
-    // 在闭包中处理工厂交易
-    let local_trades = {
-        let mut local_count = 0;
+    /// 开启suppress_unsupplied_demand时，让所有agent停止对没有工厂供应的商品产生需求
+    fn suppress_unsupplied_demand(&self, round: u64) {
+        if !self.config.suppress_unsupplied_demand {
+            return;
+        }
+        let unsupplied: HashSet<u64> = self.unsupplied_products().into_iter().collect();
+        for agent in self.agents.read().unwrap().iter() {
+            agent
+                .write()
+                .unwrap()
+                .set_unavailable_products(&unsupplied, round);
+        }
+    }
 
-        // 获取工厂列表的读写锁
-        let mut factory_list = factory_list_arc_clone;
+    /// 偏好区间上界低于工厂成本下限的需求永远无法成交，按agent的unreachable_range处理
+    fn resolve_unreachable_ranges(&self, round: u64) {
+        let cost_floors = self.cost_floors();
+        if cost_floors.values().all(|floor| *floor <= 0.0) {
+            return;
+        }
+        for agent in self.agents.read().unwrap().iter() {
+            agent
+                .write()
+                .unwrap()
+                .handle_unreachable_ranges(&cost_floors, round);
+        }
+    }
 
-        // 遍历商品下的工厂
-        for factory in factory_list.iter_mut() {
-            // 让工厂开启一次循环
-            factory.start_round(round);
+    /// 每个有在营工厂的商品的成本下限：各工厂单位成本和保留价中较高者的最小值，
+    /// 即最便宜的工厂愿意出售的最低价
+    fn cost_floors(&self) -> HashMap<u64, f64> {
+        self.factories
+            .iter()
+            .filter_map(|(product_id, list)| {
+                list.read()
+                    .unwrap()
+                    .iter()
+                    .map(|f| f.unit_cost().max(f.reservation_price()))
+                    .min_by(f64::total_cmp)
+                    .map(|floor| (*product_id, floor))
+            })
+            .collect()
+    }
 
-            // 获取agents的可变锁
-            let mut agents = agents_clone.read().unwrap();
+    /// 供需僵局：至少有一个商品有需求，且每个有需求的商品中，
+    /// 最低的工厂报价都高于所有有需求agent的区间上限
+    fn is_stalled(&self) -> bool {
+        let agents = self.agents.read().unwrap();
+        let mut has_demand = false;
+        for (product_id, factory_list) in self.factories.iter() {
+            let factory_list = factory_list.read().unwrap();
+            let Some(best_offer) = factory_list
+                .iter()
+                .map(|f| f.supply_price_range().0)
+                .min_by(|a, b| a.total_cmp(b))
+            else {
+                continue;
+            };
 
-            // 让每个agent与工厂进行交易
-            for a in agents.iter() {
-                // 检查工厂库存，如果为0则退出循环
-                if factory.get_stock(round) <= 0 {
-                    break;
+            for agent in agents.iter() {
+                let agent = agent.read().unwrap();
+                if !agent.has_demand(*product_id) {
+                    continue;
                 }
-                let has_demand = {
-                    let agent = a.read().unwrap();
-                    agent.has_demand(product_id)
+                has_demand = true;
+                let preferences = agent.preferences();
+                let Some(preference) = preferences.get(product_id) else {
+                    continue;
                 };
-                let mut trade_result = TradeResult::NotYet;
-                let mut interval_relation = None;
-                if !has_demand {
-                    trade_result = TradeResult::NotMatched;
-                } else {
-                    let mut agent = a.write().unwrap();
-                    // 调用agent的trade方法
-                    (trade_result, interval_relation) = agent.trade(factory, round);
+                if preference.current_range.1 >= best_offer {
+                    return false;
                 }
-                // 将interval_relation转换为字符串
-                let interval_relation_str = match &interval_relation {
-                    Some(rel) => match rel {
-                        crate::model::agent::IntervalRelation::Overlapping(_) => "Overlapping",
-                        crate::model::agent::IntervalRelation::AgentBelowFactory => {
-                            "AgentBelowFactory"
-                        }
-                        crate::model::agent::IntervalRelation::AgentAboveFactory => {
-                            "AgentAboveFactory"
-                        }
-                    },
-                    None => "None",
-                };
+            }
+        }
+        has_demand
+    }
+
+    /// 检测价格收敛：所有可交易商品都有成交，且最近CONVERGENCE_SAMPLE笔成交价的标准差
+    /// 不超过convergence_tolerance，连续convergence_rounds轮满足时返回true
+    pub fn check_convergence(&mut self) -> bool {
+        if self.config.convergence_rounds == 0 {
+            return false;
+        }
+        let stable = self
+            .products
+            .iter()
+            .map(|p| p.id())
+            .filter(|id| !self.untradable_products.contains(id))
+            .all(|id| {
+                let traded = self
+                    .recent_prices
+                    .read()
+                    .unwrap()
+                    .get(&id)
+                    .map_or(0, |h| h.len());
+                traded >= 2
+                    && self.price_volatility(id, CONVERGENCE_SAMPLE)
+                        <= self.config.convergence_tolerance
+            });
+        if !stable {
+            self.converged_rounds = 0;
+            return false;
+        }
+        self.converged_rounds += 1;
+        self.converged_rounds >= self.config.convergence_rounds
+    }
+
+    /// 检测供需僵局，连续stall_rounds轮僵局后执行纠正：整体下调工厂报价，或返回true结束模拟
+    pub fn check_stall(&mut self) -> bool {
+        if self.config.stall_rounds == 0 {
+            return false;
+        }
+        if !self.is_stalled() {
+            self.stalled_rounds = 0;
+            return false;
+        }
 
-                // 调用工厂的deal方法
-                factory.deal(&trade_result, round, interval_relation);
+        self.stalled_rounds += 1;
+        if self.stalled_rounds < self.config.stall_rounds {
+            return false;
+        }
 
-                // 如果交易成功，增加交易计数
-                if matches!(trade_result, crate::model::agent::TradeResult::Success(_)) {
-                    local_count += 1;
+        println!(
+            "Supply/demand stalled for {} rounds, action: {:?}",
+            self.stalled_rounds, self.config.stall_action
+        );
+        // 纠正之前打印每个工厂的诊断信息，便于排查僵局的原因
+        for factory_list in self.factories.values() {
+            for factory in factory_list.read().unwrap().iter() {
+                println!("{}", factory.diagnostics(self.round));
+            }
+        }
+        self.stalled_rounds = 0;
+        match self.config.stall_action {
+            StallAction::Shift(ratio) => {
+                for factory_list in self.factories.values() {
+                    let mut factory_list = factory_list.write().unwrap();
+                    for factory in factory_list.iter_mut() {
+                        factory.shift_supply_price_range(-ratio);
+                    }
                 }
+                false
+            }
+            StallAction::Terminate => true,
+        }
+    }
+
+    /// 立即施加供给冲击：该商品所有工厂的单位成本和报价区间按成本倍数整体缩放，
+    /// 有持续时间的冲击在round + duration轮开始时撤销
+    pub fn inject_shock(&mut self, shock: Shock, round: u64) {
+        self.scale_product_costs(shock.product_id, shock.cost_factor);
+        if let Some(duration) = shock.duration {
+            self.active_shocks.push((shock, round + duration));
+        }
+    }
 
-                // 记录交易日志
+    /// 每轮开始时撤销到期的冲击，再施加安排在本轮的冲击
+    fn apply_shocks(&mut self, round: u64) {
+        let (expired, active): (Vec<_>, Vec<_>) = std::mem::take(&mut self.active_shocks)
+            .into_iter()
+            .partition(|(_, expires_at)| *expires_at <= round);
+        self.active_shocks = active;
+        for (shock, _) in expired {
+            println!(
+                "Shock on product {} expired at round {}",
+                shock.product_id, round
+            );
+            self.scale_product_costs(shock.product_id, 1.0 / shock.cost_factor);
+        }
+
+        let scheduled: Vec<Shock> = self
+            .config
+            .shock_schedule
+            .iter()
+            .filter(|shock| shock.round == round)
+            .cloned()
+            .collect();
+        for shock in scheduled {
+            println!(
+                "Shock on product {}: cost x{} at round {}",
+                shock.product_id, shock.cost_factor, round
+            );
+            self.inject_shock(shock, round);
+        }
+    }
+
+    /// 撮合结束后为每个agent的到期订阅续购：按约定价格扣款并计入工厂收入，每次续购记为一次成交。
+    /// 工厂已退出、本轮售罄或agent资金不足时取消订阅，返回续购成功的件数
+    fn renew_subscriptions(&self, round: u64) -> u64 {
+        let mut renewed = 0;
+        for agent in self.agents.read().unwrap().iter() {
+            let due = agent.read().unwrap().due_subscriptions(round);
+            for (product_id, factory_id, price) in due {
+                let cancel = |reason| {
+                    let mut agent = agent.write().unwrap();
+                    agent.cancel_subscription(product_id, round, reason);
+                };
+                let Some(list) = self.factories.get(&product_id) else {
+                    cancel("subscription_factory_exited");
+                    continue;
+                };
+                let mut factory_list = list.write().unwrap();
+                let Some(factory) = factory_list.iter_mut().find(|f| f.id() == factory_id) else {
+                    cancel("subscription_factory_exited");
+                    continue;
+                };
+                if !factory.is_round_started(round) {
+                    factory.start_round(round);
+                }
+                if factory.get_stock(round) <= 0 {
+                    cancel("subscription_out_of_stock");
+                    continue;
+                }
+                if !agent.write().unwrap().renew_subscription(product_id, round) {
+                    continue;
+                }
+                factory.deliver_subscription(price, round);
+                renewed += 1;
+                let Some(product) = self.products.iter().find(|p| p.id() == product_id) else {
+                    continue;
+                };
                 if let Err(e) = log_trade(
+                    self.logger.as_ref(),
                     round,
-                    a.clone(),
+                    agent.clone(),
                     factory,
-                    &product_clone,
-                    &trade_result,
-                    interval_relation_str,
+                    product,
+                    &TradeResult::Success(price),
+                    "Subscription",
                 ) {
                     eprintln!("Failed to log trade: {}", e);
                 }
             }
         }
+        renewed
+    }
+
+    fn scale_product_costs(&self, product_id: u64, factor: f64) {
+        let Some(list) = self.factories.get(&product_id) else {
+            return;
+        };
+        for factory in list.write().unwrap().iter_mut() {
+            factory.scale_cost(factor);
+        }
+    }
+
+    /// 执行一轮交易：打乱顺序、按商品并发撮合、汇总成交并记录现金，返回本轮成交数
+    pub fn step(&mut self, round: u64) -> u64 {
+        self.round = round;
+        self.apply_shocks(round);
+        let mut rng = rand::thread_rng();
+        println!("Starting round {}, Total trades: {}", round, self.total_trades);
+        let mut factories = self.factories.clone();
+        // 打乱所有工厂的顺序
+        for (_product_id, factory_list_arc) in factories.iter_mut() {
+            let mut factory_list = factory_list_arc.write().unwrap();
+            factory_list.shuffle(&mut rng);
+        }
+
+        // 按配置排列本轮消费者的顺序
+        {
+            let mut agents = self.agents.write().unwrap();
+            match self.config.process_order {
+                ProcessOrder::Shuffled => agents.shuffle(&mut rng),
+                ProcessOrder::ById => agents.sort_by_key(|a| a.read().unwrap().id()),
+                ProcessOrder::ByCash => agents.sort_by(|a, b| {
+                    let (a, b) = (a.read().unwrap(), b.read().unwrap());
+                    b.cash().total_cmp(&a.cash()).then(a.id().cmp(&b.id()))
+                }),
+            }
+            for agent in agents.iter() {
+                agent.read().unwrap().start_round(round);
+            }
+        }
 
-        local_count
-    };
+        if self.config.agent.round_synced_demand {
+            self.generate_round_demands();
+        }
 
-    trades_count = local_trades;
+        // 获取可交易的产品ID列表，在营工厂不足的商品本轮跳过
+        let product_ids: Vec<u64> = self.refresh_viability();
+        self.suppress_unsupplied_demand(round);
+        self.resolve_unreachable_ranges(round);
+        // 开启区间变化记录时保存本轮开始前的区间
+        let snapshot = (self.config.diff_capacity > 0)
+            .then(|| (self.snapshot_agent_ranges(), self.snapshot_factory_ranges()));
+        if !self.untradable_products.is_empty() {
+            println!("Untradable products: {:?}", self.untradable_products());
+        }
+        let round_funnel: Arc<RwLock<ConversionFunnel>> =
+            Arc::new(RwLock::new(ConversionFunnel::default()));
+        // 先处理必需品，全部完成后再处理非必需品，现金紧张时非必需品先被放弃
+        let (needs, wants): (Vec<u64>, Vec<u64>) =
+            product_ids.iter().partition(|id| self.is_essential(**id));
+        for tier in [needs, wants] {
+            let mut handles: Vec<JoinHandle<_>> = Vec::new();
+            for product_id in tier {
+                let Some(product) = self.products.iter().find(|p| p.id() == product_id).cloned()
+                else {
+                    continue;
+                };
+                let f = factories.get(&product_id);
+                if f.is_none() {
+                    continue;
+                }
+                let f_list = f.unwrap().clone();
+                let agents = self.agents.clone();
+                let counter = round_funnel.clone();
+                let logger = self.logger.clone();
+                let recent_prices = self.recent_prices.clone();
+                let config = self.config.clone();
+                let h = thread::spawn(move || {
+                    let funnel = process_product_trades(
+                        &product,
+                        f_list,
+                        agents,
+                        round,
+                        &recent_prices,
+                        &config,
+                        logger.as_ref(),
+                    );
+                    counter.write().unwrap().add(&funnel);
+                });
+                handles.push(h);
+            }
 
-    trades_count
+            // 等待所有线程完成
+            for h in handles {
+                h.join().expect("error ");
+            }
+        }
+
+        // 订阅续购计入本轮的需求和成交
+        let renewed = self.renew_subscriptions(round);
+        if renewed > 0 {
+            println!("Round {} subscription renewals: {}", round, renewed);
+        }
+        {
+            let mut round_funnel = round_funnel.write().unwrap();
+            round_funnel.demands += renewed;
+            round_funnel.successes += renewed;
+        }
+
+        self.update_price_index(round);
+
+        // 汇总本轮交易数和转化漏斗
+        let round_funnel = *round_funnel.read().unwrap();
+        let current_round_trades = round_funnel.successes;
+        self.total_trades += current_round_trades;
+        self.funnel.add(&round_funnel);
+        if self.config.conversion_report {
+            println!(
+                "Round {} funnel: demands {}, successes {}, not matched {}, price too high {}, \
+                 price too low {}, cash shortfall {}, stockouts {} (conversion {:.1}%)",
+                round,
+                round_funnel.demands,
+                round_funnel.successes,
+                round_funnel.not_matched,
+                round_funnel.price_too_high,
+                round_funnel.price_too_low,
+                round_funnel.cash_shortfall,
+                round_funnel.stockouts,
+                round_funnel.conversion_rate() * 100.0
+            );
+        }
+
+        if let Some((agents_before, factories_before)) = snapshot {
+            self.record_round_diff(round, agents_before, factories_before);
+        }
+
+        // 更新连续0成交量轮次计数
+        if current_round_trades == 0 {
+            self.consecutive_zero_trades += 1;
+        } else {
+            self.consecutive_zero_trades = 0;
+        }
+
+        self.pay_dividends();
+
+        // 借款按轮计息，发薪日发放收入，现金紧张时抛售耐用品，偏好区间向参考价锚定
+        for agent in self.agents.read().unwrap().iter() {
+            let mut agent = agent.write().unwrap();
+            agent.accrue_interest();
+            agent.receive_income(round);
+            agent.panic_sell(round);
+            agent.anchor_preferences();
+        }
+
+        // 记录每个agent的现金情况
+        {
+            // 生成一个共享的timestamp，确保同一轮的所有log_cash事件使用同一个时间戳
+            let timestamp = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .expect("Failed to get system time")
+                .as_millis() as i64;
+
+            // agent每轮都会被打乱，按ID排序后记录，使日志顺序在多次运行间一致
+            let agents = self.agents.read().unwrap();
+            let mut sorted: Vec<_> = agents.iter().map(|a| a.read().unwrap()).collect();
+            sorted.sort_by_key(|a| a.id());
+            for a in sorted.iter() {
+                if let Err(e) = log_agent_cash(
+                    self.logger.as_ref(),
+                    timestamp,
+                    round,
+                    a.id(),
+                    a.name().to_string(),
+                    (a.cash(), self.real_cash(a.cash())),
+                    self.total_trades,
+                ) {
+                    eprintln!("Failed to log agent cash: {}", e);
+                }
+            }
+
+            // 记录每个工厂轮末的实际报价，按商品ID、工厂ID排序
+            for list in self.factories.values() {
+                let list = list.read().unwrap();
+                let mut sorted: Vec<&Factory> = list.iter().collect();
+                sorted.sort_by_key(|f| f.id());
+                for factory in sorted {
+                    if let Err(e) =
+                        log_factory_offer(self.logger.as_ref(), timestamp, round, factory)
+                    {
+                        eprintln!("Failed to log factory offer: {}", e);
+                    }
+                }
+            }
+        }
+
+        // 每轮结束时写入批量日志中不足一批的部分
+        if let Err(e) = flush_logs(self.logger.as_ref()) {
+            eprintln!("Failed to flush logs: {}", e);
+        }
+
+        current_round_trades
+    }
+
+    /// 累计的需求转化漏斗，拍卖模式只统计需求数和成交数
+    pub fn conversion_funnel(&self) -> ConversionFunnel {
+        self.funnel
+    }
+
+    /// 导出所有agent当前的偏好矩阵为CSV，每个agent-商品一行，返回写入的行数。
+    /// category为商品是否必需品(essential/non_essential)；懒加载尚未生成的偏好不输出
+    pub fn export_preferences<P: AsRef<std::path::Path>>(
+        &self,
+        path: P,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        use std::io::Write;
+
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+        writeln!(
+            writer,
+            "agent_id,product_id,category,current_range_lower,current_range_upper,current_price,original_elastic"
+        )?;
+        let mut rows = 0;
+        let agents = self.agents.read().unwrap();
+        for agent in agents.iter() {
+            let agent = agent.read().unwrap();
+            let preferences = agent.preferences();
+            let mut product_ids: Vec<u64> = preferences.keys().copied().collect();
+            product_ids.sort_unstable();
+            for product_id in product_ids {
+                let preference = &preferences[&product_id];
+                let essential = self
+                    .products
+                    .iter()
+                    .any(|p| p.id() == product_id && p.is_essential());
+                let (lower, upper) = preference.current_range;
+                writeln!(
+                    writer,
+                    "{},{},{},{},{},{},{}",
+                    agent.id(),
+                    product_id,
+                    if essential {
+                        "essential"
+                    } else {
+                        "non_essential"
+                    },
+                    lower,
+                    upper,
+                    preference.current_price,
+                    preference.original_elastic
+                )?;
+                rows += 1;
+            }
+        }
+        writer.flush()?;
+        Ok(rows)
+    }
+
+    /// 停止标志，置为true后run在当前轮结束时退出，可在其他线程（如信号处理）中设置
+    pub fn stop_handle(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    /// 性能测试：连续执行rounds轮，日志写入NullSink，轮与轮之间不等待，返回耗时和每秒轮数。
+    /// 从当前轮次之后继续，结束后恢复原来的日志记录器。
+    /// 期间强制按轮生成需求，市场应以round_synced_demand创建（不启动需求线程），固定种子时结果可复现
+    pub fn bench(&mut self, rounds: u64) -> BenchReport {
+        let logger = Logger::with_sink("bench".to_string(), Arc::new(NullSink));
+        let previous = self.logger.replace(logger);
+        self.share_logger();
+        let round_synced = self.config.agent.round_synced_demand;
+        self.config.agent.round_synced_demand = true;
+        let trades_before = self.total_trades;
+        let start = std::time::Instant::now();
+        for round in self.round + 1..=self.round + rounds {
+            self.step(round);
+        }
+        let elapsed = start.elapsed();
+        self.logger = previous;
+        self.share_logger();
+        self.config.agent.round_synced_demand = round_synced;
+
+        BenchReport {
+            rounds,
+            total_trades: self.total_trades - trades_before,
+            elapsed,
+            rounds_per_sec: rounds as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+        }
+    }
+
+    pub fn run(&mut self) -> SimulationOutcome {
+        let mut round = 1;
+        let max_round = self.config.max_round;
+
+        loop {
+            self.step(round);
+            #[cfg(feature = "metrics")]
+            self.write_metrics();
+            let stalled = self.check_stall();
+            let converged = self.check_convergence();
+            let interrupted = self.stop.load(Ordering::SeqCst);
+
+            // 检查是否所有agent的现金和剩余授信都已用完
+            let agents = self.agents.read().unwrap();
+            let all_agents_broke = agents.iter().all(|agent| {
+                let a = agent.read().unwrap();
+                a.spendable() < 0.01
+            });
+
+            // 检查退出条件
+            if round >= max_round
+                || all_agents_broke
+                || self.consecutive_zero_trades >= 20
+                || stalled
+                || converged
+                || interrupted
+            {
+                println!("Simulation ending...");
+                if round >= max_round {
+                    println!("Reason: Reached maximum rounds ({})\n", max_round);
+                }
+                if all_agents_broke {
+                    println!("Reason: All agents have zero or negative cash.\n");
+                }
+                if self.consecutive_zero_trades >= 20 {
+                    println!(
+                        "Reason: No trades for {} consecutive rounds.\n",
+                        self.consecutive_zero_trades
+                    );
+                }
+                if stalled {
+                    println!("Reason: Supply/demand stalled.\n");
+                }
+                if converged {
+                    println!("Reason: Prices converged.\n");
+                }
+                if interrupted {
+                    println!("Reason: Interrupted.\n");
+                }
+                let dropped = self
+                    .logger
+                    .as_ref()
+                    .map_or(0, |logger| logger.dropped_rows());
+                if dropped > 0 {
+                    println!("Dropped log rows: {}", dropped);
+                }
+                drop(agents);
+                println!("Wealth distribution:");
+                for (lower, count) in self.wealth_histogram(10) {
+                    println!("  >= {:.2}: {}", lower, count);
+                }
+                println!("Rounds: {}, total trades: {}", round, self.total_trades);
+
+                // 同时满足多个条件时，中断优先，其次按上面打印的顺序
+                let reason = if interrupted {
+                    EndReason::Interrupted
+                } else if round >= max_round {
+                    EndReason::MaxRounds
+                } else if all_agents_broke {
+                    EndReason::AllAgentsBroke
+                } else if stalled {
+                    EndReason::Stalled
+                } else if converged {
+                    EndReason::Converged
+                } else {
+                    EndReason::NoTrades
+                };
+                let logger = self.logger.as_ref();
+                if let Err(e) =
+                    log_run_end(logger, round, &format!("{:?}", reason), self.total_trades)
+                {
+                    eprintln!("Failed to log run end: {}", e);
+                }
+                if let Err(e) = flush_logs(logger) {
+                    eprintln!("Failed to flush logs: {}", e);
+                }
+                return SimulationOutcome {
+                    reason,
+                    rounds: round,
+                    total_trades: self.total_trades,
+                };
+            }
+
+            round += 1;
+            thread::sleep(std::time::Duration::from_millis(100));
+        }
+    }
+}
+
+/// 处理单个商品的交易逻辑（线程安全版本）
+fn process_product_trades(
+    product: &Product,
+    factories: Arc<RwLock<Vec<Factory>>>,
+    agents: Arc<RwLock<Vec<Arc<RwLock<Agent>>>>>,
+    round: u64,
+    recent_prices: &RwLock<BTreeMap<u64, VecDeque<f64>>>,
+    config: &MarketConfig,
+    logger: Option<&Logger>,
+) -> ConversionFunnel {
+    let product_id = product.id();
+    println!("dealing:{:?}", product_id);
+    let competitor_factor = config.factory.competitor_factor;
+    let max_buyers = config.max_buyers_per_product_per_round;
+    let mut funnel = ConversionFunnel::default();
+    // 获取工厂列表的Arc副本
+    let mut factory_list_arc = factories.write().unwrap();
+    // 克隆Arc，以便在闭包中使用
+    let factory_list_arc_clone = factory_list_arc;
+    let agents_clone = agents.clone();
+
+    // 在闭包中处理工厂交易
+    {
+        let mut local_count = 0;
+        funnel.demands = agents_clone
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|a| a.read().unwrap().has_demand(product_id))
+            .count() as u64;
+
+        // 获取工厂列表的读写锁
+        let mut factory_list = factory_list_arc_clone;
+
+        // 每轮开始时工厂参考同商品其他工厂的平均报价
+        if competitor_factor != 0.0 && factory_list.len() > 1 {
+            let offers: Vec<(u64, f64)> = factory_list
+                .iter()
+                .map(|f| (f.id(), f.offer_price()))
+                .collect();
+            for factory in factory_list.iter_mut() {
+                let competitors: Vec<f64> = offers
+                    .iter()
+                    .filter(|(id, _)| *id != factory.id())
+                    .map(|(_, price)| *price)
+                    .collect();
+                let average = competitors.iter().sum::<f64>() / competitors.len() as f64;
+                factory.observe_competitors(average, competitor_factor);
+            }
+        }
+
+        // 本轮买到该商品的agent，用于口碑传播
+        let mut buyers: Vec<u64> = Vec::new();
+        // 开启撮合明细日志时，每个agent本轮已尝试的工厂数
+        let mut attempts: HashMap<u64, u32> = HashMap::new();
+        if config.clearing == ClearingMode::Auction {
+            let agents = agents_clone.read().unwrap();
+            buyers = run_auction(
+                &mut factory_list,
+                &agents,
+                round,
+                product,
+                recent_prices,
+                max_buyers,
+                logger,
+            );
+            local_count = buyers.len() as u64;
+        } else {
+            // 报价相同的工厂按配置决定先后
+            order_tied_factories(&mut factory_list, config.tie_break, round);
+            // 遍历商品下的工厂
+            for index in 0..factory_list.len() {
+                // 本轮尚未轮到的工厂及其报价区间，忠诚的agent可以等待上次购买的工厂
+                let upcoming: Vec<(u64, (f64, f64))> = factory_list[index + 1..]
+                    .iter()
+                    .map(|f| (f.id(), f.supply_price_range()))
+                    .collect();
+                let factory = &mut factory_list[index];
+
+                // 让工厂开启一次循环
+                factory.start_round(round);
+
+                // 获取agents的可变锁
+                let mut agents = agents_clone.read().unwrap();
+
+                // 让每个agent与工厂进行交易
+                for a in agents.iter() {
+                    // 检查工厂库存，如果为0则退出循环
+                    if factory.get_stock(round) <= 0 {
+                        break;
+                    }
+                    // 本轮成交人数已达上限，其余agent推迟到下一轮
+                    if max_buyers.is_some_and(|cap| local_count >= cap as u64) {
+                        break;
+                    }
+                    let has_demand = {
+                        let agent = a.read().unwrap();
+                        agent.has_demand(product_id)
+                    };
+                    let mut trade_result = TradeResult::NotYet;
+                    let mut interval_relation = None;
+                    let mut quantity = 0;
+                    if !has_demand {
+                        trade_result = TradeResult::NotMatched;
+                    } else {
+                        let mut agent = a.write().unwrap();
+                        if agent.waits_for_loyal_factory(product_id, &upcoming) {
+                            // 等待上次购买的工厂，本次不交易
+                            trade_result = TradeResult::NotMatched;
+                        } else {
+                            // 调用agent的trade方法，按agent的需求数量批量成交
+                            let requested = agent.purchase_quantity();
+                            (trade_result, interval_relation, quantity) =
+                                agent.trade_units(factory, round, requested);
+                        }
+                    }
+                    // 将interval_relation转换为字符串
+                    let interval_relation_str = match &interval_relation {
+                        Some(rel) => match rel {
+                            crate::model::agent::IntervalRelation::Overlapping(_) => "Overlapping",
+                            crate::model::agent::IntervalRelation::AgentBelowFactory => {
+                                "AgentBelowFactory"
+                            }
+                            crate::model::agent::IntervalRelation::AgentAboveFactory => {
+                                "AgentAboveFactory"
+                            }
+                        },
+                        None => "None",
+                    };
+
+                    // 实际比较过报价区间的尝试逐条记录，用于排查交易失败原因
+                    if config.verbose_negotiation_log && interval_relation.is_some() {
+                        let agent_id = a.read().unwrap().id();
+                        let attempt = attempts.entry(agent_id).or_insert(0);
+                        *attempt += 1;
+                        if let Err(e) = log_negotiation_attempt(
+                            logger,
+                            round,
+                            agent_id,
+                            factory,
+                            *attempt,
+                            interval_relation_str,
+                        ) {
+                            eprintln!("Failed to log negotiation attempt: {}", e);
+                        }
+                    }
+
+                    // 有需求的agent按本次结果计入转化漏斗，成交数在最后统一计入
+                    if has_demand {
+                        match (&trade_result, &interval_relation) {
+                            (TradeResult::NotMatched, _) => funnel.not_matched += 1,
+                            (TradeResult::Failed, Some(IntervalRelation::AgentBelowFactory)) => {
+                                funnel.price_too_high += 1
+                            }
+                            (TradeResult::Failed, Some(IntervalRelation::AgentAboveFactory)) => {
+                                funnel.price_too_low += 1
+                            }
+                            (TradeResult::Failed, Some(IntervalRelation::Overlapping(_))) => {
+                                funnel.cash_shortfall += 1
+                            }
+                            _ => {}
+                        }
+                    }
+
+                    // 调用工厂的deal方法，库存按成交数量扣减
+                    factory.deal_units(&trade_result, round, interval_relation, quantity);
+
+                    // 如果交易成功，增加交易计数并记录成交价
+                    if let TradeResult::Success(price) = trade_result {
+                        local_count += 1;
+                        record_trade_price(recent_prices, product_id, price);
+                        buyers.push(a.read().unwrap().id());
+                    }
+
+                    // 记录交易日志
+                    if let Err(e) = log_trade(
+                        logger,
+                        round,
+                        a.clone(),
+                        factory,
+                        product,
+                        &trade_result,
+                        interval_relation_str,
+                    ) {
+                        eprintln!("Failed to log trade: {}", e);
+                    }
+                }
+            }
+        }
+
+        // 买到商品的agent向邻居推荐，提高邻居对该商品产生需求的概率
+        if config.word_of_mouth_neighbors > 0 && !buyers.is_empty() {
+            let agents = agents_clone.read().unwrap();
+            for buyer_id in &buyers {
+                spread_word_of_mouth(
+                    &agents,
+                    *buyer_id,
+                    product_id,
+                    config.word_of_mouth_neighbors,
+                    config.word_of_mouth_boost,
+                );
+            }
+        }
+
+        // 成交人数达到上限时，仍有需求的agent记为推迟，不计缺货
+        let capped = max_buyers.is_some_and(|cap| local_count >= cap as u64);
+        if capped && !factory_list.is_empty() {
+            let agents = agents_clone.read().unwrap();
+            for a in agents.iter() {
+                if !a.read().unwrap().has_demand(product_id) {
+                    continue;
+                }
+                if let Err(e) = log_trade(
+                    logger,
+                    round,
+                    a.clone(),
+                    &factory_list[0],
+                    product,
+                    &TradeResult::NotMatched,
+                    "Deferred",
+                ) {
+                    eprintln!("Failed to log trade: {}", e);
+                }
+            }
+        }
+
+        // 所有工厂都售罄时，仍有需求的agent记录一次缺货
+        if !capped
+            && !factory_list.is_empty()
+            && factory_list.iter().all(|f| f.get_stock(round) <= 0)
+        {
+            let agents = agents_clone.read().unwrap();
+            for a in agents.iter() {
+                let agent = a.read().unwrap();
+                if agent.has_demand(product_id) {
+                    agent.record_stockout(product_id);
+                    funnel.stockouts += 1;
+                }
+            }
+        }
+
+        funnel.successes = local_count;
+    }
+
+    funnel
+}
+
+/// 调整报价相同的工厂的先后顺序：每组同价工厂按tie_break排序后放回这组原来的位置，
+/// 报价不同的工厂位置不变。本轮尚未开启，库存按本轮产量比较
+fn order_tied_factories(factories: &mut Vec<Factory>, policy: TieBreak, round: u64) {
+    let offers: Vec<f64> = factories.iter().map(|f| f.offer_price()).collect();
+    let mut order: Vec<usize> = (0..factories.len()).collect();
+    let mut grouped = vec![false; factories.len()];
+    let mut has_tie = false;
+    for i in 0..factories.len() {
+        if grouped[i] {
+            continue;
+        }
+        let group: Vec<usize> = (i..factories.len())
+            .filter(|&j| !grouped[j] && (offers[j] - offers[i]).abs() <= PRICE_TIE_EPSILON)
+            .collect();
+        for &j in &group {
+            grouped[j] = true;
+        }
+        if group.len() < 2 {
+            continue;
+        }
+        has_tie = true;
+        let mut ranked = group.clone();
+        ranked.sort_by_key(|&j| factories[j].id());
+        match policy {
+            TieBreak::LowestId => {}
+            TieBreak::HighestStock => {
+                ranked.sort_by_key(|&j| std::cmp::Reverse(factories[j].production()))
+            }
+            TieBreak::RoundRobin => ranked.rotate_left((round % group.len() as u64) as usize),
+        }
+        for (slot, index) in group.into_iter().zip(ranked) {
+            order[slot] = index;
+        }
+    }
+    if !has_tie {
+        return;
+    }
+    let mut taken: Vec<Option<Factory>> = factories.drain(..).map(Some).collect();
+    factories.extend(order.into_iter().map(|i| taken[i].take().unwrap()));
+}
+
+/// 统一价格拍卖：所有工厂先开启本轮，有需求的agent按愿付最高价出价，每件库存按所属工厂区间下界要价。
+/// 出价从高到低、要价从低到高依次配对，直到出价低于要价；成交价取最后一对出价与要价的中点，
+/// 所有成交都按这一价格结算，每个agent最多买一件。设置了max_buyers时只成交出价最高的前max_buyers个，
+/// 其余中标者推迟到下一轮。未买到的agent与仍有库存的最便宜工厂记一次失败。返回成交的agent ID
+fn run_auction(
+    factory_list: &mut [Factory],
+    agents: &[Arc<RwLock<Agent>>],
+    round: u64,
+    product: &Product,
+    recent_prices: &RwLock<BTreeMap<u64, VecDeque<f64>>>,
+    max_buyers: Option<usize>,
+    logger: Option<&Logger>,
+) -> Vec<u64> {
+    let product_id = product.id();
+    for factory in factory_list.iter_mut() {
+        factory.start_round(round);
+    }
+
+    // 出价相同时按agent ID排序，使分配结果与agent的打乱顺序无关
+    let mut bids: Vec<(f64, u64, usize)> = agents
+        .iter()
+        .enumerate()
+        .filter_map(|(index, a)| {
+            let agent = a.read().unwrap();
+            agent
+                .auction_bid(product_id, round)
+                .map(|bid| (bid, agent.id(), index))
+        })
+        .collect();
+    bids.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+    let mut asks: Vec<(f64, usize)> = factory_list
+        .iter()
+        .enumerate()
+        .flat_map(|(index, f)| {
+            let units = f.get_stock(round).max(0) as usize;
+            std::iter::repeat_n((f.asking_range().0, index), units)
+        })
+        .collect();
+    asks.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+
+    let matched = bids
+        .iter()
+        .zip(asks.iter())
+        .take_while(|(bid, ask)| bid.0 >= ask.0)
+        .count();
+    // 超过成交人数上限的中标者推迟到下一轮，既不成交也不记失败
+    let served = max_buyers.map_or(matched, |cap| matched.min(cap));
+    let clearing_price = (served > 0).then(|| (bids[served - 1].0 + asks[served - 1].0) / 2.0);
+
+    let mut buyers = Vec::new();
+    for (position, (_, _, agent_index)) in bids.iter().enumerate() {
+        let a = &agents[*agent_index];
+        if let (Some(price), Some((_, factory_index))) = (
+            clearing_price.filter(|_| position < served),
+            asks.get(position),
+        ) {
+            let factory = &mut factory_list[*factory_index];
+            if a.write()
+                .unwrap()
+                .force_trade(factory, round, price)
+                .is_ok()
+            {
+                let trade_result = TradeResult::Success(price);
+                factory.deal_units(
+                    &trade_result,
+                    round,
+                    Some(IntervalRelation::Overlapping((price, price))),
+                    1,
+                );
+                buyers.push(a.read().unwrap().id());
+                record_trade_price(recent_prices, product_id, price);
+                if let Err(e) = log_trade(
+                    logger,
+                    round,
+                    a.clone(),
+                    factory,
+                    product,
+                    &trade_result,
+                    "Overlapping",
+                ) {
+                    eprintln!("Failed to log trade: {}", e);
+                }
+                continue;
+            }
+        }
+
+        if position < matched {
+            continue;
+        }
+
+        // 未买到：出价低于要价，区间上移；仍有库存的最便宜工厂下调报价
+        let Some(factory) = factory_list
+            .iter_mut()
+            .filter(|f| f.get_stock(round) > 0)
+            .min_by(|a, b| {
+                a.supply_price_range()
+                    .0
+                    .total_cmp(&b.supply_price_range().0)
+            })
+        else {
+            continue;
+        };
+        a.write().unwrap().auction_lost(factory, round);
+        let trade_result = TradeResult::Failed;
+        factory.deal_units(
+            &trade_result,
+            round,
+            Some(IntervalRelation::AgentBelowFactory),
+            0,
+        );
+        if let Err(e) = log_trade(
+            logger,
+            round,
+            a.clone(),
+            factory,
+            product,
+            &trade_result,
+            "AgentBelowFactory",
+        ) {
+            eprintln!("Failed to log trade: {}", e);
+        }
+    }
+    buyers
+}
+
+/// 口碑传播：agent按ID排成环，买家把对该商品的兴趣传给环上最近的neighbors个agent
+/// （依次取后1、前1、后2、前2……），每个邻居的口碑概率增加boost
+fn spread_word_of_mouth(
+    agents: &[Arc<RwLock<Agent>>],
+    buyer_id: u64,
+    product_id: u64,
+    neighbors: usize,
+    boost: f64,
+) {
+    let mut ring: Vec<(u64, &Arc<RwLock<Agent>>)> =
+        agents.iter().map(|a| (a.read().unwrap().id(), a)).collect();
+    ring.sort_by_key(|(id, _)| *id);
+    let Some(position) = ring.iter().position(|(id, _)| *id == buyer_id) else {
+        return;
+    };
+    let len = ring.len();
+    let count = neighbors.min(len - 1);
+    for i in 0..count {
+        let distance = i / 2 + 1;
+        let index = if i % 2 == 0 {
+            (position + distance) % len
+        } else {
+            (position + len - distance) % len
+        };
+        ring[index].1.read().unwrap().hear_about(product_id, boost);
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl Market {
+    /// 配置了metrics_path时把当前指标写入该文件。先写临时文件再改名，抓取时不会读到写了一半的内容
+    fn write_metrics(&self) {
+        let Some(path) = &self.config.metrics_path else {
+            return;
+        };
+        let tmp = format!("{}.tmp", path);
+        let written =
+            std::fs::write(&tmp, self.metrics_text()).and_then(|_| std::fs::rename(&tmp, path));
+        if let Err(e) = written {
+            eprintln!("Failed to write metrics to {}: {}", path, e);
+        }
+    }
+
+    /// 以Prometheus文本格式导出当前指标：累计成交数、当前轮次、每个商品的在营工厂数
+    /// 和最近成交均价、仍有资金的agent数、未满足的需求总数。配置metrics_path时run每轮写入文件
+    pub fn metrics_text(&self) -> String {
+        let mut text = String::new();
+        text.push_str("# HELP market_trades_total Total number of completed trades.\n");
+        text.push_str("# TYPE market_trades_total counter\n");
+        text.push_str(&format!("market_trades_total {}\n", self.total_trades));
+
+        let funnel = self.conversion_funnel();
+        text.push_str("# HELP market_funnel_total Demand-to-trade conversion outcomes.\n");
+        text.push_str("# TYPE market_funnel_total counter\n");
+        for (outcome, count) in [
+            ("demand", funnel.demands),
+            ("success", funnel.successes),
+            ("not_matched", funnel.not_matched),
+            ("price_too_high", funnel.price_too_high),
+            ("price_too_low", funnel.price_too_low),
+            ("cash_shortfall", funnel.cash_shortfall),
+            ("stockout", funnel.stockouts),
+        ] {
+            text.push_str(&format!(
+                "market_funnel_total{{outcome=\"{}\"}} {}\n",
+                outcome, count
+            ));
+        }
+
+        let dropped = self
+            .logger
+            .as_ref()
+            .map_or(0, |logger| logger.dropped_rows());
+        text.push_str("# HELP market_log_rows_dropped_total Log rows dropped by the writer.\n");
+        text.push_str("# TYPE market_log_rows_dropped_total counter\n");
+        text.push_str(&format!("market_log_rows_dropped_total {}\n", dropped));
+
+        text.push_str("# HELP market_round Current round.\n");
+        text.push_str("# TYPE market_round gauge\n");
+        text.push_str(&format!("market_round {}\n", self.round));
+
+        let mut product_ids: Vec<u64> = self.products.iter().map(|p| p.id()).collect();
+        product_ids.sort();
+        text.push_str("# HELP market_active_factories Factories currently in the market.\n");
+        text.push_str("# TYPE market_active_factories gauge\n");
+        for product_id in &product_ids {
+            text.push_str(&format!(
+                "market_active_factories{{product_id=\"{}\"}} {}\n",
+                product_id,
+                self.factory_count(*product_id)
+            ));
+        }
+
+        text.push_str("# HELP market_product_stock Stock across all factories of a product.\n");
+        text.push_str("# TYPE market_product_stock gauge\n");
+        for product_id in &product_ids {
+            text.push_str(&format!(
+                "market_product_stock{{product_id=\"{}\"}} {}\n",
+                product_id,
+                self.total_stock(*product_id, self.round)
+            ));
+        }
+
+        text.push_str("# HELP market_average_markup Mean offer-to-cost ratio of factories.\n");
+        text.push_str("# TYPE market_average_markup gauge\n");
+        for product_id in &product_ids {
+            text.push_str(&format!(
+                "market_average_markup{{product_id=\"{}\"}} {}\n",
+                product_id,
+                self.average_markup(*product_id, self.round)
+            ));
+        }
+
+        text.push_str("# HELP market_price_index Basket price level relative to the base round.\n");
+        text.push_str("# TYPE market_price_index gauge\n");
+        text.push_str(&format!("market_price_index {}\n", self.price_index));
+
+        // 还没有成交的商品不输出均价
+        text.push_str("# HELP market_mean_price Mean of recent trade prices.\n");
+        text.push_str("# TYPE market_mean_price gauge\n");
+        let prices = self.recent_prices.read().unwrap();
+        for product_id in &product_ids {
+            let Some(history) = prices.get(product_id).filter(|h| !h.is_empty()) else {
+                continue;
+            };
+            let mean = history.iter().sum::<f64>() / history.len() as f64;
+            text.push_str(&format!(
+                "market_mean_price{{product_id=\"{}\"}} {}\n",
+                product_id, mean
+            ));
+        }
+
+        let solvent = self
+            .agents
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|a| a.read().unwrap().cash() >= 0.01)
+            .count();
+        text.push_str("# HELP market_agents_solvent Agents with cash left.\n");
+        text.push_str("# TYPE market_agents_solvent gauge\n");
+        text.push_str(&format!("market_agents_solvent {}\n", solvent));
+
+        text.push_str("# HELP market_factory_offer Current offer price of each factory.\n");
+        text.push_str("# TYPE market_factory_offer gauge\n");
+        for list in self.factories.values() {
+            let list = list.read().unwrap();
+            let mut sorted: Vec<&Factory> = list.iter().collect();
+            sorted.sort_by_key(|f| f.id());
+            for factory in sorted {
+                text.push_str(&format!(
+                    "market_factory_offer{{product_id=\"{}\",factory_id=\"{}\"}} {}\n",
+                    factory.product_id(),
+                    factory.id(),
+                    factory.current_offer(self.round)
+                ));
+            }
+        }
+
+        let refused: u64 = self
+            .agents
+            .read()
+            .unwrap()
+            .iter()
+            .map(|a| a.read().unwrap().refused_demands())
+            .sum();
+        text.push_str(
+            "# HELP market_refused_demands_total New demands dropped because an agent's backlog was full.\n",
+        );
+        text.push_str("# TYPE market_refused_demands_total counter\n");
+        text.push_str(&format!("market_refused_demands_total {}\n", refused));
+
+        text.push_str("# HELP market_unsupplied_products Products without any factory.\n");
+        text.push_str("# TYPE market_unsupplied_products gauge\n");
+        text.push_str(&format!(
+            "market_unsupplied_products {}\n",
+            self.unsupplied_products().len()
+        ));
+
+        text.push_str("# HELP market_outstanding_demand Unmet demands across all agents.\n");
+        text.push_str("# TYPE market_outstanding_demand gauge\n");
+        text.push_str(&format!(
+            "market_outstanding_demand {}\n",
+            self.outstanding_demand()
+        ));
+        text
+    }
+}
+
+/// 记录一笔成交价，每个商品只保留最近PRICE_HISTORY个
+fn record_trade_price(prices: &RwLock<BTreeMap<u64, VecDeque<f64>>>, product_id: u64, price: f64) {
+    let mut prices = prices.write().unwrap();
+    let history = prices.entry(product_id).or_default();
+    history.push_back(price);
+    if history.len() > PRICE_HISTORY {
+        history.pop_front();
+    }
+}
+
+/// 等宽直方图，返回每个区间的(下限, 数量)，最大值落在最后一个区间
+fn histogram(values: &[f64], bucket_count: usize) -> Vec<(f64, usize)> {
+    if values.is_empty() || bucket_count == 0 {
+        return Vec::new();
+    }
+    let min = values.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    if max <= min {
+        return vec![(min, values.len())];
+    }
+
+    let width = (max - min) / bucket_count as f64;
+    let mut buckets: Vec<(f64, usize)> = (0..bucket_count)
+        .map(|i| (min + width * i as f64, 0))
+        .collect();
+    for value in values {
+        let index = (((value - min) / width) as usize).min(bucket_count - 1);
+        buckets[index].1 += 1;
+    }
+    buckets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::normal_distribute::NormalDistribution;
+    use crate::logging::{FactoryOfferLog, LoggedRow, MemorySink};
+
+    fn test_product(id: u64) -> Product {
+        Product::from(
+            id,
+            format!("product_{}", id),
+            NormalDistribution::new(50.0, id, "price_dist".to_string(), 5.0),
+            NormalDistribution::new(0.5, id, "elastic_dist".to_string(), 0.1),
+        )
+    }
+
+    // 由市场按轮生成需求、每轮对每个商品都产生需求的配置，测试无需等待需求线程
+    fn synced_demand_config() -> MarketConfig {
+        MarketConfig {
+            seed: Some(42),
+            agent: crate::config::AgentConfig {
+                round_synced_demand: true,
+                demand_model: crate::config::DemandModelConfig::Bernoulli { probability: 1.0 },
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_refresh_viability() {
+        let product = test_product(1);
+        let config = MarketConfig {
+            min_viable_factories: 3,
+            ..Default::default()
+        };
+        let mut market = Market::with_config(vec![product.clone()], config);
+
+        // 初始有3或4家工厂，满足阈值
+        assert_eq!(market.refresh_viability(), vec![1]);
+        assert!(market.untradable_products().is_empty());
+
+        // 移除工厂直到只剩2家，商品被跳过并标记
+        let mut factory_id = 1;
+        while market.factory_count(1) > 2 {
+            assert!(market.remove_factory(factory_id).is_some());
+            factory_id += 1;
+        }
+        assert!(market.refresh_viability().is_empty());
+        assert_eq!(market.untradable_products(), vec![1]);
+
+        // 新工厂进入后恢复交易
+        market.add_factory(Factory::new(100, "entrant".to_string(), &product));
+        assert_eq!(market.factory_count(1), 3);
+        assert_eq!(market.refresh_viability(), vec![1]);
+        assert!(market.untradable_products().is_empty());
+    }
+
+    #[test]
+    fn test_suppress_unsupplied_demand() {
+        let config = MarketConfig {
+            suppress_unsupplied_demand: true,
+            ..synced_demand_config()
+        };
+        let mut market = Market::with_config(vec![test_product(1), test_product(2)], config);
+        assert!(market.unsupplied_products().is_empty());
+
+        // 商品1的工厂全部退出
+        let factory_ids: Vec<u64> = market.factories[&1]
+            .read()
+            .unwrap()
+            .iter()
+            .map(|f| f.id())
+            .collect();
+        for id in factory_ids {
+            market.remove_factory(id);
+        }
+        assert_eq!(market.unsupplied_products(), vec![1]);
+        for agent in market.agents.read().unwrap().iter() {
+            agent.read().unwrap().add_demand(1);
+        }
+
+        // 之后的几轮agent不再对商品1保留或产生需求，商品2的需求不受影响
+        for round in 1..=3 {
+            market.step(round);
+            let agents = market.agents.read().unwrap();
+            assert!(agents.iter().all(|a| !a.read().unwrap().has_demand(1)));
+            assert!(agents.iter().any(|a| a.read().unwrap().has_demand(2)));
+        }
+    }
+
+    #[test]
+    fn test_form_households() {
+        let mut config = MarketConfig::default();
+        config.agent.household_size = 3;
+        let market = Market::with_config(vec![test_product(1)], config);
+
+        let agents = market.agents.read().unwrap();
+        let first = agents[0].read().unwrap();
+        let third = agents[2].read().unwrap();
+        let fourth = agents[3].read().unwrap();
+        assert_eq!(first.household().unwrap().agent_ids(), &[1, 2, 3]);
+        assert_eq!(first.household().unwrap().id(), third.household().unwrap().id());
+        assert_ne!(first.household().unwrap().id(), fourth.household().unwrap().id());
+        assert_eq!(first.cash(), 3000.0);
+        // 100个agent分成34个家庭，最后一个家庭只有1人
+        let last = agents[99].read().unwrap();
+        assert_eq!(last.household().unwrap().agent_ids(), &[100]);
+        assert_eq!(last.cash(), 1000.0);
+    }
+
+    #[test]
+    fn test_round_synced_demand() {
+        let config = MarketConfig {
+            seed: Some(42),
+            agent: crate::config::AgentConfig {
+                round_synced_demand: true,
+                demand_model: crate::config::DemandModelConfig::Poisson { rate: 0.3 },
+                initial_demands: 1.0,
+                lazy_preferences: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        // 没有工厂，需求不会因成交或交易失败而删除，只看需求的产生
+        let build = || {
+            let products = Arc::new((1..=3).map(test_product).collect::<Vec<Product>>());
+            let agents = (1..=5)
+                .map(|id| {
+                    let name = format!("agent_{}", id);
+                    let rng = StdRng::seed_from_u64(Market::agent_seed(42, id));
+                    Agent::with_shared_products(
+                        id,
+                        name,
+                        100.0,
+                        products.clone(),
+                        &config.agent,
+                        rng,
+                    )
+                })
+                .collect();
+            Market::from_parts_with_config(products.to_vec(), agents, Vec::new(), config.clone())
+        };
+        // 每个agent的需求及其偏好区间
+        let demand_sets = |market: &Market| {
+            let mut sets: Vec<(u64, Vec<(u64, (f64, f64))>)> = market
+                .agents
+                .read()
+                .unwrap()
+                .iter()
+                .map(|agent| {
+                    let agent = agent.read().unwrap();
+                    let mut products = agent.demanded_products();
+                    products.sort();
+                    let preferences = agent.preferences();
+                    let demands = products
+                        .into_iter()
+                        .map(|id| (id, preferences[&id].current_range))
+                        .collect();
+                    (agent.id(), demands)
+                })
+                .collect();
+            sets.sort_by_key(|(id, _)| *id);
+            sets
+        };
+
+        // 同一种子的两次运行，初始需求和每轮由step产生的需求及偏好完全相同
+        let mut first = build();
+        let mut second = build();
+        assert_eq!(demand_sets(&first), demand_sets(&second));
+        let mut total = 0;
+        for round in 1..=5 {
+            first.step(round);
+            second.step(round);
+            let sets = demand_sets(&first);
+            assert_eq!(sets, demand_sets(&second));
+            total = sets.iter().map(|(_, demands)| demands.len()).sum();
+        }
+        assert!(total > 0);
+    }
+
+    #[test]
+    fn test_seed_offset_only_changes_own_product() {
+        let factory_ranges = |market: &Market, product_id: u64| -> Vec<(f64, f64)> {
+            let factories = market.factories.get(&product_id).unwrap().read().unwrap();
+            factories.iter().map(|f| f.supply_price_range()).collect()
+        };
+        let config = MarketConfig {
+            seed: Some(42),
+            ..Default::default()
+        };
+
+        let base = Market::with_config(vec![test_product(1), test_product(2)], config.clone());
+        let same = Market::with_config(vec![test_product(1), test_product(2)], config.clone());
+        let varied = Market::with_config(
+            vec![test_product(1), test_product(2).with_seed_offset(1)],
+            config,
+        );
+
+        // 相同种子可复现
+        assert_eq!(factory_ranges(&base, 1), factory_ranges(&same, 1));
+        assert_eq!(factory_ranges(&base, 2), factory_ranges(&same, 2));
+        // 只有改变了seed_offset的商品工厂区间发生变化
+        assert_eq!(factory_ranges(&base, 1), factory_ranges(&varied, 1));
+        assert_ne!(factory_ranges(&base, 2), factory_ranges(&varied, 2));
+    }
+
+    #[test]
+    fn test_unreachable_ranges_use_product_cost_floor() {
+        let products: Vec<Product> = (1..=3).map(test_product).collect();
+        let factory = |id: u64, product: &Product, unit_cost: f64, reservation_factor: f64| {
+            let config = crate::config::FactoryConfig {
+                unit_cost,
+                reservation_factor,
+                ..Default::default()
+            };
+            Factory::with_config(id, format!("factory_{}", id), product, &config)
+        };
+        // 商品1最便宜的工厂最低卖55（另一家成本50但保留价60），商品2最低卖20，商品3没有工厂
+        let factories = vec![
+            factory(1, &products[0], 50.0, 1.2),
+            factory(2, &products[0], 55.0, 0.0),
+            factory(3, &products[1], 20.0, 0.0),
+        ];
+        let config = MarketConfig {
+            agent: crate::config::AgentConfig {
+                round_synced_demand: true,
+                demand_model: crate::config::DemandModelConfig::Bernoulli { probability: 0.0 },
+                unreachable_range: crate::config::UnreachableRangeAction::Widen,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let agent = Agent::with_config(1, "stingy".to_string(), 1000.0, &products, &config.agent);
+        for product in &products {
+            agent.add_demand(product.id());
+            agent.set_preference_range(product.id(), (0.0, 30.0));
+        }
+        let market = Market::from_parts_with_config(products, vec![agent], factories, config);
+
+        market.resolve_unreachable_ranges(1);
+
+        let agents = market.agents.read().unwrap();
+        let agent = agents[0].read().unwrap();
+        let preferences = agent.preferences();
+        assert!((preferences[&1].current_range.1 - 60.5).abs() < 1e-9);
+        assert_eq!(preferences[&2].current_range, (0.0, 30.0));
+        assert_eq!(preferences[&3].current_range, (0.0, 30.0));
+    }
+
+    #[test]
+    fn test_seed_reproduces_agent_preferences() {
+        let preference_ranges = |market: &Market| -> BTreeMap<(u64, u64), (f64, f64)> {
+            let mut ranges = BTreeMap::new();
+            for agent in market.agents.read().unwrap().iter() {
+                let agent = agent.read().unwrap();
+                for (product_id, preference) in agent.preferences().iter() {
+                    ranges.insert((agent.id(), *product_id), preference.current_range);
+                }
+            }
+            ranges
+        };
+        let config = MarketConfig {
+            seed: Some(42),
+            agent: crate::config::AgentConfig {
+                round_synced_demand: true,
+                range_width: Some((0.3, 0.1)),
+                preference_correlation: 0.5,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let products = vec![test_product(1), test_product(2)];
+
+        let base = Market::with_config(products.clone(), config.clone());
+        let same = Market::with_config(products.clone(), config.clone());
+        let other = Market::with_config(
+            products,
+            MarketConfig {
+                seed: Some(43),
+                ..config
+            },
+        );
+
+        // 相同种子下每个agent的偏好区间完全相同，不同种子则不同
+        assert!(!preference_ranges(&base).is_empty());
+        assert_eq!(preference_ranges(&base), preference_ranges(&same));
+        assert_ne!(preference_ranges(&base), preference_ranges(&other));
+    }
+
+    /// 把所有工厂报价抬到所有agent区间之上，并让所有agent都有需求。
+    /// 按比例平移无法移动下界为0的区间，因此直接换成报价区间固定为(1000000, 1500000)的同ID工厂
+    fn force_no_overlap(market: &Market, product_id: u64) {
+        let product = market
+            .products
+            .iter()
+            .find(|p| p.id() == product_id)
+            .unwrap();
+        let config = crate::config::FactoryConfig {
+            range_init: crate::config::RangeInitStrategy::CostPlusMarkup { markup: 1.5 },
+            unit_cost: 1_000_000.0,
+            ..Default::default()
+        };
+        let mut factories = market.factories.get(&product_id).unwrap().write().unwrap();
+        for factory in factories.iter_mut() {
+            *factory =
+                Factory::with_config(factory.id(), factory.name().to_string(), product, &config);
+        }
+        for agent in market.agents.read().unwrap().iter() {
+            agent.read().unwrap().add_demand(product_id);
+        }
+    }
+
+    #[test]
+    fn test_force_no_overlap_zero_lower_bound() {
+        let product = test_product(1);
+        // 下界为0的报价区间按比例平移不会移动
+        let config = crate::config::FactoryConfig {
+            range_init: crate::config::RangeInitStrategy::CostPlusMarkup { markup: 1.5 },
+            ..Default::default()
+        };
+        let mut factory = Factory::with_config(1, "free".to_string(), &product, &config);
+        assert_eq!(factory.supply_price_range().0, 0.0);
+        factory.shift_supply_price_range(10.0);
+        assert_eq!(factory.supply_price_range().0, 0.0);
+
+        let market = Market::from_parts(vec![product], Vec::new(), vec![factory]);
+        force_no_overlap(&market, 1);
+        let factories = market.factories.get(&1).unwrap().read().unwrap();
+        assert_eq!(factories[0].id(), 1);
+        assert!(factories[0].supply_price_range().0 >= 1_000_000.0);
+    }
+
+    #[test]
+    fn test_stall_shifts_factory_prices() {
+        let config = MarketConfig {
+            stall_rounds: 3,
+            stall_action: StallAction::Shift(0.5),
+            ..Default::default()
+        };
+        let mut market = Market::with_config(vec![test_product(1)], config);
+        force_no_overlap(&market, 1);
+        let before: Vec<(f64, f64)> = {
+            let factories = market.factories.get(&1).unwrap().read().unwrap();
+            factories.iter().map(|f| f.supply_price_range()).collect()
+        };
+
+        // 前两轮只计数，第三轮整体下调报价
+        assert!(!market.check_stall());
+        assert!(!market.check_stall());
+        {
+            let factories = market.factories.get(&1).unwrap().read().unwrap();
+            let ranges: Vec<(f64, f64)> =
+                factories.iter().map(|f| f.supply_price_range()).collect();
+            assert_eq!(ranges, before);
+        }
+        assert!(!market.check_stall());
+        let factories = market.factories.get(&1).unwrap().read().unwrap();
+        for (factory, old) in factories.iter().zip(before.iter()) {
+            assert!(factory.supply_price_range().0 < old.0 * 0.51);
+        }
+    }
+
+    #[test]
+    fn test_stall_terminates() {
+        let config = MarketConfig {
+            stall_rounds: 2,
+            stall_action: StallAction::Terminate,
+            ..Default::default()
+        };
+        let mut market = Market::with_config(vec![test_product(1)], config);
+        force_no_overlap(&market, 1);
+        assert!(!market.check_stall());
+        assert!(market.check_stall());
+
+        // 未开启检测时不会触发
+        let mut market = Market::new(vec![test_product(1)]);
+        force_no_overlap(&market, 1);
+        for _ in 0..30 {
+            assert!(!market.check_stall());
+        }
+    }
+
+    #[test]
+    fn test_step_logs_to_memory_sink() {
+        let sink = MemorySink::new();
+        let logger = Logger::with_sink("memory_sink_test".to_string(), Arc::new(sink.clone()));
+
+        let product_id = 1;
+        let mut market =
+            Market::with_config(vec![test_product(product_id)], synced_demand_config())
+                .with_logger(logger);
+        market.step(1);
+
+        let rows = sink.rows();
+        let trade_rows: Vec<_> = rows
+            .iter()
+            .filter_map(|row| match row {
+                LoggedRow::Trade(log) if log.product_id == product_id => Some(log),
+                _ => None,
+            })
+            .collect();
+        let factory_rows = rows
+            .iter()
+            .filter(|row| {
+                matches!(row, LoggedRow::FactoryRangeOptimization(log) if log.product_id == product_id)
+            })
+            .count();
+
+        assert!(!trade_rows.is_empty(), "Trades should be logged");
+        assert!(trade_rows.iter().all(|log| log.round == 1));
+        // 每次成功或失败的交易都会让工厂调整一次区间
+        let settled_trades = trade_rows
+            .iter()
+            .filter(|log| log.trade_result == "Success" || log.trade_result == "Failed")
+            .count();
+        assert!(settled_trades > 0, "Some agents should have traded");
+        assert_eq!(factory_rows, settled_trades);
+    }
+
+    #[test]
+    fn test_concurrent_markets_log_separately() {
+        let sinks = [MemorySink::new(), MemorySink::new()];
+        let handles: Vec<_> = sinks
+            .iter()
+            .enumerate()
+            .map(|(i, sink)| {
+                let logger = Logger::with_sink(format!("market_{}", i), Arc::new(sink.clone()));
+                thread::spawn(move || {
+                    let mut market = Market::with_config(
+                        vec![test_product(1 + i as u64)],
+                        synced_demand_config(),
+                    )
+                    .with_logger(logger);
+                    market.step(1);
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        for (i, sink) in sinks.iter().enumerate() {
+            let rows = sink.rows();
+            let task_id = format!("market_{}", i);
+            assert!(!rows.is_empty());
+            // 每个市场的日志只进入自己的写入目标
+            assert!(rows.iter().all(|row| row.task_id() == task_id));
+            assert!(rows.iter().all(|row| match row {
+                LoggedRow::Trade(log) => log.product_id == 1 + i as u64,
+                _ => true,
+            }));
+            assert!(rows.iter().any(|row| matches!(row, LoggedRow::Trade(_))));
+        }
+    }
+
+    #[test]
+    fn test_competitor_prices_pull_outlier_down() {
+        let product_id = 1;
+        let market = Market::new(vec![test_product(product_id)]);
+        let factories = market.factories.get(&product_id).unwrap().clone();
+        let (outlier_id, old_offer) = {
+            let mut factory_list = factories.write().unwrap();
+            let outlier = &mut factory_list[0];
+            // 平移到远高于其他工厂的报价，区间宽度不变
+            outlier.observe_competitors(20_000.0, 1.0);
+            (outlier.id(), outlier.offer_price())
+        };
+
+        let mut config = MarketConfig::default();
+        config.factory.competitor_factor = 0.5;
+        process_product_trades(
+            &market.products[0],
+            factories.clone(),
+            market.agents.clone(),
+            1,
+            &market.recent_prices,
+            &config,
+            None,
+        );
+
+        let factory_list = factories.read().unwrap();
+        let outlier = factory_list.iter().find(|f| f.id() == outlier_id).unwrap();
+        assert!(outlier.offer_price() < old_offer * 0.6);
+    }
+
+    #[test]
+    fn test_auction_allocates_to_highest_bidders() {
+        let product = test_product(1);
+        let mut factories = vec![Factory::new(1, "seller".to_string(), &product)];
+        let lower = factories[0].supply_price_range().0;
+        let config = crate::config::AgentConfig {
+            demand_model: crate::config::DemandModelConfig::Bernoulli { probability: 0.0 },
+            ..Default::default()
+        };
+        // 15个agent争夺10件库存，愿付价依次升高
+        let agents: Vec<Arc<RwLock<Agent>>> = (0..15)
+            .map(|i| {
+                let agent = Agent::with_config(
+                    i,
+                    format!("bidder_{}", i),
+                    10000.0,
+                    std::slice::from_ref(&product),
+                    &config,
+                );
+                agent.set_preference_range(1, (0.0, lower + 1.0 + i as f64));
+                agent.add_demand(1);
+                Arc::new(RwLock::new(agent))
+            })
+            .collect();
+        let recent_prices = RwLock::new(BTreeMap::new());
+
+        let buyers = run_auction(
+            &mut factories,
+            &agents,
+            1,
+            &product,
+            &recent_prices,
+            None,
+            None,
+        );
+        assert_eq!(buyers.len(), 10);
+        assert_eq!(factories[0].get_stock(1), 0);
+
+        // 出价最高的10个agent按同一价格成交，其余没有买到
+        let clearing_price = (lower + 6.0 + lower) / 2.0;
+        for (i, a) in agents.iter().enumerate() {
+            let agent = a.read().unwrap();
+            if i >= 5 {
+                assert!(!agent.has_demand(1));
+                assert!((agent.cash() - (10000.0 - clearing_price)).abs() < 1e-9);
+            } else {
+                assert_eq!(agent.cash(), 10000.0);
+            }
+        }
+        assert_eq!(recent_prices.read().unwrap()[&1].len(), 10);
+    }
+
+    #[test]
+    fn test_max_buyers_per_round() {
+        let product_id = 1;
+        let product = test_product(product_id);
+        let factories = Arc::new(RwLock::new(vec![Factory::new(
+            1,
+            "counter".to_string(),
+            &product,
+        )]));
+        let config = crate::config::AgentConfig {
+            demand_model: crate::config::DemandModelConfig::Bernoulli { probability: 0.0 },
+            ..Default::default()
+        };
+        // 三个agent都有需求且一定能成交
+        let agents: Vec<Arc<RwLock<Agent>>> = (1..=3)
+            .map(|id| {
+                let agent = Agent::with_config(
+                    id,
+                    format!("buyer_{}", id),
+                    1_000_000.0,
+                    std::slice::from_ref(&product),
+                    &config,
+                );
+                agent.set_preference_range(product_id, (0.0, 100_000.0));
+                agent.add_demand(product_id);
+                Arc::new(RwLock::new(agent))
+            })
+            .collect();
+        let recent_prices = RwLock::new(BTreeMap::new());
+
+        let trades = process_product_trades(
+            &product,
+            factories,
+            Arc::new(RwLock::new(agents.clone())),
+            1,
+            &recent_prices,
+            &MarketConfig {
+                max_buyers_per_product_per_round: Some(2),
+                ..Default::default()
+            },
+            None,
+        )
+        .successes;
+        assert_eq!(trades, 2);
+        let still_waiting: Vec<u64> = agents
+            .iter()
+            .map(|a| a.read().unwrap())
+            .filter(|a| a.has_demand(product_id))
+            .map(|a| a.id())
+            .collect();
+        assert_eq!(still_waiting, vec![3]);
+    }
+
+    #[test]
+    fn test_tie_break_highest_stock() {
+        let product_id = 1;
+        let product = test_product(product_id);
+        // 相同种子得到相同的报价区间，1号工厂产能只有2件
+        let small = Factory::with_rng(
+            1,
+            "small".to_string(),
+            &product,
+            &crate::config::FactoryConfig {
+                max_capacity: Some((2.0, 0.0)),
+                ..Default::default()
+            },
+            &mut StdRng::seed_from_u64(7),
+        );
+        let large = Factory::with_rng(
+            2,
+            "large".to_string(),
+            &product,
+            &crate::config::FactoryConfig::default(),
+            &mut StdRng::seed_from_u64(7),
+        );
+        assert_eq!(small.offer_price(), large.offer_price());
+
+        let sold_by = |tie_break: TieBreak| {
+            let factories = Arc::new(RwLock::new(vec![small.clone(), large.clone()]));
+            let agent = Agent::with_config(
+                1,
+                "buyer".to_string(),
+                1_000_000.0,
+                std::slice::from_ref(&product),
+                &crate::config::AgentConfig {
+                    demand_model: crate::config::DemandModelConfig::Bernoulli { probability: 0.0 },
+                    ..Default::default()
+                },
+            );
+            agent.set_preference_range(product_id, (0.0, 100_000.0));
+            agent.add_demand(product_id);
+            let trades = process_product_trades(
+                &product,
+                factories.clone(),
+                Arc::new(RwLock::new(vec![Arc::new(RwLock::new(agent))])),
+                1,
+                &RwLock::new(BTreeMap::new()),
+                &MarketConfig {
+                    tie_break,
+                    ..Default::default()
+                },
+                None,
+            )
+            .successes;
+            assert_eq!(trades, 1);
+            let factories = factories.read().unwrap();
+            factories.iter().find(|f| f.revenue() > 0.0).unwrap().id()
+        };
+        assert_eq!(sold_by(TieBreak::LowestId), 1);
+        assert_eq!(sold_by(TieBreak::HighestStock), 2);
+    }
+
+    #[test]
+    fn test_conversion_funnel() {
+        let product_id = 1;
+        let product = test_product(product_id);
+        let factory = Factory::with_rng(
+            1,
+            "shop".to_string(),
+            &product,
+            &crate::config::FactoryConfig::default(),
+            &mut StdRng::seed_from_u64(3),
+        );
+        let (lower, upper) = factory.supply_price_range();
+        let buyer = |id: u64, cash: f64, range: (f64, f64), config: &crate::config::AgentConfig| {
+            let agent = Agent::with_config(
+                id,
+                format!("agent_{}", id),
+                cash,
+                std::slice::from_ref(&product),
+                config,
+            );
+            agent.set_preference_range(product_id, range);
+            agent.add_demand(product_id);
+            Arc::new(RwLock::new(agent))
+        };
+        let config = crate::config::AgentConfig {
+            demand_model: crate::config::DemandModelConfig::Bernoulli { probability: 0.0 },
+            ..Default::default()
+        };
+        // 临近发薪日的agent本轮不参与撮合
+        let broke_before_payday = crate::config::AgentConfig {
+            payday_interval: 2,
+            payday_spending_decline: 1.0,
+            ..config.clone()
+        };
+        let idle = Agent::with_config(
+            1,
+            "idle".to_string(),
+            1000.0,
+            std::slice::from_ref(&product),
+            &config,
+        );
+        let agents = vec![
+            Arc::new(RwLock::new(idle)),
+            buyer(2, 1_000_000.0, (0.0, 100_000.0), &config),
+            buyer(3, 1_000_000.0, (0.0, lower * 0.5), &config),
+            buyer(4, 1_000_000.0, (upper * 2.0, upper * 3.0), &config),
+            buyer(5, 0.0, (0.0, 100_000.0), &config),
+            buyer(6, 1_000_000.0, (0.0, 100_000.0), &broke_before_payday),
+        ];
+
+        let funnel = process_product_trades(
+            &product,
+            Arc::new(RwLock::new(vec![factory.clone()])),
+            Arc::new(RwLock::new(agents)),
+            2,
+            &RwLock::new(BTreeMap::new()),
+            &MarketConfig::default(),
+            None,
+        );
+        assert_eq!(
+            funnel,
+            ConversionFunnel {
+                demands: 5,
+                successes: 1,
+                not_matched: 1,
+                price_too_high: 1,
+                price_too_low: 1,
+                cash_shortfall: 1,
+                stockouts: 0,
+            }
+        );
+
+        // 只有1件库存：第一个agent买走后第二个agent缺货
+        let small = Factory::with_rng(
+            1,
+            "small".to_string(),
+            &product,
+            &crate::config::FactoryConfig {
+                max_capacity: Some((1.0, 0.0)),
+                ..Default::default()
+            },
+            &mut StdRng::seed_from_u64(3),
+        );
+        let agents = vec![
+            buyer(7, 1_000_000.0, (0.0, 100_000.0), &config),
+            buyer(8, 1_000_000.0, (0.0, 100_000.0), &config),
+        ];
+        let funnel = process_product_trades(
+            &product,
+            Arc::new(RwLock::new(vec![small])),
+            Arc::new(RwLock::new(agents)),
+            2,
+            &RwLock::new(BTreeMap::new()),
+            &MarketConfig::default(),
+            None,
+        );
+        assert_eq!(funnel.demands, 2);
+        assert_eq!(funnel.successes, 1);
+        assert_eq!(funnel.stockouts, 1);
+        assert_eq!(funnel.conversion_rate(), 0.5);
+    }
+
+    #[test]
+    fn test_word_of_mouth_reaches_neighbors() {
+        let product_id = 1;
+        let product = test_product(product_id);
+        let factories = Arc::new(RwLock::new(vec![Factory::new(
+            1,
+            "shop".to_string(),
+            &product,
+        )]));
+        let agent_config = crate::config::AgentConfig {
+            demand_model: crate::config::DemandModelConfig::Bernoulli { probability: 0.0 },
+            word_of_mouth_decay: 0.0,
+            ..Default::default()
+        };
+        let agents: Vec<Arc<RwLock<Agent>>> = (1..=10)
+            .map(|id| {
+                let agent = Agent::with_config(
+                    id,
+                    format!("agent_{}", id),
+                    1_000_000.0,
+                    std::slice::from_ref(&product),
+                    &agent_config,
+                );
+                Arc::new(RwLock::new(agent))
+            })
+            .collect();
+        // 只有5号agent有需求并一定成交
+        {
+            let buyer = agents[4].read().unwrap();
+            buyer.set_preference_range(product_id, (0.0, 100_000.0));
+            buyer.add_demand(product_id);
+        }
+        let config = MarketConfig {
+            word_of_mouth_neighbors: 2,
+            word_of_mouth_boost: 1.0,
+            ..Default::default()
+        };
+
+        let trades = process_product_trades(
+            &product,
+            factories,
+            Arc::new(RwLock::new(agents.clone())),
+            1,
+            &RwLock::new(BTreeMap::new()),
+            &config,
+            None,
+        )
+        .successes;
+        assert_eq!(trades, 1);
+
+        // 环上相邻的4号和6号听到推荐并因此产生需求，其余agent没有新需求
+        let mut rng = StdRng::seed_from_u64(7);
+        for a in agents.iter() {
+            let agent = a.read().unwrap();
+            agent.generate_demands(&mut rng);
+            let recommended = [4, 6].contains(&agent.id());
+            assert_eq!(
+                agent.has_demand(product_id),
+                recommended,
+                "agent {}",
+                agent.id()
+            );
+        }
+    }
+
+    #[test]
+    fn test_force_trade() {
+        let sink = MemorySink::new();
+        let logger = Logger::with_sink("force_trade_test".to_string(), Arc::new(sink.clone()));
+        let product_id = 1;
+        let mut market = Market::new(vec![test_product(product_id)]).with_logger(logger);
+        let factory_id = market.factories[&product_id].read().unwrap()[0].id();
+        let agent = market
+            .agents
+            .read()
+            .unwrap()
+            .iter()
+            .find(|a| a.read().unwrap().id() == 5)
+            .cloned()
+            .unwrap();
+        let cash_before = agent.read().unwrap().cash();
+
+        market.force_trade(5, factory_id, product_id, 42.0).unwrap();
+
+        assert!((agent.read().unwrap().cash() - (cash_before - 42.0)).abs() < 1e-9);
+        assert_eq!(
+            agent.read().unwrap().last_factory(product_id),
+            Some(factory_id)
+        );
+        let stock = market.factories[&product_id].read().unwrap()[0].get_stock(0);
+        assert_eq!(stock, 9);
+
+        let trades: Vec<_> = sink
+            .rows()
+            .into_iter()
+            .filter_map(|row| match row {
+                LoggedRow::Trade(log) if log.product_id == product_id => Some(log),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].agent_id, 5);
+        assert_eq!(trades[0].factory_id, factory_id);
+        assert_eq!(trades[0].trade_result, "Success");
+        assert_eq!(trades[0].price, Some(42.0));
+
+        // 资金不足或对象不存在时报错，状态不变
+        assert!(market.force_trade(5, factory_id, product_id, 1e9).is_err());
+        assert!(
+            market
+                .force_trade(5, factory_id + 10_000, product_id, 1.0)
+                .is_err()
+        );
+        assert_eq!(
+            market.factories[&product_id].read().unwrap()[0].get_stock(0),
+            9
+        );
+    }
+
+    #[test]
+    fn test_subscription_renewal_counts_as_trade() {
+        let sink = MemorySink::new();
+        let logger = Logger::with_sink("renewal_test".to_string(), Arc::new(sink.clone()));
+        let product_id = 1;
+        let product = test_product(product_id).with_subscription_rounds(3);
+        let mut config = synced_demand_config();
+        config.agent.demand_model =
+            crate::config::DemandModelConfig::Bernoulli { probability: 0.0 };
+        let mut market = Market::with_config(vec![product], config).with_logger(logger);
+        let factory_id = market.factories[&product_id].read().unwrap()[0].id();
+        market.force_trade(5, factory_id, product_id, 42.0).unwrap();
+
+        // 下一轮没有新需求，只有订阅续购，计入成交数、漏斗和交易日志
+        market.step(1);
+        assert_eq!(market.total_trades, 2);
+        let funnel = market.conversion_funnel();
+        assert_eq!(funnel.demands, 1);
+        assert_eq!(funnel.successes, 1);
+        let renewals: Vec<_> = sink
+            .rows()
+            .into_iter()
+            .filter_map(|row| match row {
+                LoggedRow::Trade(log) if log.interval_relation == "Subscription" => Some(log),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(renewals.len(), 1);
+        assert_eq!(renewals[0].round, 1);
+        assert_eq!(renewals[0].agent_id, 5);
+        assert_eq!(renewals[0].factory_id, factory_id);
+        assert_eq!(renewals[0].price, Some(42.0));
+    }
+
+    #[test]
+    fn test_added_factory_logs_to_market_logger() {
+        let sink = MemorySink::new();
+        let logger = Logger::with_sink("entrant_log_test".to_string(), Arc::new(sink.clone()));
+        let product = test_product(1);
+        let mut market =
+            Market::with_config(vec![product.clone()], synced_demand_config()).with_logger(logger);
+        // 设置日志记录器之后才加入市场的工厂也写入市场的日志记录器
+        market.add_factory(Factory::new(100, "entrant".to_string(), &product));
+
+        market.force_trade(5, 100, 1, 42.0).unwrap();
+
+        let rows = sink.rows();
+        assert!(rows.iter().any(|row| matches!(
+            row,
+            LoggedRow::FactoryRangeOptimization(log) if log.factory_id == 100
+        )));
+        assert!(rows.iter().all(|row| row.task_id() == "entrant_log_test"));
+    }
+
+    #[test]
+    fn test_wealth_histogram() {
+        let buckets = histogram(&[0.0, 10.0, 20.0, 25.0, 60.0, 95.0, 100.0], 4);
+        assert_eq!(buckets, vec![(0.0, 3), (25.0, 1), (50.0, 1), (75.0, 2)]);
+
+        assert!(histogram(&[], 4).is_empty());
+        assert!(histogram(&[1.0], 0).is_empty());
+
+        // 新建市场所有agent资金相同，只有一个区间
+        let market = Market::new(vec![test_product(1)]);
+        assert_eq!(market.wealth_histogram(10), vec![(1000.0, 100)]);
+    }
+
+    #[test]
+    fn test_tax_revenue() {
+        let sink = MemorySink::new();
+        let logger = Logger::with_sink("tax_revenue_test".to_string(), Arc::new(sink.clone()));
+        let product_id = 1;
+        let product = test_product(product_id).with_producer_tax_rate(0.1);
+        let mut market = Market::new(vec![product]).with_logger(logger);
+        let factory_ids: Vec<u64> = market.factories[&product_id]
+            .read()
+            .unwrap()
+            .iter()
+            .map(|f| f.id())
+            .collect();
+        assert_eq!(market.tax_revenue(), 0.0);
+
+        market
+            .force_trade(1, factory_ids[0], product_id, 40.0)
+            .unwrap();
+        market
+            .force_trade(2, factory_ids[1], product_id, 60.0)
+            .unwrap();
+        assert!((market.tax_revenue() - 10.0).abs() < 1e-9);
+
+        // 交易日志同时记录成交价和扣税后的工厂入账价
+        let prices: Vec<_> = sink
+            .rows()
+            .into_iter()
+            .filter_map(|row| match row {
+                LoggedRow::Trade(log) => Some((log.price.unwrap(), log.net_price.unwrap())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(prices.len(), 2);
+        for ((price, net_price), expected) in prices.into_iter().zip([(40.0, 36.0), (60.0, 54.0)]) {
+            assert_eq!(price, expected.0);
+            assert!((net_price - expected.1).abs() < 1e-9);
+        }
+
+        // 工厂退出后已缴的税仍计入总额
+        market.remove_factory(factory_ids[0]);
+        assert!((market.tax_revenue() - 10.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_dividends() {
+        let product_id = 1;
+        let mut config = MarketConfig::default();
+        config.factory.dividend_ratio = 0.5;
+        config.factory.dividend_reserve = 10.0;
+        let mut market = Market::with_config(vec![test_product(product_id)], config);
+        let factory_id = market.factories[&product_id].read().unwrap()[0].id();
+        market.force_trade(1, factory_id, product_id, 50.0).unwrap();
+
+        // 超出保留额的40中分出一半
+        market.pay_dividends();
+        let retained = market.factories[&product_id]
+            .read()
+            .unwrap()
+            .iter()
+            .find(|f| f.id() == factory_id)
+            .unwrap()
+            .retained_revenue();
+        assert!((retained - 30.0).abs() < 1e-9);
+        assert!((market.distributed_profits() - 20.0).abs() < 1e-9);
+
+        // 下一轮继续按剩余的留存收入分红
+        market.pay_dividends();
+        assert!((market.distributed_profits() - 30.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_round_diffs() {
+        let product_id = 1;
+        let config = MarketConfig {
+            diff_capacity: 2,
+            ..synced_demand_config()
+        };
+        let mut market = Market::with_config(vec![test_product(product_id)], config);
+        market.step(1);
+
+        let diffs = market.round_diffs();
+        assert_eq!(diffs.len(), 1);
+        let diff = &diffs[0];
+        assert_eq!(diff.round, 1);
+        // 有需求的agent交易后，工厂和agent的区间都会调整
+        assert!(!diff.factories.is_empty());
+        assert!(!diff.agents.is_empty());
+        for change in diff.factories.iter().chain(diff.agents.iter()) {
+            assert_eq!(change.product_id, product_id);
+            assert_ne!(change.before, change.after);
+        }
+        let factory_list = market.factories[&product_id].read().unwrap();
+        for change in &diff.factories {
+            let factory = factory_list.iter().find(|f| f.id() == change.id).unwrap();
+            assert_eq!(factory.supply_price_range(), change.after);
+        }
+        drop(factory_list);
+
+        // 超出容量时只保留最近的几轮
+        market.step(2);
+        market.step(3);
+        let rounds: Vec<u64> = market.round_diffs().iter().map(|d| d.round).collect();
+        assert_eq!(rounds, vec![2, 3]);
+    }
+
+    #[test]
+    fn test_run_interrupted() {
+        let mut market = Market::with_config(vec![test_product(1)], synced_demand_config());
+        // 停止标志在run开始前已置位，完成第一轮后退出
+        market.stop_handle().store(true, Ordering::SeqCst);
+
+        let outcome = market.run();
+
+        assert_eq!(outcome.reason, EndReason::Interrupted);
+        assert_eq!(outcome.rounds, 1);
+        assert_eq!(outcome.total_trades, market.total_trades);
+    }
+
+    #[test]
+    fn test_bench() {
+        let config = MarketConfig {
+            seed: Some(42),
+            agent: crate::config::AgentConfig {
+                round_synced_demand: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut market = Market::with_config(vec![test_product(1)], config.clone());
+        let report = market.bench(10);
+        assert_eq!(report.rounds, 10);
+        assert!(report.rounds_per_sec > 0.0);
+        assert_eq!(market.round, 10);
+        assert!(market.logger.is_none());
+
+        // 相同种子的两次bench成交数相同
+        let mut again = Market::with_config(vec![test_product(1)], config);
+        assert_eq!(again.bench(10).total_trades, report.total_trades);
+    }
+
+    #[test]
+    fn test_run_max_round() {
+        let config = MarketConfig {
+            max_round: 2,
+            ..Default::default()
+        };
+        let sink = MemorySink::new();
+        let logger = Logger::with_sink("max_round".to_string(), Arc::new(sink.clone()));
+        let mut market = Market::with_config(vec![test_product(1)], config).with_logger(logger);
+        let outcome = market.run();
+        assert_eq!(outcome.reason, EndReason::MaxRounds);
+        assert_eq!(outcome.rounds, 2);
+
+        // 结束时写入一行汇总
+        let run_ends: Vec<(u64, String)> = sink
+            .rows()
+            .into_iter()
+            .filter_map(|row| match row {
+                LoggedRow::RunEnd(log) => Some((log.final_round, log.reason)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(run_ends, vec![(2, "MaxRounds".to_string())]);
+    }
+
+    #[test]
+    fn test_from_parts() {
+        let product = test_product(1);
+        let agents = (1..=2)
+            .map(|id| {
+                Agent::with_config(
+                    id,
+                    format!("custom_{}", id),
+                    500.0,
+                    std::slice::from_ref(&product),
+                    &crate::config::AgentConfig {
+                        demand_model: crate::config::DemandModelConfig::Bernoulli {
+                            probability: 0.0,
+                        },
+                        ..Default::default()
+                    },
+                )
+            })
+            .collect();
+        let monopolist = Factory::new(7, "monopolist".to_string(), &product);
+        let market = Market::from_parts(vec![product], agents, vec![monopolist]);
+
+        assert_eq!(market.agents.read().unwrap().len(), 2);
+        assert_eq!(market.factory_count(1), 1);
+        assert_eq!(market.factories[&1].read().unwrap()[0].id(), 7);
+    }
+
+    #[test]
+    fn test_export_preferences() {
+        let products = vec![test_product(1), test_product(2).with_essential(true)];
+        let agent_config = crate::config::AgentConfig {
+            demand_model: crate::config::DemandModelConfig::Bernoulli { probability: 0.0 },
+            ..Default::default()
+        };
+        let agents = (1..=3)
+            .map(|id| {
+                Agent::with_config(id, format!("agent_{}", id), 500.0, &products, &agent_config)
+            })
+            .collect();
+        let market = Market::from_parts(products, agents, Vec::new());
+
+        let path = std::env::temp_dir().join(format!("preferences_{}.csv", std::process::id()));
+        assert_eq!(market.export_preferences(&path).unwrap(), 6);
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(
+            lines[0],
+            "agent_id,product_id,category,current_range_lower,current_range_upper,current_price,original_elastic"
+        );
+        assert_eq!(lines.len(), 7);
+        let pairs: Vec<(u64, u64)> = lines[1..]
+            .iter()
+            .map(|line| {
+                let fields: Vec<&str> = line.split(',').collect();
+                assert_eq!(fields.len(), 7);
+                let lower: f64 = fields[3].parse().unwrap();
+                let upper: f64 = fields[4].parse().unwrap();
+                assert!(lower <= upper);
+                let category = if fields[1] == "2" {
+                    "essential"
+                } else {
+                    "non_essential"
+                };
+                assert_eq!(fields[2], category);
+                (fields[0].parse().unwrap(), fields[1].parse().unwrap())
+            })
+            .collect();
+        assert_eq!(pairs, vec![(1, 1), (1, 2), (2, 1), (2, 2), (3, 1), (3, 2)]);
+    }
+
+    #[test]
+    fn test_total_stock() {
+        let product = test_product(1);
+        let full = Factory::new(1, "full".to_string(), &product);
+        let small = Factory::with_config(
+            2,
+            "small".to_string(),
+            &product,
+            &crate::config::FactoryConfig {
+                max_capacity: Some((5.0, 0.0)),
+                ..Default::default()
+            },
+        );
+        let market = Market::from_parts(
+            vec![product, test_product(2)],
+            Vec::new(),
+            vec![full, small],
+        );
+        for list in market.factories.values() {
+            for factory in list.write().unwrap().iter_mut() {
+                factory.start_round(1);
+            }
+        }
+
+        assert_eq!(market.total_stock(1, 1), 15);
+        assert_eq!(market.total_stock(2, 1), 0);
+        assert_eq!(market.total_stock(3, 1), 0);
+    }
+
+    #[test]
+    fn test_average_markup() {
+        let product = test_product(1);
+        let cost_plus = |id: u64, unit_cost: f64, markup: f64| {
+            Factory::with_config(
+                id,
+                format!("factory_{}", id),
+                &product,
+                &crate::config::FactoryConfig {
+                    range_init: crate::config::RangeInitStrategy::CostPlusMarkup { markup },
+                    unit_cost,
+                    ..Default::default()
+                },
+            )
+        };
+        // 报价为区间中点：25/20=1.25，20/10=2.0
+        let market = Market::from_parts(
+            vec![product.clone(), test_product(2)],
+            Vec::new(),
+            vec![cost_plus(1, 20.0, 1.5), cost_plus(2, 10.0, 3.0)],
+        );
+        for list in market.factories.values() {
+            for factory in list.write().unwrap().iter_mut() {
+                factory.start_round(1);
+            }
+        }
+
+        assert!((market.average_markup(1, 1) - 1.625).abs() < 1e-9);
+        assert_eq!(market.average_markup(2, 1), 0.0);
+        assert_eq!(market.average_markup(3, 1), 0.0);
+    }
+
+    #[test]
+    fn test_scheduled_shock() {
+        let config = MarketConfig {
+            shock_schedule: vec![Shock {
+                round: 3,
+                product_id: 1,
+                cost_factor: 3.0,
+                duration: Some(2),
+            }],
+            factory: crate::config::FactoryConfig {
+                unit_cost: 10.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut market = Market::with_config(vec![test_product(1)], config);
+        let ranges = |market: &Market| -> Vec<(f64, f64)> {
+            market.factories[&1]
+                .read()
+                .unwrap()
+                .iter()
+                .map(|f| f.supply_price_range())
+                .collect()
+        };
+        let costs = |market: &Market| -> Vec<f64> {
+            market.factories[&1]
+                .read()
+                .unwrap()
+                .iter()
+                .map(|f| f.unit_cost())
+                .collect()
+        };
+        let original = ranges(&market);
+        let original_costs = costs(&market);
+
+        market.apply_shocks(2);
+        assert_eq!(ranges(&market), original);
+
+        // 第3轮生效，报价区间和单位成本都变为三倍
+        market.apply_shocks(3);
+        for (shocked, before) in ranges(&market).iter().zip(&original) {
+            assert!((shocked.0 - before.0 * 3.0).abs() < 0.02);
+            assert!((shocked.1 - before.1 * 3.0).abs() < 0.02);
+        }
+        for (shocked, before) in costs(&market).iter().zip(&original_costs) {
+            assert!((shocked - before * 3.0).abs() < 1e-9);
+        }
+        market.apply_shocks(4);
+        assert!(ranges(&market)[0].1 > original[0].1 * 2.9);
+
+        // 持续2轮后在第5轮恢复
+        market.apply_shocks(5);
+        for (reverted, before) in ranges(&market).iter().zip(&original) {
+            assert!((reverted.0 - before.0).abs() < 0.02);
+            assert!((reverted.1 - before.1).abs() < 0.02);
+        }
+        for (reverted, before) in costs(&market).iter().zip(&original_costs) {
+            assert!((reverted - before).abs() < 1e-9);
+        }
+        assert!(market.active_shocks.is_empty());
+    }
+
+    #[test]
+    fn test_price_convergence() {
+        let config = MarketConfig {
+            convergence_rounds: 3,
+            convergence_tolerance: 0.5,
+            ..Default::default()
+        };
+        let mut market = Market::with_config(vec![test_product(1)], config.clone());
+        // 还没有成交时不算收敛
+        assert!(!market.check_convergence());
+
+        // 成交价稳定，连续3轮后判定收敛
+        for round in 0..3 {
+            for price in [10.0, 10.2, 9.9, 10.1] {
+                record_trade_price(&market.recent_prices, 1, price);
+            }
+            assert_eq!(market.check_convergence(), round == 2);
+        }
+
+        // 成交价剧烈波动时不会收敛
+        let mut market = Market::with_config(vec![test_product(1)], config);
+        for _ in 0..10 {
+            for price in [5.0, 15.0, 8.0, 20.0] {
+                record_trade_price(&market.recent_prices, 1, price);
+            }
+            assert!(!market.check_convergence());
+        }
+    }
+
+    #[test]
+    fn test_price_volatility() {
+        let market = Market::new(vec![test_product(1)]);
+        assert_eq!(market.price_volatility(1, 10), 0.0);
+
+        record_trade_price(&market.recent_prices, 1, 10.0);
+        assert_eq!(market.price_volatility(1, 10), 0.0);
+
+        for price in [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0] {
+            record_trade_price(&market.recent_prices, 1, price);
+        }
+        // 最近8笔的均值为5，标准差为2
+        assert!((market.price_volatility(1, 8) - 2.0).abs() < 1e-9);
+        // 窗口只取最近两笔
+        assert!((market.price_volatility(1, 2) - 1.0).abs() < 1e-9);
+        // 其他商品不受影响
+        assert_eq!(market.price_volatility(2, 8), 0.0);
+
+        // 只保留最近PRICE_HISTORY笔
+        for _ in 0..PRICE_HISTORY {
+            record_trade_price(&market.recent_prices, 1, 3.0);
+        }
+        assert_eq!(market.price_volatility(1, PRICE_HISTORY * 2), 0.0);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_metrics_text() {
+        let product_id = 1;
+        let mut market = Market::new(vec![test_product(product_id)]);
+        let factory_id = market.factories[&product_id].read().unwrap()[0].id();
+        market.force_trade(1, factory_id, product_id, 20.0).unwrap();
+        market.step(1);
+
+        let text = market.metrics_text();
+        for name in [
+            "market_trades_total",
+            "market_round",
+            "market_active_factories",
+            "market_mean_price",
+            "market_agents_solvent",
+            "market_outstanding_demand",
+            "market_factory_offer",
+            "market_refused_demands_total",
+            "market_unsupplied_products",
+            "market_funnel_total",
+            "market_log_rows_dropped_total",
+            "market_product_stock",
+            "market_average_markup",
+            "market_price_index",
+        ] {
+            assert!(
+                text.contains(&format!("# TYPE {} ", name)),
+                "missing {}",
+                name
+            );
+        }
+        assert!(text.contains(&format!("market_trades_total {}\n", market.total_trades)));
+        assert!(text.contains("market_round 1\n"));
+        assert!(text.contains(&format!(
+            "market_active_factories{{product_id=\"{}\"}} {}\n",
+            product_id,
+            market.factory_count(product_id)
+        )));
+        assert!(text.contains("market_agents_solvent 100\n"));
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_write_metrics() {
+        let path = std::env::temp_dir().join(format!("metrics_{}.prom", std::process::id()));
+        let config = MarketConfig {
+            metrics_path: Some(path.to_string_lossy().into_owned()),
+            ..synced_demand_config()
+        };
+        let mut market = Market::with_config(vec![test_product(1)], config);
+        market.step(1);
+        market.write_metrics();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(content, market.metrics_text());
+        assert!(content.contains("market_round 1\n"));
+    }
+
+    #[test]
+    fn test_round_end_log_order() {
+        let run = |product_ids: [u64; 3]| {
+            let sink = MemorySink::new();
+            let logger = Logger::with_sink("order_test".to_string(), Arc::new(sink.clone()));
+            let products = product_ids.iter().map(|id| test_product(*id)).collect();
+            let mut market = Market::new(products).with_logger(logger);
+            market.step(1);
+
+            let cash_order: Vec<u64> = sink
+                .rows()
+                .iter()
+                .filter_map(|row| match row {
+                    LoggedRow::AgentCash(log) => Some(log.agent_id),
+                    _ => None,
+                })
+                .collect();
+            let product_order: Vec<u64> = market.factories.keys().cloned().collect();
+            (cash_order, product_order)
+        };
+
+        // 商品的创建顺序和每轮的随机打乱都不影响记录顺序
+        let (cash_a, products_a) = run([3, 1, 2]);
+        let (cash_b, products_b) = run([2, 3, 1]);
+        assert_eq!(cash_a, (1..=100).collect::<Vec<u64>>());
+        assert_eq!(cash_a, cash_b);
+        assert_eq!(products_a, vec![1, 2, 3]);
+        assert_eq!(products_a, products_b);
+    }
+
+    #[test]
+    fn test_factory_offer_log() {
+        let sink = MemorySink::new();
+        let logger = Logger::with_sink("offer_test".to_string(), Arc::new(sink.clone()));
+        let mut market = Market::new(vec![test_product(1)]).with_logger(logger);
+        market.step(1);
+
+        let offers: Vec<FactoryOfferLog> = sink
+            .rows()
+            .into_iter()
+            .filter_map(|row| match row {
+                LoggedRow::FactoryOffer(log) => Some(log),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(offers.len(), market.factory_count(1));
+        for log in &offers {
+            assert_eq!(log.round, 1);
+            assert!(log.offer_price >= log.supply_range_lower - 1e-9);
+            assert!(log.offer_price <= log.supply_range_upper + 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_log_sample_ratio() {
+        let agent_cash_rows = |ratio: f64| {
+            let sink = MemorySink::new();
+            let logger = Logger::with_sink("sample_test".to_string(), Arc::new(sink.clone()));
+            let config = MarketConfig {
+                log_sample_ratio: ratio,
+                ..Default::default()
+            };
+            let mut market = Market::with_config(vec![test_product(1)], config).with_logger(logger);
+            market.step(1);
+            market.step(2);
+            sink.rows()
+                .iter()
+                .filter_map(|row| match row {
+                    LoggedRow::AgentCash(log) => Some(log.agent_id),
+                    _ => None,
+                })
+                .collect::<Vec<u64>>()
+        };
+
+        assert!(agent_cash_rows(0.0).is_empty());
+        let all = agent_cash_rows(1.0);
+        assert_eq!(all.len(), 200);
+
+        // 部分抽样时每轮记录的是同一批agent
+        let sampled = agent_cash_rows(0.5);
+        let (first, second) = sampled.split_at(sampled.len() / 2);
+        assert!(!first.is_empty() && first.len() < 100);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn test_negotiation_attempt_log() {
+        let product = test_product(1);
+        let agent = Agent::with_config(
+            1,
+            "browser".to_string(),
+            500.0,
+            std::slice::from_ref(&product),
+            &crate::config::AgentConfig {
+                demand_model: crate::config::DemandModelConfig::Bernoulli { probability: 0.0 },
+                elasticity_bounds: (0.0, 0.0),
+                ..Default::default()
+            },
+        );
+        agent.add_demand(1);
+        // 三家工厂的报价区间固定为(500, 750)，远高于agent的区间，agent依次比较三家都无法成交
+        let factory_config = crate::config::FactoryConfig {
+            range_init: crate::config::RangeInitStrategy::CostPlusMarkup { markup: 1.5 },
+            unit_cost: 500.0,
+            ..Default::default()
+        };
+        let factories: Vec<Factory> = (1..=3)
+            .map(|id| Factory::with_config(id, format!("pricey_{}", id), &product, &factory_config))
+            .collect();
+        let config = MarketConfig {
+            verbose_negotiation_log: true,
+            ..Default::default()
+        };
+        let sink = MemorySink::new();
+        let logger = Logger::with_sink("attempt_test".to_string(), Arc::new(sink.clone()));
+        let mut market =
+            Market::from_parts_with_config(vec![product], vec![agent], factories, config)
+                .with_logger(logger);
+        market.step(1);
+
+        let attempts: Vec<_> = sink
+            .rows()
+            .into_iter()
+            .filter_map(|row| match row {
+                LoggedRow::NegotiationAttempt(log) => Some(log),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(attempts.len(), 3);
+        let mut factory_ids: Vec<u64> = attempts.iter().map(|log| log.factory_id).collect();
+        factory_ids.sort();
+        assert_eq!(factory_ids, vec![1, 2, 3]);
+        for (index, log) in attempts.iter().enumerate() {
+            assert_eq!(log.agent_id, 1);
+            assert_eq!(log.attempt, index as u32 + 1);
+            assert_eq!(log.interval_relation, "AgentBelowFactory");
+            assert!(log.offer_price >= log.supply_range_lower - 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_price_index_real_cash() {
+        let product = test_product(1);
+        let agent = Agent::with_config(
+            1,
+            "pensioner".to_string(),
+            100.0,
+            std::slice::from_ref(&product),
+            &crate::config::AgentConfig {
+                demand_model: crate::config::DemandModelConfig::Bernoulli { probability: 0.0 },
+                income: 10.0,
+                payday_interval: 1,
+                ..Default::default()
+            },
+        );
+        let sink = MemorySink::new();
+        let logger = Logger::with_sink("index_test".to_string(), Arc::new(sink.clone()));
+        let mut market =
+            Market::from_parts(vec![product], vec![agent], Vec::new()).with_logger(logger);
+
+        // 成交价每轮上涨10%，agent每轮领取固定的名义收入
+        let mut price = 20.0;
+        for round in 1..=10 {
+            record_trade_price(&market.recent_prices, 1, price);
+            market.step(round);
+            price *= 1.1;
+        }
+        assert!(market.price_index() > 1.0);
+
+        let cash_rows: Vec<(f64, f64)> = sink
+            .rows()
+            .into_iter()
+            .filter_map(|row| match row {
+                LoggedRow::AgentCash(log) => Some((log.cash, log.real_cash)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(cash_rows.len(), 10);
+        // 基期的实际现金等于名义现金，之后实际现金的增长慢于名义现金
+        let (first_cash, first_real) = cash_rows[0];
+        let (last_cash, last_real) = cash_rows[9];
+        assert!((first_cash - first_real).abs() < 1e-9);
+        assert!(last_cash > first_cash);
+        assert!(last_real - first_real < last_cash - first_cash);
+        assert!((market.real_cash(last_cash) - last_real).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_process_order() {
+        let trade_order = |order: ProcessOrder, rounds: u64| {
+            let product = test_product(1);
+            let agents: Vec<Agent> = [(1, 100.0), (2, 400.0), (3, 200.0), (4, 300.0)]
+                .into_iter()
+                .map(|(id, cash)| {
+                    Agent::with_config(
+                        id,
+                        format!("ordered_{}", id),
+                        cash,
+                        std::slice::from_ref(&product),
+                        &crate::config::AgentConfig {
+                            demand_model: crate::config::DemandModelConfig::Bernoulli {
+                                probability: 0.0,
+                            },
+                            ..Default::default()
+                        },
+                    )
+                })
+                .collect();
+            let factory = Factory::new(1, "single".to_string(), &product);
+            let config = MarketConfig {
+                process_order: order,
+                ..Default::default()
+            };
+            let sink = MemorySink::new();
+            let logger = Logger::with_sink("order_test".to_string(), Arc::new(sink.clone()));
+            let mut market =
+                Market::from_parts_with_config(vec![product], agents, vec![factory], config)
+                    .with_logger(logger);
+            for round in 1..=rounds {
+                market.step(round);
+            }
+            sink.rows()
+                .into_iter()
+                .filter_map(|row| match row {
+                    LoggedRow::Trade(log) => Some((log.round, log.agent_id)),
+                    _ => None,
+                })
+                .collect::<Vec<(u64, u64)>>()
+        };
+
+        // 每轮都按ID从小到大与工厂撮合，不受打乱顺序的随机数影响
+        let by_id = trade_order(ProcessOrder::ById, 5);
+        for round in 1..=5 {
+            let ids: Vec<u64> = by_id
+                .iter()
+                .filter(|(r, _)| *r == round)
+                .map(|(_, id)| *id)
+                .collect();
+            assert_eq!(ids, vec![1, 2, 3, 4]);
+        }
+
+        // 现金多的agent优先
+        let by_cash: Vec<u64> = trade_order(ProcessOrder::ByCash, 1)
+            .into_iter()
+            .map(|(_, id)| id)
+            .collect();
+        assert_eq!(by_cash, vec![2, 4, 3, 1]);
+    }
+
+    #[test]
+    fn test_demand_creation_log() {
+        let created_rows = |verbose: bool| {
+            let product = test_product(1);
+            let agent_config = crate::config::AgentConfig {
+                demand_model: crate::config::DemandModelConfig::Bernoulli { probability: 1.0 },
+                round_synced_demand: true,
+                verbose_demand_log: verbose,
+                ..Default::default()
+            };
+            let agent = Agent::with_config(
+                1,
+                "logged".to_string(),
+                100.0,
+                std::slice::from_ref(&product),
+                &agent_config,
+            );
+            let elastic = agent.preferences().get(&1).unwrap().original_elastic;
+            let sink = MemorySink::new();
+            let logger = Logger::with_sink("demand_test".to_string(), Arc::new(sink.clone()));
+            let config = MarketConfig {
+                agent: agent_config,
+                ..Default::default()
+            };
+            let mut market =
+                Market::from_parts_with_config(vec![product], vec![agent], Vec::new(), config)
+                    .with_logger(logger);
+            // 需求在市场的日志作用域之外生成，仍写入市场的日志记录器
+            market.step(3);
+            // 已有的需求不重复记录
+            market.step(4);
+            let rows: Vec<_> = sink
+                .rows()
+                .into_iter()
+                .filter_map(|row| match row {
+                    LoggedRow::AgentDemandCreation(log) => Some(log),
+                    _ => None,
+                })
+                .collect();
+            (rows, elastic)
+        };
+
+        let (rows, elastic) = created_rows(true);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].round, 3);
+        assert_eq!(rows[0].agent_id, 1);
+        assert_eq!(rows[0].product_id, 1);
+        assert_eq!(rows[0].agent_pref_original_elastic, Some(elastic));
+        assert_eq!(rows[0].task_id, "demand_test");
+
+        assert!(created_rows(false).0.is_empty());
+    }
 }
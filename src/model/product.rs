@@ -1,11 +1,20 @@
 use crate::entity::normal_distribute::NormalDistribution;
+use std::hash::{Hash, Hasher};
 
+/// 商品。相等与哈希只比较id，价格、弹性分布不参与比较，
+/// 因此可以直接作为HashMap/HashSet的键使用
 #[derive(Clone)]
 pub struct Product {
     id: u64,
     name: String,
     original_price_distribution: NormalDistribution,
     original_elastic_distribution: NormalDistribution,
+    seed_offset: u64,         // 与全局种子组合，单独改变该商品的随机性
+    producer_tax_rate: f64,   // 工厂每笔销售收入中缴税的比例
+    essential: bool,          // 需求档位：true为必需品，false为非必需品
+    supply_elasticity: f64,   // 供给弹性，工厂每次交易结果调整报价区间的幅度倍数
+    durable: bool,            // 耐用品：买到后由agent持有，可以在二手市场转卖
+    subscription_rounds: u32, // 订阅期轮数，成交后按成交价自动续购，0表示不订阅
 }
 
 impl Product {
@@ -21,6 +30,12 @@ impl Product {
             name,
             original_price_distribution,
             original_elastic_distribution,
+            seed_offset: 0,
+            producer_tax_rate: 0.0,
+            essential: false,
+            supply_elasticity: 1.0,
+            durable: false,
+            subscription_rounds: 0,
         }
     }
 
@@ -35,6 +50,12 @@ impl Product {
             name,
             original_price_distribution,
             original_elastic_distribution,
+            seed_offset: 0,
+            producer_tax_rate: 0.0,
+            essential: false,
+            supply_elasticity: 1.0,
+            durable: false,
+            subscription_rounds: 0,
         }
     }
 
@@ -53,6 +74,79 @@ impl Product {
     pub fn original_elastic_distribution(&self) -> &NormalDistribution {
         &self.original_elastic_distribution
     }
+
+    pub fn with_seed_offset(mut self, seed_offset: u64) -> Self {
+        self.seed_offset = seed_offset;
+        self
+    }
+
+    pub fn seed_offset(&self) -> u64 {
+        self.seed_offset
+    }
+
+    /// 设置生产者税率(0~1)，工厂每笔销售实得 单价×(1-税率)
+    pub fn with_producer_tax_rate(mut self, rate: f64) -> Self {
+        self.producer_tax_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn producer_tax_rate(&self) -> f64 {
+        self.producer_tax_rate
+    }
+
+    /// 标记为必需品：每轮先于非必需品交易，稀缺时agent愿意接受更高价格
+    pub fn with_essential(mut self, essential: bool) -> Self {
+        self.essential = essential;
+        self
+    }
+
+    pub fn is_essential(&self) -> bool {
+        self.essential
+    }
+
+    /// 设置供给弹性：工厂的涨价、降价比例乘以该值，越大报价对供需反应越剧烈，越小价格越粘滞
+    pub fn with_supply_elasticity(mut self, elasticity: f64) -> Self {
+        self.supply_elasticity = elasticity.max(0.0);
+        self
+    }
+
+    pub fn supply_elasticity(&self) -> f64 {
+        self.supply_elasticity
+    }
+
+    /// 标记为耐用品：agent持有买到的商品，现金紧张时可以折价抛售
+    pub fn with_durable(mut self, durable: bool) -> Self {
+        self.durable = durable;
+        self
+    }
+
+    pub fn is_durable(&self) -> bool {
+        self.durable
+    }
+
+    /// 设置订阅期：成交后agent在之后共rounds轮内按同一价格向同一工厂自动续购，0或1表示不订阅
+    pub fn with_subscription_rounds(mut self, rounds: u32) -> Self {
+        self.subscription_rounds = rounds;
+        self
+    }
+
+    pub fn subscription_rounds(&self) -> u32 {
+        self.subscription_rounds
+    }
+}
+
+impl PartialEq for Product {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for Product {}
+
+impl Hash for Product {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
 }
 
 #[cfg(test)]
@@ -110,4 +204,26 @@ mod tests {
         assert_eq!(product_elastic_dist.name(), elastic_dist.name());
         assert_eq!(product_elastic_dist.std_dev(), elastic_dist.std_dev());
     }
+
+    #[test]
+    fn test_eq_and_hash_by_id() {
+        use std::collections::HashSet;
+        use std::collections::hash_map::DefaultHasher;
+
+        let a = Product::new(1, "bread".to_string());
+        let b = Product::new(1, "milk".to_string());
+        let c = Product::new(2, "bread".to_string());
+        assert!(a == b);
+        assert!(a != c);
+
+        let hash = |p: &Product| {
+            let mut hasher = DefaultHasher::new();
+            p.hash(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(hash(&a), hash(&b));
+
+        let set: HashSet<Product> = [a, b, c].into_iter().collect();
+        assert_eq!(set.len(), 2);
+    }
 }
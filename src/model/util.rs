@@ -134,6 +134,14 @@ pub fn gen_price_in_range(range: (f64, f64), cash: f64) -> Option<f64> {
     }
 }
 
+/// 批量购买：在区间内生成单价，数量不超过需求量和现金能负担的数量，至少1件
+/// 返回(单价, 数量)
+pub fn gen_purchase_in_range(range: (f64, f64), cash: f64, quantity: i16) -> Option<(f64, i16)> {
+    let price = gen_price_in_range(range, cash)?;
+    let affordable = (cash / price).floor().min(i16::MAX as f64) as i16;
+    Some((price, quantity.min(affordable).max(1)))
+}
+
 pub fn gen_new_range_with_price(price: f64, old_range: (f64, f64), shrink_rate: f64) -> (f64, f64) {
     let (old_min, old_max) = old_range;
     let width = round_to_nearest_cent(old_max - old_min);
@@ -739,4 +747,19 @@ mod tests {
             rate
         );
     }
+
+    #[test]
+    fn test_gen_purchase_in_range() {
+        // 现金充足时买满需求量
+        let (price, quantity) = gen_purchase_in_range((10.0, 10.0), 1000.0, 5).unwrap();
+        assert_eq!(price, 10.0);
+        assert_eq!(quantity, 5);
+
+        // 现金只够买3件
+        let (_, quantity) = gen_purchase_in_range((10.0, 10.0), 35.0, 5).unwrap();
+        assert_eq!(quantity, 3);
+
+        // 买不起1件
+        assert!(gen_purchase_in_range((10.0, 10.0), 5.0, 5).is_none());
+    }
 }